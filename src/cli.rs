@@ -1,53 +1,76 @@
-use std::fmt::{Display, Formatter};
-
-#[derive(Clone, Debug)]
-pub enum Role {
-    Slave(String),
-    Master(String),
-}
-
-impl Display for Role {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Role::Master(_) => write!(f, "master"),
-            Role::Slave(_) => write!(f, "slave"),
-        }
-    }
-}
+use crate::redis_server::config::Config;
 
 #[derive(Debug)]
 pub struct Cli {
-    pub port: String,
-    pub master_server: String,
-    pub role: Role,
+    pub config: Config,
 }
 
 impl Cli {
+    // redis-server's arguments: an optional config file first, then any
+    // parameter as `--name value`, in any order.
     pub fn new(args: Vec<String>) -> Self {
-        print!("Command line arguments are: {:?}", args);
-        let mut port: String = String::from("127.0.0.1:");
-        let mut master_server: String = String::new();
-        let role: Role;
-        match args.get(1) {
-            Some(_port_flag) => {
-                let port_number = args.get(2).unwrap();
-                port.push_str(port_number.as_str());
+        let program = args.first().map_or("redis-starter-rust", String::as_str);
+        match args.get(1).map(String::as_str) {
+            Some("-h" | "--help") => {
+                println!("{}", usage(program));
+                std::process::exit(0);
             }
-            None => port.push_str("6379"),
-        }
-        match args.get(3) {
-            Some(_replica_of_flag) => {
-                let master_host = args.get(4).to_owned().unwrap();
-                let master_port = args.get(5).to_owned().unwrap();
-                master_server = format!("{}:{}", master_host, master_port);
-                role = Role::Slave(String::from("slave"));
+            Some("-v" | "--version") => {
+                println!("{} v={}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
             }
-            None => role = Role::Master(String::from("master")),
+            _ => {}
         }
-        Self {
-            port,
-            master_server,
-            role,
+        let file = args.get(1).filter(|arg| !arg.starts_with("--"));
+        let first_option = if file.is_some() { 2 } else { 1 };
+        let options = parse_options(args.get(first_option..).unwrap_or_default())
+            .unwrap_or_else(|e| fatal(&e, program));
+        let config =
+            Config::load(file.map(String::as_str), &options).unwrap_or_else(|e| fatal(&e, program));
+        Self { config }
+    }
+}
+
+// Each --name starts an option and the arguments up to the next one are its
+// value, so that `--replicaof host port` and `--replicaof "host port"` mean
+// the same.
+fn parse_options(args: &[String]) -> Result<Vec<Vec<String>>, String> {
+    let mut options: Vec<Vec<String>> = Vec::new();
+    for arg in args {
+        match arg.strip_prefix("--").filter(|name| !name.is_empty()) {
+            Some(name) => options.push(vec![name.to_string()]),
+            None => match options.last_mut() {
+                Some(option) => option.push(arg.clone()),
+                None => return Err(format!("Invalid argument '{}', expected --<option>", arg)),
+            },
         }
     }
+    Ok(options)
+}
+
+fn usage(program: &str) -> String {
+    [
+        format!("Usage: {} [/path/to/redis.conf] [options]", program),
+        format!("       {} -v or --version", program),
+        format!("       {} -h or --help", program),
+        String::new(),
+        "Any parameter of redis.conf or CONFIG SET can be given as --<name> <value>.".to_string(),
+        String::new(),
+        "Examples:".to_string(),
+        format!("       {} (run the server with default conf)", program),
+        format!("       {} /etc/redis/6379.conf", program),
+        format!("       {} --port 7777", program),
+        format!("       {} --port 7777 --replicaof 127.0.0.1 8888", program),
+        format!(
+            "       {} /etc/myredis.conf --maxmemory 100mb --save \"\"",
+            program
+        ),
+    ]
+    .join("\n")
+}
+
+fn fatal(error: &str, program: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("Run '{} --help' for usage.", program);
+    std::process::exit(1);
 }
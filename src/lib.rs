@@ -0,0 +1,2 @@
+// The RESP codec on its own, for tools and tests talking to the server.
+pub mod resp;
@@ -1,29 +1,191 @@
+mod acl;
+mod bitmap;
+mod client;
+pub mod config;
+mod database;
+mod eviction;
+mod functions;
+mod geo;
+mod glob;
+mod hyperloglog;
+mod indexed_map;
+mod info;
+mod keyspace;
+mod listpack;
+mod lua;
 pub mod master;
+mod notify;
+mod pubsub;
+mod random;
+mod rdb;
+mod redis_value;
 pub mod replica;
+mod resp3;
+mod scripting;
+mod server_state;
+mod set;
+mod sha1;
+mod sha256;
+mod skiplist;
+mod slot;
+mod sorted_set;
+mod stream;
+mod string;
 mod timed_hashmap;
+mod tracking;
+mod transaction;
 
-use std::borrow::Cow;
 use std::fmt::Display;
-use std::net::SocketAddr;
 // use std::fs;
 // use std::io;
-use std::num::ParseIntError;
+use std::sync::Arc;
 use std::time::Duration;
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpListener, TcpStream};
 
-use self::timed_hashmap::TimedHashMap;
+// The codec lives in the library, `resp` stays reachable from the modules
+// here under its old path.
+use redis_starter_rust::resp;
+
+use self::client::{Client, Registration};
+use self::config::Config;
+use self::keyspace::Keyspace;
+use self::pubsub::Subscriber;
+use self::resp::{Argument, Protocol, RequestDecoder, RespValue};
+use self::server_state::ServerState;
+use self::transaction::Transaction;
 
 #[warn(dead_code)]
 enum Command {
     Echo,
     Ping,
+    Hello,
+    Auth,
+    Acl,
+    Client,
+    Select,
+    Swapdb,
+    Move,
+    Flushdb,
+    Flushall,
+    Dbsize,
+    Del,
     Set,
     Get,
+    Incr,
+    Decr,
+    Incrby,
+    Decrby,
+    Incrbyfloat,
+    Append,
+    Strlen,
+    Getrange,
+    Setrange,
+    Getdel,
+    Getex,
+    Mget,
+    Mset,
+    Msetnx,
+    Setnx,
+    Setex,
+    Psetex,
+    Lcs,
+    Setbit,
+    Getbit,
+    Bitcount,
+    Bitpos,
+    Bitop,
+    Bitfield,
+    BitfieldRo,
+    Pfadd,
+    Pfcount,
+    Pfmerge,
+    Geoadd,
+    Geodist,
+    Geohash,
+    Geopos,
+    Geosearch,
+    Geosearchstore,
     Info,
     Replconf,
     Psync,
+    Subscribe,
+    Unsubscribe,
+    Psubscribe,
+    Punsubscribe,
+    Publish,
+    Pubsub,
+    Ssubscribe,
+    Sunsubscribe,
+    Spublish,
+    Quit,
+    Reset,
+    Config,
+    Multi,
+    Exec,
+    Discard,
+    Watch,
+    Unwatch,
+    Sadd,
+    Srem,
+    Smembers,
+    Scard,
+    Sismember,
+    Smismember,
+    Sinter,
+    Sinterstore,
+    Sintercard,
+    Sunion,
+    Sunionstore,
+    Sdiff,
+    Sdiffstore,
+    Spop,
+    Srandmember,
+    Smove,
+    Sscan,
+    Zadd,
+    Zincrby,
+    Zcard,
+    Zscore,
+    Zrem,
+    Zrange,
+    Zrank,
+    Zrevrank,
+    Zcount,
+    Zlexcount,
+    Zpopmin,
+    Zpopmax,
+    Zunionstore,
+    Zinterstore,
+    Zrandmember,
+    Zscan,
+    Bzpopmin,
+    Bzpopmax,
+    Xadd,
+    Xrange,
+    Xrevrange,
+    Xlen,
+    Xtrim,
+    Xdel,
+    Xread,
+    Xgroup,
+    Xack,
+    Xpending,
+    Xclaim,
+    Xautoclaim,
+    Xinfo,
+    Xreadgroup,
+    Save,
+    Bgsave,
+    Eval,
+    Evalsha,
+    EvalRo,
+    EvalshaRo,
+    Script,
+    Function,
+    Fcall,
+    FcallRo,
     Unknown,
 }
 
@@ -32,97 +194,1737 @@ impl Display for Command {
         match self {
             Command::Echo => write!(f, "echo"),
             Command::Ping => write!(f, "ping"),
+            Command::Hello => write!(f, "hello"),
+            Command::Auth => write!(f, "auth"),
+            Command::Acl => write!(f, "acl"),
+            Command::Client => write!(f, "client"),
+            Command::Select => write!(f, "select"),
+            Command::Swapdb => write!(f, "swapdb"),
+            Command::Move => write!(f, "move"),
+            Command::Flushdb => write!(f, "flushdb"),
+            Command::Flushall => write!(f, "flushall"),
+            Command::Dbsize => write!(f, "dbsize"),
+            Command::Del => write!(f, "del"),
             Command::Set => write!(f, "set"),
             Command::Get => write!(f, "get"),
+            Command::Incr => write!(f, "incr"),
+            Command::Decr => write!(f, "decr"),
+            Command::Incrby => write!(f, "incrby"),
+            Command::Decrby => write!(f, "decrby"),
+            Command::Incrbyfloat => write!(f, "incrbyfloat"),
+            Command::Append => write!(f, "append"),
+            Command::Strlen => write!(f, "strlen"),
+            Command::Getrange => write!(f, "getrange"),
+            Command::Setrange => write!(f, "setrange"),
+            Command::Getdel => write!(f, "getdel"),
+            Command::Getex => write!(f, "getex"),
+            Command::Mget => write!(f, "mget"),
+            Command::Mset => write!(f, "mset"),
+            Command::Msetnx => write!(f, "msetnx"),
+            Command::Setnx => write!(f, "setnx"),
+            Command::Setex => write!(f, "setex"),
+            Command::Psetex => write!(f, "psetex"),
+            Command::Lcs => write!(f, "lcs"),
+            Command::Setbit => write!(f, "setbit"),
+            Command::Getbit => write!(f, "getbit"),
+            Command::Bitcount => write!(f, "bitcount"),
+            Command::Bitpos => write!(f, "bitpos"),
+            Command::Bitop => write!(f, "bitop"),
+            Command::Bitfield => write!(f, "bitfield"),
+            Command::BitfieldRo => write!(f, "bitfield_ro"),
+            Command::Pfadd => write!(f, "pfadd"),
+            Command::Pfcount => write!(f, "pfcount"),
+            Command::Pfmerge => write!(f, "pfmerge"),
+            Command::Geoadd => write!(f, "geoadd"),
+            Command::Geodist => write!(f, "geodist"),
+            Command::Geohash => write!(f, "geohash"),
+            Command::Geopos => write!(f, "geopos"),
+            Command::Geosearch => write!(f, "geosearch"),
+            Command::Geosearchstore => write!(f, "geosearchstore"),
             Command::Info => write!(f, "info"),
             Command::Replconf => write!(f, "replconf"),
             Command::Psync => write!(f, "psync"),
+            Command::Subscribe => write!(f, "subscribe"),
+            Command::Unsubscribe => write!(f, "unsubscribe"),
+            Command::Psubscribe => write!(f, "psubscribe"),
+            Command::Punsubscribe => write!(f, "punsubscribe"),
+            Command::Publish => write!(f, "publish"),
+            Command::Pubsub => write!(f, "pubsub"),
+            Command::Ssubscribe => write!(f, "ssubscribe"),
+            Command::Sunsubscribe => write!(f, "sunsubscribe"),
+            Command::Spublish => write!(f, "spublish"),
+            Command::Quit => write!(f, "quit"),
+            Command::Reset => write!(f, "reset"),
+            Command::Config => write!(f, "config"),
+            Command::Multi => write!(f, "multi"),
+            Command::Exec => write!(f, "exec"),
+            Command::Discard => write!(f, "discard"),
+            Command::Watch => write!(f, "watch"),
+            Command::Unwatch => write!(f, "unwatch"),
+            Command::Sadd => write!(f, "sadd"),
+            Command::Srem => write!(f, "srem"),
+            Command::Smembers => write!(f, "smembers"),
+            Command::Scard => write!(f, "scard"),
+            Command::Sismember => write!(f, "sismember"),
+            Command::Smismember => write!(f, "smismember"),
+            Command::Sinter => write!(f, "sinter"),
+            Command::Sinterstore => write!(f, "sinterstore"),
+            Command::Sintercard => write!(f, "sintercard"),
+            Command::Sunion => write!(f, "sunion"),
+            Command::Sunionstore => write!(f, "sunionstore"),
+            Command::Sdiff => write!(f, "sdiff"),
+            Command::Sdiffstore => write!(f, "sdiffstore"),
+            Command::Spop => write!(f, "spop"),
+            Command::Srandmember => write!(f, "srandmember"),
+            Command::Smove => write!(f, "smove"),
+            Command::Sscan => write!(f, "sscan"),
+            Command::Zadd => write!(f, "zadd"),
+            Command::Zincrby => write!(f, "zincrby"),
+            Command::Zcard => write!(f, "zcard"),
+            Command::Zscore => write!(f, "zscore"),
+            Command::Zrem => write!(f, "zrem"),
+            Command::Zrange => write!(f, "zrange"),
+            Command::Zrank => write!(f, "zrank"),
+            Command::Zrevrank => write!(f, "zrevrank"),
+            Command::Zcount => write!(f, "zcount"),
+            Command::Zlexcount => write!(f, "zlexcount"),
+            Command::Zpopmin => write!(f, "zpopmin"),
+            Command::Zpopmax => write!(f, "zpopmax"),
+            Command::Zunionstore => write!(f, "zunionstore"),
+            Command::Zinterstore => write!(f, "zinterstore"),
+            Command::Zrandmember => write!(f, "zrandmember"),
+            Command::Zscan => write!(f, "zscan"),
+            Command::Bzpopmin => write!(f, "bzpopmin"),
+            Command::Bzpopmax => write!(f, "bzpopmax"),
+            Command::Xadd => write!(f, "xadd"),
+            Command::Xrange => write!(f, "xrange"),
+            Command::Xrevrange => write!(f, "xrevrange"),
+            Command::Xlen => write!(f, "xlen"),
+            Command::Xtrim => write!(f, "xtrim"),
+            Command::Xdel => write!(f, "xdel"),
+            Command::Xread => write!(f, "xread"),
+            Command::Xgroup => write!(f, "xgroup"),
+            Command::Xack => write!(f, "xack"),
+            Command::Xpending => write!(f, "xpending"),
+            Command::Xclaim => write!(f, "xclaim"),
+            Command::Xautoclaim => write!(f, "xautoclaim"),
+            Command::Xinfo => write!(f, "xinfo"),
+            Command::Xreadgroup => write!(f, "xreadgroup"),
+            Command::Save => write!(f, "save"),
+            Command::Bgsave => write!(f, "bgsave"),
+            Command::Eval => write!(f, "eval"),
+            Command::Evalsha => write!(f, "evalsha"),
+            Command::EvalRo => write!(f, "eval_ro"),
+            Command::EvalshaRo => write!(f, "evalsha_ro"),
+            Command::Script => write!(f, "script"),
+            Command::Function => write!(f, "function"),
+            Command::Fcall => write!(f, "fcall"),
+            Command::FcallRo => write!(f, "fcall_ro"),
             Command::Unknown => write!(f, "unknown"),
         }
     }
 }
 
+// Every command name, for ACL rules and categories.
+const COMMAND_NAMES: [&str; 128] = [
+    "echo",
+    "ping",
+    "hello",
+    "auth",
+    "acl",
+    "client",
+    "select",
+    "swapdb",
+    "move",
+    "flushdb",
+    "flushall",
+    "dbsize",
+    "del",
+    "set",
+    "get",
+    "incr",
+    "decr",
+    "incrby",
+    "decrby",
+    "incrbyfloat",
+    "append",
+    "strlen",
+    "getrange",
+    "setrange",
+    "getdel",
+    "getex",
+    "mget",
+    "mset",
+    "msetnx",
+    "setnx",
+    "setex",
+    "psetex",
+    "lcs",
+    "setbit",
+    "getbit",
+    "bitcount",
+    "bitpos",
+    "bitop",
+    "bitfield",
+    "bitfield_ro",
+    "pfadd",
+    "pfcount",
+    "pfmerge",
+    "geoadd",
+    "geodist",
+    "geohash",
+    "geopos",
+    "geosearch",
+    "geosearchstore",
+    "info",
+    "replconf",
+    "psync",
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "publish",
+    "pubsub",
+    "ssubscribe",
+    "sunsubscribe",
+    "spublish",
+    "quit",
+    "reset",
+    "config",
+    "multi",
+    "exec",
+    "discard",
+    "watch",
+    "unwatch",
+    "sadd",
+    "srem",
+    "smembers",
+    "scard",
+    "sismember",
+    "smismember",
+    "sinter",
+    "sinterstore",
+    "sintercard",
+    "sunion",
+    "sunionstore",
+    "sdiff",
+    "sdiffstore",
+    "spop",
+    "srandmember",
+    "smove",
+    "sscan",
+    "zadd",
+    "zincrby",
+    "zcard",
+    "zscore",
+    "zrem",
+    "zrange",
+    "zrank",
+    "zrevrank",
+    "zcount",
+    "zlexcount",
+    "zpopmin",
+    "zpopmax",
+    "zunionstore",
+    "zinterstore",
+    "zrandmember",
+    "zscan",
+    "bzpopmin",
+    "bzpopmax",
+    "xadd",
+    "xrange",
+    "xrevrange",
+    "xlen",
+    "xtrim",
+    "xdel",
+    "xread",
+    "xgroup",
+    "xack",
+    "xpending",
+    "xclaim",
+    "xautoclaim",
+    "xinfo",
+    "xreadgroup",
+    "save",
+    "bgsave",
+    "eval",
+    "evalsha",
+    "eval_ro",
+    "evalsha_ro",
+    "script",
+    "function",
+    "fcall",
+    "fcall_ro",
+];
+
+// Commands that only read or modify the keyspace. They run while holding the
+// keyspace lock and hand back their reply.
+type KeyspaceHandler = fn(&mut Keyspace, &[Argument]) -> RespValue;
+
+impl Command {
+    fn from_name(name: &str) -> Command {
+        match name.to_lowercase().as_str() {
+            "echo" => Command::Echo,
+            "ping" => Command::Ping,
+            "hello" => Command::Hello,
+            "auth" => Command::Auth,
+            "acl" => Command::Acl,
+            "client" => Command::Client,
+            "select" => Command::Select,
+            "swapdb" => Command::Swapdb,
+            "move" => Command::Move,
+            "flushdb" => Command::Flushdb,
+            "flushall" => Command::Flushall,
+            "dbsize" => Command::Dbsize,
+            "del" => Command::Del,
+            "set" => Command::Set,
+            "get" => Command::Get,
+            "incr" => Command::Incr,
+            "decr" => Command::Decr,
+            "incrby" => Command::Incrby,
+            "decrby" => Command::Decrby,
+            "incrbyfloat" => Command::Incrbyfloat,
+            "append" => Command::Append,
+            "strlen" => Command::Strlen,
+            "getrange" => Command::Getrange,
+            "setrange" => Command::Setrange,
+            "getdel" => Command::Getdel,
+            "getex" => Command::Getex,
+            "mget" => Command::Mget,
+            "mset" => Command::Mset,
+            "msetnx" => Command::Msetnx,
+            "setnx" => Command::Setnx,
+            "setex" => Command::Setex,
+            "psetex" => Command::Psetex,
+            "lcs" => Command::Lcs,
+            "setbit" => Command::Setbit,
+            "getbit" => Command::Getbit,
+            "bitcount" => Command::Bitcount,
+            "bitpos" => Command::Bitpos,
+            "bitop" => Command::Bitop,
+            "bitfield" => Command::Bitfield,
+            "bitfield_ro" => Command::BitfieldRo,
+            "pfadd" => Command::Pfadd,
+            "pfcount" => Command::Pfcount,
+            "pfmerge" => Command::Pfmerge,
+            "geoadd" => Command::Geoadd,
+            "geodist" => Command::Geodist,
+            "geohash" => Command::Geohash,
+            "geopos" => Command::Geopos,
+            "geosearch" => Command::Geosearch,
+            "geosearchstore" => Command::Geosearchstore,
+            "info" => Command::Info,
+            "replconf" => Command::Replconf,
+            "psync" => Command::Psync,
+            "subscribe" => Command::Subscribe,
+            "unsubscribe" => Command::Unsubscribe,
+            "psubscribe" => Command::Psubscribe,
+            "punsubscribe" => Command::Punsubscribe,
+            "publish" => Command::Publish,
+            "pubsub" => Command::Pubsub,
+            "ssubscribe" => Command::Ssubscribe,
+            "sunsubscribe" => Command::Sunsubscribe,
+            "spublish" => Command::Spublish,
+            "quit" => Command::Quit,
+            "reset" => Command::Reset,
+            "config" => Command::Config,
+            "multi" => Command::Multi,
+            "exec" => Command::Exec,
+            "discard" => Command::Discard,
+            "watch" => Command::Watch,
+            "unwatch" => Command::Unwatch,
+            "sadd" => Command::Sadd,
+            "srem" => Command::Srem,
+            "smembers" => Command::Smembers,
+            "scard" => Command::Scard,
+            "sismember" => Command::Sismember,
+            "smismember" => Command::Smismember,
+            "sinter" => Command::Sinter,
+            "sinterstore" => Command::Sinterstore,
+            "sintercard" => Command::Sintercard,
+            "sunion" => Command::Sunion,
+            "sunionstore" => Command::Sunionstore,
+            "sdiff" => Command::Sdiff,
+            "sdiffstore" => Command::Sdiffstore,
+            "spop" => Command::Spop,
+            "srandmember" => Command::Srandmember,
+            "smove" => Command::Smove,
+            "sscan" => Command::Sscan,
+            "zadd" => Command::Zadd,
+            "zincrby" => Command::Zincrby,
+            "zcard" => Command::Zcard,
+            "zscore" => Command::Zscore,
+            "zrem" => Command::Zrem,
+            "zrange" => Command::Zrange,
+            "zrank" => Command::Zrank,
+            "zrevrank" => Command::Zrevrank,
+            "zcount" => Command::Zcount,
+            "zlexcount" => Command::Zlexcount,
+            "zpopmin" => Command::Zpopmin,
+            "zpopmax" => Command::Zpopmax,
+            "zunionstore" => Command::Zunionstore,
+            "zinterstore" => Command::Zinterstore,
+            "zrandmember" => Command::Zrandmember,
+            "zscan" => Command::Zscan,
+            "bzpopmin" => Command::Bzpopmin,
+            "bzpopmax" => Command::Bzpopmax,
+            "xadd" => Command::Xadd,
+            "xrange" => Command::Xrange,
+            "xrevrange" => Command::Xrevrange,
+            "xlen" => Command::Xlen,
+            "xtrim" => Command::Xtrim,
+            "xdel" => Command::Xdel,
+            "xread" => Command::Xread,
+            "xgroup" => Command::Xgroup,
+            "xack" => Command::Xack,
+            "xpending" => Command::Xpending,
+            "xclaim" => Command::Xclaim,
+            "xautoclaim" => Command::Xautoclaim,
+            "xinfo" => Command::Xinfo,
+            "xreadgroup" => Command::Xreadgroup,
+            "save" => Command::Save,
+            "bgsave" => Command::Bgsave,
+            "eval" => Command::Eval,
+            "evalsha" => Command::Evalsha,
+            "eval_ro" => Command::EvalRo,
+            "evalsha_ro" => Command::EvalshaRo,
+            "script" => Command::Script,
+            "function" => Command::Function,
+            "fcall" => Command::Fcall,
+            "fcall_ro" => Command::FcallRo,
+            _ => Command::Unknown,
+        }
+    }
+
+    fn keyspace_handler(&self) -> Option<KeyspaceHandler> {
+        match self {
+            Command::Incr => Some(string::handle_incr),
+            Command::Decr => Some(string::handle_decr),
+            Command::Incrby => Some(string::handle_incrby),
+            Command::Decrby => Some(string::handle_decrby),
+            Command::Incrbyfloat => Some(string::handle_incrbyfloat),
+            Command::Append => Some(string::handle_append),
+            Command::Strlen => Some(string::handle_strlen),
+            Command::Getrange => Some(string::handle_getrange),
+            Command::Getdel => Some(string::handle_getdel),
+            Command::Getex => Some(string::handle_getex),
+            Command::Mget => Some(string::handle_mget),
+            Command::Lcs => Some(string::handle_lcs),
+            Command::Setrange => Some(string::handle_setrange),
+            Command::Mset => Some(string::handle_mset),
+            Command::Msetnx => Some(string::handle_msetnx),
+            Command::Setnx => Some(string::handle_setnx),
+            Command::Setex => Some(string::handle_setex),
+            Command::Psetex => Some(string::handle_psetex),
+            Command::Setbit => Some(bitmap::handle_setbit),
+            Command::Getbit => Some(bitmap::handle_getbit),
+            Command::Bitcount => Some(bitmap::handle_bitcount),
+            Command::Bitpos => Some(bitmap::handle_bitpos),
+            Command::Bitop => Some(bitmap::handle_bitop),
+            Command::Bitfield => Some(bitmap::handle_bitfield),
+            Command::BitfieldRo => Some(bitmap::handle_bitfield_ro),
+            Command::Pfadd => Some(hyperloglog::handle_pfadd),
+            Command::Pfcount => Some(hyperloglog::handle_pfcount),
+            Command::Pfmerge => Some(hyperloglog::handle_pfmerge),
+            Command::Geoadd => Some(geo::handle_geoadd),
+            Command::Geodist => Some(geo::handle_geodist),
+            Command::Geohash => Some(geo::handle_geohash),
+            Command::Geopos => Some(geo::handle_geopos),
+            Command::Geosearch => Some(geo::handle_geosearch),
+            Command::Geosearchstore => Some(geo::handle_geosearchstore),
+            Command::Select => Some(database::handle_select),
+            Command::Swapdb => Some(database::handle_swapdb),
+            Command::Move => Some(database::handle_move),
+            Command::Flushdb => Some(database::handle_flushdb),
+            Command::Flushall => Some(database::handle_flushall),
+            Command::Dbsize => Some(database::handle_dbsize),
+            Command::Del => Some(database::handle_del),
+            Command::Sadd => Some(set::handle_sadd),
+            Command::Srem => Some(set::handle_srem),
+            Command::Smembers => Some(set::handle_smembers),
+            Command::Scard => Some(set::handle_scard),
+            Command::Sismember => Some(set::handle_sismember),
+            Command::Smismember => Some(set::handle_smismember),
+            Command::Sinter => Some(set::handle_sinter),
+            Command::Sinterstore => Some(set::handle_sinterstore),
+            Command::Sintercard => Some(set::handle_sintercard),
+            Command::Sunion => Some(set::handle_sunion),
+            Command::Sunionstore => Some(set::handle_sunionstore),
+            Command::Sdiff => Some(set::handle_sdiff),
+            Command::Sdiffstore => Some(set::handle_sdiffstore),
+            Command::Spop => Some(set::handle_spop),
+            Command::Srandmember => Some(set::handle_srandmember),
+            Command::Smove => Some(set::handle_smove),
+            Command::Sscan => Some(set::handle_sscan),
+            Command::Zadd => Some(sorted_set::handle_zadd),
+            Command::Zincrby => Some(sorted_set::handle_zincrby),
+            Command::Zcard => Some(sorted_set::handle_zcard),
+            Command::Zscore => Some(sorted_set::handle_zscore),
+            Command::Zrem => Some(sorted_set::handle_zrem),
+            Command::Zrange => Some(sorted_set::handle_zrange),
+            Command::Zrank => Some(sorted_set::handle_zrank),
+            Command::Zrevrank => Some(sorted_set::handle_zrevrank),
+            Command::Zcount => Some(sorted_set::handle_zcount),
+            Command::Zlexcount => Some(sorted_set::handle_zlexcount),
+            Command::Zpopmin => Some(sorted_set::handle_zpopmin),
+            Command::Zpopmax => Some(sorted_set::handle_zpopmax),
+            Command::Zunionstore => Some(sorted_set::handle_zunionstore),
+            Command::Zinterstore => Some(sorted_set::handle_zinterstore),
+            Command::Zrandmember => Some(sorted_set::handle_zrandmember),
+            Command::Zscan => Some(sorted_set::handle_zscan),
+            Command::Xadd => Some(stream::handle_xadd),
+            Command::Xrange => Some(stream::handle_xrange),
+            Command::Xrevrange => Some(stream::handle_xrevrange),
+            Command::Xlen => Some(stream::handle_xlen),
+            Command::Xtrim => Some(stream::handle_xtrim),
+            Command::Xdel => Some(stream::handle_xdel),
+            Command::Xgroup => Some(stream::handle_xgroup),
+            Command::Xack => Some(stream::handle_xack),
+            Command::Xpending => Some(stream::handle_xpending),
+            Command::Xclaim => Some(stream::handle_xclaim),
+            Command::Xautoclaim => Some(stream::handle_xautoclaim),
+            Command::Xinfo => Some(stream::handle_xinfo),
+            Command::Save => Some(rdb::handle_save),
+            Command::Bgsave => Some(rdb::handle_bgsave),
+            _ => None,
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set
+                | Command::Incr
+                | Command::Decr
+                | Command::Incrby
+                | Command::Decrby
+                | Command::Incrbyfloat
+                | Command::Append
+                | Command::Setrange
+                | Command::Getdel
+                | Command::Getex
+                | Command::Mset
+                | Command::Msetnx
+                | Command::Setnx
+                | Command::Setex
+                | Command::Psetex
+                | Command::Setbit
+                | Command::Bitop
+                | Command::Bitfield
+                | Command::Pfadd
+                | Command::Pfcount
+                | Command::Pfmerge
+                | Command::Geoadd
+                | Command::Geosearchstore
+                | Command::Sadd
+                | Command::Srem
+                | Command::Sinterstore
+                | Command::Sunionstore
+                | Command::Sdiffstore
+                | Command::Spop
+                | Command::Smove
+                | Command::Zadd
+                | Command::Zincrby
+                | Command::Zrem
+                | Command::Zpopmin
+                | Command::Zpopmax
+                | Command::Zunionstore
+                | Command::Zinterstore
+                | Command::Bzpopmin
+                | Command::Bzpopmax
+                | Command::Xadd
+                | Command::Xtrim
+                | Command::Xdel
+                | Command::Xgroup
+                | Command::Xack
+                | Command::Xclaim
+                | Command::Xautoclaim
+                | Command::Xreadgroup
+                | Command::Eval
+                | Command::Evalsha
+                | Command::Fcall
+                | Command::Swapdb
+                | Command::Move
+                | Command::Flushdb
+                | Command::Flushall
+                | Command::Del
+        )
+    }
+
+    // Writes that may take more memory, which are refused when maxmemory
+    // is reached and nothing can be evicted. Deletions go through, like
+    // Redis' commands without the denyoom flag.
+    fn denies_oom(&self) -> bool {
+        self.is_write()
+            && !matches!(
+                self,
+                Command::Getdel
+                    | Command::Getex
+                    | Command::Pfcount
+                    | Command::Srem
+                    | Command::Spop
+                    | Command::Smove
+                    | Command::Zrem
+                    | Command::Zpopmin
+                    | Command::Zpopmax
+                    | Command::Bzpopmin
+                    | Command::Bzpopmax
+                    | Command::Xtrim
+                    | Command::Xdel
+                    | Command::Xack
+                    | Command::Xclaim
+                    | Command::Xautoclaim
+                    | Command::Xreadgroup
+                    | Command::Swapdb
+                    | Command::Move
+                    | Command::Flushdb
+                    | Command::Flushall
+                    | Command::Del
+            )
+    }
+
+    // Writes whose arguments don't tell replicas what happened (random
+    // picks, generated IDs, idle times). They record their effects in the
+    // keyspace instead and are never propagated verbatim.
+    fn replicates_effects(&self) -> bool {
+        matches!(
+            self,
+            Command::Spop
+                | Command::Bzpopmin
+                | Command::Bzpopmax
+                | Command::Xadd
+                | Command::Xclaim
+                | Command::Xautoclaim
+                | Command::Xreadgroup
+                | Command::Eval
+                | Command::Evalsha
+                | Command::Fcall
+        )
+    }
+
+    // The only commands a connection may run while it is subscribed to
+    // channels or patterns.
+    fn allowed_when_subscribed(&self) -> bool {
+        matches!(
+            self,
+            Command::Subscribe
+                | Command::Unsubscribe
+                | Command::Psubscribe
+                | Command::Punsubscribe
+                | Command::Ssubscribe
+                | Command::Sunsubscribe
+                | Command::Ping
+                | Command::Quit
+                | Command::Reset
+        )
+    }
+
+    // Number of arguments including the command name, like Redis: -N means
+    // at least N. Checked when commands are queued in a transaction.
+    fn arity(&self) -> i32 {
+        match self {
+            Command::Reset
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Unwatch
+            | Command::Save
+            | Command::Dbsize => 1,
+            Command::Echo
+            | Command::Get
+            | Command::Incr
+            | Command::Decr
+            | Command::Strlen
+            | Command::Getdel
+            | Command::Smembers
+            | Command::Scard
+            | Command::Zcard
+            | Command::Xlen
+            | Command::Select => 2,
+            Command::Incrby
+            | Command::Decrby
+            | Command::Incrbyfloat
+            | Command::Append
+            | Command::Setnx
+            | Command::Getbit
+            | Command::Publish
+            | Command::Spublish
+            | Command::Sismember
+            | Command::Zscore
+            | Command::Swapdb
+            | Command::Move => 3,
+            Command::Getrange
+            | Command::Setrange
+            | Command::Setex
+            | Command::Psetex
+            | Command::Setbit
+            | Command::Smove
+            | Command::Zincrby
+            | Command::Zcount
+            | Command::Zlexcount => 4,
+            Command::Ping
+            | Command::Hello
+            | Command::Info
+            | Command::Replconf
+            | Command::Unsubscribe
+            | Command::Punsubscribe
+            | Command::Sunsubscribe
+            | Command::Quit
+            | Command::Bgsave
+            | Command::Flushdb
+            | Command::Flushall => -1,
+            Command::Getex
+            | Command::Mget
+            | Command::Bitcount
+            | Command::Bitfield
+            | Command::BitfieldRo
+            | Command::Pfadd
+            | Command::Pfcount
+            | Command::Pfmerge
+            | Command::Geohash
+            | Command::Geopos
+            | Command::Subscribe
+            | Command::Psubscribe
+            | Command::Ssubscribe
+            | Command::Pubsub
+            | Command::Config
+            | Command::Watch
+            | Command::Sinter
+            | Command::Sunion
+            | Command::Sdiff
+            | Command::Spop
+            | Command::Srandmember
+            | Command::Zpopmin
+            | Command::Zpopmax
+            | Command::Zrandmember
+            | Command::Xgroup
+            | Command::Xinfo
+            | Command::Script
+            | Command::Function
+            | Command::Auth
+            | Command::Acl
+            | Command::Client
+            | Command::Del => -2,
+            Command::Set
+            | Command::Mset
+            | Command::Msetnx
+            | Command::Lcs
+            | Command::Bitpos
+            | Command::Psync
+            | Command::Sadd
+            | Command::Srem
+            | Command::Smismember
+            | Command::Sinterstore
+            | Command::Sintercard
+            | Command::Sunionstore
+            | Command::Sdiffstore
+            | Command::Sscan
+            | Command::Zrem
+            | Command::Zrank
+            | Command::Zrevrank
+            | Command::Zscan
+            | Command::Bzpopmin
+            | Command::Bzpopmax
+            | Command::Xdel
+            | Command::Xpending
+            | Command::Eval
+            | Command::Evalsha
+            | Command::EvalRo
+            | Command::EvalshaRo
+            | Command::Fcall
+            | Command::FcallRo => -3,
+            Command::Bitop
+            | Command::Geodist
+            | Command::Zadd
+            | Command::Zrange
+            | Command::Zunionstore
+            | Command::Zinterstore
+            | Command::Xrange
+            | Command::Xrevrange
+            | Command::Xtrim
+            | Command::Xread
+            | Command::Xack => -4,
+            Command::Geoadd | Command::Xadd => -5,
+            Command::Xclaim | Command::Xautoclaim => -6,
+            Command::Geosearch | Command::Xreadgroup => -7,
+            Command::Geosearchstore => -8,
+            Command::Unknown => 0,
+        }
+    }
+
+    // Commands that go through the connection handler or change what the
+    // connection is subscribed to, which EXEC can't run.
+    fn allowed_in_transaction(&self) -> bool {
+        !matches!(
+            self,
+            Command::Replconf
+                | Command::Psync
+                | Command::Subscribe
+                | Command::Unsubscribe
+                | Command::Psubscribe
+                | Command::Punsubscribe
+                | Command::Ssubscribe
+                | Command::Sunsubscribe
+        )
+    }
+
+    // Unknown commands and wrong numbers of arguments are refused before
+    // anything looks at the arguments, ACL rules and MULTI included.
+    fn check_arguments(&self, decoded_str: &[Argument]) -> Result<(), RespValue> {
+        if let Command::Unknown = self {
+            let arguments: String = decoded_str[1..]
+                .iter()
+                .map(|argument| format!("'{}' ", argument))
+                .collect();
+            return Err(RespValue::error(format!(
+                "ERR unknown command '{}', with args beginning with: {}",
+                decoded_str[0], arguments
+            )));
+        }
+        if !self.accepts_argument_count(decoded_str.len()) {
+            return Err(wrong_number_of_arguments(&self.to_string()));
+        }
+        Ok(())
+    }
+
+    // Whether `count` arguments, the command name included, fit the arity.
+    fn accepts_argument_count(&self, count: usize) -> bool {
+        let (arity, count) = (self.arity(), count as i32);
+        if arity > 0 {
+            count == arity
+        } else {
+            count >= -arity
+        }
+    }
+
+    // Scripts run commands the way EXEC does, without the ones managing
+    // transactions or running other scripts.
+    fn allowed_in_script(&self) -> bool {
+        self.allowed_in_transaction()
+            && !matches!(
+                self,
+                Command::Multi
+                    | Command::Exec
+                    | Command::Discard
+                    | Command::Watch
+                    | Command::Unwatch
+                    | Command::Quit
+                    | Command::Reset
+                    | Command::Eval
+                    | Command::Evalsha
+                    | Command::EvalRo
+                    | Command::EvalshaRo
+                    | Command::Script
+                    | Command::Function
+                    | Command::Fcall
+                    | Command::FcallRo
+                    | Command::Info
+                    | Command::Hello
+                    | Command::Auth
+                    | Command::Acl
+                    | Command::Client
+            )
+    }
+
+    // Commands clients may run before authenticating, which ACL rules don't
+    // apply to either.
+    fn allowed_without_auth(&self) -> bool {
+        matches!(
+            self,
+            Command::Auth | Command::Hello | Command::Quit | Command::Reset
+        )
+    }
+
+    // Commands whose first argument picks what they do, which ACL rules can
+    // allow one by one as "command|subcommand".
+    fn has_subcommands(&self) -> bool {
+        matches!(
+            self,
+            Command::Acl
+                | Command::Client
+                | Command::Config
+                | Command::Pubsub
+                | Command::Script
+                | Command::Function
+                | Command::Xgroup
+                | Command::Xinfo
+        )
+    }
+
+    // ACL categories, the same Redis puts the commands in.
+    fn categories(&self) -> Vec<&'static str> {
+        let group = match self {
+            Command::Set
+            | Command::Get
+            | Command::Incr
+            | Command::Decr
+            | Command::Incrby
+            | Command::Decrby
+            | Command::Incrbyfloat
+            | Command::Append
+            | Command::Strlen
+            | Command::Getrange
+            | Command::Setrange
+            | Command::Getdel
+            | Command::Getex
+            | Command::Mget
+            | Command::Mset
+            | Command::Msetnx
+            | Command::Setnx
+            | Command::Setex
+            | Command::Psetex
+            | Command::Lcs => "string",
+            Command::Setbit
+            | Command::Getbit
+            | Command::Bitcount
+            | Command::Bitpos
+            | Command::Bitop
+            | Command::Bitfield
+            | Command::BitfieldRo => "bitmap",
+            Command::Pfadd | Command::Pfcount | Command::Pfmerge => "hyperloglog",
+            Command::Geoadd
+            | Command::Geodist
+            | Command::Geohash
+            | Command::Geopos
+            | Command::Geosearch
+            | Command::Geosearchstore => "geo",
+            Command::Sadd
+            | Command::Srem
+            | Command::Smembers
+            | Command::Scard
+            | Command::Sismember
+            | Command::Smismember
+            | Command::Sinter
+            | Command::Sinterstore
+            | Command::Sintercard
+            | Command::Sunion
+            | Command::Sunionstore
+            | Command::Sdiff
+            | Command::Sdiffstore
+            | Command::Spop
+            | Command::Srandmember
+            | Command::Smove
+            | Command::Sscan => "set",
+            Command::Zadd
+            | Command::Zincrby
+            | Command::Zcard
+            | Command::Zscore
+            | Command::Zrem
+            | Command::Zrange
+            | Command::Zrank
+            | Command::Zrevrank
+            | Command::Zcount
+            | Command::Zlexcount
+            | Command::Zpopmin
+            | Command::Zpopmax
+            | Command::Zunionstore
+            | Command::Zinterstore
+            | Command::Zrandmember
+            | Command::Zscan
+            | Command::Bzpopmin
+            | Command::Bzpopmax => "sortedset",
+            Command::Xadd
+            | Command::Xrange
+            | Command::Xrevrange
+            | Command::Xlen
+            | Command::Xtrim
+            | Command::Xdel
+            | Command::Xread
+            | Command::Xgroup
+            | Command::Xack
+            | Command::Xpending
+            | Command::Xclaim
+            | Command::Xautoclaim
+            | Command::Xinfo
+            | Command::Xreadgroup => "stream",
+            Command::Subscribe
+            | Command::Unsubscribe
+            | Command::Psubscribe
+            | Command::Punsubscribe
+            | Command::Publish
+            | Command::Pubsub
+            | Command::Ssubscribe
+            | Command::Sunsubscribe
+            | Command::Spublish => "pubsub",
+            Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch
+            | Command::Unwatch => "transaction",
+            Command::Eval
+            | Command::Evalsha
+            | Command::EvalRo
+            | Command::EvalshaRo
+            | Command::Script
+            | Command::Function
+            | Command::Fcall
+            | Command::FcallRo => "scripting",
+            Command::Echo
+            | Command::Ping
+            | Command::Hello
+            | Command::Auth
+            | Command::Client
+            | Command::Select
+            | Command::Quit
+            | Command::Reset => "connection",
+            Command::Swapdb
+            | Command::Move
+            | Command::Flushdb
+            | Command::Flushall
+            | Command::Dbsize
+            | Command::Del => "keyspace",
+            Command::Acl
+            | Command::Config
+            | Command::Replconf
+            | Command::Psync
+            | Command::Save
+            | Command::Bgsave => "admin",
+            Command::Info | Command::Unknown => "",
+        };
+        let mut categories = vec![group];
+        if matches!(
+            group,
+            "string"
+                | "bitmap"
+                | "hyperloglog"
+                | "geo"
+                | "set"
+                | "sortedset"
+                | "stream"
+                | "keyspace"
+        ) {
+            let writes = self.is_write() && !matches!(self, Command::Pfcount);
+            categories.push(if writes { "write" } else { "read" });
+        }
+        if matches!(
+            self,
+            Command::Info | Command::Swapdb | Command::Flushdb | Command::Flushall
+        ) || group == "admin"
+        {
+            categories.push("dangerous");
+        }
+        if matches!(
+            self,
+            Command::Bzpopmin | Command::Bzpopmax | Command::Xread | Command::Xreadgroup
+        ) {
+            categories.push("blocking");
+        }
+        let fast = matches!(
+            self,
+            Command::Echo
+                | Command::Ping
+                | Command::Hello
+                | Command::Auth
+                | Command::Reset
+                | Command::Get
+                | Command::Incr
+                | Command::Decr
+                | Command::Incrby
+                | Command::Decrby
+                | Command::Incrbyfloat
+                | Command::Append
+                | Command::Strlen
+                | Command::Getdel
+                | Command::Getex
+                | Command::Mget
+                | Command::Setnx
+                | Command::Getbit
+                | Command::Pfadd
+                | Command::Sadd
+                | Command::Srem
+                | Command::Scard
+                | Command::Sismember
+                | Command::Smismember
+                | Command::Spop
+                | Command::Smove
+                | Command::Zadd
+                | Command::Zincrby
+                | Command::Zcard
+                | Command::Zscore
+                | Command::Zrem
+                | Command::Zrank
+                | Command::Zrevrank
+                | Command::Zcount
+                | Command::Zlexcount
+                | Command::Zpopmin
+                | Command::Zpopmax
+                | Command::Bzpopmin
+                | Command::Bzpopmax
+                | Command::Xadd
+                | Command::Xlen
+                | Command::Xdel
+                | Command::Xack
+                | Command::Publish
+                | Command::Spublish
+                | Command::Multi
+                | Command::Discard
+                | Command::Watch
+                | Command::Unwatch
+                | Command::Select
+                | Command::Swapdb
+                | Command::Move
+                | Command::Dbsize
+        );
+        categories.push(if fast { "fast" } else { "slow" });
+        categories.retain(|category| !category.is_empty());
+        categories
+    }
+
+    // Where the keys are among the arguments, like Redis' key specs.
+    fn key_indexes(&self, decoded_str: &[Argument]) -> Vec<usize> {
+        let count = decoded_str.len();
+        // Keys counted by a numkeys argument at `at`.
+        let counted = |at: usize| -> Vec<usize> {
+            let keys = decoded_str
+                .get(at)
+                .and_then(|keys| keys.parse::<usize>().ok())
+                .unwrap_or(0);
+            (at + 1..count.min(at + 1 + keys)).collect()
+        };
+        match self {
+            Command::Mget
+            | Command::Pfcount
+            | Command::Pfmerge
+            | Command::Sinter
+            | Command::Sinterstore
+            | Command::Sunion
+            | Command::Sunionstore
+            | Command::Sdiff
+            | Command::Sdiffstore
+            | Command::Watch
+            | Command::Del => (1..count).collect(),
+            Command::Mset | Command::Msetnx => (1..count).step_by(2).collect(),
+            Command::Lcs | Command::Smove | Command::Geosearchstore => (1..count.min(3)).collect(),
+            Command::Bitop => (2..count).collect(),
+            Command::Sintercard => counted(1),
+            Command::Zunionstore | Command::Zinterstore => {
+                (1..count.min(2)).chain(counted(2)).collect()
+            }
+            Command::Eval
+            | Command::Evalsha
+            | Command::EvalRo
+            | Command::EvalshaRo
+            | Command::Fcall
+            | Command::FcallRo => counted(2),
+            // The last argument is the timeout.
+            Command::Bzpopmin | Command::Bzpopmax => (1..count.saturating_sub(1)).collect(),
+            // Stream names come first after STREAMS, then as many IDs.
+            Command::Xread | Command::Xreadgroup => decoded_str
+                .iter()
+                .position(|argument| argument.eq_ignore_ascii_case("streams"))
+                .map_or(Vec::new(), |at| {
+                    (at + 1..at + 1 + (count - at - 1) / 2).collect()
+                }),
+            Command::Xgroup | Command::Xinfo => (2..count.min(3)).collect(),
+            Command::Echo
+            | Command::Ping
+            | Command::Hello
+            | Command::Auth
+            | Command::Acl
+            | Command::Client
+            | Command::Select
+            | Command::Swapdb
+            | Command::Flushdb
+            | Command::Flushall
+            | Command::Dbsize
+            | Command::Info
+            | Command::Replconf
+            | Command::Psync
+            | Command::Subscribe
+            | Command::Unsubscribe
+            | Command::Psubscribe
+            | Command::Punsubscribe
+            | Command::Publish
+            | Command::Pubsub
+            | Command::Ssubscribe
+            | Command::Sunsubscribe
+            | Command::Spublish
+            | Command::Quit
+            | Command::Reset
+            | Command::Config
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Unwatch
+            | Command::Save
+            | Command::Bgsave
+            | Command::Script
+            | Command::Function
+            | Command::Unknown => Vec::new(),
+            _ => (1..count.min(2)).collect(),
+        }
+    }
+
+    // Where the channels are among the arguments, or the patterns for
+    // PSUBSCRIBE. Unsubscribing is always allowed.
+    fn channel_indexes(&self, decoded_str: &[Argument]) -> Vec<usize> {
+        match self {
+            Command::Publish | Command::Spublish => (1..decoded_str.len().min(2)).collect(),
+            Command::Subscribe | Command::Ssubscribe | Command::Psubscribe => {
+                (1..decoded_str.len()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
 pub trait ConnectionHandler {
-    async fn handle_echo(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>>;
-    async fn handle_ping(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>>;
-    async fn handle_set(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>>;
-    async fn handle_get(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>>;
-    async fn handle_info(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>>;
-    async fn handle_replconf(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    fn state(&self) -> &Arc<ServerState>;
+    // "master" or "replica", as HELLO reports it.
+    fn role(&self) -> &'static str;
+    fn handle_echo(&mut self, decoded_str: &[Argument]) -> RespValue;
+    fn handle_ping(&mut self) -> RespValue;
+    fn handle_set(&mut self, db: &mut usize, decoded_str: &[Argument]) -> RespValue;
+    fn handle_get(&mut self, db: &mut usize, decoded_str: &[Argument]) -> RespValue;
+    // The Replication section of INFO.
+    fn replication_info(&self) -> String;
+    fn handle_info(&mut self, decoded_str: &[Argument]) -> RespValue {
+        let replication = self.replication_info();
+        let mut keyspace = self.state().keyspace.lock().unwrap();
+        info::handle_info(&mut keyspace, &replication, decoded_str)
+    }
+    fn handle_replconf(&mut self) -> RespValue;
+    // Turns the connection into a replication link, it doesn't return
+    // until the replica goes away.
     async fn handle_psync(
         &mut self,
         stream: &mut TcpStream,
-        replication_id: &str,
     ) -> Result<(), Box<dyn std::error::Error>>;
 }
 
+// Runs a keyspace command in the database `db` and hands its effects to the
+// replicas before the keyspace is unlocked, so that a replica syncing
+// meanwhile gets every write exactly once, either in its snapshot or right
+// after it. SELECT leaves another database in `db`.
+fn execute_keyspace_command(
+    state: &ServerState,
+    db: &mut usize,
+    command: &Command,
+    keyspace_handler: KeyspaceHandler,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let mut keyspace = state.lock_keyspace(*db);
+    let response = execute_keyspace_command_locked(
+        state,
+        &mut keyspace,
+        command,
+        keyspace_handler,
+        decoded_str,
+    );
+    *db = keyspace.selected();
+    state.propagate_effects(&mut keyspace);
+    response
+}
+
+// Same with the keyspace already locked. The write is only recorded as an
+// effect, whoever locked the keyspace forwards it to the replicas.
+fn execute_keyspace_command_locked(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    command: &Command,
+    keyspace_handler: KeyspaceHandler,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let response = keyspace_handler(keyspace, decoded_str);
+    keyspace.reclaim_expired();
+    // Commands replicating their effects have recorded them already.
+    if !command.replicates_effects() && command.is_write() && !response.is_error() {
+        keyspace.propagate(decoded_str.to_vec());
+    }
+    // A failed command leaves nothing worth notifying behind.
+    if response.is_error() {
+        keyspace.take_notifications();
+    }
+    state.publish_notifications(keyspace);
+    response
+}
+
+// Runs a command queued by MULTI, with the keyspace locked by EXEC for the
+// whole transaction. Blocking commands don't block there.
+fn execute_queued_command(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    client: &Client,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let command = Command::from_name(&decoded_str[0]);
+    let keyspace_handler: Option<KeyspaceHandler> = match command {
+        Command::Set => Some(string::handle_set),
+        Command::Get => Some(string::handle_get),
+        Command::Bzpopmin => Some(sorted_set::handle_bzpopmin_without_blocking),
+        Command::Bzpopmax => Some(sorted_set::handle_bzpopmax_without_blocking),
+        Command::Xread => Some(stream::handle_xread_without_blocking),
+        Command::Xreadgroup => Some(stream::handle_xreadgroup_without_blocking),
+        _ => command.keyspace_handler(),
+    };
+    if let Some(keyspace_handler) = keyspace_handler {
+        return execute_keyspace_command_locked(
+            state,
+            keyspace,
+            &command,
+            keyspace_handler,
+            decoded_str,
+        );
+    }
+    match command {
+        Command::Echo => RespValue::bulk(&decoded_str[1]),
+        Command::Ping => match decoded_str.get(1) {
+            Some(message) => RespValue::bulk(message),
+            None => RespValue::simple("PONG"),
+        },
+        Command::Publish => pubsub::publish_locked(state, keyspace, decoded_str),
+        Command::Spublish => pubsub::spublish_locked(state, keyspace, decoded_str),
+        Command::Pubsub => pubsub::handle_pubsub(state, decoded_str),
+        Command::Config => config::handle_config(state, decoded_str),
+        Command::Eval | Command::Evalsha | Command::EvalRo | Command::EvalshaRo => {
+            scripting::eval_locked(state, keyspace, client, decoded_str)
+        }
+        Command::Script => scripting::handle_script(state, decoded_str),
+        Command::Function => functions::function_locked(state, keyspace, decoded_str),
+        Command::Fcall | Command::FcallRo => {
+            functions::fcall_locked(state, keyspace, client, decoded_str)
+        }
+        // Watched keys were already forgotten by EXEC.
+        Command::Unwatch => RespValue::ok(),
+        _ => unreachable!("'{}' is rejected when queued", command),
+    }
+}
+
+// Same for EXEC, which also runs the commands about the connection it was
+// queued on.
+fn execute_queued_connection_command<H: ConnectionHandler>(
+    handler: &H,
+    client: &mut Client,
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    match Command::from_name(&decoded_str[0]) {
+        Command::Info => info::handle_info(keyspace, &handler.replication_info(), decoded_str),
+        Command::Hello => {
+            client::handle_hello(handler.state(), client, handler.role(), decoded_str)
+        }
+        Command::Auth => acl::handle_auth(handler.state(), client, decoded_str),
+        Command::Acl => acl::handle_acl(handler.state(), client, decoded_str),
+        Command::Client => client::handle_client(handler.state(), client, decoded_str),
+        _ => execute_queued_command(handler.state(), keyspace, client, decoded_str),
+    }
+}
+
+// Listens on the port on every bind address, giving up if any of them
+// can't be bound like Redis does.
+async fn bind(config: &Config) -> Vec<TcpListener> {
+    let mut listeners = Vec::new();
+    for address in &config.bind {
+        let address = if address.contains(':') {
+            format!("[{}]:{}", address, config.port)
+        } else {
+            format!("{}:{}", address, config.port)
+        };
+        match TcpListener::bind(&address).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => {
+                eprintln!(
+                    "Could not create server TCP listening socket {}: {}",
+                    address, e
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    listeners
+}
+
 async fn handle_connection<H: ConnectionHandler>(
     mut stream: TcpStream,
     mut handler: H,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut buf = [0; 1024];
+    let maxclients = handler.state().config.lock().unwrap().maxclients;
+    if handler.state().clients.count() >= maxclients {
+        let error = RespValue::error("ERR max number of clients reached");
+        send_response(&mut stream, &error.encode(Protocol::Resp2)).await?;
+        return Ok(());
+    }
+    // Holds a request split over several reads, or the next ones of a
+    // pipeline.
+    let mut requests = RequestDecoder::new();
+    let mut subscriber = Subscriber::new(handler.state());
+    let authenticated = handler.state().acl.lock().unwrap().default_user_is_open();
+    let address = |addr: std::io::Result<std::net::SocketAddr>| {
+        addr.map(|addr| addr.to_string()).unwrap_or_default()
+    };
+    let mut client = Client::new(
+        subscriber.id(),
+        authenticated,
+        address(stream.peer_addr()),
+        address(stream.local_addr()),
+    );
+    // Lists the connection in CLIENT LIST until it closes, CLIENT KILL
+    // closes it through `killed`.
+    let mut registration = Registration::new(handler.state(), &client);
+    let mut transaction = Transaction::new(handler.state());
 
     loop {
-        match stream.read(&mut buf).await {
-            Ok(bytes_read) => {
-                if bytes_read == 0 {
-                    println!("Connection closed by client.");
+        if registration.killed.try_recv().is_ok() {
+            return Ok(());
+        }
+        let request = match requests.decode() {
+            Ok(Some(request)) => request,
+            Ok(None) => {
+                // Published messages are written out whenever the client is
+                // idle. Idle clients go away after `timeout` seconds, unless
+                // they are waiting for messages.
+                let timeout = handler.state().config.lock().unwrap().timeout;
+                let waits_forever = timeout == 0 || subscriber.is_subscribed();
+                let idle = async {
+                    if waits_forever {
+                        std::future::pending::<()>().await;
+                    }
+                    tokio::time::sleep(Duration::from_secs(timeout)).await;
+                };
+                let read = tokio::select! {
+                    read = stream.read_buf(requests.buffer()) => read,
+                    _ = &mut registration.killed => return Ok(()),
+                    _ = idle => {
+                        return Ok(());
+                    }
+                    Some(message) = subscriber.receiver.recv() => {
+                        let message = match client.protocol {
+                            Protocol::Resp2 => message,
+                            Protocol::Resp3 => resp3::push(message),
+                        };
+                        send_response(&mut stream, &message.encode(client.protocol)).await?;
+                        continue;
+                    }
+                };
+                match read {
+                    Ok(0) => {
+                        println!("Connection closed by client.");
+                        return Ok(());
+                    }
+                    Ok(_) => continue,
+                    Err(e) => {
+                        eprintln!("Error reading from client: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            Err(e) => {
+                let response = RespValue::error(format!("ERR Protocol error: {}", e));
+                send_response(&mut stream, &response.encode(client.protocol)).await?;
+                return Ok(());
+            }
+        };
+
+        let Some(decoded_str) = request.into_command() else {
+            let response =
+                RespValue::error("ERR Protocol error: expected an array of bulk strings");
+            send_response(&mut stream, &response.encode(client.protocol)).await?;
+            return Ok(());
+        };
+        // Like Redis, empty requests get no reply.
+        if decoded_str.is_empty() {
+            continue;
+        }
+
+        let command = Command::from_name(&decoded_str[0]);
+        client.touch(&command, &decoded_str);
+        client.subscriptions = subscriber.counts();
+        client.multi = transaction.is_active().then(|| transaction.queued().len());
+        client.query_buffer = requests.pending();
+        handler.state().clients.update(&client);
+
+        if let Err(error) = command.check_arguments(&decoded_str) {
+            transaction.abort();
+            send_response(&mut stream, &error.encode(client.protocol)).await?;
+            continue;
+        }
+
+        // Clients have to authenticate while the default user has a password
+        // or is disabled. Then the ACL rules of their user apply, before
+        // anything else happens to the command.
+        if !command.allowed_without_auth() {
+            let checked = {
+                let mut acl = handler.state().acl.lock().unwrap();
+                // Like Redis, connections of deleted users are closed.
+                if !acl.has_user(&client.user) {
                     return Ok(());
                 }
+                if !client.authenticated && !acl.default_user_is_open() {
+                    Err("NOAUTH Authentication required.".to_string())
+                } else {
+                    acl.check_command(&client, "toplevel", &command, &decoded_str)
+                }
+            };
+            if let Err(error) = checked {
+                transaction.abort();
+                send_response(
+                    &mut stream,
+                    &RespValue::Error(error).encode(client.protocol),
+                )
+                .await?;
+                continue;
+            }
+        }
 
-                let request: Cow<'_, str> = String::from_utf8_lossy(&buf[..bytes_read]);
-                let decoded_str: Vec<String> = decode_resp_bulk_string(request.to_string())?;
-
-                let command: Command = match decoded_str[0].to_lowercase().as_str() {
-                    "echo" => Command::Echo,
-                    "ping" => Command::Ping,
-                    "set" => Command::Set,
-                    "get" => Command::Get,
-                    "info" => Command::Info,
-                    "replconf" => Command::Replconf,
-                    "psync" => Command::Psync,
-                    _ => Command::Unknown,
-                };
+        // CLIENT PAUSE holds commands back until it times out or CLIENT
+        // UNPAUSE.
+        handler.state().clients.wait_while_paused(&command).await;
 
-                match command {
-                    Command::Echo => handler.handle_echo(&decoded_str, &mut stream)?,
-                    Command::Ping => handler.handle_ping(&mut stream)?,
-                    Command::Set => handler.handle_set(&decoded_str, &mut stream)?,
-                    Command::Get => handler.handle_get(&decoded_str, &mut stream)?,
-                    Command::Info => handler.handle_info(&mut stream)?,
-                    Command::Replconf => handler.handle_replconf(&mut stream)?,
-                    Command::Psync => handler.handle_psync(&mut stream, &handler.replication_id)?,
-                    Command::Unknown => {
-                        eprintln!("Failed to parse command: unknown command.");
-                        return Ok(());
+        // The keyspace stays locked while a script runs. Commands wait for it
+        // up to the busy threshold, then are refused until it ends or gets
+        // killed.
+        if !scripting::allowed_when_busy(&command, &decoded_str)
+            && handler.state().scripts.wait_until_idle().await
+        {
+            let response = RespValue::Error(
+                "BUSY Redis is busy running a script. You can only call SCRIPT KILL or FUNCTION KILL.".to_string(),
+            );
+            send_response(&mut stream, &response.encode(client.protocol)).await?;
+            continue;
+        }
+
+        // RESP3 tells replies and messages apart, subscribed connections may
+        // run anything there.
+        if subscriber.is_subscribed()
+            && client.protocol == Protocol::Resp2
+            && !command.allowed_when_subscribed()
+        {
+            let response = RespValue::error(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                command
+            ));
+            send_response(&mut stream, &response.encode(client.protocol)).await?;
+            continue;
+        }
+
+        // Past maxmemory keys get evicted before a command that may take
+        // more memory runs, it is refused when not enough can be. So is EXEC
+        // queuing one. Replicas leave evictions to their master.
+        let denies_oom = command.denies_oom()
+            || (matches!(command, Command::Exec)
+                && transaction
+                    .queued()
+                    .iter()
+                    .any(|queued| Command::from_name(&queued[0]).denies_oom()));
+        if denies_oom && handler.role() == "master" && !handler.state().free_memory() {
+            let error = "OOM command not allowed when used memory > 'maxmemory'.";
+            let error = if let Command::Exec = command {
+                transaction.reset();
+                format!("EXECABORT Transaction discarded because of: {}", error)
+            } else {
+                transaction.abort();
+                error.to_string()
+            };
+            send_response(
+                &mut stream,
+                &RespValue::Error(error).encode(client.protocol),
+            )
+            .await?;
+            continue;
+        }
+
+        // After MULTI everything but the commands ending the transaction, or
+        // the connection, waits for EXEC. WATCH is refused right away.
+        if transaction.is_active()
+            && !matches!(
+                command,
+                Command::Multi
+                    | Command::Exec
+                    | Command::Discard
+                    | Command::Watch
+                    | Command::Quit
+                    | Command::Reset
+            )
+        {
+            let response = transaction.queue(&command, &decoded_str);
+            send_response(&mut stream, &response.encode(client.protocol)).await?;
+            continue;
+        }
+
+        // EXEC forgets the commands it runs, client side caching needs the
+        // keys they read.
+        let queued = matches!(command, Command::Exec).then(|| transaction.queued().to_vec());
+
+        let responses: Vec<RespValue> = if let Some(keyspace_handler) = command.keyspace_handler() {
+            vec![execute_keyspace_command(
+                handler.state(),
+                &mut client.db,
+                &command,
+                keyspace_handler,
+                &decoded_str,
+            )]
+        } else {
+            match command {
+                Command::Echo => vec![handler.handle_echo(&decoded_str)],
+                // Subscribed clients get PING replies in the shape of a
+                // message.
+                Command::Ping
+                    if subscriber.is_subscribed() && client.protocol == Protocol::Resp2 =>
+                {
+                    let message = decoded_str.get(1).map_or("", Argument::as_str);
+                    vec![RespValue::Array(vec![
+                        RespValue::bulk("pong"),
+                        RespValue::bulk(message),
+                    ])]
+                }
+                Command::Ping => vec![handler.handle_ping()],
+                Command::Set => vec![handler.handle_set(&mut client.db, &decoded_str)],
+                Command::Get => vec![handler.handle_get(&mut client.db, &decoded_str)],
+                Command::Info => vec![handler.handle_info(&decoded_str)],
+                Command::Replconf => vec![handler.handle_replconf()],
+                Command::Psync => {
+                    client.replica = true;
+                    handler.state().clients.update(&client);
+                    handler.handle_psync(&mut stream).await?;
+                    continue;
+                }
+                Command::Bzpopmin => vec![
+                    sorted_set::handle_bzpopmin(handler.state(), client.db, &decoded_str).await,
+                ],
+                Command::Bzpopmax => vec![
+                    sorted_set::handle_bzpopmax(handler.state(), client.db, &decoded_str).await,
+                ],
+                Command::Xread => {
+                    vec![stream::handle_xread(handler.state(), client.db, &decoded_str).await]
+                }
+                Command::Xreadgroup => {
+                    vec![stream::handle_xreadgroup(handler.state(), client.db, &decoded_str).await]
+                }
+                Command::Subscribe => pubsub::handle_subscribe(&mut subscriber, &decoded_str),
+                Command::Unsubscribe => pubsub::handle_unsubscribe(&mut subscriber, &decoded_str),
+                Command::Psubscribe => pubsub::handle_psubscribe(&mut subscriber, &decoded_str),
+                Command::Punsubscribe => pubsub::handle_punsubscribe(&mut subscriber, &decoded_str),
+                Command::Ssubscribe => pubsub::handle_ssubscribe(&mut subscriber, &decoded_str),
+                Command::Sunsubscribe => pubsub::handle_sunsubscribe(&mut subscriber, &decoded_str),
+                Command::Spublish => {
+                    vec![pubsub::handle_spublish(handler.state(), &decoded_str)]
+                }
+                Command::Publish => vec![pubsub::handle_publish(handler.state(), &decoded_str)],
+                Command::Pubsub => vec![pubsub::handle_pubsub(handler.state(), &decoded_str)],
+                Command::Config => vec![config::handle_config(handler.state(), &decoded_str)],
+                Command::Quit => {
+                    send_response(&mut stream, &RespValue::ok().encode(client.protocol)).await?;
+                    return Ok(());
+                }
+                Command::Multi => vec![transaction::handle_multi(&mut transaction)],
+                Command::Exec => {
+                    let queued = transaction.queued().to_vec();
+                    let mut db = client.db;
+                    let response = transaction::handle_exec(
+                        handler.state(),
+                        &mut transaction,
+                        &mut db,
+                        |keyspace, decoded_str| {
+                            execute_queued_connection_command(
+                                &handler,
+                                &mut client,
+                                keyspace,
+                                decoded_str,
+                            )
+                        },
+                    );
+                    client.db = db;
+                    match client.protocol {
+                        Protocol::Resp2 => vec![response],
+                        Protocol::Resp3 => vec![resp3::upgrade_exec(&queued, response)],
                     }
                 }
+                Command::Hello => vec![client::handle_hello(
+                    handler.state(),
+                    &mut client,
+                    handler.role(),
+                    &decoded_str,
+                )],
+                Command::Auth => vec![acl::handle_auth(handler.state(), &mut client, &decoded_str)],
+                Command::Acl => vec![acl::handle_acl(handler.state(), &client, &decoded_str)],
+                Command::Client => vec![client::handle_client(
+                    handler.state(),
+                    &mut client,
+                    &decoded_str,
+                )],
+                Command::Discard => vec![transaction::handle_discard(&mut transaction)],
+                Command::Watch => vec![transaction::handle_watch(
+                    &mut transaction,
+                    client.db,
+                    &decoded_str,
+                )],
+                Command::Unwatch => vec![transaction::handle_unwatch(&mut transaction)],
+                Command::Eval | Command::Evalsha | Command::EvalRo | Command::EvalshaRo => vec![
+                    scripting::handle_script_command(
+                        handler.state(),
+                        &client,
+                        &decoded_str,
+                        scripting::eval_locked,
+                    )
+                    .await,
+                ],
+                Command::Fcall | Command::FcallRo => vec![
+                    scripting::handle_script_command(
+                        handler.state(),
+                        &client,
+                        &decoded_str,
+                        functions::fcall_locked,
+                    )
+                    .await,
+                ],
+                Command::Function => {
+                    vec![functions::handle_function(handler.state(), &decoded_str)]
+                }
+                Command::Script => {
+                    vec![scripting::handle_script(handler.state(), &decoded_str)]
+                }
+                Command::Reset => {
+                    subscriber.reset();
+                    transaction.reset();
+                    client.reset(handler.state().acl.lock().unwrap().default_user_is_open());
+                    handler.state().tracking.lock().unwrap().disable(client.id);
+                    vec![RespValue::SimpleString("RESET".to_string())]
+                }
+                _ => unreachable!("keyspace and unknown commands are dispatched above"),
             }
-            Err(e) => {
-                eprintln!("Error reading from client: {}", e);
-                return Err(e.into());
+        };
+
+        // Clients caching the keys the command changed hear about it, then
+        // the keys it read get tracked. A script still holding the keyspace
+        // leaves the changes to whoever locks it next.
+        {
+            let state = handler.state();
+            if let Ok(mut keyspace) = state.keyspace.try_lock() {
+                state.invalidate_modified(&mut keyspace, Some(client.id));
             }
+            let read = queued
+                .as_deref()
+                .unwrap_or(std::slice::from_ref(&decoded_str));
+            let keep_caching = transaction.is_active()
+                || (matches!(command, Command::Client)
+                    && decoded_str
+                        .get(1)
+                        .is_some_and(|subcommand| subcommand.eq_ignore_ascii_case("caching")));
+            state
+                .tracking
+                .lock()
+                .unwrap()
+                .remember(client.id, read, keep_caching);
+        }
+
+        // Replies are built in RESP2, connections that asked for RESP3 get
+        // them upgraded.
+        let responses = match client.protocol {
+            Protocol::Resp2 => responses,
+            Protocol::Resp3 => resp3::upgrade(&command, &decoded_str, responses),
+        };
+        let mut response = Vec::new();
+        for reply in &responses {
+            response.extend(reply.encode(client.protocol));
+        }
+        send_response(&mut stream, &response).await?;
+
+        client.subscriptions = subscriber.counts();
+        client.multi = transaction.is_active().then(|| transaction.queued().len());
+        handler.state().clients.update(&client);
+
+        if command.is_write() {
+            handler.state().keyspace_written.notify_waiters();
         }
     }
 }
@@ -346,78 +2148,86 @@ async fn handle_connection<H: ConnectionHandler>(
 //     }
 // }
 //
-fn decode_resp_bulk_string(input: String) -> Option<Vec<String>> {
-    if let Some(dollar_byte_idx) = input.find('$') {
-        let bulk_string: Vec<String> =
-            get_command_and_arguments(input[dollar_byte_idx..].to_string());
-        Some(bulk_string)
-    } else {
-        None
-    }
-}
-
-fn get_command_and_arguments(input: String) -> Vec<String> {
-    let mut ptr: usize = 0;
-    let mut commands: Vec<String> = Vec::new();
-    let mut result: Vec<String> = Vec::new();
 
-    for (idx, char) in input.char_indices() {
-        if char == '\n' {
-            ptr = idx;
-        }
-        if char == '\r' {
-            let command = &input[ptr + 1..idx];
-            commands.push(command.to_string());
-        }
+// Formats a double like Redis does in replies: the shortest representation
+// that round trips, switching to an exponent for very large or small values
+// the same way `%.17g` would.
+fn format_double(input: f64) -> String {
+    if input.is_infinite() {
+        return if input > 0.0 { "inf" } else { "-inf" }.to_string();
     }
-
-    for (idx, command) in commands.iter().enumerate() {
-        if idx % 2 == 1 {
-            result.push(command.to_string());
-        }
+    if input == 0.0 {
+        return if input.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    let scientific = format!("{:e}", input);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    if (-5..17).contains(&exponent) {
+        format!("{}", input)
+    } else {
+        format!(
+            "{}e{}{:02}",
+            mantissa,
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
     }
-
-    result
 }
 
-fn encode_simple_string(input: &str) -> String {
-    let response = format!("+{}{}", input, String::from("\r\n"));
-    response
+fn wrong_number_of_arguments(command: &str) -> RespValue {
+    RespValue::error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command
+    ))
 }
 
-fn encode_resp_bulk_string(input: &str) -> String {
-    let length: String = input.len().to_string();
-    let response = format!(
-        "${}{}{}{}",
-        length,
-        String::from("\r\n"),
-        input,
-        String::from("\r\n")
-    );
-    response
+fn parse_integer_argument(input: &str) -> Result<i64, RespValue> {
+    input
+        .parse::<i64>()
+        .map_err(|_| RespValue::error("ERR value is not an integer or out of range"))
 }
 
-pub fn encode_resp_array(input: &[&str]) -> String {
-    let mut response = String::new();
-    response.push_str(format!("*{}{}", input.len(), String::from("\r\n")).as_str());
-    for el in input.iter() {
-        response.push_str(encode_resp_bulk_string(el).as_str());
+// The count of SRANDMEMBER and ZRANDMEMBER, where a negative one means that
+// many members with repeats. Like Redis, anything but -2^63, whose absolute
+// value overflows.
+fn parse_random_count(input: &str) -> Result<i64, RespValue> {
+    let count = parse_integer_argument(input)?;
+    if count == i64::MIN {
+        return Err(RespValue::error(format!(
+            "ERR value is out of range, value must between {} and {}",
+            -i64::MAX,
+            i64::MAX
+        )));
     }
-    response
+    Ok(count)
 }
 
-fn hex_to_binary(hex_str: &str) -> Result<Vec<u8>, ParseIntError> {
-    // since each byte is is represented by two hexadecimal characters
-    let mut binary: Vec<u8> = Vec::with_capacity(hex_str.len() / 2);
-
-    // iterate over hexadecimal string two chars at a time
-    for i in (0..hex_str.len()).step_by(2) {
-        // parse each pair of hexadecimal char as u8
-        let byte = u8::from_str_radix(&hex_str[i..i + 2], 16)?;
-        binary.push(byte);
+// Parses the trailing `[MATCH pattern] [COUNT count]` options of the SCAN
+// family, COUNT defaults to 10.
+fn parse_scan_options(options: &[Argument]) -> Result<(Option<&str>, usize), RespValue> {
+    let mut pattern: Option<&str> = None;
+    let mut count: usize = 10;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match (option.to_lowercase().as_str(), options.next()) {
+            ("match", Some(value)) => pattern = Some(value),
+            ("count", Some(value)) => match parse_integer_argument(value) {
+                Ok(value) if value >= 1 => count = value as usize,
+                Ok(_) => return Err(RespValue::error("ERR syntax error")),
+                Err(error) => return Err(error),
+            },
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
     }
+    Ok((pattern, count))
+}
 
-    Ok(binary)
+async fn send_response<R: AsRef<[u8]> + ?Sized>(
+    stream: &mut TcpStream,
+    response: &R,
+) -> Result<(), Box<dyn std::error::Error>> {
+    stream.write_all(response.as_ref()).await?;
+    Ok(())
 }
 
 // fn send_rdb_file(file: Vec<u8>) {
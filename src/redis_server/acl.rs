@@ -0,0 +1,908 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs,
+    io::Read,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    client::Client,
+    glob::glob_match,
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    sha256::sha256_hex,
+    Command, COMMAND_NAMES,
+};
+
+// The categories Redis puts commands in, some of them have none of ours.
+const CATEGORIES: [&str; 21] = [
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+// Like acllog-max-len.
+const MAX_LOG_LENGTH: usize = 128;
+// Failures repeating the same denial within this time are counted in the
+// same log entry.
+const LOG_GROUPING_MS: u64 = 60_000;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn is_password_hash(hash: &str) -> bool {
+    hash.len() == 64
+        && hash
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+// What a command does with the key at `index` of its arguments: whether it
+// reads it, and whether it writes it.
+fn key_access(command: &Command, decoded_str: &[Argument], index: usize) -> (bool, bool) {
+    match command {
+        Command::Pfcount => (true, false),
+        _ if !command.is_write() => (true, false),
+        Command::Sinterstore
+        | Command::Sunionstore
+        | Command::Sdiffstore
+        | Command::Zunionstore
+        | Command::Zinterstore
+        | Command::Geosearchstore
+            if index == 1 =>
+        {
+            (false, true)
+        }
+        Command::Bitop if index == 2 => (false, true),
+        Command::Sinterstore
+        | Command::Sunionstore
+        | Command::Sdiffstore
+        | Command::Zunionstore
+        | Command::Zinterstore
+        | Command::Geosearchstore
+        | Command::Bitop => (true, false),
+        Command::Pfmerge => (true, index == 1),
+        Command::Set => (
+            decoded_str
+                .get(3..)
+                .unwrap_or_default()
+                .iter()
+                .any(|option| option.eq_ignore_ascii_case("get")),
+            true,
+        ),
+        // Writes that don't hand back anything stored at the key.
+        Command::Setrange
+        | Command::Append
+        | Command::Mset
+        | Command::Msetnx
+        | Command::Setnx
+        | Command::Setex
+        | Command::Psetex
+        | Command::Pfadd
+        | Command::Geoadd
+        | Command::Sadd
+        | Command::Srem
+        | Command::Smove
+        | Command::Zadd
+        | Command::Zrem
+        | Command::Xadd
+        | Command::Xtrim
+        | Command::Xdel
+        | Command::Xgroup
+        | Command::Xack => (false, true),
+        _ => (true, true),
+    }
+}
+
+// Why a user may not run a command.
+enum Denial {
+    Command(String),
+    Key(String),
+    Channel(String),
+}
+
+impl Denial {
+    fn reason(&self) -> &'static str {
+        match self {
+            Denial::Command(_) => "command",
+            Denial::Key(_) => "key",
+            Denial::Channel(_) => "channel",
+        }
+    }
+
+    fn object(&self) -> &str {
+        match self {
+            Denial::Command(object) | Denial::Key(object) | Denial::Channel(object) => object,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    nopass: bool,
+    // SHA-256 hashes of the passwords, in hex.
+    passwords: Vec<String>,
+    commands: BTreeSet<&'static str>,
+    // Subcommands allowed or denied regardless of their command, by
+    // "command|subcommand".
+    subcommands: BTreeMap<String, bool>,
+    // The command rules applied so far, starting with +@all or -@all, which
+    // is how the user gets described.
+    command_rules: Vec<String>,
+    // Key patterns, with whether they allow reading and writing.
+    keys: Vec<(String, bool, bool)>,
+    channels: Vec<String>,
+}
+
+impl User {
+    // New users can't do anything until rules say so.
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            commands: BTreeSet::new(),
+            subcommands: BTreeMap::new(),
+            command_rules: vec!["-@all".to_string()],
+            keys: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    // The default user, which clients start as.
+    fn default_user() -> Self {
+        let mut user = Self::new("default");
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply(rule)?;
+                }
+            }
+            _ => return self.apply_pattern_or_command(rule),
+        }
+        Ok(())
+    }
+
+    fn apply_pattern_or_command(&mut self, rule: &str) -> Result<(), String> {
+        let (first, rest) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+        match first {
+            ">" => {
+                let hash = sha256_hex(rest.as_bytes());
+                if !self.passwords.contains(&hash) {
+                    self.passwords.push(hash);
+                }
+                self.nopass = false;
+            }
+            "#" if is_password_hash(rest) => {
+                if !self.passwords.iter().any(|hash| hash == rest) {
+                    self.passwords.push(rest.to_string());
+                }
+                self.nopass = false;
+            }
+            "#" | "!" if !is_password_hash(rest) => {
+                return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+            }
+            "<" | "!" => {
+                let hash = if first == "<" {
+                    sha256_hex(rest.as_bytes())
+                } else {
+                    rest.to_string()
+                };
+                let count = self.passwords.len();
+                self.passwords.retain(|password| *password != hash);
+                if self.passwords.len() == count {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist"
+                            .to_string(),
+                    );
+                }
+            }
+            "~" | "%" => {
+                let (read, write, pattern) = if first == "~" {
+                    (true, true, rest)
+                } else {
+                    let (flags, pattern) = rest.split_once('~').ok_or("Syntax error")?;
+                    let flags = flags.to_uppercase();
+                    if flags.is_empty() || flags.chars().any(|flag| flag != 'R' && flag != 'W') {
+                        return Err("Syntax error".to_string());
+                    }
+                    (flags.contains('R'), flags.contains('W'), pattern)
+                };
+                if self.keys.contains(&("*".to_string(), true, true)) {
+                    return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns".to_string());
+                }
+                if pattern == "*" && read && write {
+                    self.keys.clear();
+                }
+                self.keys.push((pattern.to_string(), read, write));
+            }
+            "&" => {
+                if self.channels.iter().any(|pattern| pattern == "*") {
+                    return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels".to_string());
+                }
+                if rest == "*" {
+                    self.channels.clear();
+                }
+                self.channels.push(rest.to_string());
+            }
+            "+" | "-" => self.apply_command_rule(first == "+", &rest.to_lowercase())?,
+            _ => return Err("Syntax error".to_string()),
+        }
+        Ok(())
+    }
+
+    fn apply_command_rule(&mut self, allow: bool, name: &str) -> Result<(), String> {
+        let unknown = || "Unknown command or category name in ACL".to_string();
+        let sign = if allow { "+" } else { "-" };
+        if let Some(category) = name.strip_prefix('@') {
+            if category != "all" && !CATEGORIES.contains(&category) {
+                return Err(unknown());
+            }
+            let names = COMMAND_NAMES.iter().filter(|command| {
+                category == "all" || Command::from_name(command).categories().contains(&category)
+            });
+            for command in names {
+                self.set_command(command, allow);
+            }
+            if category == "all" {
+                self.command_rules.clear();
+            }
+        } else if let Some((command, subcommand)) = name.split_once('|') {
+            if !COMMAND_NAMES.contains(&command)
+                || !Command::from_name(command).has_subcommands()
+                || subcommand.is_empty()
+            {
+                return Err(unknown());
+            }
+            self.subcommands.insert(name.to_string(), allow);
+            self.command_rules.retain(|rule| rule[1..] != *name);
+        } else {
+            let command = COMMAND_NAMES
+                .iter()
+                .find(|command| **command == name)
+                .ok_or_else(unknown)?;
+            self.set_command(command, allow);
+            // Only the last rule about a command matters.
+            self.command_rules.retain(|rule| rule[1..] != *name);
+        }
+        self.command_rules.push(format!("{}{}", sign, name));
+        Ok(())
+    }
+
+    fn set_command(&mut self, command: &'static str, allow: bool) {
+        if allow {
+            self.commands.insert(command);
+        } else {
+            self.commands.remove(command);
+        }
+        let prefix = format!("{}|", command);
+        self.subcommands
+            .retain(|subcommand, _| !subcommand.starts_with(&prefix));
+    }
+
+    fn check_password(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&sha256_hex(password.as_bytes()))
+    }
+
+    fn may_access_key(&self, key: &str, read: bool, write: bool) -> bool {
+        let allows = |wanted: bool, flag: fn(&(String, bool, bool)) -> bool| {
+            !wanted
+                || self
+                    .keys
+                    .iter()
+                    .any(|entry| flag(entry) && glob_match(entry.0.as_bytes(), key.as_bytes()))
+        };
+        allows(read, |entry| entry.1) && allows(write, |entry| entry.2)
+    }
+
+    // Patterns given to PSUBSCRIBE have to be allowed as they are.
+    fn may_access_channel(&self, channel: &str, is_pattern: bool) -> bool {
+        self.channels.iter().any(|pattern| {
+            pattern == "*"
+                || if is_pattern {
+                    pattern == channel
+                } else {
+                    glob_match(pattern.as_bytes(), channel.as_bytes())
+                }
+        })
+    }
+
+    fn check(&self, command: &Command, decoded_str: &[Argument]) -> Result<(), Denial> {
+        let name = command.to_string();
+        let subcommand = decoded_str
+            .get(1)
+            .filter(|_| command.has_subcommands())
+            .map(|subcommand| format!("{}|{}", name, subcommand.to_lowercase()));
+        let allowed = match subcommand
+            .as_ref()
+            .and_then(|name| self.subcommands.get(name))
+        {
+            Some(allowed) => *allowed,
+            None => self.commands.contains(name.as_str()),
+        };
+        if !allowed {
+            return Err(Denial::Command(subcommand.unwrap_or(name)));
+        }
+        for index in command.key_indexes(decoded_str) {
+            let Some(key) = decoded_str.get(index) else {
+                continue;
+            };
+            let (read, write) = key_access(command, decoded_str, index);
+            if !self.may_access_key(key, read, write) {
+                return Err(Denial::Key(key.to_string()));
+            }
+        }
+        let is_pattern = matches!(command, Command::Psubscribe);
+        for index in command.channel_indexes(decoded_str) {
+            let Some(channel) = decoded_str.get(index) else {
+                continue;
+            };
+            if !self.may_access_channel(channel, is_pattern) {
+                return Err(Denial::Channel(channel.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn describe_denial(&self, denial: &Denial) -> String {
+        match denial {
+            Denial::Command(command) => format!(
+                "User {} has no permissions to run the '{}' command",
+                self.name, command
+            ),
+            Denial::Key(key) => format!(
+                "User {} has no permissions to access the '{}' key",
+                self.name, key
+            ),
+            Denial::Channel(channel) => format!(
+                "User {} has no permissions to access the '{}' channel",
+                self.name, channel
+            ),
+        }
+    }
+
+    fn describe_keys(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|(pattern, read, write)| match (read, write) {
+                (true, true) => format!("~{}", pattern),
+                (true, false) => format!("%R~{}", pattern),
+                _ => format!("%W~{}", pattern),
+            })
+            .collect()
+    }
+
+    fn describe_channels(&self) -> Vec<String> {
+        self.channels
+            .iter()
+            .map(|pattern| format!("&{}", pattern))
+            .collect()
+    }
+
+    fn describe_commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    // How ACL LIST and the aclfile show users, as the rules recreating them.
+    fn describe(&self) -> String {
+        let mut rules = vec![
+            "user".to_string(),
+            self.name.clone(),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.extend(self.describe_keys());
+        let channels = self.describe_channels();
+        if channels.is_empty() {
+            rules.push("resetchannels".to_string());
+        }
+        rules.extend(channels);
+        rules.push(self.describe_commands());
+        rules.join(" ")
+    }
+}
+
+#[derive(Debug)]
+struct LogEntry {
+    count: u64,
+    reason: &'static str,
+    context: &'static str,
+    object: String,
+    username: String,
+    created: u64,
+    updated: u64,
+    entry_id: u64,
+    client_info: String,
+}
+
+// Users, what they were denied, and where they are saved.
+#[derive(Debug)]
+pub struct Acl {
+    users: BTreeMap<String, User>,
+    // Newest first.
+    log: VecDeque<LogEntry>,
+    next_entry_id: u64,
+    aclfile: Option<String>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        let mut users = BTreeMap::new();
+        users.insert("default".to_string(), User::default_user());
+        Self {
+            users,
+            log: VecDeque::new(),
+            next_entry_id: 0,
+            aclfile: None,
+        }
+    }
+
+    // An empty password lets anyone in as the default user again.
+    pub fn set_requirepass(&mut self, password: &str) {
+        let default = self.users.get_mut("default").unwrap();
+        if password.is_empty() {
+            default.apply("nopass").unwrap();
+        } else {
+            default.apply("resetpass").unwrap();
+            default.apply(&format!(">{}", password)).unwrap();
+        }
+    }
+
+    // Connections are authenticated as the default user from the start,
+    // unless it has a password or is disabled.
+    pub fn default_user_is_open(&self) -> bool {
+        let default = &self.users["default"];
+        default.enabled && default.nopass
+    }
+
+    pub fn has_user(&self, name: &str) -> bool {
+        self.users.contains_key(name)
+    }
+
+    fn authenticate(&mut self, client: &Client, username: &str, password: &str) -> bool {
+        let authenticated = self
+            .users
+            .get(username)
+            .is_some_and(|user| user.enabled && user.check_password(password));
+        if !authenticated {
+            self.log_denial(client, "toplevel", "auth", "AUTH", username);
+        }
+        authenticated
+    }
+
+    // The error to reply with when the client's user may not run the
+    // command, which also gets logged. `context` is where the command came
+    // from, "toplevel" or "lua" for scripts.
+    pub(super) fn check_command(
+        &mut self,
+        client: &Client,
+        context: &'static str,
+        command: &Command,
+        decoded_str: &[Argument],
+    ) -> Result<(), String> {
+        // The user may have been deleted while a script of theirs runs.
+        let Some(user) = self.users.get(&client.user) else {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                client.user, command
+            ));
+        };
+        let Err(denial) = user.check(command, decoded_str) else {
+            return Ok(());
+        };
+        let error = match &denial {
+            Denial::Command(_) => format!("NOPERM {}", user.describe_denial(&denial)),
+            Denial::Key(_) => "NOPERM No permissions to access a key".to_string(),
+            Denial::Channel(_) => "NOPERM No permissions to access a channel".to_string(),
+        };
+        let username = client.user.clone();
+        self.log_denial(client, context, denial.reason(), denial.object(), &username);
+        Err(error)
+    }
+
+    fn log_denial(
+        &mut self,
+        client: &Client,
+        context: &'static str,
+        reason: &'static str,
+        object: &str,
+        username: &str,
+    ) {
+        let now = now_ms();
+        let client_info = client.info();
+        let similar = self.log.iter_mut().find(|entry| {
+            entry.reason == reason
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+                && now - entry.updated < LOG_GROUPING_MS
+        });
+        if let Some(entry) = similar {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info;
+            return;
+        }
+        self.log.push_front(LogEntry {
+            count: 1,
+            reason,
+            context,
+            object: object.to_string(),
+            username: username.to_string(),
+            created: now,
+            updated: now,
+            entry_id: self.next_entry_id,
+            client_info,
+        });
+        self.next_entry_id += 1;
+        self.log.truncate(MAX_LOG_LENGTH);
+    }
+
+    // Reads users from an aclfile, all of them or none. Lines look like what
+    // ACL LIST shows.
+    fn read_users(path: &str) -> Result<BTreeMap<String, User>, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Error loading ACLs, opening file '{}': {}", path, e))?;
+        let mut users = BTreeMap::new();
+        let mut errors = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+            let error = |message: &str| format!("{}:{}: {}", path, number + 1, message);
+            if keyword != "user" {
+                errors.push(error("should start with user keyword"));
+                continue;
+            }
+            let Some(name) = words.next() else {
+                errors.push(error("user name is missing"));
+                continue;
+            };
+            if users.contains_key(name) {
+                errors.push(error(&format!("Duplicate user '{}' found", name)));
+                continue;
+            }
+            let mut user = User::new(name);
+            for rule in words {
+                if let Err(e) = user.apply(rule) {
+                    errors.push(error(&e));
+                    break;
+                }
+            }
+            users.insert(name.to_string(), user);
+        }
+        if !errors.is_empty() {
+            return Err(errors.join(". "));
+        }
+        users
+            .entry("default".to_string())
+            .or_insert_with(User::default_user);
+        Ok(users)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.aclfile else {
+            return Err(NO_ACLFILE.to_string());
+        };
+        let text: String = self
+            .users
+            .values()
+            .map(|user| format!("{}\n", user.describe()))
+            .collect();
+        // Written aside first so that a failure leaves the old file as is.
+        let temporary = format!("{}.tmp", path);
+        fs::write(&temporary, text)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("There was an error trying to save the ACLs: {}", e))
+    }
+}
+
+const NO_ACLFILE: &str = "This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.";
+
+// Applies the ACL options given on startup. Like Redis, the server doesn't
+// start with an aclfile it can't load.
+pub fn configure(state: &ServerState, aclfile: Option<String>, requirepass: Option<String>) {
+    let mut acl = state.acl.lock().unwrap();
+    if let Some(password) = requirepass {
+        acl.set_requirepass(&password);
+    }
+    if let Some(path) = aclfile {
+        match Acl::read_users(&path) {
+            Ok(users) => acl.users = users,
+            Err(e) => {
+                eprintln!("Failed to load the aclfile: {}", e);
+                std::process::exit(1);
+            }
+        }
+        acl.aclfile = Some(path);
+    }
+}
+
+// Random bytes fit for passwords, unlike our xorshift.
+fn random_bytes(count: usize) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0; count];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+        .map_err(|e| format!("ERR Failed to generate a random password: {}", e))?;
+    Ok(bytes)
+}
+
+fn error(message: &str) -> RespValue {
+    RespValue::Error(message.to_string())
+}
+
+fn bulk_strings(strings: impl IntoIterator<Item = impl AsRef<[u8]>>) -> RespValue {
+    RespValue::Array(strings.into_iter().map(RespValue::bulk).collect())
+}
+
+// AUTH [username] password
+pub fn handle_auth(
+    state: &ServerState,
+    client: &mut Client,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let (username, password) = match decoded_str {
+        [_, password] => ("default", password),
+        [_, username, password] => (username.as_str(), password),
+        _ => return error("ERR syntax error"),
+    };
+    let mut acl = state.acl.lock().unwrap();
+    if decoded_str.len() == 2 && acl.users["default"].nopass {
+        return error("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?");
+    }
+    authenticate(&mut acl, client, username, password)
+}
+
+// Logs the client in as `username`, for AUTH and HELLO.
+pub fn authenticate(
+    acl: &mut Acl,
+    client: &mut Client,
+    username: &str,
+    password: &str,
+) -> RespValue {
+    if !acl.authenticate(client, username, password) {
+        return error("WRONGPASS invalid username-password pair or user is disabled.");
+    }
+    client.user = username.to_string();
+    client.authenticated = true;
+    RespValue::ok()
+}
+
+// ACL SETUSER | GETUSER | DELUSER | LIST | USERS | WHOAMI | CAT | LOG |
+// DRYRUN | GENPASS | SAVE | LOAD
+pub fn handle_acl(state: &ServerState, client: &Client, decoded_str: &[Argument]) -> RespValue {
+    let Some(subcommand) = decoded_str
+        .get(1)
+        .map(|subcommand| subcommand.to_lowercase())
+    else {
+        return error("ERR wrong number of arguments for 'acl' command");
+    };
+    let count = decoded_str.len();
+    let mut acl = state.acl.lock().unwrap();
+    match subcommand.as_str() {
+        "setuser" if count >= 3 => {
+            let name = decoded_str[2].as_str();
+            let mut user = acl
+                .users
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| User::new(name));
+            for rule in &decoded_str[3..] {
+                if let Err(e) = user.apply(rule) {
+                    return error(&format!(
+                        "ERR Error in ACL SETUSER modifier '{}': {}",
+                        rule, e
+                    ));
+                }
+            }
+            acl.users.insert(name.to_string(), user);
+            RespValue::ok()
+        }
+        "getuser" if count == 3 => {
+            let Some(user) = acl.users.get(decoded_str[2].as_str()) else {
+                return RespValue::Null;
+            };
+            let mut flags = vec![if user.enabled { "on" } else { "off" }];
+            if user.nopass {
+                flags.push("nopass");
+            }
+            let fields = [
+                ("flags", bulk_strings(flags)),
+                ("passwords", bulk_strings(&user.passwords)),
+                ("commands", RespValue::bulk(user.describe_commands())),
+                ("keys", RespValue::bulk(user.describe_keys().join(" "))),
+                (
+                    "channels",
+                    RespValue::bulk(user.describe_channels().join(" ")),
+                ),
+                ("selectors", RespValue::Array(Vec::new())),
+            ];
+            RespValue::Map(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (RespValue::bulk(key), value))
+                    .collect(),
+            )
+        }
+        "deluser" if count >= 3 => {
+            if decoded_str[2..].iter().any(|name| name == "default") {
+                return error("ERR The 'default' user cannot be removed");
+            }
+            let deleted = decoded_str[2..]
+                .iter()
+                .filter(|name| acl.users.remove(name.as_str()).is_some())
+                .count();
+            RespValue::Integer(deleted as i64)
+        }
+        "list" if count == 2 => bulk_strings(acl.users.values().map(User::describe)),
+        "users" if count == 2 => bulk_strings(acl.users.keys()),
+        "whoami" if count == 2 => RespValue::bulk(&client.user),
+        "cat" if count == 2 => bulk_strings(CATEGORIES),
+        "cat" if count == 3 => {
+            let category = decoded_str[2].to_lowercase();
+            if !CATEGORIES.contains(&category.as_str()) {
+                return error(&format!("ERR Unknown category '{}'", decoded_str[2]));
+            }
+            bulk_strings(COMMAND_NAMES.iter().filter(|name| {
+                Command::from_name(name)
+                    .categories()
+                    .contains(&category.as_str())
+            }))
+        }
+        "log" if count <= 3 => {
+            let limit = match decoded_str.get(2) {
+                None => 10,
+                Some(argument) if argument.eq_ignore_ascii_case("reset") => {
+                    acl.log.clear();
+                    return RespValue::ok();
+                }
+                Some(argument) => match argument.parse::<usize>() {
+                    Ok(limit) => limit,
+                    Err(_) => return error("ERR value is out of range, must be positive"),
+                },
+            };
+            let now = now_ms();
+            RespValue::Array(
+                acl.log
+                    .iter()
+                    .take(limit)
+                    .map(|entry| {
+                        let fields = [
+                            ("count", RespValue::Integer(entry.count as i64)),
+                            ("reason", RespValue::bulk(entry.reason)),
+                            ("context", RespValue::bulk(entry.context)),
+                            ("object", RespValue::bulk(&entry.object)),
+                            ("username", RespValue::bulk(&entry.username)),
+                            (
+                                "age-seconds",
+                                RespValue::Double((now - entry.created) as f64 / 1000.0),
+                            ),
+                            ("client-info", RespValue::bulk(&entry.client_info)),
+                            ("entry-id", RespValue::Integer(entry.entry_id as i64)),
+                            (
+                                "timestamp-created",
+                                RespValue::Integer(entry.created as i64),
+                            ),
+                            (
+                                "timestamp-last-updated",
+                                RespValue::Integer(entry.updated as i64),
+                            ),
+                        ];
+                        RespValue::Map(
+                            fields
+                                .into_iter()
+                                .map(|(key, value)| (RespValue::bulk(key), value))
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+        "dryrun" if count >= 4 => {
+            let Some(user) = acl.users.get(decoded_str[2].as_str()) else {
+                return error(&format!("ERR User '{}' not found", decoded_str[2]));
+            };
+            let command = Command::from_name(&decoded_str[3]);
+            if let Command::Unknown = command {
+                return error(&format!("ERR Command '{}' not found", decoded_str[3]));
+            }
+            if !command.accepts_argument_count(count - 3) {
+                return error(&format!(
+                    "ERR wrong number of arguments for '{}' command",
+                    command
+                ));
+            }
+            match user.check(&command, &decoded_str[3..]) {
+                Ok(()) => RespValue::ok(),
+                Err(denial) => RespValue::bulk(user.describe_denial(&denial)),
+            }
+        }
+        "genpass" if count <= 3 => {
+            let bits = match decoded_str.get(2).map(|bits| bits.parse::<usize>()) {
+                None => 256,
+                Some(Ok(bits)) if (1..=4096).contains(&bits) => bits,
+                Some(_) => return error("ERR ACL GENPASS argument must be the number of bits for the output password, a positive number up to 4096"),
+            };
+            let digits = bits.div_ceil(4);
+            match random_bytes(digits.div_ceil(2)) {
+                Ok(bytes) => {
+                    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    RespValue::bulk(&hex[..digits])
+                }
+                Err(e) => error(&e),
+            }
+        }
+        "save" if count == 2 => match acl.save() {
+            Ok(()) => RespValue::ok(),
+            Err(e) => error(&format!("ERR {}", e)),
+        },
+        "load" if count == 2 => {
+            let Some(path) = acl.aclfile.clone() else {
+                return error(&format!("ERR {}", NO_ACLFILE));
+            };
+            match Acl::read_users(&path) {
+                Ok(users) => {
+                    acl.users = users;
+                    RespValue::ok()
+                }
+                Err(e) => error(&format!("ERR {}", e)),
+            }
+        }
+        _ => error(&format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try ACL HELP.",
+            decoded_str[1]
+        )),
+    }
+}
@@ -0,0 +1,523 @@
+use std::borrow::Cow;
+
+use super::{
+    keyspace::Keyspace,
+    notify::EventClass,
+    parse_integer_argument,
+    redis_value::{RedisValue, WRONGTYPE_ERROR},
+    resp::{Argument, RespValue},
+    string::{clamp_range, get_string, RedisString, MAX_STRING_LENGTH},
+    wrong_number_of_arguments,
+};
+
+// Bits are numbered from the most significant bit of the first byte, like in
+// Redis, so that a bitmap reads left to right.
+fn get_bit(bytes: &[u8], offset: u64) -> u8 {
+    let byte = (offset >> 3) as usize;
+    bytes
+        .get(byte)
+        .map_or(0, |value| (value >> (7 - (offset & 7))) & 1)
+}
+
+fn set_bit(bytes: &mut [u8], offset: u64, bit: u8) {
+    let byte = (offset >> 3) as usize;
+    let mask = 1 << (7 - (offset & 7));
+    if bit == 1 {
+        bytes[byte] |= mask;
+    } else {
+        bytes[byte] &= !mask;
+    }
+}
+
+fn invalid_bit_offset() -> RespValue {
+    RespValue::error("ERR bit offset is not an integer or out of range")
+}
+
+// Parses a bit offset, which must fall within the largest string allowed.
+// BITFIELD also accepts `#N`, meaning the Nth field of `width` bits.
+fn parse_bit_offset(input: &str, width: Option<u32>) -> Result<u64, RespValue> {
+    let (input, multiplier) = match (input.strip_prefix('#'), width) {
+        (Some(index), Some(width)) => (index, width as i64),
+        _ => (input, 1),
+    };
+    match input
+        .parse::<i64>()
+        .ok()
+        .and_then(|offset| offset.checked_mul(multiplier))
+    {
+        Some(offset) if offset >= 0 && ((offset >> 3) as usize) < MAX_STRING_LENGTH => {
+            Ok(offset as u64)
+        }
+        _ => Err(invalid_bit_offset()),
+    }
+}
+
+// Bytes of the string at `key` for in place edits, created empty when
+// missing and zero padded to at least `len` bytes.
+fn get_bitmap_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+    len: usize,
+) -> Result<&'a mut Vec<u8>, RespValue> {
+    if !keyspace.contains_key(key) {
+        keyspace.insert(
+            key.to_string(),
+            RedisValue::String(RedisString::Raw(Vec::new())),
+            None,
+        );
+    }
+    match keyspace.get_mut(key) {
+        Some(RedisValue::String(value)) => {
+            let bytes = value.raw_bytes_mut();
+            if bytes.len() < len {
+                bytes.resize(len, 0);
+            }
+            Ok(bytes)
+        }
+        _ => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+fn get_bitmap<'a>(keyspace: &'a Keyspace, key: &str) -> Result<Option<Cow<'a, [u8]>>, RespValue> {
+    get_string(keyspace, key).map(|value| value.map(RedisString::as_bytes))
+}
+
+pub fn handle_setbit(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("setbit");
+    }
+    let offset = match parse_bit_offset(&decoded_str[2], None) {
+        Ok(offset) => offset,
+        Err(error) => return error,
+    };
+    let bit = match decoded_str[3].as_str() {
+        "0" => 0,
+        "1" => 1,
+        _ => return RespValue::error("ERR bit is not an integer or out of range"),
+    };
+    let previous = match get_bitmap_mut(keyspace, &decoded_str[1], (offset >> 3) as usize + 1) {
+        Ok(bytes) => {
+            let previous = get_bit(bytes, offset);
+            set_bit(bytes, offset, bit);
+            previous
+        }
+        Err(error) => return error,
+    };
+    keyspace.notify(EventClass::String, "setbit", &decoded_str[1]);
+    RespValue::Integer(previous as i64)
+}
+
+pub fn handle_getbit(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("getbit");
+    }
+    let offset = match parse_bit_offset(&decoded_str[2], None) {
+        Ok(offset) => offset,
+        Err(error) => return error,
+    };
+    match get_bitmap(keyspace, &decoded_str[1]) {
+        Ok(bytes) => RespValue::Integer(bytes.map_or(0, |bytes| get_bit(&bytes, offset)) as i64),
+        Err(error) => error,
+    }
+}
+
+// Parses the `start end [BYTE|BIT]` range of BITCOUNT and BITPOS. `end` is
+// optional for BITPOS only, its absence is told apart because BITPOS then
+// treats the string as padded with zeros on the right.
+struct BitRange {
+    start: i64,
+    end: Option<i64>,
+    bit_mode: bool,
+}
+
+fn parse_bit_range(arguments: &[Argument]) -> Result<BitRange, RespValue> {
+    let parse = |input: &Argument| parse_integer_argument(input);
+    let start = arguments.first().map_or(Ok(0), parse)?;
+    let end = arguments.get(1).map(parse).transpose()?;
+    let bit_mode = match arguments.get(2).map(|mode| mode.to_lowercase()).as_deref() {
+        None | Some("byte") => false,
+        Some("bit") => true,
+        Some(_) => return Err(RespValue::error("ERR syntax error")),
+    };
+    if arguments.len() > 3 {
+        return Err(RespValue::error("ERR syntax error"));
+    }
+    Ok(BitRange {
+        start,
+        end,
+        bit_mode,
+    })
+}
+
+// Turns a range into inclusive bit offsets within `bytes`, `None` when it
+// selects nothing.
+fn resolve_bit_range(range: &BitRange, bytes: &[u8]) -> Option<(u64, u64)> {
+    let len = if range.bit_mode {
+        bytes.len() * 8
+    } else {
+        bytes.len()
+    };
+    let end = range.end.unwrap_or(-1);
+    let (start, end) = clamp_range(range.start, end, len)?;
+    if range.bit_mode {
+        Some((start as u64, end as u64))
+    } else {
+        Some((start as u64 * 8, end as u64 * 8 + 7))
+    }
+}
+
+// Mask of the bits of byte `byte` lying within the inclusive bit range.
+fn byte_mask(byte: u64, start: u64, end: u64) -> u8 {
+    let mut mask = 0xFF;
+    if byte == start >> 3 {
+        mask &= 0xFF >> (start & 7);
+    }
+    if byte == end >> 3 {
+        mask &= 0xFF << (7 - (end & 7));
+    }
+    mask
+}
+
+pub fn handle_bitcount(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("bitcount");
+    }
+    // Either no range at all or both of its ends.
+    if decoded_str.len() == 3 {
+        return RespValue::error("ERR syntax error");
+    }
+    let range = match parse_bit_range(&decoded_str[2..]) {
+        Ok(range) => range,
+        Err(error) => return error,
+    };
+    let bytes = match get_bitmap(keyspace, &decoded_str[1]) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return RespValue::Integer(0),
+        Err(error) => return error,
+    };
+    let Some((start, end)) = resolve_bit_range(&range, &bytes) else {
+        return RespValue::Integer(0);
+    };
+    let count: u32 = (start >> 3..=end >> 3)
+        .map(|byte| (bytes[byte as usize] & byte_mask(byte, start, end)).count_ones())
+        .sum();
+    RespValue::Integer(count as i64)
+}
+
+pub fn handle_bitpos(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("bitpos");
+    }
+    let bit = match parse_integer_argument(&decoded_str[2]) {
+        Ok(bit @ (0 | 1)) => bit as u8,
+        Ok(_) => return RespValue::error("ERR The bit argument must be 1 or 0."),
+        Err(error) => return error,
+    };
+    let range = match parse_bit_range(&decoded_str[3..]) {
+        Ok(range) => range,
+        Err(error) => return error,
+    };
+    // A missing key is an endless run of zeros.
+    let bytes = match get_bitmap(keyspace, &decoded_str[1]) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return RespValue::Integer(if bit == 1 { -1 } else { 0 }),
+        Err(error) => return error,
+    };
+    let Some((start, end)) = resolve_bit_range(&range, &bytes) else {
+        return RespValue::Integer(-1);
+    };
+    for byte in start >> 3..=end >> 3 {
+        let value = bytes[byte as usize];
+        let candidates = if bit == 1 { value } else { !value } & byte_mask(byte, start, end);
+        if candidates != 0 {
+            return RespValue::Integer((byte * 8 + candidates.leading_zeros() as u64) as i64);
+        }
+    }
+    // Looking for a clear bit without an explicit end finds the first bit
+    // past the string.
+    if bit == 0 && range.end.is_none() {
+        RespValue::Integer(bytes.len() as i64 * 8)
+    } else {
+        RespValue::Integer(-1)
+    }
+}
+
+pub fn handle_bitop(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments("bitop");
+    }
+    let operation = decoded_str[1].to_lowercase();
+    if !matches!(operation.as_str(), "and" | "or" | "xor" | "not") {
+        return RespValue::error("ERR syntax error");
+    }
+    if operation == "not" && decoded_str.len() != 4 {
+        return RespValue::error("ERR BITOP NOT must be called with a single source key.");
+    }
+
+    let mut sources: Vec<Vec<u8>> = Vec::with_capacity(decoded_str.len() - 3);
+    for key in &decoded_str[3..] {
+        match get_bitmap(keyspace, key) {
+            Ok(bytes) => sources.push(bytes.map_or_else(Vec::new, Cow::into_owned)),
+            Err(error) => return error,
+        }
+    }
+    // Shorter strings are zero padded to the longest one.
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    let byte_at = |source: &Vec<u8>, i: usize| source.get(i).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|i| {
+            let mut bytes = sources.iter().map(|source| byte_at(source, i));
+            let first = bytes.next().unwrap_or(0);
+            match operation.as_str() {
+                "and" => bytes.fold(first, |acc, byte| acc & byte),
+                "or" => bytes.fold(first, |acc, byte| acc | byte),
+                "xor" => bytes.fold(first, |acc, byte| acc ^ byte),
+                _ => !first,
+            }
+        })
+        .collect();
+
+    let destination = &decoded_str[2];
+    if result.is_empty() {
+        if keyspace.remove(destination).is_some() {
+            keyspace.notify(EventClass::Generic, "del", destination);
+        }
+    } else {
+        keyspace.insert(
+            destination.to_string(),
+            RedisValue::String(RedisString::new(result)),
+            None,
+        );
+        keyspace.notify(EventClass::String, "set", destination);
+    }
+    RespValue::Integer(len as i64)
+}
+
+#[derive(Clone, Copy)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Clone, Copy)]
+struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl FieldType {
+    fn parse(input: &str) -> Result<Self, RespValue> {
+        let signed = match input.as_bytes().first() {
+            Some(b'i' | b'I') => true,
+            Some(b'u' | b'U') => false,
+            _ => return Err(Self::invalid()),
+        };
+        // Unsigned values must fit in the integer replies.
+        let max_bits = if signed { 64 } else { 63 };
+        match input[1..].parse::<u32>() {
+            Ok(bits) if (1..=max_bits).contains(&bits) => Ok(FieldType { signed, bits }),
+            _ => Err(Self::invalid()),
+        }
+    }
+
+    fn invalid() -> RespValue {
+        RespValue::error(
+            "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.",
+        )
+    }
+
+    fn range(&self) -> (i128, i128) {
+        if self.signed {
+            (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1 << self.bits) - 1)
+        }
+    }
+
+    // Keeps the lowest `bits` bits of `value`, sign extending signed fields.
+    fn wrap(&self, value: i128) -> i128 {
+        let shift = 128 - self.bits;
+        if self.signed {
+            (value << shift) >> shift
+        } else {
+            (((value as u128) << shift) >> shift) as i128
+        }
+    }
+
+    fn read(&self, bytes: &[u8], offset: u64) -> i128 {
+        let unsigned = (0..self.bits as u64).fold(0u128, |acc, i| {
+            (acc << 1) | get_bit(bytes, offset + i) as u128
+        });
+        self.wrap(unsigned as i128)
+    }
+
+    fn write(&self, bytes: &mut [u8], offset: u64, value: i128) {
+        for i in 0..self.bits as u64 {
+            let bit = (value >> (self.bits as u64 - 1 - i)) & 1;
+            set_bit(bytes, offset + i, bit as u8);
+        }
+    }
+
+    // Applies the overflow policy to a value that may not fit the field.
+    // `None` when the FAIL policy rejects it.
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i128> {
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            return Some(value);
+        }
+        match overflow {
+            Overflow::Wrap => Some(self.wrap(value)),
+            Overflow::Sat => Some(if value > max { max } else { min }),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+enum FieldOperation {
+    Get,
+    Set(i64),
+    Incrby(i64),
+}
+
+struct FieldCommand {
+    operation: FieldOperation,
+    field_type: FieldType,
+    offset: u64,
+    overflow: Overflow,
+}
+
+fn parse_bitfield(arguments: &[Argument], read_only: bool) -> Result<Vec<FieldCommand>, RespValue> {
+    let syntax_error = || RespValue::error("ERR syntax error");
+    let mut commands = Vec::new();
+    let mut overflow = Overflow::Wrap;
+    let mut arguments = arguments.iter();
+    while let Some(subcommand) = arguments.next() {
+        let subcommand = subcommand.to_lowercase();
+        if subcommand == "overflow" {
+            overflow = match arguments.next().map(|mode| mode.to_lowercase()).as_deref() {
+                Some("wrap") => Overflow::Wrap,
+                Some("sat") => Overflow::Sat,
+                Some("fail") => Overflow::Fail,
+                Some(_) => return Err(RespValue::error("ERR Invalid OVERFLOW type specified")),
+                None => return Err(syntax_error()),
+            };
+            continue;
+        }
+        if !matches!(subcommand.as_str(), "get" | "set" | "incrby") {
+            return Err(syntax_error());
+        }
+        if read_only && subcommand != "get" {
+            return Err(RespValue::error(
+                "ERR BITFIELD_RO only supports the GET subcommand",
+            ));
+        }
+        let (Some(field_type), Some(offset)) = (arguments.next(), arguments.next()) else {
+            return Err(syntax_error());
+        };
+        let field_type = FieldType::parse(field_type)?;
+        let offset = parse_bit_offset(offset, Some(field_type.bits))?;
+        let operation = match subcommand.as_str() {
+            "get" => FieldOperation::Get,
+            _ => {
+                let value = parse_integer_argument(arguments.next().ok_or_else(syntax_error)?)?;
+                if subcommand == "set" {
+                    FieldOperation::Set(value)
+                } else {
+                    FieldOperation::Incrby(value)
+                }
+            }
+        };
+        commands.push(FieldCommand {
+            operation,
+            field_type,
+            offset,
+            overflow,
+        });
+    }
+    Ok(commands)
+}
+
+// Runs the parsed subcommands in order against `bytes`, which is only
+// written to by SET and INCRBY.
+fn apply_bitfield(bytes: &mut Cow<[u8]>, commands: &[FieldCommand]) -> Vec<RespValue> {
+    let mut replies = Vec::with_capacity(commands.len());
+    for command in commands {
+        let field_type = command.field_type;
+        let current = field_type.read(bytes, command.offset);
+        let (updated, reply) = match command.operation {
+            FieldOperation::Get => {
+                replies.push(RespValue::Integer(current as i64));
+                continue;
+            }
+            FieldOperation::Set(value) => {
+                // Unsigned fields see the value as its two's complement bits.
+                let value = if field_type.signed {
+                    value as i128
+                } else {
+                    value as u64 as i128
+                };
+                (field_type.fit(value, command.overflow), current)
+            }
+            FieldOperation::Incrby(increment) => {
+                let updated = field_type.fit(current + increment as i128, command.overflow);
+                (updated, updated.unwrap_or_default())
+            }
+        };
+        match updated {
+            Some(updated) => {
+                field_type.write(bytes.to_mut(), command.offset, updated);
+                replies.push(RespValue::Integer(reply as i64));
+            }
+            None => replies.push(RespValue::Null),
+        }
+    }
+    replies
+}
+
+fn run_bitfield(keyspace: &mut Keyspace, decoded_str: &[Argument], read_only: bool) -> RespValue {
+    let commands = match parse_bitfield(&decoded_str[2..], read_only) {
+        Ok(commands) => commands,
+        Err(error) => return error,
+    };
+    let key = &decoded_str[1];
+
+    // Writes create the key and make room for every field up front, as Redis
+    // does, even when an overflow ends up failing them.
+    let write_len = commands
+        .iter()
+        .filter(|command| !matches!(command.operation, FieldOperation::Get))
+        .map(|command| ((command.offset + command.field_type.bits as u64 - 1) >> 3) as usize + 1)
+        .max();
+    let replies = match write_len {
+        Some(len) => match get_bitmap_mut(keyspace, key, len) {
+            Ok(bitmap) => {
+                let mut bytes = Cow::Owned(std::mem::take(bitmap));
+                let replies = apply_bitfield(&mut bytes, &commands);
+                *bitmap = bytes.into_owned();
+                keyspace.notify(EventClass::String, "setbit", key);
+                replies
+            }
+            Err(error) => return error,
+        },
+        None => match get_bitmap(keyspace, key) {
+            Ok(bytes) => apply_bitfield(&mut bytes.unwrap_or_default(), &commands),
+            Err(error) => return error,
+        },
+    };
+    RespValue::Array(replies)
+}
+
+pub fn handle_bitfield(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("bitfield");
+    }
+    run_bitfield(keyspace, decoded_str, false)
+}
+
+pub fn handle_bitfield_ro(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("bitfield_ro");
+    }
+    run_bitfield(keyspace, decoded_str, true)
+}
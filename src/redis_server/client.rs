@@ -0,0 +1,576 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    sync::{oneshot, Notify},
+    time::timeout_at,
+};
+
+use super::{
+    acl,
+    rdb::REDIS_VERSION,
+    resp::{Argument, Protocol, RespValue},
+    server_state::ServerState,
+    tracking, Command,
+};
+
+// What a connection negotiated with HELLO, who it is logged in as, and what
+// CLIENT LIST shows about it.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub id: u64,
+    pub protocol: Protocol,
+    pub name: Option<String>,
+    pub user: String,
+    pub authenticated: bool,
+    // The database SELECT picked.
+    pub db: usize,
+    addr: String,
+    laddr: String,
+    created: Instant,
+    last_interaction: Instant,
+    // Like "client|list", for the command run last.
+    last_command: String,
+    lib_name: Option<String>,
+    lib_ver: Option<String>,
+    no_evict: bool,
+    // Copied from the connection before each command: its channels,
+    // patterns and shard channels, the commands queued by MULTI and the
+    // bytes received but not run yet.
+    pub subscriptions: (usize, usize, usize),
+    pub multi: Option<usize>,
+    pub query_buffer: usize,
+    // Set once the connection turned into a replication link.
+    pub replica: bool,
+}
+
+impl Client {
+    // Clients start as the default user, authenticated if it needs no
+    // password.
+    pub fn new(id: u64, authenticated: bool, addr: String, laddr: String) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            protocol: Protocol::Resp2,
+            name: None,
+            user: "default".to_string(),
+            authenticated,
+            db: 0,
+            addr,
+            laddr,
+            created: now,
+            last_interaction: now,
+            last_command: "NULL".to_string(),
+            lib_name: None,
+            lib_ver: None,
+            no_evict: false,
+            subscriptions: (0, 0, 0),
+            multi: None,
+            query_buffer: 0,
+            replica: false,
+        }
+    }
+
+    // RESET goes back to RESP2 and database 0, forgets the name and logs the
+    // client back in as the default user, like a new connection.
+    pub fn reset(&mut self, authenticated: bool) {
+        self.protocol = Protocol::Resp2;
+        self.db = 0;
+        self.name = None;
+        self.user = "default".to_string();
+        self.authenticated = authenticated;
+        self.no_evict = false;
+    }
+
+    // Records the command the client is about to run.
+    pub(super) fn touch(&mut self, command: &Command, decoded_str: &[Argument]) {
+        self.last_interaction = Instant::now();
+        self.last_command = match decoded_str.get(1) {
+            Some(subcommand) if command.has_subcommands() => {
+                format!("{}|{}", command, subcommand.to_lowercase())
+            }
+            _ => command.to_string(),
+        };
+    }
+
+    fn is_subscribed(&self) -> bool {
+        let (channels, patterns, shard_channels) = self.subscriptions;
+        channels + patterns + shard_channels > 0
+    }
+
+    // What CLIENT KILL TYPE and CLIENT LIST TYPE go by.
+    fn kind(&self) -> &'static str {
+        if self.replica {
+            "replica"
+        } else if self.is_subscribed() {
+            "pubsub"
+        } else {
+            "normal"
+        }
+    }
+
+    fn flags(&self) -> String {
+        let mut flags = String::new();
+        if self.replica {
+            flags.push('S');
+        }
+        if self.is_subscribed() {
+            flags.push('P');
+        }
+        if self.multi.is_some() {
+            flags.push('x');
+        }
+        if self.no_evict {
+            flags.push('e');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        flags
+    }
+
+    // The line CLIENT LIST and CLIENT INFO show, also in the ACL log. Replies
+    // are written out right away, so output buffers are always empty.
+    pub fn info(&self) -> String {
+        let now = Instant::now();
+        let (channels, patterns, shard_channels) = self.subscriptions;
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub={} psub={} ssub={} multi={} qbuf={} qbuf-free={} obl=0 oll=0 omem=0 events=r cmd={} user={} redir=-1 resp={} lib-name={} lib-ver={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            self.name.as_deref().unwrap_or(""),
+            (now - self.created).as_secs(),
+            (now - self.last_interaction).as_secs(),
+            self.flags(),
+            self.db,
+            channels,
+            patterns,
+            shard_channels,
+            self.multi.map_or(-1, |queued| queued as i64),
+            self.query_buffer,
+            self.query_buffer.next_power_of_two().max(1024) - self.query_buffer,
+            self.last_command,
+            self.user,
+            if self.protocol == Protocol::Resp3 { 3 } else { 2 },
+            self.lib_name.as_deref().unwrap_or(""),
+            self.lib_ver.as_deref().unwrap_or(""),
+        )
+    }
+}
+
+// Pauses set by CLIENT PAUSE, until when and whether reads wait as well.
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+    until: Instant,
+    all: bool,
+}
+
+// A connected client as of its last command, with a way to close its
+// connection.
+type Connection = (Client, Option<oneshot::Sender<()>>);
+
+// Every connected client.
+#[derive(Debug, Default)]
+pub struct Clients {
+    clients: Mutex<BTreeMap<u64, Connection>>,
+    pause: Mutex<Option<Pause>>,
+    unpaused: Notify,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&self, client: &Client) {
+        if let Some((registered, _)) = self.clients.lock().unwrap().get_mut(&client.id) {
+            *registered = client.clone();
+        }
+    }
+
+    // Connections open right now, checked against maxclients.
+    pub fn count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    // Commands that could change the dataset wait out CLIENT PAUSE WRITE,
+    // any command waits out CLIENT PAUSE ALL. CLIENT itself never does, so
+    // that UNPAUSE gets through.
+    pub fn protocol(&self, id: u64) -> Option<Protocol> {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|(client, _)| client.protocol)
+    }
+
+    pub(super) async fn wait_while_paused(&self, command: &Command) {
+        if matches!(command, Command::Client) {
+            return;
+        }
+        let writes = command.is_write()
+            || matches!(
+                command,
+                Command::Publish | Command::Spublish | Command::Function
+            );
+        loop {
+            let unpaused = self.unpaused.notified();
+            let Some(pause) = *self.pause.lock().unwrap() else {
+                return;
+            };
+            if Instant::now() >= pause.until || !(pause.all || writes) {
+                return;
+            }
+            let _ = timeout_at(pause.until.into(), unpaused).await;
+        }
+    }
+
+    fn kill(&self, ids: &[u64]) {
+        let mut clients = self.clients.lock().unwrap();
+        for id in ids {
+            if let Some(kill) = clients.get_mut(id).and_then(|(_, kill)| kill.take()) {
+                let _ = kill.send(());
+            }
+        }
+    }
+}
+
+// A connection's entry among the clients, removed when the connection goes
+// away. `killed` fires when CLIENT KILL picked it.
+pub struct Registration {
+    state: Arc<ServerState>,
+    id: u64,
+    pub killed: oneshot::Receiver<()>,
+}
+
+impl Registration {
+    pub fn new(state: &Arc<ServerState>, client: &Client) -> Self {
+        let (kill, killed) = oneshot::channel();
+        state
+            .clients
+            .clients
+            .lock()
+            .unwrap()
+            .insert(client.id, (client.clone(), Some(kill)));
+        Self {
+            state: Arc::clone(state),
+            id: client.id,
+            killed,
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.state.clients.clients.lock().unwrap().remove(&self.id);
+        self.state.tracking.lock().unwrap().disable(self.id);
+    }
+}
+
+// Names show up in lists of clients, one per line with space separated
+// fields.
+fn is_valid_name(name: &str) -> bool {
+    name.bytes().all(|byte| (b'!'..=b'~').contains(&byte))
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+pub fn handle_hello(
+    state: &ServerState,
+    client: &mut Client,
+    role: &str,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let error = |message: &str| RespValue::Error(message.to_string());
+    let mut protocol = client.protocol;
+    if let Some(version) = decoded_str.get(1) {
+        protocol = match version.parse::<i64>() {
+            Ok(2) => Protocol::Resp2,
+            Ok(3) => Protocol::Resp3,
+            Ok(_) => return error("NOPROTO unsupported protocol version"),
+            Err(_) => return error("ERR Protocol version is not an integer or out of range"),
+        };
+    }
+    let mut name = None;
+    let mut credentials = None;
+    let mut index = 2;
+    while index < decoded_str.len() {
+        let option = decoded_str[index].to_lowercase();
+        let remaining = decoded_str.len() - index - 1;
+        match option.as_str() {
+            "auth" if remaining >= 2 => {
+                credentials = Some((&decoded_str[index + 1], &decoded_str[index + 2]));
+                index += 3;
+            }
+            "setname" if remaining >= 1 => {
+                name = Some(&decoded_str[index + 1]);
+                index += 2;
+            }
+            _ => {
+                return error(&format!(
+                    "ERR Syntax error in HELLO option '{}'",
+                    decoded_str[index]
+                ))
+            }
+        }
+    }
+    if name.is_some_and(|name| !is_valid_name(name)) {
+        return error("ERR Client names cannot contain spaces, newlines or special characters.");
+    }
+    let mut acl = state.acl.lock().unwrap();
+    if let Some((username, password)) = credentials {
+        if let RespValue::Error(e) = acl::authenticate(&mut acl, client, username, password) {
+            return RespValue::Error(e);
+        }
+    } else if !client.authenticated && !acl.default_user_is_open() {
+        return error("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time");
+    }
+    drop(acl);
+    if let Some(name) = name {
+        client.name = (!name.is_empty()).then(|| name.to_string());
+    }
+    client.protocol = protocol;
+
+    // A map, which RESP2 clients get flattened into an array.
+    let fields = [
+        ("server", RespValue::bulk("redis")),
+        ("version", RespValue::bulk(REDIS_VERSION)),
+        (
+            "proto",
+            RespValue::Integer(if protocol == Protocol::Resp3 { 3 } else { 2 }),
+        ),
+        ("id", RespValue::Integer(client.id as i64)),
+        ("mode", RespValue::bulk("standalone")),
+        ("role", RespValue::bulk(role)),
+        ("modules", RespValue::Array(Vec::new())),
+    ];
+    RespValue::Map(
+        fields
+            .into_iter()
+            .map(|(key, value)| (RespValue::bulk(key), value))
+            .collect(),
+    )
+}
+
+// What CLIENT LIST and CLIENT KILL accept after TYPE.
+fn parse_kind(kind: &str) -> Result<&'static str, RespValue> {
+    match kind.to_lowercase().as_str() {
+        "normal" => Ok("normal"),
+        "master" => Ok("master"),
+        "replica" | "slave" => Ok("replica"),
+        "pubsub" => Ok("pubsub"),
+        _ => Err(RespValue::Error(format!(
+            "ERR Unknown client type '{}'",
+            kind
+        ))),
+    }
+}
+
+// CLIENT LIST [TYPE type] [ID id [id ...]]
+fn list_clients(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    let mut kind = None;
+    let mut ids = None;
+    match decoded_str
+        .get(2)
+        .map(|option| option.to_lowercase())
+        .as_deref()
+    {
+        None => {}
+        Some("type") if decoded_str.len() == 4 => match parse_kind(&decoded_str[3]) {
+            Ok(parsed) => kind = Some(parsed),
+            Err(error) => return error,
+        },
+        Some("id") if decoded_str.len() >= 4 => {
+            match decoded_str[3..]
+                .iter()
+                .map(|id| id.parse::<u64>())
+                .collect::<Result<Vec<u64>, _>>()
+            {
+                Ok(parsed) => ids = Some(parsed),
+                Err(_) => return RespValue::Error("ERR Invalid client ID".to_string()),
+            }
+        }
+        Some(_) => return RespValue::Error("ERR syntax error".to_string()),
+    }
+    let clients = state.clients.clients.lock().unwrap();
+    let list: String = clients
+        .values()
+        .map(|(client, _)| client)
+        .filter(|client| kind.is_none_or(|kind| client.kind() == kind))
+        .filter(|client| ids.as_ref().is_none_or(|ids| ids.contains(&client.id)))
+        .map(|client| format!("{}\n", client.info()))
+        .collect();
+    RespValue::bulk(list)
+}
+
+type Filter = Box<dyn Fn(&Client) -> bool>;
+
+// CLIENT KILL addr:port | CLIENT KILL <filter> <value> [<filter> <value> ...]
+fn kill_clients(state: &ServerState, client: &Client, decoded_str: &[Argument]) -> RespValue {
+    let error = |message: &str| RespValue::Error(message.to_string());
+    let clients = &state.clients;
+    if decoded_str.len() == 3 {
+        let Some(id) = clients
+            .clients
+            .lock()
+            .unwrap()
+            .values()
+            .find(|(other, _)| other.addr == decoded_str[2].as_str())
+            .map(|(other, _)| other.id)
+        else {
+            return error("ERR No such client");
+        };
+        clients.kill(&[id]);
+        return RespValue::ok();
+    }
+
+    let mut filters: Vec<Filter> = Vec::new();
+    let mut skip_me = true;
+    for pair in decoded_str[2..].chunks(2) {
+        let value = pair[1].to_string();
+        match pair[0].to_lowercase().as_str() {
+            "id" => match value.parse::<u64>() {
+                Ok(id) if id > 0 => filters.push(Box::new(move |other| other.id == id)),
+                _ => return error("ERR client-id should be greater than 0"),
+            },
+            "addr" => filters.push(Box::new(move |other| other.addr == value)),
+            "laddr" => filters.push(Box::new(move |other| other.laddr == value)),
+            "user" => {
+                if !state.acl.lock().unwrap().has_user(&value) {
+                    return error(&format!("ERR No such user '{}'", value));
+                }
+                filters.push(Box::new(move |other| other.user == value));
+            }
+            "type" => match parse_kind(&value) {
+                Ok(kind) => filters.push(Box::new(move |other| other.kind() == kind)),
+                Err(error) => return error,
+            },
+            "maxage" => match value.parse::<u64>() {
+                Ok(age) => filters.push(Box::new(move |other| {
+                    other.created.elapsed() >= Duration::from_secs(age)
+                })),
+                Err(_) => return error("ERR syntax error"),
+            },
+            "skipme" => match value.to_lowercase().as_str() {
+                "yes" => skip_me = true,
+                "no" => skip_me = false,
+                _ => return error("ERR syntax error"),
+            },
+            _ => return error("ERR syntax error"),
+        }
+    }
+    let ids: Vec<u64> = clients
+        .clients
+        .lock()
+        .unwrap()
+        .values()
+        .map(|(other, _)| other)
+        .filter(|other| !(skip_me && other.id == client.id))
+        .filter(|other| filters.iter().all(|filter| filter(other)))
+        .map(|other| other.id)
+        .collect();
+    clients.kill(&ids);
+    RespValue::Integer(ids.len() as i64)
+}
+
+// CLIENT ID | INFO | LIST | SETNAME | GETNAME | SETINFO | KILL | PAUSE |
+// UNPAUSE | TRACKING | CACHING | GETREDIR | TRACKINGINFO | NO-EVICT
+pub fn handle_client(
+    state: &ServerState,
+    client: &mut Client,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let error = |message: &str| RespValue::Error(message.to_string());
+    let count = decoded_str.len();
+    let Some(subcommand) = decoded_str.get(1) else {
+        return error("ERR wrong number of arguments for 'client' command");
+    };
+    match subcommand.to_lowercase().as_str() {
+        "id" if count == 2 => RespValue::Integer(client.id as i64),
+        "info" if count == 2 => RespValue::bulk(format!("{}\n", client.info())),
+        "list" => list_clients(state, decoded_str),
+        "setname" if count == 3 => {
+            if !is_valid_name(&decoded_str[2]) {
+                return error(
+                    "ERR Client names cannot contain spaces, newlines or special characters.",
+                );
+            }
+            client.name = (!decoded_str[2].is_empty()).then(|| decoded_str[2].to_string());
+            RespValue::ok()
+        }
+        "getname" if count == 2 => client
+            .name
+            .as_ref()
+            .map_or(RespValue::Null, RespValue::bulk),
+        "setinfo" if count == 4 => {
+            let value = &decoded_str[3];
+            let attribute = match decoded_str[2].to_lowercase().as_str() {
+                "lib-name" => &mut client.lib_name,
+                "lib-ver" => &mut client.lib_ver,
+                _ => {
+                    return error(&format!("ERR Unrecognized option '{}'", decoded_str[2]));
+                }
+            };
+            if !is_valid_name(value) {
+                return error(&format!(
+                    "ERR {} cannot contain spaces, newlines or special characters.",
+                    decoded_str[2]
+                ));
+            }
+            *attribute = (!value.is_empty()).then(|| value.to_string());
+            RespValue::ok()
+        }
+        "kill" if count == 3 || (count >= 4 && count.is_multiple_of(2)) => {
+            kill_clients(state, client, decoded_str)
+        }
+        // CLIENT PAUSE timeout [WRITE|ALL]
+        "pause" if count == 3 || count == 4 => {
+            let timeout = match decoded_str[2].parse::<i64>() {
+                Ok(timeout) if timeout < 0 => return error("ERR timeout is negative"),
+                Ok(timeout) => timeout as u64,
+                Err(_) => return error("ERR timeout is not an integer or out of range"),
+            };
+            let all = match decoded_str
+                .get(3)
+                .map(|mode| mode.to_lowercase())
+                .as_deref()
+            {
+                None | Some("all") => true,
+                Some("write") => false,
+                Some(_) => return error("ERR syntax error"),
+            };
+            *state.clients.pause.lock().unwrap() = Some(Pause {
+                until: Instant::now() + Duration::from_millis(timeout),
+                all,
+            });
+            RespValue::ok()
+        }
+        "unpause" if count == 2 => {
+            *state.clients.pause.lock().unwrap() = None;
+            state.clients.unpaused.notify_waiters();
+            RespValue::ok()
+        }
+        "tracking" if count >= 3 => tracking::handle_tracking(state, client, decoded_str),
+        "caching" if count == 3 => tracking::handle_caching(state, client, decoded_str),
+        "getredir" if count == 2 => tracking::handle_getredir(state, client),
+        "trackinginfo" if count == 2 => tracking::handle_trackinginfo(state, client),
+        "no-evict" if count == 3 => match decoded_str[2].to_lowercase().as_str() {
+            "on" => {
+                client.no_evict = true;
+                RespValue::ok()
+            }
+            "off" => {
+                client.no_evict = false;
+                RespValue::ok()
+            }
+            _ => error("ERR syntax error"),
+        },
+        _ => error(&format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try CLIENT HELP.",
+            decoded_str[1]
+        )),
+    }
+}
@@ -0,0 +1,643 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use super::{
+    eviction::{self, Eviction, Policy},
+    glob::glob_match,
+    keyspace,
+    notify::NotifyFlags,
+    rdb,
+    resp::split_arguments,
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    wrong_number_of_arguments,
+};
+
+const LOGLEVELS: [&str; 5] = ["debug", "verbose", "notice", "warning", "nothing"];
+
+// CONFIG REWRITE puts it above the parameters it adds to the file.
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+// A snapshot is saved once `changes` writes happened within `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+// Every parameter, as read from redis.conf and the command line on startup,
+// then changed by CONFIG SET.
+#[derive(Debug, Clone)]
+pub struct Config {
+    // The file CONFIG REWRITE writes back to, if the server was started
+    // with one.
+    pub file: Option<String>,
+    pub bind: Vec<String>,
+    pub port: u16,
+    pub replicaof: Option<(String, u16)>,
+    pub databases: usize,
+    // The working directory, where snapshots go. The one the server started
+    // in unless changed.
+    pub dir: String,
+    pub dbfilename: String,
+    pub save: Vec<SavePoint>,
+    // No append only file is written, the setting is only kept and reported
+    // back.
+    pub appendonly: bool,
+    // Seconds before idle clients get disconnected, 0 for never.
+    pub timeout: u64,
+    pub maxclients: usize,
+    // Only kept and reported back, like appendonly.
+    pub loglevel: &'static str,
+    pub requirepass: String,
+    pub aclfile: String,
+    pub notify_keyspace_events: NotifyFlags,
+    pub eviction: Eviction,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            replicaof: None,
+            databases: keyspace::DEFAULT_DATABASES,
+            dir: ".".to_string(),
+            dbfilename: rdb::DEFAULT_FILENAME.to_string(),
+            save: vec![
+                SavePoint {
+                    seconds: 3600,
+                    changes: 1,
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 100,
+                },
+                SavePoint {
+                    seconds: 60,
+                    changes: 10000,
+                },
+            ],
+            appendonly: false,
+            timeout: 0,
+            maxclients: 10000,
+            loglevel: "notice",
+            requirepass: String::new(),
+            aclfile: String::new(),
+            notify_keyspace_events: NotifyFlags::default(),
+            eviction: Eviction::default(),
+        }
+    }
+}
+
+impl Config {
+    // The defaults, then the directives of `file` if any, then the options
+    // given on the command line, which come as if they were more lines of
+    // the file. Errors tell which line or option is wrong, like Redis does
+    // before refusing to start.
+    pub fn load(file: Option<&str>, options: &[Vec<String>]) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Ok(dir) = std::env::current_dir() {
+            config.dir = dir.display().to_string();
+        }
+        let mut saved = false;
+        if let Some(file) = file {
+            let contents = fs::read_to_string(file)
+                .map_err(|e| format!("Fatal error, can't open config file '{}': {}", file, e))?;
+            for (number, line) in contents.lines().enumerate() {
+                let fail = |error: &str| {
+                    format!(
+                        "*** FATAL CONFIG FILE ERROR ***\nReading the configuration file, at line {}\n>>> '{}'\n{}",
+                        number + 1,
+                        line.trim(),
+                        error
+                    )
+                };
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let arguments: Vec<String> = split_arguments(line.as_bytes())
+                    .ok_or_else(|| fail("Unbalanced quotes in configuration line"))?
+                    .into_iter()
+                    .map(|argument| String::from_utf8_lossy(&argument).into_owned())
+                    .collect();
+                config
+                    .apply_directive(&arguments, &mut saved)
+                    .map_err(|error| fail(&error))?;
+            }
+            config.file = Some(file.to_string());
+        }
+        for option in options {
+            config
+                .apply_directive(option, &mut saved)
+                .map_err(|error| {
+                    format!(
+                    "*** FATAL CONFIG ERROR ***\nReading the command line option\n>>> '--{}'\n{}",
+                    option.join(" "),
+                    error
+                )
+                })?;
+        }
+        Ok(config)
+    }
+
+    // A parameter name and its arguments, from a line of redis.conf or an
+    // option. Each save directive adds save points to the ones of the
+    // directives before it, `saved` tells whether there were any.
+    fn apply_directive(&mut self, arguments: &[String], saved: &mut bool) -> Result<(), String> {
+        let name = arguments[0].to_lowercase();
+        let parameter = parameter(&name)
+            .filter(|parameter| parameter.multiple || arguments.len() == 2)
+            .ok_or_else(|| "Bad directive or wrong number of arguments".to_string())?;
+        let mut value = arguments[1..].join(" ");
+        if name == "save" {
+            if *saved && !value.is_empty() {
+                value = format!("{} {}", (parameter.get)(self), value);
+            }
+            *saved = true;
+        }
+        (parameter.set)(self, &value)
+    }
+}
+
+// How CONFIG GET and SET read and write a parameter of `Config`.
+struct Parameter {
+    name: &'static str,
+    // Only read on startup, CONFIG SET refuses to change it.
+    immutable: bool,
+    // The value is a list of arguments, given one by one in redis.conf.
+    multiple: bool,
+    get: fn(&Config) -> String,
+    set: fn(&mut Config, &str) -> Result<(), String>,
+}
+
+const PARAMETERS: [Parameter; 19] = [
+    Parameter {
+        name: "bind",
+        immutable: true,
+        multiple: true,
+        get: |config| config.bind.join(" "),
+        set: |config, value| {
+            let addresses: Vec<String> = value.split_whitespace().map(String::from).collect();
+            if addresses.is_empty() {
+                return Err("argument must not be empty".to_string());
+            }
+            config.bind = addresses;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "port",
+        immutable: true,
+        multiple: false,
+        get: |config| config.port.to_string(),
+        set: |config, value| {
+            config.port = parse_bounded(value, 0, 65535)? as u16;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "replicaof",
+        immutable: true,
+        multiple: true,
+        get: |config| {
+            config
+                .replicaof
+                .as_ref()
+                .map_or(String::new(), |(host, port)| format!("{} {}", host, port))
+        },
+        set: |config, value| {
+            let arguments: Vec<&str> = value.split_whitespace().collect();
+            config.replicaof = match arguments[..] {
+                [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => {
+                    None
+                }
+                [host, port] => Some((host.to_string(), parse_bounded(port, 0, 65535)? as u16)),
+                _ => return Err("argument must be 'no one' or 'host port'".to_string()),
+            };
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "databases",
+        immutable: true,
+        multiple: false,
+        get: |config| config.databases.to_string(),
+        set: |config, value| {
+            config.databases = parse_bounded(value, 1, i32::MAX.into())? as usize;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "dir",
+        immutable: false,
+        multiple: false,
+        get: |config| config.dir.clone(),
+        set: |config, value| {
+            std::env::set_current_dir(value).map_err(|e| e.to_string())?;
+            config.dir = std::env::current_dir()
+                .map_err(|e| e.to_string())?
+                .display()
+                .to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "dbfilename",
+        immutable: false,
+        multiple: false,
+        get: |config| config.dbfilename.clone(),
+        set: |config, value| {
+            if value.is_empty() || Path::new(value).file_name() != Some(value.as_ref()) {
+                return Err("dbfilename can't be a path, just a filename".to_string());
+            }
+            config.dbfilename = value.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "save",
+        immutable: false,
+        multiple: true,
+        get: |config| {
+            config
+                .save
+                .iter()
+                .map(|point| format!("{} {}", point.seconds, point.changes))
+                .collect::<Vec<String>>()
+                .join(" ")
+        },
+        set: |config, value| {
+            let numbers: Vec<&str> = value.split_whitespace().collect();
+            if !numbers.len().is_multiple_of(2) {
+                return Err("Invalid save parameters".to_string());
+            }
+            config.save = numbers
+                .chunks(2)
+                .map(|pair| {
+                    let seconds = pair[0].parse().ok().filter(|seconds| *seconds > 0);
+                    let changes = pair[1].parse().ok().filter(|changes| *changes > 0);
+                    match (seconds, changes) {
+                        (Some(seconds), Some(changes)) => Ok(SavePoint { seconds, changes }),
+                        _ => Err("Invalid save parameters".to_string()),
+                    }
+                })
+                .collect::<Result<Vec<SavePoint>, String>>()?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "appendonly",
+        immutable: false,
+        multiple: false,
+        get: |config| yes_or_no(config.appendonly),
+        set: |config, value| {
+            config.appendonly = parse_bool(value)?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "timeout",
+        immutable: false,
+        multiple: false,
+        get: |config| config.timeout.to_string(),
+        set: |config, value| {
+            config.timeout = parse_bounded(value, 0, i32::MAX.into())? as u64;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxclients",
+        immutable: false,
+        multiple: false,
+        get: |config| config.maxclients.to_string(),
+        set: |config, value| {
+            config.maxclients = parse_bounded(value, 1, i32::MAX.into())? as usize;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "loglevel",
+        immutable: false,
+        multiple: false,
+        get: |config| config.loglevel.to_string(),
+        set: |config, value| {
+            config.loglevel = LOGLEVELS
+                .iter()
+                .find(|level| level.eq_ignore_ascii_case(value))
+                .ok_or_else(|| {
+                    format!(
+                        "argument(s) must be one of the following: {}",
+                        LOGLEVELS.join(", ")
+                    )
+                })?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "requirepass",
+        immutable: false,
+        multiple: false,
+        get: |config| config.requirepass.clone(),
+        set: |config, value| {
+            config.requirepass = value.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "aclfile",
+        immutable: true,
+        multiple: false,
+        get: |config| config.aclfile.clone(),
+        set: |config, value| {
+            config.aclfile = value.to_string();
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "notify-keyspace-events",
+        immutable: false,
+        multiple: false,
+        get: |config| config.notify_keyspace_events.to_string(),
+        set: |config, value| {
+            config.notify_keyspace_events = NotifyFlags::parse(value)
+                .ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmdn'.")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory",
+        immutable: false,
+        multiple: false,
+        get: |config| config.eviction.maxmemory.to_string(),
+        set: |config, value| {
+            config.eviction.maxmemory =
+                eviction::parse_memory(value).ok_or("argument must be a memory value")?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        immutable: false,
+        multiple: false,
+        get: |config| config.eviction.policy.to_string(),
+        set: |config, value| {
+            config.eviction.policy = Policy::parse(value).ok_or_else(|| {
+                format!(
+                    "argument(s) must be one of the following: {}",
+                    Policy::names()
+                )
+            })?;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "maxmemory-samples",
+        immutable: false,
+        multiple: false,
+        get: |config| config.eviction.samples.to_string(),
+        set: |config, value| {
+            config.eviction.samples = parse_bounded(value, 1, 64)? as usize;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "lfu-log-factor",
+        immutable: false,
+        multiple: false,
+        get: |config| config.eviction.lfu_log_factor.to_string(),
+        set: |config, value| {
+            config.eviction.lfu_log_factor = parse_bounded(value, 0, i32::MAX.into())? as u32;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "lfu-decay-time",
+        immutable: false,
+        multiple: false,
+        get: |config| config.eviction.lfu_decay_time.to_string(),
+        set: |config, value| {
+            config.eviction.lfu_decay_time = parse_bounded(value, 0, i32::MAX.into())? as u64;
+            Ok(())
+        },
+    },
+];
+
+fn parameter(name: &str) -> Option<&'static Parameter> {
+    PARAMETERS.iter().find(|parameter| parameter.name == name)
+}
+
+// An integer parameter within `min..=max`.
+fn parse_bounded(value: &str, min: i64, max: i64) -> Result<i64, String> {
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|value| (min..=max).contains(value))
+        .ok_or_else(|| format!("argument must be between {} and {} inclusive", min, max))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn yes_or_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+// Hands a parameter changed by CONFIG SET to the part of the server that
+// keeps its own copy of it.
+fn apply(state: &ServerState, config: &Config, name: &str) {
+    match name {
+        "requirepass" => state
+            .acl
+            .lock()
+            .unwrap()
+            .set_requirepass(&config.requirepass),
+        "notify-keyspace-events" => state.set_notify_keyspace_events(config.notify_keyspace_events),
+        "dbfilename" => state
+            .keyspace
+            .lock()
+            .unwrap()
+            .set_dbfilename(&config.dbfilename),
+        "maxmemory" | "maxmemory-policy" | "maxmemory-samples" | "lfu-log-factor"
+        | "lfu-decay-time" => state.keyspace.lock().unwrap().set_eviction(config.eviction),
+        _ => {}
+    }
+}
+
+// An argument of redis.conf, quoted when it has to be.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '"' && c != '\'' && c != '\\');
+    if plain {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// The line of redis.conf setting a parameter to its current value. Not being
+// a replica takes none.
+fn rewritten_line(parameter: &Parameter, config: &Config) -> Option<String> {
+    let value = (parameter.get)(config);
+    if !parameter.multiple {
+        return Some(format!("{} {}", parameter.name, quote(&value)));
+    }
+    match parameter.name {
+        "replicaof" if value.is_empty() => None,
+        _ if value.is_empty() => Some(format!("{} \"\"", parameter.name)),
+        _ => Some(format!("{} {}", parameter.name, value)),
+    }
+}
+
+// Writes the parameters back to the config file in place: lines setting
+// them are updated, comments and anything else stay as they are, and the
+// ones changed from their default but missing get added at the end.
+fn rewrite(config: &Config) -> Result<(), String> {
+    let path = config
+        .file
+        .as_deref()
+        .ok_or("The server is running without a config file")?;
+    let fail = |e: std::io::Error| format!("Rewriting config file: {}", e);
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let mut lines: Vec<String> = Vec::new();
+    let mut rewritten: HashSet<&str> = HashSet::new();
+    for line in contents.lines() {
+        if line == REWRITE_SIGNATURE {
+            continue;
+        }
+        let name = split_arguments(line.trim().as_bytes())
+            .and_then(|arguments| arguments.into_iter().next())
+            .map(|name| String::from_utf8_lossy(&name).to_lowercase());
+        match name.as_deref().and_then(parameter) {
+            Some(parameter) if !line.trim().starts_with('#') => {
+                // Later lines for the same parameter are dropped.
+                if rewritten.insert(parameter.name) {
+                    lines.extend(rewritten_line(parameter, config));
+                }
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    let default = Config::default();
+    let added: Vec<String> = PARAMETERS
+        .iter()
+        .filter(|parameter| {
+            !rewritten.contains(parameter.name)
+                && (parameter.get)(config) != (parameter.get)(&default)
+        })
+        .filter_map(|parameter| rewritten_line(parameter, config))
+        .collect();
+    if !added.is_empty() {
+        lines.push(REWRITE_SIGNATURE.to_string());
+        lines.extend(added);
+    }
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    rdb::save(Path::new(path), contents.as_bytes()).map_err(fail)
+}
+
+// CONFIG GET parameter [parameter ...] | SET parameter value [parameter value ...]
+// | RESETSTAT | REWRITE
+pub fn handle_config(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("config");
+    }
+    match decoded_str[1].to_lowercase().as_str() {
+        "get" if decoded_str.len() >= 3 => {
+            let config = state.config.lock().unwrap();
+            let mut elements = Vec::new();
+            for parameter in &PARAMETERS {
+                let matches = decoded_str[2..].iter().any(|pattern| {
+                    glob_match(pattern.to_lowercase().as_bytes(), parameter.name.as_bytes())
+                });
+                if matches {
+                    elements.push(RespValue::bulk(parameter.name));
+                    elements.push(RespValue::bulk((parameter.get)(&config)));
+                }
+            }
+            RespValue::Array(elements)
+        }
+        "set" if decoded_str.len() >= 4 && decoded_str.len().is_multiple_of(2) => {
+            let mut pairs: Vec<(&Parameter, &str)> = Vec::new();
+            for pair in decoded_str[2..].chunks(2) {
+                let name = pair[0].to_lowercase();
+                let Some(parameter) = parameter(&name) else {
+                    return RespValue::error(format!(
+                        "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    ));
+                };
+                let error = if parameter.immutable {
+                    Some("can't set immutable config")
+                } else if pairs.iter().any(|(other, _)| other.name == parameter.name) {
+                    Some("duplicate parameter")
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    return RespValue::error(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                        name, error
+                    ));
+                }
+                pairs.push((parameter, pair[1].as_str()));
+            }
+            // Either every parameter changes or none does.
+            let updated = {
+                let mut config = state.config.lock().unwrap();
+                let mut updated = config.clone();
+                for (parameter, value) in &pairs {
+                    if let Err(error) = (parameter.set)(&mut updated, value) {
+                        return RespValue::error(format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                            parameter.name, error
+                        ));
+                    }
+                }
+                *config = updated.clone();
+                updated
+            };
+            for (parameter, _) in pairs {
+                apply(state, &updated, parameter.name);
+            }
+            RespValue::ok()
+        }
+        "resetstat" if decoded_str.len() == 2 => {
+            state.keyspace.lock().unwrap().reset_stats();
+            RespValue::ok()
+        }
+        "rewrite" if decoded_str.len() == 2 => {
+            let config = state.config.lock().unwrap();
+            match rewrite(&config) {
+                Ok(()) => RespValue::ok(),
+                Err(error) => RespValue::error(format!("ERR {}", error)),
+            }
+        }
+        _ => RespValue::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try CONFIG HELP.",
+            decoded_str[1]
+        )),
+    }
+}
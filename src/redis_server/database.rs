@@ -0,0 +1,173 @@
+use std::time::Instant;
+
+use super::{
+    keyspace::{Database, Keyspace},
+    notify::EventClass,
+    resp::{Argument, RespValue},
+    wrong_number_of_arguments,
+};
+
+// A database index given as an argument.
+fn parse_index(keyspace: &Keyspace, index: &str, invalid: &str) -> Result<usize, RespValue> {
+    let index: i64 = index.parse().map_err(|_| RespValue::error(invalid))?;
+    usize::try_from(index)
+        .ok()
+        .filter(|index| *index < keyspace.databases())
+        .ok_or_else(|| RespValue::error("ERR DB index is out of range"))
+}
+
+// SELECT index. The connection, or the script or transaction running it,
+// goes on with the database it leaves selected.
+pub fn handle_select(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("select");
+    }
+    match parse_index(
+        keyspace,
+        &decoded_str[1],
+        "ERR value is not an integer or out of range",
+    ) {
+        Ok(db) => {
+            keyspace.select(db);
+            RespValue::ok()
+        }
+        Err(error) => error,
+    }
+}
+
+// SWAPDB index1 index2
+pub fn handle_swapdb(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("swapdb");
+    }
+    let first = match parse_index(keyspace, &decoded_str[1], "ERR invalid first DB index") {
+        Ok(first) => first,
+        Err(error) => return error,
+    };
+    let second = match parse_index(keyspace, &decoded_str[2], "ERR invalid second DB index") {
+        Ok(second) => second,
+        Err(error) => return error,
+    };
+    keyspace.swap(first, second);
+    RespValue::ok()
+}
+
+// MOVE key db
+pub fn handle_move(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("move");
+    }
+    let key = &decoded_str[1];
+    let db = match parse_index(
+        keyspace,
+        &decoded_str[2],
+        "ERR value is not an integer or out of range",
+    ) {
+        Ok(db) => db,
+        Err(error) => return error,
+    };
+    let source = keyspace.selected();
+    if db == source {
+        return RespValue::error("ERR source and destination objects are the same");
+    }
+    if !keyspace.move_key(key, db) {
+        return RespValue::Integer(0);
+    }
+    keyspace.notify(EventClass::Generic, "move_from", key);
+    keyspace.select(db);
+    keyspace.notify(EventClass::Generic, "move_to", key);
+    keyspace.select(source);
+    RespValue::Integer(1)
+}
+
+// DEL key [key ...]
+pub fn handle_del(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("del");
+    }
+    let mut deleted = 0;
+    for key in &decoded_str[1..] {
+        if keyspace.remove(key).is_some() {
+            keyspace.notify(EventClass::Generic, "del", key);
+            deleted += 1;
+        }
+    }
+    RespValue::Integer(deleted)
+}
+
+// Frees flushed databases in the background for ASYNC, right away
+// otherwise.
+fn flush(keyspace: &mut Keyspace, db: Option<usize>, decoded_str: &[Argument]) -> RespValue {
+    let lazy = match decoded_str
+        .get(1)
+        .map(|mode| mode.to_lowercase())
+        .as_deref()
+    {
+        None | Some("sync") => false,
+        Some("async") => true,
+        Some(_) => return RespValue::error("ERR syntax error"),
+    };
+    let flushed: Vec<Database> = keyspace.flush(db);
+    if lazy {
+        tokio::task::spawn_blocking(move || drop(flushed));
+    }
+    RespValue::ok()
+}
+
+// FLUSHDB [ASYNC|SYNC]
+pub fn handle_flushdb(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() > 2 {
+        return wrong_number_of_arguments("flushdb");
+    }
+    let db = keyspace.selected();
+    flush(keyspace, Some(db), decoded_str)
+}
+
+// FLUSHALL [ASYNC|SYNC]
+pub fn handle_flushall(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() > 2 {
+        return wrong_number_of_arguments("flushall");
+    }
+    flush(keyspace, None, decoded_str)
+}
+
+// DBSIZE
+pub fn handle_dbsize(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 1 {
+        return wrong_number_of_arguments("dbsize");
+    }
+    RespValue::Integer(keyspace.len() as i64)
+}
+
+// The Keyspace section of INFO: a line for each database holding keys, with
+// the average time to live of the ones expiring, in milliseconds.
+pub fn keyspace_info(keyspace: &Keyspace) -> String {
+    let now = Instant::now();
+    let mut info = "# Keyspace\r\n".to_string();
+    for db in 0..keyspace.databases() {
+        let mut keys = 0;
+        let mut ttls = Vec::new();
+        for (_, _, expiration) in keyspace.iter_database(db) {
+            keys += 1;
+            if let Some(expiration) = expiration {
+                ttls.push(expiration.saturating_duration_since(now).as_millis());
+            }
+        }
+        if keys == 0 {
+            continue;
+        }
+        let avg_ttl = if ttls.is_empty() {
+            0
+        } else {
+            ttls.iter().sum::<u128>() / ttls.len() as u128
+        };
+        info.push_str(&format!(
+            "db{}:keys={},expires={},avg_ttl={}\r\n",
+            db,
+            keys,
+            ttls.len(),
+            avg_ttl
+        ));
+    }
+    info
+}
@@ -0,0 +1,248 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use super::{keyspace::Keyspace, random::random_u64, redis_value::RedisValue};
+
+// The counter new keys start with, so that they get a chance to be accessed
+// again before LFU evicts them.
+const LFU_INIT_VAL: u8 = 5;
+
+// Which keys maxmemory-policy lets go once the data outgrows maxmemory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    NoEviction,
+    AllkeysLru,
+    AllkeysLfu,
+    AllkeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+const POLICIES: [(&str, Policy); 8] = [
+    ("volatile-lru", Policy::VolatileLru),
+    ("allkeys-lru", Policy::AllkeysLru),
+    ("volatile-lfu", Policy::VolatileLfu),
+    ("allkeys-lfu", Policy::AllkeysLfu),
+    ("volatile-random", Policy::VolatileRandom),
+    ("allkeys-random", Policy::AllkeysRandom),
+    ("volatile-ttl", Policy::VolatileTtl),
+    ("noeviction", Policy::NoEviction),
+];
+
+impl Policy {
+    pub fn parse(name: &str) -> Option<Self> {
+        POLICIES
+            .iter()
+            .find(|(policy_name, _)| policy_name.eq_ignore_ascii_case(name))
+            .map(|(_, policy)| *policy)
+    }
+
+    // Only keys with a time to live may be evicted.
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            Policy::VolatileLru
+                | Policy::VolatileLfu
+                | Policy::VolatileRandom
+                | Policy::VolatileTtl
+        )
+    }
+
+    pub fn names() -> String {
+        POLICIES
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = POLICIES
+            .iter()
+            .find(|(_, policy)| policy == self)
+            .expect("every policy has a name");
+        write!(f, "{}", name)
+    }
+}
+
+// The eviction settings of CONFIG, same defaults as Redis.
+#[derive(Debug, Clone, Copy)]
+pub struct Eviction {
+    // In bytes, 0 for no limit.
+    pub maxmemory: u64,
+    pub policy: Policy,
+    // Keys sampled in each database when looking for one to evict.
+    pub samples: usize,
+    // How much harder the LFU counter gets to increment as it grows.
+    pub lfu_log_factor: u32,
+    // Minutes after which an idle key's LFU counter goes down by one.
+    pub lfu_decay_time: u64,
+}
+
+impl Default for Eviction {
+    fn default() -> Self {
+        Self {
+            maxmemory: 0,
+            policy: Policy::NoEviction,
+            samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+        }
+    }
+}
+
+impl Eviction {
+    // How good a candidate for eviction an entry is, higher is better: the
+    // longest idle for LRU, the least frequently accessed for LFU and the
+    // closest to expiring for volatile-ttl.
+    pub fn score(&self, access: Access, expiration: Option<Instant>) -> u128 {
+        match self.policy {
+            Policy::AllkeysLfu | Policy::VolatileLfu => {
+                u128::from(u8::MAX - access.frequency(self))
+            }
+            Policy::VolatileTtl => {
+                let ttl = expiration.map_or(u128::MAX, |expiration| {
+                    expiration
+                        .saturating_duration_since(Instant::now())
+                        .as_millis()
+                });
+                u128::MAX - ttl
+            }
+            _ => access.idle().as_millis(),
+        }
+    }
+}
+
+// When an entry was last read or written, and a logarithmic counter of how
+// often, like the LRU clock and LFU counter Redis keeps in each object.
+#[derive(Debug, Clone, Copy)]
+pub struct Access {
+    accessed: Instant,
+    counter: u8,
+}
+
+impl Access {
+    pub fn new() -> Self {
+        Self {
+            accessed: Instant::now(),
+            counter: LFU_INIT_VAL,
+        }
+    }
+
+    // The counter decays for the time the entry was idle, then goes up with
+    // a probability falling as it grows, so that 255 takes about a million
+    // accesses with the default log factor.
+    pub fn touch(self, eviction: &Eviction) -> Self {
+        let counter = self.frequency(eviction);
+        let base = f64::from(counter.saturating_sub(LFU_INIT_VAL));
+        let probability = 1.0 / (base * f64::from(eviction.lfu_log_factor) + 1.0);
+        let counter = if counter < u8::MAX && random_f64() < probability {
+            counter + 1
+        } else {
+            counter
+        };
+        Self {
+            accessed: Instant::now(),
+            counter,
+        }
+    }
+
+    pub fn idle(&self) -> Duration {
+        self.accessed.elapsed()
+    }
+
+    // The counter less one for each lfu-decay-time minutes since the last
+    // access.
+    pub fn frequency(&self, eviction: &Eviction) -> u8 {
+        if eviction.lfu_decay_time == 0 {
+            return self.counter;
+        }
+        let periods = self.idle().as_secs() / 60 / eviction.lfu_decay_time;
+        self.counter
+            .saturating_sub(u8::try_from(periods).unwrap_or(u8::MAX))
+    }
+}
+
+// In [0, 1).
+fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// An estimate of the memory keys and values take, which is what maxmemory
+// is compared to.
+pub trait MemoryUsage {
+    fn memory_usage(&self) -> usize;
+}
+
+impl MemoryUsage for String {
+    fn memory_usage(&self) -> usize {
+        size_of::<String>() + self.capacity()
+    }
+}
+
+impl MemoryUsage for RedisValue {
+    fn memory_usage(&self) -> usize {
+        size_of::<RedisValue>()
+            + match self {
+                RedisValue::String(string) => string.memory_usage(),
+                RedisValue::Set(set) => set.memory_usage(),
+                RedisValue::SortedSet(sorted_set) => sorted_set.memory_usage(),
+                RedisValue::Stream(stream) => stream.memory_usage(),
+            }
+    }
+}
+
+// Memory amounts the way redis.conf writes them: 1k is 1000 bytes, 1kb is
+// 1024, same for m, mb, g and gb.
+pub fn parse_memory(value: &str) -> Option<u64> {
+    const UNITS: [(&str, u64); 7] = [
+        ("gb", 1 << 30),
+        ("mb", 1 << 20),
+        ("kb", 1 << 10),
+        ("g", 1_000_000_000),
+        ("m", 1_000_000),
+        ("k", 1_000),
+        ("b", 1),
+    ];
+    let value = value.to_lowercase();
+    let (amount, unit) = UNITS
+        .iter()
+        .find_map(|(suffix, unit)| value.strip_suffix(suffix).map(|amount| (amount, *unit)))
+        .unwrap_or((&value, 1));
+    amount.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+// 1.50M and the like, for INFO.
+fn human_bytes(bytes: u64) -> String {
+    let units = [(1u64 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+    match units.iter().find(|(unit, _)| bytes >= *unit) {
+        Some((unit, suffix)) => format!("{:.2}{}", bytes as f64 / *unit as f64, suffix),
+        None => format!("{}B", bytes),
+    }
+}
+
+// The Memory section of INFO.
+pub fn memory_info(keyspace: &mut Keyspace) -> String {
+    let used_memory = keyspace.used_memory() as u64;
+    let eviction = *keyspace.eviction();
+    format!(
+        "# Memory\r\nused_memory:{}\r\nused_memory_human:{}\r\nmaxmemory:{}\r\nmaxmemory_human:{}\r\nmaxmemory_policy:{}\r\n",
+        used_memory,
+        human_bytes(used_memory),
+        eviction.maxmemory,
+        human_bytes(eviction.maxmemory),
+        eviction.policy,
+    )
+}
+
+// The Stats section of INFO, of which only the number of evicted keys is
+// kept track of.
+pub fn stats_info(keyspace: &Keyspace) -> String {
+    format!("# Stats\r\nevicted_keys:{}\r\n", keyspace.evicted_keys())
+}
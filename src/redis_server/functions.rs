@@ -0,0 +1,508 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{
+    client::Client,
+    glob::glob_match,
+    keyspace::Keyspace,
+    lua::{self, FunctionBody, Host, Lua, LuaError, TableRef, Value},
+    rdb,
+    resp::{Argument, RespValue},
+    scripting::{self, describe_error, keys_and_arguments, string_table},
+    server_state::ServerState,
+    wrong_number_of_arguments, Command,
+};
+
+const CHUNK_NAME: &str = "user_function";
+
+// Like Redis, loading a library only runs its top level code, which must
+// not take long.
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+const FUNCTION_FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+// A library loaded with FUNCTION LOAD. Its code runs again before each
+// call, as every script gets a fresh interpreter.
+#[derive(Debug)]
+pub struct Library {
+    pub name: String,
+    pub code: String,
+    body: Arc<FunctionBody>,
+    functions: BTreeMap<String, FunctionInfo>,
+}
+
+#[derive(Debug)]
+struct FunctionInfo {
+    description: Option<String>,
+    flags: Vec<String>,
+}
+
+impl FunctionInfo {
+    fn no_writes(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "no-writes")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Libraries {
+    libraries: BTreeMap<String, Arc<Library>>,
+}
+
+impl Libraries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Library>> {
+        self.libraries.values()
+    }
+
+    // Adds a library, replacing the one with the same name only if asked
+    // to. Function names are unique across libraries.
+    pub fn insert(&mut self, library: Library, replace: bool) -> Result<(), String> {
+        if !replace && self.libraries.contains_key(&library.name) {
+            return Err(format!("ERR Library '{}' already exists", library.name));
+        }
+        for other in self.iter().filter(|other| other.name != library.name) {
+            if let Some(name) = library
+                .functions
+                .keys()
+                .find(|name| other.functions.contains_key(*name))
+            {
+                return Err(format!("ERR Function {} already exists", name));
+            }
+        }
+        self.libraries
+            .insert(library.name.clone(), Arc::new(library));
+        Ok(())
+    }
+
+    fn find_function(&self, name: &str) -> Option<(&Arc<Library>, &FunctionInfo)> {
+        self.iter()
+            .find_map(|library| Some((library, library.functions.get(name)?)))
+    }
+}
+
+// Compiles a library and runs its code to learn which functions it
+// registers. Errors are messages, not replies yet.
+pub fn load_library(code: &str) -> Result<Library, String> {
+    let Some(shebang) = code.strip_prefix("#!") else {
+        return Err("ERR Missing library metadata".to_string());
+    };
+    let mut parts = shebang
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if engine != "lua" {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+    let Some(name) = name else {
+        return Err("ERR Library name was not given".to_string());
+    };
+    if !is_valid_name(name) {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+
+    // The header is commented out rather than removed so that line numbers
+    // don't change.
+    let mut source = code.as_bytes().to_vec();
+    source[..2].copy_from_slice(b"--");
+    let body = lua::compile(&source, CHUNK_NAME)
+        .map_err(|error| format!("ERR Error compiling function: {}", error))?;
+
+    let functions = scripting::on_script_stack(|| {
+        let mut host = LoadHost {
+            deadline: Instant::now() + LOAD_TIMEOUT,
+        };
+        let mut lua = Lua::new(&mut host);
+        scripting::open_redis(&lua);
+        // Nothing may run commands while loading.
+        if let Value::Table(redis) = lua.globals.get_field("redis") {
+            redis.set_field("call", Value::Nil);
+            redis.set_field("pcall", Value::Nil);
+        }
+        define_functions(&mut lua, &body)
+            .map(|registry| function_infos(&registry))
+            .map_err(|error| format!("ERR Error registering functions: {}", load_error(error)))
+    })?;
+    if functions.is_empty() {
+        return Err("ERR No functions registered".to_string());
+    }
+    Ok(Library {
+        name: name.to_string(),
+        code: code.to_string(),
+        body,
+        functions,
+    })
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+// Errors raised while loading already tell the line they came from.
+fn load_error(error: LuaError) -> String {
+    let (message, _) = describe_error(error);
+    match message.strip_prefix("ERR ") {
+        Some(message) => message.to_string(),
+        None => message,
+    }
+}
+
+// What loading code reaches, which can't run commands and is stopped once
+// it takes too long.
+struct LoadHost {
+    deadline: Instant,
+}
+
+impl Host for LoadHost {
+    fn call(&mut self, _: &[Vec<u8>]) -> RespValue {
+        RespValue::error("ERR This Redis command is not allowed from script")
+    }
+
+    fn interrupted(&mut self) -> Option<String> {
+        (Instant::now() >= self.deadline).then(|| "ERR FUNCTION LOAD timeout".to_string())
+    }
+}
+
+// Runs the library code with `redis.register_function` available, returns
+// the table of what it registered by function name.
+fn define_functions(lua: &mut Lua, body: &Arc<FunctionBody>) -> Result<TableRef, LuaError> {
+    let registry = TableRef::new();
+    if let Value::Table(redis) = lua.globals.get_field("redis") {
+        redis.set_field(
+            "register_function",
+            Value::native_with(register_function, vec![Value::Table(registry.clone())]),
+        );
+    }
+    scripting::protect_globals(lua);
+    let chunk = lua.load(Arc::clone(body));
+    lua.call(&chunk, Vec::new())?;
+    // Functions can't register more functions when called.
+    registry.0.borrow_mut().readonly = true;
+    Ok(registry)
+}
+
+// redis.register_function(name, callback) or
+// redis.register_function{function_name=..., callback=..., flags=...,
+// description=...}
+fn register_function(
+    _: &mut Lua,
+    upvalues: &[Value],
+    args: Vec<Value>,
+) -> Result<Vec<Value>, LuaError> {
+    let error = |message: &str| Err(LuaError::Builtin(message.to_string()));
+    let Some(Value::Table(registry)) = upvalues.first() else {
+        unreachable!("register_function is created with its registry");
+    };
+    if registry.0.borrow().readonly {
+        return error("redis.register_function can only be called on FUNCTION LOAD command");
+    }
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(named)] => {
+            let mut key = Value::Nil;
+            while let Ok(Some((next, _))) = named.0.borrow().next(&key) {
+                let known = matches!(&next, Value::String(field) if [&b"function_name"[..], b"callback", b"flags", b"description"].contains(&&**field));
+                if !known {
+                    return error("unknown argument given to redis.register_function");
+                }
+                key = next;
+            }
+            (
+                named.get_field("function_name"),
+                named.get_field("callback"),
+                named.get_field("flags"),
+                named.get_field("description"),
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return error("wrong number of arguments to redis.register_function"),
+    };
+    let Value::String(name) = name else {
+        return error("function_name argument given to redis.register_function must be a string");
+    };
+    if !matches!(callback, Value::Function(_)) {
+        return error("callback argument given to redis.register_function must be a function");
+    }
+    match &flags {
+        Value::Nil => {}
+        Value::Table(flags) => {
+            for index in 1..=flags.len() {
+                let known = matches!(flags.get(&Value::Number(index as f64)), Value::String(flag) if FUNCTION_FLAGS.iter().any(|known| known.as_bytes() == &*flag));
+                if !known {
+                    return error("unknown flag given");
+                }
+            }
+        }
+        _ => return error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        ),
+    }
+    if !matches!(description, Value::Nil | Value::String(_)) {
+        return error("description argument given to redis.register_function must a string");
+    }
+    if !is_valid_name(&String::from_utf8_lossy(&name)) {
+        return error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long");
+    }
+    if !registry.get(&Value::String(name.clone())).is_nil() {
+        return error("Function already exists in the library");
+    }
+    let entry = TableRef::new();
+    entry.set_field("callback", callback);
+    entry.set_field("flags", flags);
+    entry.set_field("description", description);
+    registry
+        .set(Value::String(name), Value::Table(entry))
+        .map_err(LuaError::Builtin)?;
+    Ok(Vec::new())
+}
+
+fn function_infos(registry: &TableRef) -> BTreeMap<String, FunctionInfo> {
+    let mut functions = BTreeMap::new();
+    let mut key = Value::Nil;
+    while let Ok(Some((next, entry))) = registry.0.borrow().next(&key) {
+        if let (Value::String(name), Value::Table(entry)) = (&next, entry) {
+            let description = match entry.get_field("description") {
+                Value::String(description) => {
+                    Some(String::from_utf8_lossy(&description).into_owned())
+                }
+                _ => None,
+            };
+            let mut flags = Vec::new();
+            if let Value::Table(table) = entry.get_field("flags") {
+                for index in 1..=table.len() {
+                    if let Value::String(flag) = table.get(&Value::Number(index as f64)) {
+                        flags.push(String::from_utf8_lossy(&flag).into_owned());
+                    }
+                }
+            }
+            functions.insert(
+                String::from_utf8_lossy(name).into_owned(),
+                FunctionInfo { description, flags },
+            );
+        }
+        key = next;
+    }
+    functions
+}
+
+// FCALL function numkeys [key ...] [arg ...] and FCALL_RO.
+pub fn fcall_locked(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    client: &Client,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let (keys, argv) = match keys_and_arguments(decoded_str) {
+        Ok(split) => split,
+        Err(error) => return error,
+    };
+    let name = &decoded_str[1];
+    let Some((library, function)) = keyspace.functions().find_function(name) else {
+        return RespValue::error("ERR Function not found");
+    };
+    let read_only = function.no_writes();
+    if !read_only && matches!(Command::from_name(&decoded_str[0]), Command::FcallRo) {
+        return RespValue::error(
+            "ERR Can not execute a script with write flag using *_ro command.",
+        );
+    }
+    let library = Arc::clone(library);
+    scripting::run_locked(
+        state,
+        keyspace,
+        client,
+        read_only,
+        name,
+        CHUNK_NAME,
+        |lua| {
+            let registry = define_functions(lua, &library.body)?;
+            let callback = match registry.get(&Value::string(name)) {
+                Value::Table(entry) => entry.get_field("callback"),
+                _ => Value::Nil,
+            };
+            lua.call(
+                &callback,
+                vec![
+                    Value::Table(string_table(keys)),
+                    Value::Table(string_table(argv)),
+                ],
+            )
+        },
+    )
+}
+
+// FUNCTION KILL doesn't wait for the keyspace, the function it kills holds
+// it.
+pub fn handle_function(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    if scripting::allowed_when_busy(&Command::Function, decoded_str) && decoded_str.len() == 2 {
+        return scripting::handle_kill(state);
+    }
+    let mut keyspace = state.keyspace.lock().unwrap();
+    let response = function_locked(state, &mut keyspace, decoded_str);
+    state.propagate_effects(&mut keyspace);
+    response
+}
+
+// FUNCTION LOAD [REPLACE] code | LIST [WITHCODE] [LIBRARYNAME pattern] |
+// DELETE library | FLUSH [ASYNC|SYNC] | DUMP | RESTORE payload
+// [FLUSH|APPEND|REPLACE] | KILL
+// Changes are replicated as the FUNCTION command itself.
+pub fn function_locked(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("function");
+    }
+    let subcommand = decoded_str[1].to_lowercase();
+    let result = match (subcommand.as_str(), decoded_str.len()) {
+        ("load", 3 | 4) => function_load(keyspace, &decoded_str[2..]),
+        ("list", _) => function_list(keyspace, &decoded_str[2..]),
+        ("delete", 3) => match keyspace
+            .functions_mut()
+            .libraries
+            .remove(decoded_str[2].as_str())
+        {
+            Some(_) => Ok(RespValue::ok()),
+            None => Err("ERR Library not found".to_string()),
+        },
+        ("flush", 2 | 3) => match decoded_str.get(2).map(|mode| mode.to_lowercase()) {
+            None => Ok(()),
+            Some(mode) if mode == "async" || mode == "sync" => Ok(()),
+            Some(_) => Err("ERR FUNCTION FLUSH only supports SYNC|ASYNC option".to_string()),
+        }
+        .map(|()| {
+            *keyspace.functions_mut() = Libraries::new();
+            RespValue::ok()
+        }),
+        ("dump", 2) => Ok(RespValue::bulk(rdb::dump_functions(keyspace.functions()))),
+        ("restore", 3 | 4) => function_restore(keyspace, &decoded_str[2..]),
+        ("kill", 2) => return scripting::handle_kill(state),
+        _ => Err(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try FUNCTION HELP.",
+            decoded_str[1]
+        )),
+    };
+    match result {
+        Ok(response) => {
+            if matches!(subcommand.as_str(), "load" | "delete" | "flush" | "restore") {
+                keyspace.propagate(decoded_str.to_vec());
+            }
+            response
+        }
+        Err(error) => RespValue::error(error),
+    }
+}
+
+fn function_load(keyspace: &mut Keyspace, args: &[Argument]) -> Result<RespValue, String> {
+    let replace = match args {
+        [_] => false,
+        [option, _] if option.eq_ignore_ascii_case("replace") => true,
+        [option, _] => return Err(format!("ERR Unknown option given: {}", option)),
+        _ => unreachable!("checked by the caller"),
+    };
+    let library = load_library(&args[args.len() - 1])?;
+    let name = library.name.clone();
+    keyspace.functions_mut().insert(library, replace)?;
+    Ok(RespValue::bulk(&name))
+}
+
+fn function_list(keyspace: &Keyspace, args: &[Argument]) -> Result<RespValue, String> {
+    let mut with_code = false;
+    let mut pattern = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.to_lowercase().as_str() {
+            "withcode" => with_code = true,
+            "libraryname" => match args.next() {
+                Some(name) => pattern = Some(name),
+                None => return Err("ERR library name argument was not given".to_string()),
+            },
+            _ => return Err(format!("ERR Unknown argument {}", arg)),
+        }
+    }
+    let mut libraries = Vec::new();
+    for library in keyspace.functions().iter() {
+        if let Some(pattern) = pattern {
+            if !glob_match(pattern.as_bytes(), library.name.as_bytes()) {
+                continue;
+            }
+        }
+        let functions: Vec<RespValue> = library
+            .functions
+            .iter()
+            .map(|(name, function)| {
+                let flags: Vec<RespValue> = function.flags.iter().map(RespValue::bulk).collect();
+                RespValue::Array(vec![
+                    RespValue::bulk("name"),
+                    RespValue::bulk(name),
+                    RespValue::bulk("description"),
+                    match &function.description {
+                        Some(description) => RespValue::bulk(description),
+                        None => RespValue::Null,
+                    },
+                    RespValue::bulk("flags"),
+                    RespValue::Array(flags),
+                ])
+            })
+            .collect();
+        let mut fields = vec![
+            RespValue::bulk("library_name"),
+            RespValue::bulk(&library.name),
+            RespValue::bulk("engine"),
+            RespValue::bulk("LUA"),
+            RespValue::bulk("functions"),
+            RespValue::Array(functions),
+        ];
+        if with_code {
+            fields.push(RespValue::bulk("library_code"));
+            fields.push(RespValue::bulk(&library.code));
+        }
+        libraries.push(RespValue::Array(fields));
+    }
+    Ok(RespValue::Array(libraries))
+}
+
+// Restores the libraries of a FUNCTION DUMP payload. Nothing changes when
+// any of them fails to load or conflicts with what is there.
+fn function_restore(keyspace: &mut Keyspace, args: &[Argument]) -> Result<RespValue, String> {
+    let policy = args.get(1).map(|policy| policy.to_lowercase());
+    let mut libraries =
+        match policy.as_deref() {
+            None | Some("append") | Some("replace") => keyspace.functions().clone(),
+            Some("flush") => Libraries::new(),
+            Some(_) => return Err(
+                "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                    .to_string(),
+            ),
+        };
+    let replace = policy.as_deref() == Some("replace");
+    for code in rdb::read_function_dump(args[0].as_bytes())? {
+        libraries.insert(load_library(&code)?, replace)?;
+    }
+    *keyspace.functions_mut() = libraries;
+    Ok(RespValue::ok())
+}
@@ -0,0 +1,1010 @@
+use super::{
+    keyspace::Keyspace,
+    notify::EventClass,
+    parse_integer_argument,
+    redis_value::RedisValue,
+    resp::{Argument, RespValue},
+    skiplist::ScoreRange,
+    sorted_set::{get_sorted_set, handle_zadd, parse_score, SortedSet},
+    wrong_number_of_arguments,
+};
+
+// Geo sets are plain sorted sets whose scores are 52-bit interleaved
+// geohashes, the layout (and the math below) follows Redis' geohash.c,
+// geohash_helper.c and geo.c so scores and results match it exactly.
+const GEO_STEP_MAX: u8 = 26;
+const GEO_LAT_MIN: f64 = -85.05112878;
+const GEO_LAT_MAX: f64 = 85.05112878;
+const GEO_LONG_MIN: f64 = -180.0;
+const GEO_LONG_MAX: f64 = 180.0;
+
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Debug, Clone, Copy)]
+struct GeoRange {
+    min: f64,
+    max: f64,
+}
+
+const WGS84_LAT_RANGE: GeoRange = GeoRange {
+    min: GEO_LAT_MIN,
+    max: GEO_LAT_MAX,
+};
+const LONG_RANGE: GeoRange = GeoRange {
+    min: GEO_LONG_MIN,
+    max: GEO_LONG_MAX,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GeoHash {
+    bits: u64,
+    step: u8,
+}
+
+impl GeoHash {
+    const ZERO: GeoHash = GeoHash { bits: 0, step: 0 };
+
+    fn is_zero(&self) -> bool {
+        self.bits == 0 && self.step == 0
+    }
+
+    // Scores covered by this box once aligned to the 52-bit precision of the
+    // stored members, as a [min, max) range.
+    fn score_range(&self) -> ScoreRange {
+        let shift = 2 * (GEO_STEP_MAX - self.step) as u32;
+        ScoreRange {
+            min: (self.bits << shift) as f64,
+            max: ((self.bits + 1) << shift) as f64,
+            min_exclusive: false,
+            max_exclusive: true,
+        }
+    }
+
+    // Longitude lives in the odd bits.
+    fn move_x(&mut self, direction: i8) {
+        let x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let y = self.bits & 0x5555555555555555;
+        let zz = 0x5555555555555555u64 >> (64 - self.step as u32 * 2);
+        let x = if direction > 0 {
+            x.wrapping_add(zz + 1)
+        } else {
+            (x | zz).wrapping_sub(zz + 1)
+        };
+        let x = x & (0xaaaaaaaaaaaaaaaau64 >> (64 - self.step as u32 * 2));
+        self.bits = x | y;
+    }
+
+    // Latitude lives in the even bits.
+    fn move_y(&mut self, direction: i8) {
+        let x = self.bits & 0xaaaaaaaaaaaaaaaa;
+        let y = self.bits & 0x5555555555555555;
+        let zz = 0xaaaaaaaaaaaaaaaau64 >> (64 - self.step as u32 * 2);
+        let y = if direction > 0 {
+            y.wrapping_add(zz + 1)
+        } else {
+            (y | zz).wrapping_sub(zz + 1)
+        };
+        let y = y & (0x5555555555555555u64 >> (64 - self.step as u32 * 2));
+        self.bits = x | y;
+    }
+
+    fn neighbor(&self, dx: i8, dy: i8) -> GeoHash {
+        let mut neighbor = *self;
+        if dx != 0 {
+            neighbor.move_x(dx);
+        }
+        if dy != 0 {
+            neighbor.move_y(dy);
+        }
+        neighbor
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GeoArea {
+    longitude: GeoRange,
+    latitude: GeoRange,
+}
+
+// Spreads the 32 bits of `value` over the even bits of the result.
+fn spread(value: u32) -> u64 {
+    let mut value = value as u64;
+    value = (value | (value << 16)) & 0x0000ffff0000ffff;
+    value = (value | (value << 8)) & 0x00ff00ff00ff00ff;
+    value = (value | (value << 4)) & 0x0f0f0f0f0f0f0f0f;
+    value = (value | (value << 2)) & 0x3333333333333333;
+    (value | (value << 1)) & 0x5555555555555555
+}
+
+fn squash(value: u64) -> u32 {
+    let mut value = value & 0x5555555555555555;
+    value = (value | (value >> 1)) & 0x3333333333333333;
+    value = (value | (value >> 2)) & 0x0f0f0f0f0f0f0f0f;
+    value = (value | (value >> 4)) & 0x00ff00ff00ff00ff;
+    value = (value | (value >> 8)) & 0x0000ffff0000ffff;
+    ((value | (value >> 16)) & 0x00000000ffffffff) as u32
+}
+
+fn encode(
+    long_range: GeoRange,
+    lat_range: GeoRange,
+    longitude: f64,
+    latitude: f64,
+    step: u8,
+) -> Option<GeoHash> {
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+        || !(long_range.min..=long_range.max).contains(&longitude)
+        || !(lat_range.min..=lat_range.max).contains(&latitude)
+    {
+        return None;
+    }
+    let scale = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_range.min) / (lat_range.max - lat_range.min) * scale;
+    let long_offset = (longitude - long_range.min) / (long_range.max - long_range.min) * scale;
+    Some(GeoHash {
+        bits: spread(lat_offset as u32) | (spread(long_offset as u32) << 1),
+        step,
+    })
+}
+
+fn encode_wgs84(longitude: f64, latitude: f64, step: u8) -> Option<GeoHash> {
+    encode(LONG_RANGE, WGS84_LAT_RANGE, longitude, latitude, step)
+}
+
+fn decode(long_range: GeoRange, lat_range: GeoRange, hash: GeoHash) -> GeoArea {
+    let lat_offset = squash(hash.bits) as f64;
+    let long_offset = squash(hash.bits >> 1) as f64;
+    let scale = (1u64 << hash.step) as f64;
+    let lat_scale = lat_range.max - lat_range.min;
+    let long_scale = long_range.max - long_range.min;
+    GeoArea {
+        latitude: GeoRange {
+            min: lat_range.min + (lat_offset / scale) * lat_scale,
+            max: lat_range.min + ((lat_offset + 1.0) / scale) * lat_scale,
+        },
+        longitude: GeoRange {
+            min: long_range.min + (long_offset / scale) * long_scale,
+            max: long_range.min + ((long_offset + 1.0) / scale) * long_scale,
+        },
+    }
+}
+
+fn decode_wgs84(hash: GeoHash) -> GeoArea {
+    decode(LONG_RANGE, WGS84_LAT_RANGE, hash)
+}
+
+// Center of the area, as (longitude, latitude).
+fn area_center(area: &GeoArea) -> (f64, f64) {
+    let longitude =
+        ((area.longitude.min + area.longitude.max) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX);
+    let latitude = ((area.latitude.min + area.latitude.max) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX);
+    (longitude, latitude)
+}
+
+fn score_to_position(score: f64) -> (f64, f64) {
+    area_center(&decode_wgs84(GeoHash {
+        bits: score as u64,
+        step: GEO_STEP_MAX,
+    }))
+}
+
+fn deg_rad(angle: f64) -> f64 {
+    angle * (std::f64::consts::PI / 180.0)
+}
+
+fn rad_deg(angle: f64) -> f64 {
+    angle / (std::f64::consts::PI / 180.0)
+}
+
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (deg_rad(lat2) - deg_rad(lat1)).abs()
+}
+
+// Haversine distance in meters.
+fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((deg_rad(lon2) - deg_rad(lon1)) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let (lat1, lat2) = (deg_rad(lat1), deg_rad(lat2));
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+struct SearchArea {
+    center: (f64, f64),
+    shape: Shape,
+    // Meters per unit the shape was given in.
+    conversion: f64,
+}
+
+impl SearchArea {
+    // The distance from the center when the point falls inside the shape.
+    fn distance_to(&self, longitude: f64, latitude: f64) -> Option<f64> {
+        let (center_longitude, center_latitude) = self.center;
+        match self.shape {
+            Shape::Radius(radius) => {
+                let distance = distance(center_longitude, center_latitude, longitude, latitude);
+                (distance <= radius * self.conversion).then_some(distance)
+            }
+            Shape::Box { width, height } => {
+                if lat_distance(latitude, center_latitude) > height * self.conversion / 2.0 {
+                    return None;
+                }
+                let long_distance = distance(longitude, latitude, center_longitude, latitude);
+                if long_distance > width * self.conversion / 2.0 {
+                    return None;
+                }
+                Some(distance(
+                    center_longitude,
+                    center_latitude,
+                    longitude,
+                    latitude,
+                ))
+            }
+        }
+    }
+
+    // (min longitude, min latitude, max longitude, max latitude) around the
+    // shape.
+    fn bounding_box(&self) -> (f64, f64, f64, f64) {
+        let (longitude, latitude) = self.center;
+        let (width, height) = match self.shape {
+            Shape::Radius(radius) => (radius, radius),
+            Shape::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let (width, height) = (width * self.conversion, height * self.conversion);
+        let lat_delta = rad_deg(height / EARTH_RADIUS_IN_METERS);
+        let long_delta_top =
+            rad_deg(width / EARTH_RADIUS_IN_METERS / deg_rad(latitude + lat_delta).cos());
+        let long_delta_bottom =
+            rad_deg(width / EARTH_RADIUS_IN_METERS / deg_rad(latitude - lat_delta).cos());
+        let long_delta = if latitude < 0.0 {
+            long_delta_bottom
+        } else {
+            long_delta_top
+        };
+        (
+            longitude - long_delta,
+            latitude - lat_delta,
+            longitude + long_delta,
+            latitude + lat_delta,
+        )
+    }
+
+    // The box containing the center plus its eight neighbours, at a step
+    // coarse enough for the shape to fit. Useless boxes are zeroed.
+    fn covering_boxes(&self) -> [GeoHash; 9] {
+        let (min_longitude, min_latitude, max_longitude, max_latitude) = self.bounding_box();
+        let (longitude, latitude) = self.center;
+        let radius = match self.shape {
+            Shape::Radius(radius) => radius,
+            Shape::Box { width, height } => {
+                ((width / 2.0) * (width / 2.0) + (height / 2.0) * (height / 2.0)).sqrt()
+            }
+        } * self.conversion;
+
+        let mut step = estimate_steps_by_radius(radius, latitude);
+        let mut hash = encode_wgs84(longitude, latitude, step).unwrap_or(GeoHash::ZERO);
+        let north = decode_wgs84(hash.neighbor(0, 1));
+        let south = decode_wgs84(hash.neighbor(0, -1));
+        let east = decode_wgs84(hash.neighbor(1, 0));
+        let west = decode_wgs84(hash.neighbor(-1, 0));
+        // Near the edge of the center box a neighbour may be too small to
+        // cover the whole shape, in which case one step coarser is needed.
+        if step > 1
+            && (north.latitude.max < max_latitude
+                || south.latitude.min > min_latitude
+                || east.longitude.max < max_longitude
+                || west.longitude.min > min_longitude)
+        {
+            step -= 1;
+            hash = encode_wgs84(longitude, latitude, step).unwrap_or(GeoHash::ZERO);
+        }
+        let area = decode_wgs84(hash);
+
+        let mut boxes = [
+            hash,
+            hash.neighbor(0, 1),
+            hash.neighbor(0, -1),
+            hash.neighbor(1, 0),
+            hash.neighbor(-1, 0),
+            hash.neighbor(1, 1),
+            hash.neighbor(-1, 1),
+            hash.neighbor(1, -1),
+            hash.neighbor(-1, -1),
+        ];
+        const NORTH: usize = 1;
+        const SOUTH: usize = 2;
+        const EAST: usize = 3;
+        const WEST: usize = 4;
+        const NORTH_EAST: usize = 5;
+        const NORTH_WEST: usize = 6;
+        const SOUTH_EAST: usize = 7;
+        const SOUTH_WEST: usize = 8;
+        if step >= 2 {
+            let mut exclude = |indexes: [usize; 3]| {
+                for index in indexes {
+                    boxes[index] = GeoHash::ZERO;
+                }
+            };
+            if area.latitude.min < min_latitude {
+                exclude([SOUTH, SOUTH_WEST, SOUTH_EAST]);
+            }
+            if area.latitude.max > max_latitude {
+                exclude([NORTH, NORTH_EAST, NORTH_WEST]);
+            }
+            if area.longitude.min < min_longitude {
+                exclude([WEST, SOUTH_WEST, NORTH_WEST]);
+            }
+            if area.longitude.max > max_longitude {
+                exclude([EAST, SOUTH_EAST, NORTH_EAST]);
+            }
+        }
+        boxes
+    }
+}
+
+fn estimate_steps_by_radius(mut range_meters: f64, latitude: f64) -> u8 {
+    if range_meters == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while range_meters < MERCATOR_MAX {
+        range_meters *= 2.0;
+        step += 1;
+    }
+    // Make sure the range is included in most of the base cases.
+    step -= 2;
+    // Boxes get narrower towards the poles.
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u8
+}
+
+fn parse_unit(unit: &str) -> Result<f64, RespValue> {
+    match unit.to_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(RespValue::error(
+            "ERR unsupported unit provided. please use M, KM, FT, MI",
+        )),
+    }
+}
+
+fn parse_position(longitude: &str, latitude: &str) -> Result<(f64, f64), RespValue> {
+    let (Some(longitude), Some(latitude)) = (parse_score(longitude), parse_score(latitude)) else {
+        return Err(RespValue::error("ERR value is not a valid float"));
+    };
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+    {
+        return Err(RespValue::error(format!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            longitude, latitude
+        )));
+    }
+    Ok((longitude, latitude))
+}
+
+fn parse_distance(input: &str, error: &str) -> Result<f64, RespValue> {
+    parse_score(input).ok_or_else(|| RespValue::error(error))
+}
+
+// Formats a coordinate the way Redis' "human" long double replies do: 17
+// decimals with the trailing zeroes removed.
+fn format_coordinate(value: f64) -> String {
+    let formatted = format!("{:.17}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+fn format_distance(meters: f64, conversion: f64) -> String {
+    format!("{:.4}", meters / conversion)
+}
+
+fn encode_position(score: f64) -> RespValue {
+    let (longitude, latitude) = score_to_position(score);
+    RespValue::bulks([&format_coordinate(longitude), &format_coordinate(latitude)])
+}
+
+// GEOADD key [NX|XX] [CH] longitude latitude member [longitude latitude member ...]
+pub fn handle_geoadd(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 5 {
+        return wrong_number_of_arguments("geoadd");
+    }
+    let mut idx = 2;
+    while idx < decoded_str.len()
+        && matches!(decoded_str[idx].to_lowercase().as_str(), "nx" | "xx" | "ch")
+    {
+        idx += 1;
+    }
+    let elements = &decoded_str[idx..];
+    if elements.is_empty() || !elements.len().is_multiple_of(3) {
+        return RespValue::error(
+            "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ",
+        );
+    }
+
+    // Rewritten into a ZADD with the geohashes as scores.
+    let mut arguments: Vec<Argument> = decoded_str[..idx].to_vec();
+    for triple in elements.chunks(3) {
+        let (longitude, latitude) = match parse_position(&triple[0], &triple[1]) {
+            Ok(position) => position,
+            Err(error) => return error,
+        };
+        let hash = match encode_wgs84(longitude, latitude, GEO_STEP_MAX) {
+            Some(hash) => hash,
+            None => return RespValue::error("ERR syntax error"),
+        };
+        arguments.push(hash.bits.to_string().into());
+        arguments.push(triple[2].clone());
+    }
+    handle_zadd(keyspace, &arguments)
+}
+
+// GEODIST key member1 member2 [M|KM|FT|MI]
+pub fn handle_geodist(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments("geodist");
+    }
+    if decoded_str.len() > 5 {
+        return RespValue::error("ERR syntax error");
+    }
+    let conversion = match decoded_str.get(4) {
+        Some(unit) => match parse_unit(unit) {
+            Ok(conversion) => conversion,
+            Err(error) => return error,
+        },
+        None => 1.0,
+    };
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(Some(sorted_set)) => sorted_set,
+        Ok(None) => return RespValue::Null,
+        Err(error) => return error,
+    };
+    match (
+        sorted_set.score(&decoded_str[2]),
+        sorted_set.score(&decoded_str[3]),
+    ) {
+        (Some(first), Some(second)) => {
+            let (lon1, lat1) = score_to_position(first);
+            let (lon2, lat2) = score_to_position(second);
+            RespValue::bulk(format_distance(
+                distance(lon1, lat1, lon2, lat2),
+                conversion,
+            ))
+        }
+        _ => RespValue::Null,
+    }
+}
+
+// GEOHASH key [member ...]
+pub fn handle_geohash(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("geohash");
+    }
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => sorted_set,
+        Err(error) => return error,
+    };
+    // The stored hashes use the Mercator latitude limits, the standard
+    // geohash strings span -90..90, so the position is re-encoded.
+    let standard_lat_range = GeoRange {
+        min: -90.0,
+        max: 90.0,
+    };
+    let elements: Vec<RespValue> = decoded_str[2..]
+        .iter()
+        .map(|member| {
+            let Some(score) = sorted_set.and_then(|sorted_set| sorted_set.score(member)) else {
+                return RespValue::Null;
+            };
+            let (longitude, latitude) = score_to_position(score);
+            let bits = encode(
+                LONG_RANGE,
+                standard_lat_range,
+                longitude,
+                latitude,
+                GEO_STEP_MAX,
+            )
+            .map_or(0, |hash| hash.bits);
+            // 52 bits only give 10 characters, the 11th is always '0'.
+            let hash: String = (0..11)
+                .map(|i| {
+                    let index = if i == 10 {
+                        0
+                    } else {
+                        (bits >> (52 - (i + 1) * 5)) & 0x1f
+                    };
+                    GEOHASH_ALPHABET[index as usize] as char
+                })
+                .collect();
+            RespValue::bulk(&hash)
+        })
+        .collect();
+    RespValue::Array(elements)
+}
+
+// GEOPOS key [member ...]
+pub fn handle_geopos(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("geopos");
+    }
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => sorted_set,
+        Err(error) => return error,
+    };
+    let elements: Vec<RespValue> = decoded_str[2..]
+        .iter()
+        .map(
+            |member| match sorted_set.and_then(|sorted_set| sorted_set.score(member)) {
+                Some(score) => encode_position(score),
+                None => RespValue::NullArray,
+            },
+        )
+        .collect();
+    RespValue::Array(elements)
+}
+
+enum Center {
+    Member(String),
+    Position(f64, f64),
+}
+
+#[derive(PartialEq)]
+enum Order {
+    Unsorted,
+    Ascending,
+    Descending,
+}
+
+struct SearchOptions {
+    center: Center,
+    shape: Shape,
+    conversion: f64,
+    order: Order,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+struct GeoPoint {
+    member: String,
+    score: f64,
+    distance: f64,
+}
+
+fn parse_search_options(
+    command: &str,
+    options: &[Argument],
+    store: bool,
+) -> Result<SearchOptions, RespValue> {
+    let mut center: Option<Center> = None;
+    let mut shape: Option<(Shape, f64)> = None;
+    let mut order = Order::Unsorted;
+    let mut count: Option<usize> = None;
+    let (mut any, mut with_coord, mut with_dist, mut with_hash, mut store_dist) =
+        (false, false, false, false, false);
+    let center_error = || {
+        RespValue::error(format!(
+            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+            command
+        ))
+    };
+    let shape_error = || {
+        RespValue::error(format!(
+            "ERR exactly one of BYRADIUS and BYBOX can be specified for {}",
+            command
+        ))
+    };
+
+    let mut idx = 0;
+    while idx < options.len() {
+        let remaining = options.len() - idx - 1;
+        match options[idx].to_lowercase().as_str() {
+            "frommember" if remaining >= 1 => {
+                if center.is_some() {
+                    return Err(center_error());
+                }
+                center = Some(Center::Member(options[idx + 1].to_string()));
+                idx += 1;
+            }
+            "fromlonlat" if remaining >= 2 => {
+                if center.is_some() {
+                    return Err(center_error());
+                }
+                let (longitude, latitude) = parse_position(&options[idx + 1], &options[idx + 2])?;
+                center = Some(Center::Position(longitude, latitude));
+                idx += 2;
+            }
+            "byradius" if remaining >= 2 => {
+                if shape.is_some() {
+                    return Err(shape_error());
+                }
+                let radius = parse_distance(&options[idx + 1], "ERR need numeric radius")?;
+                if radius < 0.0 {
+                    return Err(RespValue::error("ERR radius cannot be negative"));
+                }
+                shape = Some((Shape::Radius(radius), parse_unit(&options[idx + 2])?));
+                idx += 2;
+            }
+            "bybox" if remaining >= 3 => {
+                if shape.is_some() {
+                    return Err(shape_error());
+                }
+                let width = parse_distance(&options[idx + 1], "ERR need numeric width")?;
+                let height = parse_distance(&options[idx + 2], "ERR need numeric height")?;
+                if width < 0.0 || height < 0.0 {
+                    return Err(RespValue::error("ERR height or width cannot be negative"));
+                }
+                shape = Some((Shape::Box { width, height }, parse_unit(&options[idx + 3])?));
+                idx += 3;
+            }
+            "asc" => order = Order::Ascending,
+            "desc" => order = Order::Descending,
+            "count" if remaining >= 1 => {
+                let value = parse_integer_argument(&options[idx + 1])?;
+                if value <= 0 {
+                    return Err(RespValue::error("ERR COUNT must be > 0"));
+                }
+                count = Some(value as usize);
+                idx += 1;
+                if options
+                    .get(idx + 1)
+                    .is_some_and(|option| option.eq_ignore_ascii_case("any"))
+                {
+                    any = true;
+                    idx += 1;
+                }
+            }
+            "withcoord" if !store => with_coord = true,
+            "withdist" if !store => with_dist = true,
+            "withhash" if !store => with_hash = true,
+            "storedist" if store => store_dist = true,
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+        idx += 1;
+    }
+
+    let center = center.ok_or_else(center_error)?;
+    let (shape, conversion) = shape.ok_or_else(shape_error)?;
+    // Without an order COUNT would return arbitrary members rather than the
+    // closest ones, unless ANY asked for exactly that.
+    if count.is_some() && !any && order == Order::Unsorted {
+        order = Order::Ascending;
+    }
+    Ok(SearchOptions {
+        center,
+        shape,
+        conversion,
+        order,
+        count,
+        any,
+        with_coord,
+        with_dist,
+        with_hash,
+        store_dist,
+    })
+}
+
+fn search(sorted_set: &SortedSet, area: &SearchArea, limit: Option<usize>) -> Vec<GeoPoint> {
+    let boxes = area.covering_boxes();
+    let mut points: Vec<GeoPoint> = Vec::new();
+    let mut last_processed: Option<GeoHash> = None;
+    for hash in boxes {
+        if hash.is_zero() {
+            continue;
+        }
+        // Neighbours collapse into the same box near the poles and at low
+        // steps, don't report their members twice.
+        if last_processed == Some(hash) {
+            continue;
+        }
+        last_processed = Some(hash);
+        for (member, score) in sorted_set.range_by_score(&hash.score_range(), false, 0, None) {
+            if limit.is_some_and(|limit| points.len() >= limit) {
+                return points;
+            }
+            let (longitude, latitude) = score_to_position(score);
+            if let Some(distance) = area.distance_to(longitude, latitude) {
+                points.push(GeoPoint {
+                    member,
+                    score,
+                    distance,
+                });
+            }
+        }
+    }
+    points
+}
+
+// Resolves the options against the source key, Ok(None) when the source
+// doesn't exist.
+fn run_search(
+    keyspace: &Keyspace,
+    key: &str,
+    options: &SearchOptions,
+) -> Result<Option<Vec<GeoPoint>>, RespValue> {
+    let Some(sorted_set) = get_sorted_set(keyspace, key)? else {
+        return Ok(None);
+    };
+    let center = match &options.center {
+        Center::Position(longitude, latitude) => (*longitude, *latitude),
+        Center::Member(member) => match sorted_set.score(member) {
+            Some(score) => score_to_position(score),
+            None => {
+                return Err(RespValue::error(
+                    "ERR could not decode requested zset member",
+                ))
+            }
+        },
+    };
+    let area = SearchArea {
+        center,
+        shape: options.shape,
+        conversion: options.conversion,
+    };
+    let limit = if options.any { options.count } else { None };
+    let mut points = search(sorted_set, &area, limit);
+    match options.order {
+        Order::Ascending => points.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        Order::Descending => points.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        Order::Unsorted => {}
+    }
+    if let Some(count) = options.count {
+        points.truncate(count);
+    }
+    Ok(Some(points))
+}
+
+// GEOSEARCH key <FROMMEMBER member | FROMLONLAT longitude latitude>
+//   <BYRADIUS radius unit | BYBOX width height unit> [ASC|DESC]
+//   [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+pub fn handle_geosearch(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 7 {
+        return wrong_number_of_arguments("geosearch");
+    }
+    let options = match parse_search_options("GEOSEARCH", &decoded_str[2..], false) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+    let points = match run_search(keyspace, &decoded_str[1], &options) {
+        Ok(Some(points)) => points,
+        Ok(None) => return RespValue::Array(vec![]),
+        Err(error) => return error,
+    };
+
+    let plain = !(options.with_dist || options.with_hash || options.with_coord);
+    let elements: Vec<RespValue> = points
+        .iter()
+        .map(|point| {
+            if plain {
+                return RespValue::bulk(&point.member);
+            }
+            let mut fields = vec![RespValue::bulk(&point.member)];
+            if options.with_dist {
+                fields.push(RespValue::bulk(format_distance(
+                    point.distance,
+                    options.conversion,
+                )));
+            }
+            if options.with_hash {
+                fields.push(RespValue::Integer(point.score as i64));
+            }
+            if options.with_coord {
+                fields.push(encode_position(point.score));
+            }
+            RespValue::Array(fields)
+        })
+        .collect();
+    RespValue::Array(elements)
+}
+
+// GEOSEARCHSTORE destination source <FROMMEMBER member | FROMLONLAT longitude
+//   latitude> <BYRADIUS radius unit | BYBOX width height unit> [ASC|DESC]
+//   [COUNT count [ANY]] [STOREDIST]
+pub fn handle_geosearchstore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 8 {
+        return wrong_number_of_arguments("geosearchstore");
+    }
+    let options = match parse_search_options("GEOSEARCHSTORE", &decoded_str[3..], true) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+    let points = match run_search(keyspace, &decoded_str[2], &options) {
+        Ok(points) => points.unwrap_or_default(),
+        Err(error) => return error,
+    };
+
+    let destination = &decoded_str[1];
+    let existed = keyspace.remove(destination).is_some();
+    if points.is_empty() {
+        if existed {
+            keyspace.notify(EventClass::Generic, "del", destination);
+        }
+        return RespValue::Integer(0);
+    }
+    let mut result = SortedSet::new();
+    for point in &points {
+        let score = if options.store_dist {
+            point.distance / options.conversion
+        } else {
+            point.score
+        };
+        result.insert(point.member.to_owned(), score);
+    }
+    keyspace.insert(destination.to_string(), RedisValue::SortedSet(result), None);
+    keyspace.notify(EventClass::Zset, "geosearchstore", destination);
+    RespValue::Integer(points.len() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(
+        handler: fn(&mut Keyspace, &[Argument]) -> RespValue,
+        keyspace: &mut Keyspace,
+        command: &[&str],
+    ) -> RespValue {
+        let arguments: Vec<Argument> = command.iter().map(|&argument| argument.into()).collect();
+        handler(keyspace, &arguments)
+    }
+
+    // The example of the Redis documentation.
+    fn sicily() -> Keyspace {
+        let mut keyspace = Keyspace::new(1);
+        let added = run(
+            handle_geoadd,
+            &mut keyspace,
+            &[
+                "GEOADD",
+                "Sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ],
+        );
+        assert_eq!(added, RespValue::Integer(2));
+        keyspace
+    }
+
+    #[test]
+    fn scores_match_redis() {
+        let palermo = encode_wgs84(13.361389, 38.115556, GEO_STEP_MAX).unwrap();
+        let catania = encode_wgs84(15.087269, 37.502669, GEO_STEP_MAX).unwrap();
+        assert_eq!(palermo.bits, 3479099956230698);
+        assert_eq!(catania.bits, 3479447370796909);
+    }
+
+    #[test]
+    fn positions_decode_to_the_cell_center() {
+        let (longitude, latitude) = score_to_position(3479099956230698.0);
+        assert_eq!(format_coordinate(longitude), "13.36138933897018433");
+        assert_eq!(format_coordinate(latitude), "38.11555639549629859");
+
+        let mut keyspace = sicily();
+        assert_eq!(
+            run(
+                handle_geopos,
+                &mut keyspace,
+                &["GEOPOS", "Sicily", "Catania", "Nowhere"]
+            ),
+            RespValue::Array(vec![
+                RespValue::bulks(["15.08726745843887329", "37.50266842333162032"]),
+                RespValue::NullArray,
+            ])
+        );
+    }
+
+    #[test]
+    fn interleaving_round_trips() {
+        for value in [0, 1, 0xdead_beef, u32::MAX] {
+            assert_eq!(squash(spread(value)), value);
+            assert_eq!(spread(value) & 0xaaaa_aaaa_aaaa_aaaa, 0);
+        }
+    }
+
+    #[test]
+    fn out_of_range_positions_are_refused() {
+        assert_eq!(encode_wgs84(0.0, 86.0, GEO_STEP_MAX), None);
+        assert_eq!(encode_wgs84(181.0, 0.0, GEO_STEP_MAX), None);
+        let mut keyspace = Keyspace::new(1);
+        assert_eq!(
+            run(
+                handle_geoadd,
+                &mut keyspace,
+                &["GEOADD", "k", "0", "86", "m"]
+            ),
+            RespValue::error("ERR invalid longitude,latitude pair 0.000000,86.000000")
+        );
+    }
+
+    #[test]
+    fn geohash_strings_match_redis() {
+        let mut keyspace = sicily();
+        assert_eq!(
+            run(
+                handle_geohash,
+                &mut keyspace,
+                &["GEOHASH", "Sicily", "Palermo", "Catania"]
+            ),
+            RespValue::bulks(["sqc8b49rny0", "sqdtr74hyu0"])
+        );
+    }
+
+    #[test]
+    fn distances_match_redis() {
+        let mut keyspace = sicily();
+        let geodist = |keyspace: &mut Keyspace, unit| {
+            run(
+                handle_geodist,
+                keyspace,
+                &["GEODIST", "Sicily", "Palermo", "Catania", unit],
+            )
+        };
+        assert_eq!(geodist(&mut keyspace, "m"), RespValue::bulk("166274.1516"));
+        assert_eq!(geodist(&mut keyspace, "km"), RespValue::bulk("166.2742"));
+        assert_eq!(geodist(&mut keyspace, "mi"), RespValue::bulk("103.3182"));
+    }
+
+    #[test]
+    fn search_by_radius_matches_redis() {
+        let mut keyspace = sicily();
+        let search = |keyspace: &mut Keyspace, radius| {
+            run(
+                handle_geosearch,
+                keyspace,
+                &[
+                    "GEOSEARCH",
+                    "Sicily",
+                    "FROMLONLAT",
+                    "15",
+                    "37",
+                    "BYRADIUS",
+                    radius,
+                    "km",
+                    "ASC",
+                    "WITHDIST",
+                ],
+            )
+        };
+        assert_eq!(
+            search(&mut keyspace, "200"),
+            RespValue::Array(vec![
+                RespValue::bulks(["Catania", "56.4413"]),
+                RespValue::bulks(["Palermo", "190.4424"]),
+            ])
+        );
+        assert_eq!(
+            search(&mut keyspace, "100"),
+            RespValue::Array(vec![RespValue::bulks(["Catania", "56.4413"])])
+        );
+    }
+}
@@ -0,0 +1,90 @@
+// Glob-style matching with the same rules as Redis' `stringmatchlen`:
+// `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next character.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p: usize = 0;
+    let mut s: usize = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                while s <= string.len() {
+                    if glob_match(&pattern[p + 1..], &string[s..]) {
+                        return true;
+                    }
+                    s += 1;
+                }
+                return false;
+            }
+            b'?' => {
+                if s == string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                if s == string.len() {
+                    return false;
+                }
+                p += 1;
+                let not = p < pattern.len() && pattern[p] == b'^';
+                if not {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    if p >= pattern.len() {
+                        // Unterminated class, treat the end of the pattern as ']'.
+                        p -= 1;
+                        break;
+                    }
+                    if pattern[p] == b'\\' && p + 2 < pattern.len() {
+                        p += 1;
+                        if pattern[p] == string[s] {
+                            matched = true;
+                        }
+                    } else if pattern[p] == b']' {
+                        break;
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        p += 2;
+                        if string[s] >= start && string[s] <= end {
+                            matched = true;
+                        }
+                    } else if pattern[p] == string[s] {
+                        matched = true;
+                    }
+                    p += 1;
+                }
+                if matched == not {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if s == string.len() || pattern[p] != string[s] {
+                    return false;
+                }
+                s += 1;
+            }
+            byte => {
+                if s == string.len() || byte != string[s] {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    s == string.len()
+}
@@ -0,0 +1,750 @@
+// HyperLogLog values, stored as plain strings laid out exactly like Redis'
+// `HYLL` objects so that they can be exchanged through RDB files:
+//
+// <"HYLL"> <encoding u8> <3 unused bytes> <cached cardinality u64 LE>
+// <registers>
+//
+// The most significant bit of the cached cardinality marks it as stale.
+// Registers are either dense, 16384 packed 6-bit counters, or sparse, a run
+// length encoding of them made of three opcodes:
+//
+// ZERO   00xxxxxx            1 to 64 registers set to 0
+// XZERO  01xxxxxx yyyyyyyy   1 to 16384 registers set to 0
+// VAL    1vvvvvxx            1 to 4 registers set to a value from 1 to 32
+//
+// Sparse HLLs are edited in place the same way Redis does, which keeps the
+// representations byte for byte identical.
+
+use super::{
+    keyspace::Keyspace,
+    notify::EventClass,
+    redis_value::{RedisValue, WRONGTYPE_ERROR},
+    resp::{Argument, RespValue},
+    string::RedisString,
+    wrong_number_of_arguments,
+};
+
+const HLL_P: u32 = 14;
+const HLL_Q: u32 = 64 - HLL_P;
+const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HEADER_SIZE: usize = 16;
+const HLL_DENSE_SIZE: usize = HLL_HEADER_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+const HLL_SEED: u64 = 0xadc8_3b19;
+
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+// Same default as Redis' `hll-sparse-max-bytes`, past it HLLs turn dense.
+const SPARSE_MAX_BYTES: usize = 3000;
+
+const INVALID_HLL_ERROR: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+const CORRUPTED_HLL_ERROR: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+// Where the cached cardinality lives in the header.
+const CARDINALITY_OFFSET: usize = 8;
+
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// Register an element maps to, and the length of the run of zeros ending
+// its hash (plus one), which is what registers keep the maximum of.
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, HLL_SEED);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    // The extra bit caps the count at Q + 1.
+    let hash = (hash >> HLL_P) | (1 << HLL_Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn new_hll() -> Vec<u8> {
+    let mut hll = vec![0; HLL_HEADER_SIZE];
+    hll[..4].copy_from_slice(b"HYLL");
+    hll[4] = HLL_SPARSE;
+    // Every register is zero, which XZERO opcodes cover 16384 at a time.
+    for _ in 0..HLL_REGISTERS / SPARSE_XZERO_MAX_LEN {
+        push_xzero(&mut hll, SPARSE_XZERO_MAX_LEN);
+    }
+    hll
+}
+
+fn is_valid_hll(bytes: &[u8]) -> bool {
+    bytes.len() >= HLL_HEADER_SIZE
+        && &bytes[..4] == b"HYLL"
+        && match bytes[4] {
+            HLL_DENSE => bytes.len() == HLL_DENSE_SIZE,
+            HLL_SPARSE => true,
+            _ => false,
+        }
+}
+
+fn cached_cardinality(hll: &[u8]) -> Option<u64> {
+    let cached = u64::from_le_bytes(hll[CARDINALITY_OFFSET..HLL_HEADER_SIZE].try_into().unwrap());
+    (cached >> 63 == 0).then_some(cached)
+}
+
+fn set_cached_cardinality(hll: &mut [u8], cardinality: u64) {
+    hll[CARDINALITY_OFFSET..HLL_HEADER_SIZE].copy_from_slice(&cardinality.to_le_bytes());
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[HLL_HEADER_SIZE - 1] |= 1 << 7;
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * HLL_BITS / 8;
+    let shift = index * HLL_BITS % 8;
+    let low = registers[byte] as u16;
+    let high = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((low | (high << 8)) >> shift) as u8) & HLL_REGISTER_MAX
+}
+
+fn dense_set(registers: &mut [u8], index: usize, value: u8) {
+    let byte = index * HLL_BITS / 8;
+    let shift = index * HLL_BITS % 8;
+    let value = value as u16;
+    registers[byte] &= !((HLL_REGISTER_MAX as u16) << shift) as u8;
+    registers[byte] |= (value << shift) as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((HLL_REGISTER_MAX as u16) >> (8 - shift)) as u8;
+        *next |= (value >> (8 - shift)) as u8;
+    }
+}
+
+enum SparseOpcode {
+    Zero(usize),
+    Xzero(usize),
+    Val(u8, usize),
+}
+
+impl SparseOpcode {
+    fn read(bytes: &[u8], pos: usize) -> Option<Self> {
+        let byte = *bytes.get(pos)?;
+        Some(match byte & 0xc0 {
+            0x00 => SparseOpcode::Zero((byte & 0x3f) as usize + 1),
+            0x40 => {
+                let low = *bytes.get(pos + 1)? as usize;
+                SparseOpcode::Xzero(((((byte & 0x3f) as usize) << 8) | low) + 1)
+            }
+            _ => SparseOpcode::Val(((byte >> 2) & 0x1f) + 1, (byte & 0x3) as usize + 1),
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            SparseOpcode::Xzero(_) => 2,
+            _ => 1,
+        }
+    }
+
+    fn span(&self) -> usize {
+        match self {
+            SparseOpcode::Zero(len) | SparseOpcode::Xzero(len) | SparseOpcode::Val(_, len) => *len,
+        }
+    }
+}
+
+fn val_opcode(value: u8, len: usize) -> u8 {
+    0x80 | ((value - 1) << 2) | (len as u8 - 1)
+}
+
+fn push_xzero(out: &mut Vec<u8>, len: usize) {
+    let len = len - 1;
+    out.push(0x40 | (len >> 8) as u8);
+    out.push(len as u8);
+}
+
+// Zero run, with the shortest opcode that fits it.
+fn push_zeros(out: &mut Vec<u8>, len: usize) {
+    if len > SPARSE_ZERO_MAX_LEN {
+        push_xzero(out, len);
+    } else {
+        out.push(len as u8 - 1);
+    }
+}
+
+// Calls `visit(first_register, opcode)` for every opcode, and makes sure
+// they cover exactly the 16384 registers.
+fn walk_sparse(hll: &[u8], mut visit: impl FnMut(usize, &SparseOpcode)) -> Result<(), RespValue> {
+    let mut pos = HLL_HEADER_SIZE;
+    let mut index = 0;
+    while pos < hll.len() {
+        let opcode = SparseOpcode::read(hll, pos).ok_or_else(corrupted)?;
+        if index + opcode.span() > HLL_REGISTERS {
+            return Err(corrupted());
+        }
+        visit(index, &opcode);
+        index += opcode.span();
+        pos += opcode.size();
+    }
+    if index == HLL_REGISTERS {
+        Ok(())
+    } else {
+        Err(corrupted())
+    }
+}
+
+fn corrupted() -> RespValue {
+    RespValue::error(CORRUPTED_HLL_ERROR)
+}
+
+fn sparse_to_dense(hll: &mut Vec<u8>) -> Result<(), RespValue> {
+    if hll[4] == HLL_DENSE {
+        return Ok(());
+    }
+    // The header, along with the cached cardinality, is kept.
+    let mut dense = hll[..HLL_HEADER_SIZE].to_vec();
+    dense[4] = HLL_DENSE;
+    dense.resize(HLL_DENSE_SIZE, 0);
+    let registers = &mut dense[HLL_HEADER_SIZE..];
+    walk_sparse(hll, |first, opcode| {
+        if let SparseOpcode::Val(value, len) = opcode {
+            for index in first..first + len {
+                dense_set(registers, index, *value);
+            }
+        }
+    })?;
+    *hll = dense;
+    Ok(())
+}
+
+// Sets a sparse register to `count` if that raises it, splitting the opcode
+// covering it and merging adjacent VAL opcodes afterwards just like Redis.
+// Turns the HLL dense when the value or the size don't fit the sparse
+// representation anymore.
+fn sparse_set(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, RespValue> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote(hll, index, count);
+    }
+
+    // Step 1: find the opcode covering the register.
+    let mut pos = HLL_HEADER_SIZE;
+    let mut first = 0;
+    let mut prev: Option<usize> = None;
+    let opcode = loop {
+        let opcode = SparseOpcode::read(hll, pos).ok_or_else(corrupted)?;
+        if index < first + opcode.span() {
+            break opcode;
+        }
+        prev = Some(pos);
+        pos += opcode.size();
+        first += opcode.span();
+    };
+    let span = opcode.span();
+
+    // Step 2: trivial updates are done in place, the rest splits the opcode
+    // into up to three new ones.
+    let mut sequence = Vec::with_capacity(5);
+    match opcode {
+        SparseOpcode::Val(value, _) if value >= count => return Ok(false),
+        SparseOpcode::Val(_, 1) | SparseOpcode::Zero(1) => {
+            hll[pos] = val_opcode(count, 1);
+        }
+        SparseOpcode::Val(value, _) => {
+            if index != first {
+                sequence.push(val_opcode(value, index - first));
+            }
+            sequence.push(val_opcode(count, 1));
+            let last = first + span - 1;
+            if index != last {
+                sequence.push(val_opcode(value, last - index));
+            }
+        }
+        SparseOpcode::Zero(_) | SparseOpcode::Xzero(_) => {
+            if index != first {
+                push_zeros(&mut sequence, index - first);
+            }
+            sequence.push(val_opcode(count, 1));
+            let last = first + span - 1;
+            if index != last {
+                push_zeros(&mut sequence, last - index);
+            }
+        }
+    }
+
+    // Step 3: swap the old opcode for the new sequence.
+    if !sequence.is_empty() {
+        let old_size = opcode.size();
+        if sequence.len() > old_size && hll.len() + sequence.len() - old_size > SPARSE_MAX_BYTES {
+            return promote(hll, index, count);
+        }
+        hll.splice(pos..pos + old_size, sequence);
+    }
+
+    // Step 4: merge adjacent VAL opcodes holding the same value, scanning up
+    // to five opcodes from the one before the change.
+    let mut p = prev.unwrap_or(HLL_HEADER_SIZE);
+    let mut scan = 5;
+    while p < hll.len() && scan > 0 {
+        scan -= 1;
+        match hll[p] & 0xc0 {
+            0x40 => {
+                p += 2;
+                continue;
+            }
+            0x00 => {
+                p += 1;
+                continue;
+            }
+            _ => {}
+        }
+        if let (Some(SparseOpcode::Val(v1, len1)), Some(SparseOpcode::Val(v2, len2))) =
+            (SparseOpcode::read(hll, p), SparseOpcode::read(hll, p + 1))
+        {
+            if v1 == v2 && len1 + len2 <= SPARSE_VAL_MAX_LEN {
+                hll[p + 1] = val_opcode(v1, len1 + len2);
+                hll.remove(p);
+                // Try to merge the result with the next one too.
+                continue;
+            }
+        }
+        p += 1;
+    }
+
+    invalidate_cache(hll);
+    Ok(true)
+}
+
+fn promote(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, RespValue> {
+    sparse_to_dense(hll)?;
+    dense_set(&mut hll[HLL_HEADER_SIZE..], index, count);
+    Ok(true)
+}
+
+// Raises register `index` to `count`, returning whether it changed.
+fn set_register(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, RespValue> {
+    if hll[4] == HLL_SPARSE {
+        return sparse_set(hll, index, count);
+    }
+    let registers = &mut hll[HLL_HEADER_SIZE..];
+    if dense_get(registers, index) >= count {
+        return Ok(false);
+    }
+    dense_set(registers, index, count);
+    Ok(true)
+}
+
+// Merges the registers of `hll` into `max`, keeping the highest values.
+fn merge_registers(max: &mut [u8], hll: &[u8]) -> Result<(), RespValue> {
+    if hll[4] == HLL_DENSE {
+        let registers = &hll[HLL_HEADER_SIZE..];
+        for (index, value) in max.iter_mut().enumerate() {
+            *value = (*value).max(dense_get(registers, index));
+        }
+        return Ok(());
+    }
+    walk_sparse(hll, |first, opcode| {
+        if let SparseOpcode::Val(value, len) = opcode {
+            for register in &mut max[first..first + len] {
+                *register = (*register).max(*value);
+            }
+        }
+    })
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+// Estimates the cardinality from how many registers hold each value, with
+// the improved estimator from Otmar Ertl's "New cardinality estimation
+// algorithms for HyperLogLog sketches" that Redis uses.
+fn estimate(histogram: &[u32]) -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let q = HLL_Q as usize;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for j in (1..=q).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+
+fn count(hll: &[u8]) -> Result<u64, RespValue> {
+    let mut histogram = [0u32; HLL_Q as usize + 2];
+    if hll[4] == HLL_DENSE {
+        let registers = &hll[HLL_HEADER_SIZE..];
+        for index in 0..HLL_REGISTERS {
+            histogram[dense_get(registers, index) as usize] += 1;
+        }
+    } else {
+        walk_sparse(hll, |_, opcode| match opcode {
+            SparseOpcode::Zero(len) | SparseOpcode::Xzero(len) => histogram[0] += *len as u32,
+            SparseOpcode::Val(value, len) => histogram[*value as usize] += *len as u32,
+        })?;
+    }
+    Ok(estimate(&histogram))
+}
+
+fn count_registers(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; HLL_Q as usize + 2];
+    for value in registers {
+        histogram[*value as usize] += 1;
+    }
+    estimate(&histogram)
+}
+
+// The HLL stored at `key`, if any, or the reason why it can't be used.
+fn get_hll(keyspace: &Keyspace, key: &str) -> Result<Option<Vec<u8>>, RespValue> {
+    match keyspace.get(key) {
+        None => Ok(None),
+        Some(RedisValue::String(value)) => {
+            let bytes = value.as_bytes();
+            if is_valid_hll(&bytes) {
+                Ok(Some(bytes.into_owned()))
+            } else {
+                Err(RespValue::error(INVALID_HLL_ERROR))
+            }
+        }
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+fn get_hll_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut Vec<u8>>, RespValue> {
+    match keyspace.get_mut(key) {
+        None => Ok(None),
+        Some(RedisValue::String(value)) => {
+            let bytes = value.raw_bytes_mut();
+            if is_valid_hll(bytes) {
+                Ok(Some(bytes))
+            } else {
+                Err(RespValue::error(INVALID_HLL_ERROR))
+            }
+        }
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+fn get_or_create_hll<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<(&'a mut Vec<u8>, bool), RespValue> {
+    let created = !keyspace.contains_key(key);
+    if created {
+        keyspace.insert(
+            key.to_string(),
+            RedisValue::String(RedisString::Raw(new_hll())),
+            None,
+        );
+    }
+    match get_hll_mut(keyspace, key)? {
+        Some(hll) => Ok((hll, created)),
+        None => unreachable!("the HLL was created above"),
+    }
+}
+
+pub fn handle_pfadd(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("pfadd");
+    }
+    let (hll, created) = match get_or_create_hll(keyspace, &decoded_str[1]) {
+        Ok(result) => result,
+        Err(error) => return error,
+    };
+    let mut updated = created;
+    for element in &decoded_str[2..] {
+        let (index, count) = pattern_len(element.as_bytes());
+        match set_register(hll, index, count) {
+            Ok(changed) => updated |= changed,
+            Err(error) => return error,
+        }
+    }
+    if updated {
+        invalidate_cache(hll);
+        keyspace.notify(EventClass::String, "pfadd", &decoded_str[1]);
+    }
+    RespValue::Integer(updated as i64)
+}
+
+pub fn handle_pfcount(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("pfcount");
+    }
+
+    // A single key uses, and refreshes, the cached cardinality.
+    if decoded_str.len() == 2 {
+        return match get_hll_mut(keyspace, &decoded_str[1]) {
+            Ok(Some(hll)) => match cached_cardinality(hll) {
+                Some(cardinality) => RespValue::Integer(cardinality as i64),
+                None => match count(hll) {
+                    Ok(cardinality) => {
+                        set_cached_cardinality(hll, cardinality);
+                        RespValue::Integer(cardinality as i64)
+                    }
+                    Err(error) => error,
+                },
+            },
+            Ok(None) => RespValue::Integer(0),
+            Err(error) => error,
+        };
+    }
+
+    // Several keys are counted as their union, without touching them.
+    let mut max = vec![0u8; HLL_REGISTERS];
+    for key in &decoded_str[1..] {
+        match get_hll(keyspace, key) {
+            Ok(Some(hll)) => {
+                if let Err(error) = merge_registers(&mut max, &hll) {
+                    return error;
+                }
+            }
+            Ok(None) => {}
+            Err(error) => return error,
+        }
+    }
+    RespValue::Integer(count_registers(&max) as i64)
+}
+
+pub fn handle_pfmerge(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("pfmerge");
+    }
+    // The destination counts as a source too.
+    let mut max = vec![0u8; HLL_REGISTERS];
+    let mut use_dense = false;
+    for key in &decoded_str[1..] {
+        match get_hll(keyspace, key) {
+            Ok(Some(hll)) => {
+                use_dense |= hll[4] == HLL_DENSE;
+                if let Err(error) = merge_registers(&mut max, &hll) {
+                    return error;
+                }
+            }
+            Ok(None) => {}
+            Err(error) => return error,
+        }
+    }
+
+    let hll = match get_or_create_hll(keyspace, &decoded_str[1]) {
+        Ok((hll, _)) => hll,
+        Err(error) => return error,
+    };
+    if use_dense {
+        if let Err(error) = sparse_to_dense(hll) {
+            return error;
+        }
+    }
+    for (index, value) in max.into_iter().enumerate() {
+        if value == 0 {
+            continue;
+        }
+        if let Err(error) = set_register(hll, index, value) {
+            return error;
+        }
+    }
+    invalidate_cache(hll);
+    keyspace.notify(EventClass::String, "pfadd", &decoded_str[1]);
+    RespValue::ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis_server::string;
+
+    fn run(
+        handler: fn(&mut Keyspace, &[Argument]) -> RespValue,
+        keyspace: &mut Keyspace,
+        command: &[&[u8]],
+    ) -> RespValue {
+        let arguments: Vec<Argument> = command
+            .iter()
+            .map(|argument| Argument::new(argument.to_vec()))
+            .collect();
+        handler(keyspace, &arguments)
+    }
+
+    fn stored(keyspace: &mut Keyspace, key: &str) -> Vec<u8> {
+        match run(string::handle_get, keyspace, &[b"GET", key.as_bytes()]) {
+            RespValue::BulkString(bytes) => bytes,
+            other => panic!("GET {} replied {:?}", key, other),
+        }
+    }
+
+    // What Redis stores for `PFADD h a`: `a` lands in register 12711 with a
+    // run of 2, between two XZERO opcodes, and the cache is marked stale.
+    const REDIS_PFADD_A: &[u8] =
+        b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\x71\xa6\x84\x4e\x57";
+
+    #[test]
+    fn murmurhash_matches_reference() {
+        assert_eq!(murmurhash64a(b"", HLL_SEED), 0xd8df_ea65_85bc_9732);
+        assert_eq!(murmurhash64a(b"a", HLL_SEED), 0x53d2_470a_9b43_b1a7);
+        // One whole block and a tail.
+        assert_eq!(murmurhash64a(b"foobar!!x", HLL_SEED), 0xdfea_e3eb_d728_15f8);
+        assert_eq!(pattern_len(b"a"), (12711, 2));
+        assert_eq!(pattern_len(b"hello"), (9216, 1));
+    }
+
+    #[test]
+    fn empty_hll_matches_redis() {
+        let mut keyspace = Keyspace::new(1);
+        assert_eq!(
+            run(handle_pfadd, &mut keyspace, &[b"PFADD", b"h"]),
+            RespValue::Integer(1)
+        );
+        // Creating the key counts as an update, which marks the cache stale.
+        assert_eq!(
+            stored(&mut keyspace, "h"),
+            b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\x7f\xff"
+        );
+    }
+
+    #[test]
+    fn sparse_hll_matches_redis() {
+        let mut keyspace = Keyspace::new(1);
+        run(handle_pfadd, &mut keyspace, &[b"PFADD", b"h", b"a"]);
+        assert_eq!(stored(&mut keyspace, "h"), REDIS_PFADD_A);
+
+        // PFCOUNT caches the cardinality, little endian.
+        assert_eq!(
+            run(handle_pfcount, &mut keyspace, &[b"PFCOUNT", b"h"]),
+            RespValue::Integer(1)
+        );
+        let hll = stored(&mut keyspace, "h");
+        assert_eq!(
+            &hll[CARDINALITY_OFFSET..HLL_HEADER_SIZE],
+            &[1, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(&hll[HLL_HEADER_SIZE..], &REDIS_PFADD_A[HLL_HEADER_SIZE..]);
+    }
+
+    #[test]
+    fn hll_written_by_redis_is_read_back() {
+        let mut keyspace = Keyspace::new(1);
+        run(
+            string::handle_set,
+            &mut keyspace,
+            &[b"SET", b"h", REDIS_PFADD_A],
+        );
+        assert_eq!(
+            run(handle_pfcount, &mut keyspace, &[b"PFCOUNT", b"h"]),
+            RespValue::Integer(1)
+        );
+        // Already counted, nothing changes.
+        assert_eq!(
+            run(handle_pfadd, &mut keyspace, &[b"PFADD", b"h", b"a"]),
+            RespValue::Integer(0)
+        );
+    }
+
+    #[test]
+    fn dense_registers_are_packed_lsb_first() {
+        let mut registers = vec![0; 12];
+        dense_set(&mut registers, 1, HLL_REGISTER_MAX);
+        assert_eq!(&registers[..3], &[0xc0, 0x0f, 0x00]);
+        dense_set(&mut registers, 2, 5);
+        assert_eq!(&registers[..3], &[0xc0, 0x5f, 0x00]);
+        assert_eq!(dense_get(&registers, 0), 0);
+        assert_eq!(dense_get(&registers, 1), HLL_REGISTER_MAX);
+        assert_eq!(dense_get(&registers, 2), 5);
+        assert_eq!(dense_get(&registers, 3), 0);
+    }
+
+    #[test]
+    fn sparse_opcodes() {
+        assert_eq!(val_opcode(1, 1), 0x80);
+        assert_eq!(val_opcode(SPARSE_VAL_MAX_VALUE, SPARSE_VAL_MAX_LEN), 0xff);
+        let mut out = Vec::new();
+        push_zeros(&mut out, SPARSE_ZERO_MAX_LEN);
+        push_zeros(&mut out, SPARSE_ZERO_MAX_LEN + 1);
+        assert_eq!(out, [0x3f, 0x40, 0x40]);
+    }
+
+    #[test]
+    fn many_elements_go_dense_and_count_closely() {
+        let mut keyspace = Keyspace::new(1);
+        let elements: Vec<Vec<u8>> = (0..20000)
+            .map(|i| format!("element:{}", i).into_bytes())
+            .collect();
+        for chunk in elements.chunks(1000) {
+            let mut command: Vec<&[u8]> = vec![b"PFADD", b"h"];
+            command.extend(chunk.iter().map(Vec::as_slice));
+            run(handle_pfadd, &mut keyspace, &command);
+        }
+        let hll = stored(&mut keyspace, "h");
+        assert_eq!(hll[4], HLL_DENSE);
+        assert_eq!(hll.len(), HLL_DENSE_SIZE);
+        let RespValue::Integer(count) = run(handle_pfcount, &mut keyspace, &[b"PFCOUNT", b"h"])
+        else {
+            panic!("PFCOUNT didn't reply with an integer");
+        };
+        assert!((count - 20000).abs() < 20000 / 50, "estimated {}", count);
+    }
+
+    #[test]
+    fn corrupted_sparse_hll_is_refused() {
+        let mut keyspace = Keyspace::new(1);
+        // A single ZERO opcode covers one register out of 16384.
+        let hll = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\x00";
+        run(string::handle_set, &mut keyspace, &[b"SET", b"h", hll]);
+        assert_eq!(
+            run(handle_pfcount, &mut keyspace, &[b"PFCOUNT", b"h"]),
+            RespValue::error(CORRUPTED_HLL_ERROR)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+use super::random::random_index;
+
+// A hash map that also keeps its entries packed in a vector, so that one can
+// be picked at random in constant time, the way Redis picks a random bucket
+// of its dicts. Removing an entry moves the last one into its slot.
+#[derive(Debug, Clone)]
+pub struct IndexedMap<K, V> {
+    entries: Vec<(K, V)>,
+    positions: HashMap<K, usize>,
+}
+
+impl<K, V> Default for IndexedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> IndexedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Returns the value the key had before, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.positions.get(&key) {
+            Some(&position) => Some(std::mem::replace(&mut self.entries[position].1, value)),
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let position = *self.positions.get(key)?;
+        Some(&self.entries[position].1)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let position = *self.positions.get(key)?;
+        let (key, value) = &self.entries[position];
+        Some((key, value))
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let position = *self.positions.get(key)?;
+        Some(&mut self.entries[position].1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.positions.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let position = self.positions.remove(key)?;
+        let entry = self.entries.swap_remove(position);
+        if let Some((moved, _)) = self.entries.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+        Some(entry)
+    }
+
+    pub fn random(&self) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let (key, value) = &self.entries[random_index(self.entries.len())];
+        Some((key, value))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+}
@@ -0,0 +1,40 @@
+use super::{
+    database, eviction,
+    keyspace::Keyspace,
+    resp::{Argument, RespValue},
+};
+
+// INFO [section ...]: a single plain-text payload of `# Section` headers
+// followed by `field:value` lines, with a blank line between sections. No
+// section, "default", "all" or "everything" select every section there is,
+// names are matched case-insensitively and unknown ones select nothing.
+pub fn handle_info(
+    keyspace: &mut Keyspace,
+    replication: &str,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let requested: Vec<String> = decoded_str[1..]
+        .iter()
+        .map(|section| section.to_lowercase())
+        .collect();
+    let everything = requested.is_empty()
+        || requested
+            .iter()
+            .any(|section| matches!(section.as_str(), "default" | "all" | "everything"));
+    let wanted = |section: &str| everything || requested.iter().any(|name| name == section);
+
+    let mut sections = Vec::new();
+    if wanted("replication") {
+        sections.push(replication.to_string());
+    }
+    if wanted("memory") {
+        sections.push(eviction::memory_info(keyspace));
+    }
+    if wanted("stats") {
+        sections.push(eviction::stats_info(keyspace));
+    }
+    if wanted("keyspace") {
+        sections.push(database::keyspace_info(keyspace));
+    }
+    RespValue::bulk(sections.join("\r\n"))
+}
@@ -0,0 +1,587 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::{
+    eviction::{Eviction, Policy},
+    functions::Libraries,
+    notify::{EventClass, KeyspaceEvent},
+    random::random_index,
+    rdb,
+    redis_value::RedisValue,
+    resp::Argument,
+    timed_hashmap::TimedHashMap,
+};
+
+pub const DEFAULT_DATABASES: usize = 16;
+
+// Same size as Redis' eviction pool.
+const EVICTION_POOL_SIZE: usize = 16;
+
+// Same as Redis' active expire cycle: keys with a time to live sampled per
+// database at a time, and the share of them found expired, in percent, past
+// which the database is sampled again.
+const ACTIVE_EXPIRE_KEYS_PER_LOOP: usize = 20;
+const ACTIVE_EXPIRE_ACCEPTABLE_STALE: usize = 10;
+
+pub type Database = TimedHashMap<String, RedisValue>;
+
+// Every key of the server, in numbered databases. Commands see the one
+// selected by whoever locked the keyspace. Besides the values themselves it
+// collects the effects a command wants replicated in place of its own
+// arguments, e.g. XADD with `*` is propagated with the ID it generated, and
+// the keyspace events to publish once the command is done.
+#[derive(Debug)]
+pub struct Keyspace {
+    databases: Vec<Database>,
+    selected: usize,
+    // The database replicas applied the last write to, SELECT goes out
+    // first when the next one is for another. Unknown once a replica
+    // attached.
+    replicated: Option<usize>,
+    propagated: Vec<Vec<Argument>>,
+    notifications: Vec<KeyspaceEvent>,
+    // Keys some connection is WATCHing, with a version bumped whenever the
+    // key changes so that EXEC can tell whether it did since.
+    watched: HashMap<(usize, String), WatchedKey>,
+    // Keys changed since client side caching last looked, whether watched
+    // or not.
+    modified: Vec<String>,
+    // Set by FLUSHDB and FLUSHALL, which invalidate every cached key.
+    flushed: bool,
+    // Function libraries live with the keys, so that snapshots and
+    // replicas get them too.
+    functions: Libraries,
+    eviction: Eviction,
+    // The best candidates for eviction sampled so far, worst first. Kept
+    // from one eviction to the next, which makes sampling closer to true
+    // LRU and LFU.
+    eviction_pool: Vec<EvictionCandidate>,
+    evicted_keys: u64,
+    // The database the next active expire cycle starts with.
+    expire_cursor: usize,
+    // Expired keys found by lookups that can't delete them, deleted by the
+    // next change to the keyspace or once the command is done.
+    expired_reads: RefCell<Vec<(usize, String)>>,
+    // Where SAVE and BGSAVE write, they run as keyspace commands.
+    dbfilename: String,
+    // Changes since the last snapshot, for the save points.
+    dirty: u64,
+    last_save: Instant,
+}
+
+#[derive(Debug)]
+struct WatchedKey {
+    watchers: usize,
+    version: u64,
+    // The version the key got when it was deleted for having expired. That
+    // is no change for whoever watched it already expired.
+    reclaimed: Option<u64>,
+}
+
+#[derive(Debug)]
+struct EvictionCandidate {
+    score: u128,
+    db: usize,
+    key: String,
+}
+
+impl Keyspace {
+    pub fn new(databases: usize) -> Self {
+        Self {
+            databases: (0..databases).map(|_| TimedHashMap::new()).collect(),
+            selected: 0,
+            replicated: None,
+            propagated: Vec::new(),
+            notifications: Vec::new(),
+            watched: HashMap::new(),
+            modified: Vec::new(),
+            flushed: false,
+            functions: Libraries::new(),
+            eviction: Eviction::default(),
+            eviction_pool: Vec::new(),
+            evicted_keys: 0,
+            expire_cursor: 0,
+            expired_reads: RefCell::new(Vec::new()),
+            dbfilename: rdb::DEFAULT_FILENAME.to_string(),
+            dirty: 0,
+            last_save: Instant::now(),
+        }
+    }
+
+    pub fn databases(&self) -> usize {
+        self.databases.len()
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // `db` must be below `databases()`.
+    pub fn select(&mut self, db: usize) {
+        self.selected = db;
+    }
+
+    // Lookups find out about expired keys, like in Redis: they are deleted,
+    // with an `expired` event and a DEL for the replicas, before the command
+    // goes on as if they were missing.
+    pub fn get(&self, key: &str) -> Option<&RedisValue> {
+        self.note_if_expired(key);
+        self.databases[self.selected].access(key, &self.eviction)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut RedisValue> {
+        self.expire_if_needed(self.selected, key);
+        self.databases[self.selected].access_mut(key, &self.eviction)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.note_if_expired(key);
+        self.databases[self.selected].contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: String, value: RedisValue, ttl: Option<Duration>) {
+        self.expire_if_needed(self.selected, &key);
+        if !self.contains_key(&key) {
+            self.notify(EventClass::New, "new", &key);
+        }
+        self.touch(self.selected, &key);
+        self.databases[self.selected].insert(key, value, ttl, &self.eviction);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<RedisValue> {
+        self.expire_if_needed(self.selected, key);
+        let removed = self.databases[self.selected].remove(key);
+        if removed.is_some() {
+            self.touch(self.selected, key);
+        }
+        removed
+    }
+
+    pub fn expiration(&self, key: &str) -> Option<Instant> {
+        self.note_if_expired(key);
+        self.databases[self.selected].expiration(key)
+    }
+
+    // The number of keys of the selected database, expired ones not deleted
+    // yet included.
+    pub fn len(&self) -> usize {
+        self.databases[self.selected].len()
+    }
+
+    pub fn iter_database(
+        &self,
+        db: usize,
+    ) -> impl Iterator<Item = (&String, &RedisValue, Option<Instant>)> {
+        self.databases[db].iter()
+    }
+
+    // MOVE: false when the key is missing or already in `db`.
+    pub fn move_key(&mut self, key: &str, db: usize) -> bool {
+        self.expire_if_needed(db, key);
+        if !self.contains_key(key) || self.databases[db].contains_key(key) {
+            return false;
+        }
+        let expiration = self.expiration(key);
+        let Some(value) = self.remove(key) else {
+            return false;
+        };
+        let ttl = expiration.map(|expiration| expiration.saturating_duration_since(Instant::now()));
+        self.touch(db, key);
+        self.databases[db].insert(key.to_string(), value, ttl, &self.eviction);
+        true
+    }
+
+    // SWAPDB: clients see the other database's keys from now on, whatever
+    // they watched in either changed.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.databases.swap(a, b);
+        self.dirty += 1;
+        for ((db, _), watched) in self.watched.iter_mut() {
+            if *db == a || *db == b {
+                watched.version += 1;
+            }
+        }
+    }
+
+    // Empties one database or all of them, handing the keys back so that
+    // they can be freed elsewhere.
+    pub fn flush(&mut self, db: Option<usize>) -> Vec<Database> {
+        let flushed: Vec<usize> = match db {
+            Some(db) => vec![db],
+            None => (0..self.databases.len()).collect(),
+        };
+        for ((db, key), watched) in self.watched.iter_mut() {
+            if flushed.contains(db) && self.databases[*db].contains_key(key.as_str()) {
+                watched.version += 1;
+            }
+        }
+        self.flushed = true;
+        self.dirty += 1;
+        flushed
+            .into_iter()
+            .map(|db| std::mem::replace(&mut self.databases[db], TimedHashMap::new()))
+            .collect()
+    }
+
+    pub fn take_flushed(&mut self) -> bool {
+        std::mem::take(&mut self.flushed)
+    }
+
+    pub fn functions(&self) -> &Libraries {
+        &self.functions
+    }
+
+    pub fn functions_mut(&mut self) -> &mut Libraries {
+        &mut self.functions
+    }
+
+    pub fn eviction(&self) -> &Eviction {
+        &self.eviction
+    }
+
+    // Candidates sampled under another policy were scored differently.
+    pub fn set_eviction(&mut self, eviction: Eviction) {
+        if eviction.policy != self.eviction.policy {
+            self.eviction_pool.clear();
+        }
+        self.eviction = eviction;
+    }
+
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys
+    }
+
+    // CONFIG RESETSTAT
+    pub fn reset_stats(&mut self) {
+        self.evicted_keys = 0;
+    }
+
+    pub fn dbfilename(&self) -> &str {
+        &self.dbfilename
+    }
+
+    pub fn set_dbfilename(&mut self, dbfilename: &str) {
+        self.dbfilename = dbfilename.to_string();
+    }
+
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    pub fn last_save(&self) -> Instant {
+        self.last_save
+    }
+
+    // A snapshot of the keys as they are now was taken.
+    pub fn saved(&mut self) {
+        self.dirty = 0;
+        self.last_save = Instant::now();
+    }
+
+    // What maxmemory is compared to: the estimated size of every key and
+    // value.
+    pub fn used_memory(&mut self) -> usize {
+        self.databases
+            .iter_mut()
+            .map(|database| database.used_memory())
+            .sum()
+    }
+
+    // Evicts keys the way maxmemory-policy says until the data fits in
+    // maxmemory. Replicas get a DEL for each, like for any other deletion.
+    // False when the policy can't evict enough.
+    pub fn evict(&mut self) -> bool {
+        let maxmemory = usize::try_from(self.eviction.maxmemory).unwrap_or(usize::MAX);
+        while maxmemory != 0 && self.used_memory() > maxmemory {
+            let Some((db, key)) = self.eviction_victim() else {
+                return false;
+            };
+            let selected = self.selected;
+            self.selected = db;
+            self.remove(&key);
+            self.notify(EventClass::Evicted, "evicted", &key);
+            self.propagate(vec!["DEL".to_string(), key]);
+            self.selected = selected;
+            self.evicted_keys += 1;
+        }
+        true
+    }
+
+    // Random policies take any key of a database picked at random. The
+    // others sample every database and evict the best candidate of the
+    // pool.
+    fn eviction_victim(&mut self) -> Option<(usize, String)> {
+        let volatile = self.eviction.policy.is_volatile();
+        match self.eviction.policy {
+            Policy::NoEviction => None,
+            Policy::AllkeysRandom | Policy::VolatileRandom => {
+                let count = self.databases.len();
+                let start = random_index(count);
+                (0..count).map(|db| (start + db) % count).find_map(|db| {
+                    let (key, _, _) = self.databases[db].sample(1, volatile).pop()?;
+                    Some((db, key))
+                })
+            }
+            _ => {
+                for db in 0..self.databases.len() {
+                    let samples = self.databases[db].sample(self.eviction.samples, volatile);
+                    for (key, access, expiration) in samples {
+                        let score = self.eviction.score(access, expiration);
+                        self.add_eviction_candidate(EvictionCandidate { score, db, key });
+                    }
+                }
+                // Candidates may have been deleted or persisted since they
+                // were sampled.
+                while let Some(candidate) = self.eviction_pool.pop() {
+                    let database = &self.databases[candidate.db];
+                    let evictable = if volatile {
+                        database.expiration(candidate.key.as_str()).is_some()
+                    } else {
+                        database.contains_key(candidate.key.as_str())
+                    };
+                    if evictable {
+                        return Some((candidate.db, candidate.key));
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn add_eviction_candidate(&mut self, candidate: EvictionCandidate) {
+        self.eviction_pool
+            .retain(|other| other.db != candidate.db || other.key != candidate.key);
+        let at = self
+            .eviction_pool
+            .partition_point(|other| other.score <= candidate.score);
+        self.eviction_pool.insert(at, candidate);
+        if self.eviction_pool.len() > EVICTION_POOL_SIZE {
+            self.eviction_pool.remove(0);
+        }
+    }
+
+    // Deletes `key` from the database `db` if it has expired, the way a
+    // command touching it finds out.
+    pub fn expire_if_needed(&mut self, db: usize, key: &str) {
+        self.reclaim_expired();
+        if self.databases[db].remove_if_expired(key) {
+            self.expired(db, key);
+        }
+    }
+
+    // Deletes the expired keys lookups came across.
+    pub fn reclaim_expired(&mut self) {
+        for (db, key) in self.expired_reads.take() {
+            if self.databases[db].remove_if_expired(key.as_str()) {
+                self.expired(db, &key);
+            }
+        }
+    }
+
+    fn note_if_expired(&self, key: &str) {
+        if self.databases[self.selected].has_expired(key) {
+            self.expired_reads
+                .borrow_mut()
+                .push((self.selected, key.to_string()));
+        }
+    }
+
+    // `key` was deleted from `db` for having expired: its event fires and
+    // replicas, which don't expire keys themselves, get a DEL.
+    fn expired(&mut self, db: usize, key: &str) {
+        let selected = self.selected;
+        self.selected = db;
+        self.notify(EventClass::Expired, "expired", key);
+        self.propagate(vec!["DEL", key]);
+        self.selected = selected;
+        if let Some(watched) = self.watched.get_mut(&(db, key.to_string())) {
+            watched.reclaimed = Some(watched.version);
+        }
+    }
+
+    // Active expiry, like Redis' activeExpireCycle: the databases take
+    // turns, each sampled again as long as many of its keys had expired,
+    // until `budget` is spent. The next cycle carries on with the database
+    // after the last one looked at.
+    pub fn active_expire_cycle(&mut self, budget: Duration) {
+        let start = Instant::now();
+        let selected = self.selected;
+        for _ in 0..self.databases.len() {
+            let db = self.expire_cursor;
+            self.expire_cursor = (db + 1) % self.databases.len();
+            self.selected = db;
+            loop {
+                let (sampled, expired) =
+                    self.databases[db].expire_sample(ACTIVE_EXPIRE_KEYS_PER_LOOP);
+                for key in &expired {
+                    self.expired(db, key);
+                }
+                if expired.len() * 100 <= sampled * ACTIVE_EXPIRE_ACCEPTABLE_STALE
+                    || start.elapsed() >= budget
+                {
+                    break;
+                }
+            }
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        self.selected = selected;
+    }
+
+    // Replicates `command` instead of the command being executed, in the
+    // selected database.
+    pub fn propagate<T: Into<Argument>>(&mut self, command: Vec<T>) {
+        if self.replicated != Some(self.selected) {
+            self.propagated
+                .push(vec!["SELECT".into(), self.selected.to_string().into()]);
+            self.replicated = Some(self.selected);
+        }
+        self.propagated
+            .push(command.into_iter().map(Into::into).collect());
+    }
+
+    // A replica attached: its stream has to start with SELECT.
+    pub fn forget_replicated_database(&mut self) {
+        self.replicated = None;
+    }
+
+    pub fn take_propagated(&mut self) -> Vec<Vec<Argument>> {
+        std::mem::take(&mut self.propagated)
+    }
+
+    // Every change to a key is reported here, which makes it the place to
+    // invalidate WATCH and count changes for the save points too. A key miss
+    // changes nothing, and a new key comes with the event of the write that
+    // created it.
+    pub fn notify(&mut self, class: EventClass, event: &'static str, key: &str) {
+        // Expired keys the command looked up went first.
+        self.reclaim_expired();
+        if class != EventClass::KeyMiss {
+            self.touch(self.selected, key);
+        }
+        if !matches!(class, EventClass::KeyMiss | EventClass::New) {
+            self.dirty += 1;
+        }
+        self.notifications.push(KeyspaceEvent {
+            db: self.selected,
+            class,
+            event,
+            key: key.to_string(),
+        });
+    }
+
+    pub fn take_notifications(&mut self) -> Vec<KeyspaceEvent> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    pub fn take_modified(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.modified)
+    }
+
+    // Starts watching `key` in `db`, returns its current version and
+    // whether it has expired without being deleted yet.
+    pub fn watch(&mut self, db: usize, key: &str) -> (u64, bool) {
+        let expired = self.databases[db].has_expired(key);
+        let watched = self
+            .watched
+            .entry((db, key.to_string()))
+            .or_insert(WatchedKey {
+                watchers: 0,
+                version: 0,
+                reclaimed: None,
+            });
+        watched.watchers += 1;
+        (watched.version, expired)
+    }
+
+    pub fn unwatch(&mut self, db: usize, key: &str) {
+        let watched_key = (db, key.to_string());
+        if let Some(watched) = self.watched.get_mut(&watched_key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                self.watched.remove(&watched_key);
+            }
+        }
+    }
+
+    // Whether `key` in `db` changed since it was watched at `version`, like
+    // Redis deleting a key that was `expired` already then doesn't count.
+    pub fn changed_since(&self, db: usize, key: &str, version: u64, expired: bool) -> bool {
+        let Some(watched) = self.watched.get(&(db, key.to_string())) else {
+            return true;
+        };
+        let reclaimed_only =
+            expired && watched.version == version + 1 && watched.reclaimed == Some(watched.version);
+        watched.version != version && !reclaimed_only
+    }
+
+    // Marks `key` as modified for whoever is watching or caching it.
+    fn touch(&mut self, db: usize, key: &str) {
+        self.modified.push(key.to_string());
+        if let Some(watched) = self.watched.get_mut(&(db, key.to_string())) {
+            watched.version += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::string::RedisString;
+    use super::*;
+
+    fn expiring_key(keyspace: &mut Keyspace, key: &str) {
+        let value = RedisValue::String(RedisString::Integer(1));
+        keyspace.insert(key.to_string(), value, Some(Duration::from_millis(1)));
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    #[test]
+    fn reclaiming_a_key_watched_expired_is_no_change() {
+        let mut keyspace = Keyspace::new(1);
+        expiring_key(&mut keyspace, "f");
+        let (version, expired) = keyspace.watch(0, "f");
+        assert!(expired);
+        keyspace.expire_if_needed(0, "f");
+        assert!(!keyspace.changed_since(0, "f", version, expired));
+
+        // Writing it afterwards is one.
+        keyspace.insert(
+            "f".to_string(),
+            RedisValue::String(RedisString::Integer(2)),
+            None,
+        );
+        assert!(keyspace.changed_since(0, "f", version, expired));
+    }
+
+    #[test]
+    fn a_watched_key_expiring_is_a_change() {
+        let mut keyspace = Keyspace::new(1);
+        let value = RedisValue::String(RedisString::Integer(1));
+        keyspace.insert("f".to_string(), value, Some(Duration::from_millis(1)));
+        let (version, expired) = keyspace.watch(0, "f");
+        assert!(!expired);
+        std::thread::sleep(Duration::from_millis(5));
+        keyspace.expire_if_needed(0, "f");
+        assert!(keyspace.changed_since(0, "f", version, expired));
+    }
+
+    #[test]
+    fn expirations_fire_events_and_replicate_as_del() {
+        let mut keyspace = Keyspace::new(1);
+        expiring_key(&mut keyspace, "f");
+        assert!(keyspace.get("f").is_none());
+        keyspace.reclaim_expired();
+        let events: Vec<&str> = keyspace
+            .take_notifications()
+            .iter()
+            .map(|event| event.event)
+            .collect();
+        assert_eq!(events, ["new", "expired"]);
+        let propagated = keyspace.take_propagated();
+        assert_eq!(propagated.last().unwrap(), &["DEL", "f"]);
+    }
+}
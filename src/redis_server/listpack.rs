@@ -0,0 +1,174 @@
+// Encoder and decoder for Redis' listpack format, which RDB files use for
+// stream nodes and small sets and sorted sets. Layout:
+// <total-bytes u32> <num-elements u16> <entry>* <0xFF>, each entry being
+// <encoding-type><data><backlen>.
+
+const HEADER_SIZE: usize = 6;
+const EOF: u8 = 0xFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListpackEntry {
+    Integer(i64),
+    String(Vec<u8>),
+}
+
+impl ListpackEntry {
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            ListpackEntry::Integer(value) => value.to_string().into_bytes(),
+            ListpackEntry::String(bytes) => bytes,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            ListpackEntry::Integer(value) => Some(*value),
+            ListpackEntry::String(bytes) => std::str::from_utf8(bytes).ok()?.parse().ok(),
+        }
+    }
+}
+
+// Bytes taken by the backlen of an entry whose encoding and data span `len`.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+fn push_backlen(out: &mut Vec<u8>, len: usize) {
+    let size = backlen_size(len);
+    // Stored so that it can be read right to left: the last byte holds the
+    // lowest 7 bits and every byte but the first one has the high bit set.
+    for i in (0..size).rev() {
+        let mut byte = ((len >> (7 * i)) & 127) as u8;
+        if i != size - 1 {
+            byte |= 128;
+        }
+        out.push(byte);
+    }
+}
+
+fn encode_integer(value: i64) -> Vec<u8> {
+    if (0..=127).contains(&value) {
+        vec![value as u8]
+    } else if (-4096..=4095).contains(&value) {
+        let value = (value as u64) & 0x1FFF;
+        vec![0xC0 | (value >> 8) as u8, value as u8]
+    } else if i16::try_from(value).is_ok() {
+        let mut encoded = vec![0xF1];
+        encoded.extend_from_slice(&(value as i16).to_le_bytes());
+        encoded
+    } else if (-(1 << 23)..(1 << 23)).contains(&value) {
+        let mut encoded = vec![0xF2];
+        encoded.extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+        encoded
+    } else if i32::try_from(value).is_ok() {
+        let mut encoded = vec![0xF3];
+        encoded.extend_from_slice(&(value as i32).to_le_bytes());
+        encoded
+    } else {
+        let mut encoded = vec![0xF4];
+        encoded.extend_from_slice(&value.to_le_bytes());
+        encoded
+    }
+}
+
+fn encode_string(bytes: &[u8]) -> Vec<u8> {
+    let len = bytes.len();
+    let mut encoded = if len < 64 {
+        vec![0x80 | len as u8]
+    } else if len < 4096 {
+        vec![0xE0 | (len >> 8) as u8, len as u8]
+    } else {
+        let mut header = vec![0xF0];
+        header.extend_from_slice(&(len as u32).to_le_bytes());
+        header
+    };
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+pub fn encode(entries: &[ListpackEntry]) -> Vec<u8> {
+    let mut out = vec![0; HEADER_SIZE];
+    for entry in entries {
+        let encoded = match entry {
+            ListpackEntry::Integer(value) => encode_integer(*value),
+            ListpackEntry::String(bytes) => encode_string(bytes),
+        };
+        out.extend_from_slice(&encoded);
+        push_backlen(&mut out, encoded.len());
+    }
+    out.push(EOF);
+
+    let total_bytes = out.len() as u32;
+    // Like Redis, counts that do not fit are stored as "unknown".
+    let num_elements = u16::try_from(entries.len()).unwrap_or(u16::MAX);
+    out[..4].copy_from_slice(&total_bytes.to_le_bytes());
+    out[4..6].copy_from_slice(&num_elements.to_le_bytes());
+    out
+}
+
+// Sign-extends the lowest `bits` bits of `value`.
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<ListpackEntry>, String> {
+    let truncated = || "truncated listpack".to_string();
+    if bytes.len() < HEADER_SIZE + 1 {
+        return Err(truncated());
+    }
+    let mut entries = Vec::new();
+    let mut pos = HEADER_SIZE;
+    loop {
+        let encoding = *bytes.get(pos).ok_or_else(truncated)?;
+        if encoding == EOF {
+            return Ok(entries);
+        }
+        let read = |from: usize, len: usize| bytes.get(from..from + len).ok_or_else(truncated);
+        let (entry, len) = match encoding {
+            0x00..=0x7F => (ListpackEntry::Integer(encoding as i64), 1),
+            0x80..=0xBF => {
+                let len = (encoding & 0x3F) as usize;
+                (ListpackEntry::String(read(pos + 1, len)?.to_vec()), 1 + len)
+            }
+            0xC0..=0xDF => {
+                let low = *read(pos + 1, 1)?.first().ok_or_else(truncated)?;
+                let value = (((encoding & 0x1F) as u64) << 8) | low as u64;
+                (ListpackEntry::Integer(sign_extend(value, 13)), 2)
+            }
+            0xE0..=0xEF => {
+                let low = *read(pos + 1, 1)?.first().ok_or_else(truncated)?;
+                let len = (((encoding & 0x0F) as usize) << 8) | low as usize;
+                (ListpackEntry::String(read(pos + 2, len)?.to_vec()), 2 + len)
+            }
+            0xF0 => {
+                let header = read(pos + 1, 4)?;
+                let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+                (ListpackEntry::String(read(pos + 5, len)?.to_vec()), 5 + len)
+            }
+            0xF1..=0xF4 => {
+                let width = match encoding {
+                    0xF1 => 2,
+                    0xF2 => 3,
+                    0xF3 => 4,
+                    _ => 8,
+                };
+                let mut value: u64 = 0;
+                for (i, byte) in read(pos + 1, width)?.iter().enumerate() {
+                    value |= (*byte as u64) << (8 * i);
+                }
+                let value = sign_extend(value, 8 * width as u32);
+                (ListpackEntry::Integer(value), 1 + width)
+            }
+            _ => return Err(format!("invalid listpack encoding {:#04x}", encoding)),
+        };
+        entries.push(entry);
+        pos += len + backlen_size(len);
+    }
+}
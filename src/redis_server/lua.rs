@@ -0,0 +1,269 @@
+// A Lua 5.1 interpreter for scripts: a parser resolving variables ahead of
+// time and a tree walking interpreter, with the base, string, table, math,
+// bit, cjson, cmsgpack and struct libraries. There are no coroutines: the
+// interpreter runs on the Rust stack, which can't be suspended.
+mod ast;
+mod cjson;
+mod cmsgpack;
+mod interpreter;
+mod lexer;
+mod parser;
+mod pattern;
+mod stdlib;
+mod strings;
+mod structs;
+mod value;
+
+pub use self::ast::FunctionBody;
+pub use self::interpreter::{first, Host, Lua};
+pub use self::stdlib::{argument, check_any, check_string, register};
+pub use self::value::{format_number, TableRef, Value};
+
+pub enum LuaError {
+    // A value raised by `error` or a runtime error, with the line of the
+    // script it was raised at.
+    Raised(Value, u32),
+    // An error from a builtin, positioned where the script called it.
+    Builtin(String),
+    // The host stopped the script, pcall doesn't catch it.
+    Interrupted(String),
+}
+
+// Compiles a chunk, errors read like "user_script:1: ... near 'x'".
+pub fn compile(source: &[u8], chunk_name: &str) -> Result<std::sync::Arc<FunctionBody>, String> {
+    parser::parse(source, chunk_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::resp::RespValue;
+    use super::*;
+
+    // Never called, and stops scripts once they ran `budget` checks.
+    struct TestHost {
+        budget: usize,
+    }
+
+    impl Host for TestHost {
+        fn call(&mut self, _: &[Vec<u8>]) -> RespValue {
+            unreachable!()
+        }
+
+        fn interrupted(&mut self) -> Option<String> {
+            self.budget = self.budget.checked_sub(1)?;
+            (self.budget == 0).then(|| "stopped".to_string())
+        }
+    }
+
+    // What the script returns, each value shown like `tostring`.
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let body = compile(source.as_bytes(), "user_script")?;
+        let mut host = TestHost { budget: 3 };
+        let mut lua = Lua::new(&mut host);
+        let function = lua.load(body);
+        match lua.call(&function, Vec::new()) {
+            Ok(values) => Ok(values.iter().map(Value::to_string).collect()),
+            Err(LuaError::Raised(value, _)) => Err(value.to_string()),
+            Err(LuaError::Builtin(message) | LuaError::Interrupted(message)) => Err(message),
+        }
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_their_line() {
+        assert_eq!(
+            run("local x = 1\nx = = 2").unwrap_err(),
+            "user_script:2: unexpected symbol near '='"
+        );
+        assert_eq!(
+            run("return 'abc").unwrap_err(),
+            "user_script:1: unfinished string near '<eof>'"
+        );
+        assert_eq!(
+            run("for i = 1 do end").unwrap_err(),
+            "user_script:1: ',' expected near 'do'"
+        );
+    }
+
+    #[test]
+    fn follows_lua_precedence_and_coercions() {
+        assert_eq!(
+            run("return 2^3^2, -2^2, 1 .. 2, 7 % -3, '10' + 1, not nil == true, 1 < 2 and 'y' or 'n'")
+                .unwrap(),
+            ["512", "-4", "12", "-2", "11", "true", "y"]
+        );
+        assert_eq!(
+            run("return 0x10, 1e2, 10 / 4, 2^53").unwrap(),
+            ["16", "100", "2.5", "9.007199254741e+15"]
+        );
+    }
+
+    #[test]
+    fn closures_capture_each_iteration() {
+        assert_eq!(
+            run("local fs = {}
+                 for i = 1, 3 do fs[i] = function() i = i + 10 return i end end
+                 return fs[1](), fs[1](), fs[3]()")
+            .unwrap(),
+            ["11", "21", "13"]
+        );
+        assert_eq!(
+            run("local function counter()
+                   local n = 0
+                   return function() n = n + 1 return n end
+                 end
+                 local a, b = counter(), counter()
+                 a() a()
+                 return a(), b()")
+            .unwrap(),
+            ["3", "1"]
+        );
+    }
+
+    #[test]
+    fn passes_varargs_and_multiple_results() {
+        assert_eq!(
+            run("local function f(...) return select('#', ...), ... end
+                 return f(1, nil, 3)")
+            .unwrap(),
+            ["3", "1", "nil", "3"]
+        );
+        assert_eq!(
+            run("local function two() return 1, 2 end
+                 local t = {two(), two()}
+                 return #t, (two())")
+            .unwrap(),
+            ["3", "1"]
+        );
+    }
+
+    #[test]
+    fn errors_carry_their_position() {
+        assert_eq!(
+            run("local ok, e = pcall(function()\n error('boom')\n end) return ok, e").unwrap(),
+            ["false", "user_script:2: boom"]
+        );
+        assert_eq!(
+            run("local ok, e = pcall(error, {code = 7}) return e.code").unwrap(),
+            ["7"]
+        );
+        assert_eq!(
+            run("local t\nreturn t.x").unwrap_err(),
+            "user_script:2: attempt to index local 't' (a nil value)"
+        );
+        assert_eq!(
+            run("return {} < {}").unwrap_err(),
+            "user_script:1: attempt to compare two table values"
+        );
+    }
+
+    #[test]
+    fn uses_metamethods() {
+        assert_eq!(
+            run("local mt = {}
+                 mt.__index = function(t, k) return k .. '!' end
+                 mt.__add = function(a, b) return 42 end
+                 mt.__call = function(self, x) return x * 2 end
+                 local t = setmetatable({}, mt)
+                 return t.hi, t + 1, t(21), rawget(t, 'hi')")
+            .unwrap(),
+            ["hi!", "42", "42", "nil"]
+        );
+    }
+
+    #[test]
+    fn runs_the_string_library() {
+        assert_eq!(
+            run("return string.format('%5.2f|%d|%q', 3.14159, 42, 'a\\n'),
+                 ('hello world'):gsub('o', '0'),
+                 ('x'):rep(3),
+                 string.match('key:123', '(%a+):(%d+)')")
+            .unwrap(),
+            [" 3.14|42|\"a\\\n\"", "hell0 w0rld", "xxx", "key", "123"]
+        );
+    }
+
+    #[test]
+    fn keeps_json_nulls() {
+        assert_eq!(
+            run("local t = cjson.decode('{\"a\":null,\"b\":[1,null,3]}')
+                 return cjson.encode(t.a == cjson.null and t), #t.b, type(cjson.null),
+                   cjson.encode({cjson.null, false})")
+            .unwrap(),
+            [
+                "{\"a\":null,\"b\":[1,null,3]}",
+                "3",
+                "userdata",
+                "[null,false]"
+            ]
+        );
+    }
+
+    #[test]
+    fn packs_structs() {
+        assert_eq!(
+            run("local s = struct.pack('>I2<i2bs', 258, -2, 7, 'hi')
+                 return s == '\\1\\2\\254\\255\\7hi\\0', struct.unpack('>I2<i2bs', s)")
+            .unwrap(),
+            ["true", "258", "-2", "7", "hi", "9"]
+        );
+        assert_eq!(
+            run("return struct.size('!4bi'), struct.unpack('bc0d', struct.pack('bc0d', 3, 'abc', 1.5))")
+                .unwrap(),
+            ["8", "abc", "1.5", "13"]
+        );
+        assert_eq!(
+            run("return struct.pack('z')").unwrap_err(),
+            "user_script:1: bad argument #1 to 'pack' (invalid format option 'z')"
+        );
+        assert_eq!(
+            run("return struct.unpack('i4', 'abc')").unwrap_err(),
+            "user_script:1: bad argument #2 to 'unpack' (data string too short)"
+        );
+    }
+
+    #[test]
+    fn packs_msgpack() {
+        assert_eq!(
+            run("return cmsgpack.pack(1, -1, 'a', true, nil, 300, 0.5) ==
+                   '\\1\\255\\161a\\195\\192\\205\\1\\44\\202\\63\\0\\0\\0'")
+            .unwrap(),
+            ["true"]
+        );
+        assert_eq!(
+            run(
+                "local t = cmsgpack.unpack(cmsgpack.pack({10, {x = 'y'}, {}}))
+                 return #t, t[1], t[2].x, #t[3]"
+            )
+            .unwrap(),
+            ["3", "10", "y", "0"]
+        );
+        assert_eq!(
+            run("local s = cmsgpack.pack(1, 2)
+                 local o1, a = cmsgpack.unpack_one(s)
+                 local o2, b = cmsgpack.unpack_one(s, o1)
+                 return o1, a, o2, b")
+            .unwrap(),
+            ["1", "1", "-1", "2"]
+        );
+        assert_eq!(
+            run("return cmsgpack.unpack('\\146\\1')").unwrap_err(),
+            "user_script:1: Missing bytes in input."
+        );
+    }
+
+    #[test]
+    fn refuses_coroutines() {
+        assert_eq!(
+            run("return coroutine.wrap(function() end)").unwrap_err(),
+            "user_script:1: coroutines are not supported"
+        );
+    }
+
+    #[test]
+    fn the_host_can_stop_a_script() {
+        assert_eq!(
+            run("pcall(function() while true do end end) return 1").unwrap_err(),
+            "stopped"
+        );
+    }
+}
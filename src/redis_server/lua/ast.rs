@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+// Compiled functions. Variables are resolved by the parser: locals live in
+// numbered slots of their function's frame, upvalues are captured when the
+// closure is created, anything else is a global.
+#[derive(Debug)]
+pub struct FunctionBody {
+    pub source: Arc<str>,
+    pub parameters: usize,
+    pub is_vararg: bool,
+    pub block: Block,
+    pub slot_count: usize,
+    pub captures: Vec<Capture>,
+}
+
+// Where a closure finds each of its upvalues when it is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    // A local of the enclosing function.
+    Local(usize),
+    // An upvalue of the enclosing function.
+    Upvalue(usize),
+}
+
+pub type Block = Vec<Stat>;
+
+#[derive(Debug)]
+pub enum Stat {
+    Local {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+    },
+    LocalFunction {
+        slot: usize,
+        body: Arc<FunctionBody>,
+    },
+    Assign {
+        targets: Vec<Target>,
+        values: Vec<Expr>,
+    },
+    Call(Expr),
+    Do(Block),
+    While {
+        condition: Expr,
+        block: Block,
+    },
+    Repeat {
+        block: Block,
+        condition: Expr,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    NumericFor {
+        slot: usize,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        block: Block,
+        line: u32,
+    },
+    GenericFor {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+        block: Block,
+        line: u32,
+    },
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+pub enum Target {
+    Local(usize),
+    Upvalue(usize),
+    Global(Box<[u8]>, u32),
+    Index { table: Expr, key: Expr, line: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Vararg,
+    Number(f64),
+    String(Box<[u8]>),
+    Function(Arc<FunctionBody>),
+    // Names are kept for error messages.
+    Local(usize, Box<str>),
+    Upvalue(usize, Box<str>),
+    Global(Box<[u8]>, u32),
+    Index(Box<Expr>, Box<Expr>, u32),
+    Call(Box<Expr>, Vec<Expr>, u32),
+    Method(Box<Expr>, Box<[u8]>, Vec<Expr>, u32),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>, u32),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>, u32),
+    Table(Vec<Field>, u32),
+    // Parentheses keep only the first value of a call or `...`.
+    Paren(Box<Expr>),
+}
+
+impl Expr {
+    // Calls and `...` can produce any number of values.
+    pub fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::Vararg)
+    }
+}
+
+#[derive(Debug)]
+pub enum Field {
+    Positional(Expr),
+    Named(Expr, Expr),
+}
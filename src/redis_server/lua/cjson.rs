@@ -0,0 +1,386 @@
+use super::{
+    interpreter::Lua,
+    stdlib::{check_any, check_string, register},
+    value::{format_number, TableRef, Value},
+    LuaError,
+};
+
+// Like Lua CJSON's defaults.
+const MAX_DEPTH: usize = 1000;
+const SPARSE_RATIO: usize = 2;
+const SPARSE_SAFE: usize = 10;
+
+pub fn open(lua: &mut Lua) {
+    let cjson = TableRef::new();
+    register(&cjson, &[("decode", decode), ("encode", encode)]);
+    // JSON null decodes to it rather than nil, so that it stays in tables.
+    cjson.set_field("null", Value::Null);
+    lua.globals.set_field("cjson", Value::Table(cjson));
+}
+
+fn encode(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let value = check_any(&args, 1, "encode")?;
+    let mut json = Vec::new();
+    encode_value(&value, 0, &mut json).map_err(LuaError::Builtin)?;
+    Ok(vec![Value::string(json)])
+}
+
+fn unsupported(value: &Value, reason: &str) -> String {
+    format!("Cannot serialise {}: {}", value.type_name(), reason)
+}
+
+fn encode_string(string: &[u8], json: &mut Vec<u8>) {
+    json.push(b'"');
+    for &byte in string {
+        match byte {
+            b'"' => json.extend_from_slice(b"\\\""),
+            b'\\' => json.extend_from_slice(b"\\\\"),
+            b'/' => json.extend_from_slice(b"\\/"),
+            b'\x08' => json.extend_from_slice(b"\\b"),
+            b'\t' => json.extend_from_slice(b"\\t"),
+            b'\n' => json.extend_from_slice(b"\\n"),
+            b'\x0C' => json.extend_from_slice(b"\\f"),
+            b'\r' => json.extend_from_slice(b"\\r"),
+            0..=0x1F => json.extend_from_slice(format!("\\u{:04x}", byte).as_bytes()),
+            _ => json.push(byte),
+        }
+    }
+    json.push(b'"');
+}
+
+fn encode_number(number: f64, json: &mut Vec<u8>) -> Result<(), String> {
+    if !number.is_finite() {
+        return Err(unsupported(
+            &Value::Number(number),
+            "must not be NaN or Inf",
+        ));
+    }
+    json.extend_from_slice(format_number(number).as_bytes());
+    Ok(())
+}
+
+// The length of the array a table stands for, or None for an object.
+fn array_length(table: &TableRef) -> Result<Option<usize>, String> {
+    let mut key = Value::Nil;
+    let (mut max, mut items) = (0, 0);
+    while let Some((next, _)) = table.0.borrow().next(&key)? {
+        match next {
+            Value::Number(number) if number >= 1.0 && number.fract() == 0.0 => {
+                max = max.max(number as usize);
+                items += 1;
+            }
+            _ => return Ok(None),
+        }
+        key = next;
+    }
+    if max > items * SPARSE_RATIO && max > SPARSE_SAFE {
+        return Err(unsupported(
+            &Value::Table(table.clone()),
+            "excessively sparse array",
+        ));
+    }
+    Ok((max > 0).then_some(max))
+}
+
+fn encode_value(value: &Value, depth: usize, json: &mut Vec<u8>) -> Result<(), String> {
+    match value {
+        Value::Nil | Value::Null => json.extend_from_slice(b"null"),
+        Value::Boolean(boolean) => {
+            json.extend_from_slice(if *boolean { b"true" } else { b"false" })
+        }
+        Value::Number(number) => encode_number(*number, json)?,
+        Value::String(string) => encode_string(string, json),
+        Value::Table(table) => {
+            if depth >= MAX_DEPTH {
+                return Err(format!(
+                    "Cannot serialise, excessive nesting ({})",
+                    depth + 1
+                ));
+            }
+            match array_length(table)? {
+                Some(length) => {
+                    json.push(b'[');
+                    for index in 1..=length {
+                        if index > 1 {
+                            json.push(b',');
+                        }
+                        encode_value(&table.get(&Value::Number(index as f64)), depth + 1, json)?;
+                    }
+                    json.push(b']');
+                }
+                None => {
+                    json.push(b'{');
+                    let mut key = Value::Nil;
+                    let mut first = true;
+                    while let Some((next, item)) = table.0.borrow().next(&key)? {
+                        if !first {
+                            json.push(b',');
+                        }
+                        first = false;
+                        match &next {
+                            Value::String(string) => encode_string(string, json),
+                            Value::Number(number) => {
+                                json.push(b'"');
+                                encode_number(*number, json)?;
+                                json.push(b'"');
+                            }
+                            other => {
+                                return Err(unsupported(
+                                    other,
+                                    "table key must be a number or string",
+                                ))
+                            }
+                        }
+                        json.push(b':');
+                        encode_value(&item, depth + 1, json)?;
+                        key = next;
+                    }
+                    json.push(b'}');
+                }
+            }
+        }
+        Value::Function(_) => return Err(unsupported(value, "type not supported")),
+    }
+    Ok(())
+}
+
+fn decode(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let json = check_string(&args, 1, "decode")?;
+    let mut decoder = Decoder {
+        json: &json,
+        position: 0,
+        depth: 0,
+    };
+    let value = decoder.value().map_err(LuaError::Builtin)?;
+    decoder.skip_whitespace();
+    if decoder.position < json.len() {
+        return Err(LuaError::Builtin(decoder.unexpected("the end")));
+    }
+    Ok(vec![value])
+}
+
+struct Decoder<'a> {
+    json: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .json
+            .get(self.position)
+            .is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.position += 1;
+        }
+    }
+
+    // Names the token found where something else was expected.
+    fn unexpected(&self, expected: &str) -> String {
+        let found = match self.json.get(self.position) {
+            None => "T_END",
+            Some(b'{') => "T_OBJ_BEGIN",
+            Some(b'}') => "T_OBJ_END",
+            Some(b'[') => "T_ARR_BEGIN",
+            Some(b']') => "T_ARR_END",
+            Some(b'"') => "T_STRING",
+            Some(b'-' | b'0'..=b'9') => "T_NUMBER",
+            Some(b't' | b'f') => "T_BOOLEAN",
+            Some(b'n') => "T_NULL",
+            Some(b':') => "T_COLON",
+            Some(b',') => "T_COMMA",
+            Some(_) => "invalid token",
+        };
+        format!(
+            "Expected {} but found {} at character {}",
+            expected,
+            found,
+            self.position + 1
+        )
+    }
+
+    fn literal(&mut self, literal: &[u8], value: Value) -> Result<Value, String> {
+        if self.json[self.position..].starts_with(literal) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(format!(
+                "Expected value but found invalid token at character {}",
+                self.position + 1
+            ))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.json.get(self.position) {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Value::string(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal(b"true", Value::Boolean(true)),
+            Some(b'f') => self.literal(b"false", Value::Boolean(false)),
+            Some(b'n') => self.literal(b"null", Value::Null),
+            _ => Err(self.unexpected("value")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!(
+                "Found too many nested data structures ({}) at character {}",
+                self.depth,
+                self.position + 1
+            ));
+        }
+        self.position += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        let table = TableRef::new();
+        self.skip_whitespace();
+        if self.json.get(self.position) == Some(&b'}') {
+            self.position += 1;
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.json.get(self.position) != Some(&b'"') {
+                return Err(self.unexpected("object key string"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.json.get(self.position) != Some(&b':') {
+                return Err(self.unexpected("colon"));
+            }
+            self.position += 1;
+            let value = self.value()?;
+            table.set(Value::string(key), value)?;
+            self.skip_whitespace();
+            match self.json.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Table(table));
+                }
+                _ => return Err(self.unexpected("comma or object end")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        let table = TableRef::new();
+        self.skip_whitespace();
+        if self.json.get(self.position) == Some(&b']') {
+            self.position += 1;
+            return Ok(Value::Table(table));
+        }
+        let mut index = 1;
+        loop {
+            let value = self.value()?;
+            table.set(Value::Number(index as f64), value)?;
+            index += 1;
+            self.skip_whitespace();
+            match self.json.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Table(table));
+                }
+                _ => return Err(self.unexpected("comma or array end")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.position;
+        while self
+            .json
+            .get(self.position)
+            .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.json[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| {
+                format!(
+                    "Expected value but found invalid number at character {}",
+                    start + 1
+                )
+            })
+    }
+
+    fn hex_escape(&mut self) -> Option<u32> {
+        let digits = self.json.get(self.position..self.position + 4)?;
+        let code = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+        self.position += 4;
+        Some(code)
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        let start = self.position;
+        self.position += 1;
+        let mut string = Vec::new();
+        let invalid = |reason: &str| {
+            format!(
+                "Expected value but found invalid {} at character {}",
+                reason,
+                start + 1
+            )
+        };
+        loop {
+            let Some(&byte) = self.json.get(self.position) else {
+                return Err(invalid("token"));
+            };
+            self.position += 1;
+            match byte {
+                b'"' => return Ok(string),
+                b'\\' => {
+                    let Some(&escaped) = self.json.get(self.position) else {
+                        return Err(invalid("token"));
+                    };
+                    self.position += 1;
+                    match escaped {
+                        b'"' | b'\\' | b'/' => string.push(escaped),
+                        b'b' => string.push(0x08),
+                        b'f' => string.push(0x0C),
+                        b'n' => string.push(b'\n'),
+                        b'r' => string.push(b'\r'),
+                        b't' => string.push(b'\t'),
+                        b'u' => {
+                            let mut code = self
+                                .hex_escape()
+                                .ok_or_else(|| invalid("unicode escape code"))?;
+                            // Surrogate pairs make a single code point.
+                            if (0xD800..0xDC00).contains(&code) {
+                                if !self.json[self.position..].starts_with(b"\\u") {
+                                    return Err(invalid("unicode escape code"));
+                                }
+                                self.position += 2;
+                                let low = self
+                                    .hex_escape()
+                                    .filter(|low| (0xDC00..0xE000).contains(low))
+                                    .ok_or_else(|| invalid("unicode escape code"))?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            let character = char::from_u32(code)
+                                .ok_or_else(|| invalid("unicode escape code"))?;
+                            let mut buffer = [0; 4];
+                            string.extend_from_slice(character.encode_utf8(&mut buffer).as_bytes());
+                        }
+                        _ => return Err(invalid("escape code")),
+                    }
+                }
+                _ => string.push(byte),
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use super::{
+    interpreter::Lua,
+    stdlib::{argument_error, check_integer, check_string, optional_integer, register},
+    value::{TableRef, Value},
+    LuaError,
+};
+
+// The cmsgpack library, encoding like lua_cmsgpack: tables nested deeper
+// than this are packed as nil, which stops cycles.
+const MAX_NESTING: usize = 16;
+// Unpacking recurses for each nested array or map.
+const MAX_DEPTH: usize = 1000;
+
+pub fn open(lua: &mut Lua) {
+    let cmsgpack = TableRef::new();
+    register(
+        &cmsgpack,
+        &[
+            ("pack", pack),
+            ("unpack", unpack),
+            ("unpack_one", unpack_one),
+            ("unpack_limit", unpack_limit),
+        ],
+    );
+    lua.globals.set_field("cmsgpack", Value::Table(cmsgpack));
+}
+
+// Each argument packed one after the other.
+fn pack(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.is_empty() {
+        return Err(argument_error(0, "pack", "MessagePack pack needs input."));
+    }
+    let mut packed = Vec::new();
+    for value in &args {
+        encode(value, 0, &mut packed)?;
+    }
+    Ok(vec![Value::string(packed)])
+}
+
+// The header of an array or a map, `wide` the 16 bit length one.
+fn encode_header(packed: &mut Vec<u8>, length: usize, fixed: u8, wide: u8) {
+    if length <= 15 {
+        packed.push(fixed | length as u8);
+    } else if length <= 0xFFFF {
+        packed.push(wide);
+        packed.extend_from_slice(&(length as u16).to_be_bytes());
+    } else {
+        packed.push(wide + 1);
+        packed.extend_from_slice(&(length as u32).to_be_bytes());
+    }
+}
+
+fn encode_integer(number: i64, packed: &mut Vec<u8>) {
+    match number {
+        0..=127 => packed.push(number as u8),
+        128..=0xFF => packed.extend_from_slice(&[0xCC, number as u8]),
+        0x100..=0xFFFF => {
+            packed.push(0xCD);
+            packed.extend_from_slice(&(number as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            packed.push(0xCE);
+            packed.extend_from_slice(&(number as u32).to_be_bytes());
+        }
+        0x1_0000_0000.. => {
+            packed.push(0xCF);
+            packed.extend_from_slice(&(number as u64).to_be_bytes());
+        }
+        -32..=-1 => packed.push(number as u8),
+        -128..=-33 => packed.extend_from_slice(&[0xD0, number as u8]),
+        -32768..=-129 => {
+            packed.push(0xD1);
+            packed.extend_from_slice(&(number as i16).to_be_bytes());
+        }
+        -2_147_483_648..=-32769 => {
+            packed.push(0xD2);
+            packed.extend_from_slice(&(number as i32).to_be_bytes());
+        }
+        _ => {
+            packed.push(0xD3);
+            packed.extend_from_slice(&number.to_be_bytes());
+        }
+    }
+}
+
+// Numbers are integers when they are one, else floats when that loses
+// nothing.
+fn encode_number(number: f64, packed: &mut Vec<u8>) {
+    if number.is_finite() && number as i64 as f64 == number {
+        encode_integer(number as i64, packed);
+    } else if number as f32 as f64 == number {
+        packed.push(0xCA);
+        packed.extend_from_slice(&(number as f32).to_be_bytes());
+    } else {
+        packed.push(0xCB);
+        packed.extend_from_slice(&number.to_be_bytes());
+    }
+}
+
+// Tables with keys 1..n, the empty one included, are arrays.
+fn is_array(table: &TableRef) -> Result<bool, LuaError> {
+    let (mut count, mut max) = (0, 0.0f64);
+    let mut key = Value::Nil;
+    while let Some((next, _)) = table.0.borrow().next(&key).map_err(LuaError::Builtin)? {
+        match next {
+            Value::Number(number) if number > 0.0 && number.fract() == 0.0 => {
+                max = max.max(number);
+                count += 1;
+            }
+            _ => return Ok(false),
+        }
+        key = next;
+    }
+    Ok(max == count as f64)
+}
+
+fn encode(value: &Value, level: usize, packed: &mut Vec<u8>) -> Result<(), LuaError> {
+    match value {
+        Value::String(string) => {
+            let length = string.len();
+            if length < 32 {
+                packed.push(0xA0 | length as u8);
+            } else if length <= 0xFF {
+                packed.extend_from_slice(&[0xD9, length as u8]);
+            } else if length <= 0xFFFF {
+                packed.push(0xDA);
+                packed.extend_from_slice(&(length as u16).to_be_bytes());
+            } else {
+                packed.push(0xDB);
+                packed.extend_from_slice(&(length as u32).to_be_bytes());
+            }
+            packed.extend_from_slice(string);
+        }
+        Value::Boolean(boolean) => packed.push(if *boolean { 0xC3 } else { 0xC2 }),
+        Value::Number(number) => encode_number(*number, packed),
+        Value::Table(table) if level < MAX_NESTING => {
+            if is_array(table)? {
+                let length = table.len();
+                encode_header(packed, length, 0x90, 0xDC);
+                for index in 1..=length {
+                    encode(&table.get(&Value::Number(index as f64)), level + 1, packed)?;
+                }
+            } else {
+                let mut entries = Vec::new();
+                let mut key = Value::Nil;
+                while let Some((next, item)) =
+                    table.0.borrow().next(&key).map_err(LuaError::Builtin)?
+                {
+                    entries.push((next.clone(), item));
+                    key = next;
+                }
+                encode_header(packed, entries.len(), 0x80, 0xDE);
+                for (key, item) in &entries {
+                    encode(key, level + 1, packed)?;
+                    encode(item, level + 1, packed)?;
+                }
+            }
+        }
+        _ => packed.push(0xC0),
+    }
+    Ok(())
+}
+
+// Every value packed in the string.
+fn unpack(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    unpack_from(&args, 0, 0)
+}
+
+// The first value packed from the 0-based offset, after the offset of the
+// next one, -1 once there are no more.
+fn unpack_one(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let offset = optional_integer(&args, 2, "unpack_one", 0)?;
+    unpack_from(&args, 1, offset)
+}
+
+fn unpack_limit(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let limit = check_integer(&args, 2, "unpack_limit")?;
+    let offset = optional_integer(&args, 3, "unpack_limit", 0)?;
+    unpack_from(&args, limit, offset)
+}
+
+// Like lua_cmsgpack, no limit and no offset unpack everything and don't
+// return the offset.
+fn unpack_from(args: &[Value], limit: i64, offset: i64) -> Result<Vec<Value>, LuaError> {
+    let packed = check_string(args, 1, "unpack")?;
+    if offset < 0 || limit < 0 {
+        return Err(LuaError::Builtin(format!(
+            "Invalid request to unpack with offset of {} and limit of {}.",
+            offset, limit
+        )));
+    }
+    if offset as usize > packed.len() {
+        return Err(LuaError::Builtin(format!(
+            "Start offset {} greater than input length {}.",
+            offset,
+            packed.len()
+        )));
+    }
+    let everything = limit == 0 && offset == 0;
+    let mut decoder = Decoder {
+        packed: &packed,
+        position: offset as usize,
+    };
+    let mut values = Vec::new();
+    while decoder.position < packed.len() && (everything || values.len() < limit as usize) {
+        values.push(decoder.value(0)?);
+    }
+    if !everything {
+        let next = if decoder.position == packed.len() {
+            -1
+        } else {
+            decoder.position as i64
+        };
+        values.insert(0, Value::Number(next as f64));
+    }
+    Ok(values)
+}
+
+struct Decoder<'a> {
+    packed: &'a [u8],
+    position: usize,
+}
+
+fn missing_bytes() -> LuaError {
+    LuaError::Builtin("Missing bytes in input.".to_string())
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], LuaError> {
+        let bytes = self
+            .packed
+            .get(self.position..self.position + count)
+            .ok_or_else(missing_bytes)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], LuaError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn length(&mut self, bytes: usize) -> Result<usize, LuaError> {
+        Ok(match bytes {
+            1 => self.take_array::<1>()?[0] as usize,
+            2 => u16::from_be_bytes(self.take_array()?) as usize,
+            _ => u32::from_be_bytes(self.take_array()?) as usize,
+        })
+    }
+
+    fn string(&mut self, length: usize) -> Result<Value, LuaError> {
+        Ok(Value::string(self.take(length)?))
+    }
+
+    fn array(&mut self, length: usize, depth: usize) -> Result<Value, LuaError> {
+        let table = TableRef::new();
+        for index in 1..=length {
+            let value = self.value(depth + 1)?;
+            table
+                .set(Value::Number(index as f64), value)
+                .map_err(LuaError::Builtin)?;
+        }
+        Ok(Value::Table(table))
+    }
+
+    fn map(&mut self, length: usize, depth: usize) -> Result<Value, LuaError> {
+        let table = TableRef::new();
+        for _ in 0..length {
+            let key = self.value(depth + 1)?;
+            let value = self.value(depth + 1)?;
+            table.set(key, value).map_err(LuaError::Builtin)?;
+        }
+        Ok(Value::Table(table))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, LuaError> {
+        if depth > MAX_DEPTH {
+            return Err(LuaError::Builtin(
+                "Too many nested data structures in input.".to_string(),
+            ));
+        }
+        let number = |number: f64| Ok(Value::Number(number));
+        let [kind] = self.take_array::<1>()?;
+        match kind {
+            0x00..=0x7F => number(kind as f64),
+            0x80..=0x8F => self.map((kind & 0x0F) as usize, depth),
+            0x90..=0x9F => self.array((kind & 0x0F) as usize, depth),
+            0xA0..=0xBF => self.string((kind & 0x1F) as usize),
+            0xC0 => Ok(Value::Nil),
+            0xC2 => Ok(Value::Boolean(false)),
+            0xC3 => Ok(Value::Boolean(true)),
+            0xC4 | 0xD9 => {
+                let length = self.length(1)?;
+                self.string(length)
+            }
+            0xC5 | 0xDA => {
+                let length = self.length(2)?;
+                self.string(length)
+            }
+            0xC6 | 0xDB => {
+                let length = self.length(4)?;
+                self.string(length)
+            }
+            0xCA => number(f32::from_be_bytes(self.take_array()?) as f64),
+            0xCB => number(f64::from_be_bytes(self.take_array()?)),
+            0xCC => number(self.take_array::<1>()?[0] as f64),
+            0xCD => number(u16::from_be_bytes(self.take_array()?) as f64),
+            0xCE => number(u32::from_be_bytes(self.take_array()?) as f64),
+            0xCF => number(u64::from_be_bytes(self.take_array()?) as f64),
+            0xD0 => number(self.take_array::<1>()?[0] as i8 as f64),
+            0xD1 => number(i16::from_be_bytes(self.take_array()?) as f64),
+            0xD2 => number(i32::from_be_bytes(self.take_array()?) as f64),
+            0xD3 => number(i64::from_be_bytes(self.take_array()?) as f64),
+            0xDC => {
+                let length = self.length(2)?;
+                self.array(length, depth)
+            }
+            0xDD => {
+                let length = self.length(4)?;
+                self.array(length, depth)
+            }
+            0xDE => {
+                let length = self.length(2)?;
+                self.map(length, depth)
+            }
+            0xDF => {
+                let length = self.length(4)?;
+                self.map(length, depth)
+            }
+            0xE0..=0xFF => number(kind as i8 as f64),
+            _ => Err(LuaError::Builtin("Bad data format in input.".to_string())),
+        }
+    }
+}
@@ -0,0 +1,906 @@
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+use super::super::resp::RespValue;
+use super::{
+    ast::{BinaryOp, Capture, Expr, Field, FunctionBody, Stat, Target, UnaryOp},
+    stdlib,
+    value::{break_cycles, Cell, Closure, Function, LuaString, TableRef, Value},
+    LuaError,
+};
+
+// Nested calls before "stack overflow", every call takes a few frames of
+// the native stack.
+const MAX_DEPTH: usize = 4000;
+
+// How often, in calls and loop iterations, the host is asked whether the
+// script should stop.
+const CHECK_INTERVAL: u32 = 10000;
+
+// Metamethod chains longer than this are considered loops.
+const MAX_TAG_LOOP: usize = 100;
+
+// What scripts reach outside of Lua through.
+pub trait Host {
+    // Runs a command for `redis.call` and `redis.pcall`.
+    fn call(&mut self, args: &[Vec<u8>]) -> RespValue;
+
+    // Checked every so often while a script runs, stops it with the
+    // returned message.
+    fn interrupted(&mut self) -> Option<String>;
+}
+
+pub struct Lua<'h> {
+    pub globals: TableRef,
+    pub string_metatable: Option<TableRef>,
+    pub host: &'h mut dyn Host,
+    // The state of math.random, like Redis's rand48.
+    pub random_state: u64,
+    depth: usize,
+    ticks: u32,
+    // Where the Lua functions being run were called from, for `error`.
+    call_sites: Vec<(Arc<str>, u32)>,
+}
+
+impl Drop for Lua<'_> {
+    fn drop(&mut self) {
+        break_cycles();
+    }
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+struct Frame<'f> {
+    slots: Vec<Cell>,
+    varargs: Vec<Value>,
+    upvalues: &'f [Cell],
+    source: &'f Arc<str>,
+}
+
+impl Frame<'_> {
+    // Locals declared in loops or by calls get fresh cells, so that closures
+    // keep what they captured. Cells nothing captured are reused.
+    fn declare(&mut self, slot: usize, value: Value) {
+        let cell = &mut self.slots[slot];
+        if Rc::strong_count(cell) == 1 {
+            *cell.borrow_mut() = value;
+        } else {
+            *cell = Rc::new(RefCell::new(value));
+        }
+    }
+}
+
+pub fn first(values: Vec<Value>) -> Value {
+    values.into_iter().next().unwrap_or_default()
+}
+
+// How error messages call what an expression names, like "local 'x'".
+fn describe(expression: &Expr) -> Option<String> {
+    match expression {
+        Expr::Local(_, name) => Some(format!("local '{}'", name)),
+        Expr::Upvalue(_, name) => Some(format!("upvalue '{}'", name)),
+        Expr::Global(name, _) => Some(format!("global '{}'", String::from_utf8_lossy(name))),
+        Expr::Index(_, key, _) => match &**key {
+            Expr::String(key) => Some(format!("field '{}'", String::from_utf8_lossy(key))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn type_error(operation: &str, value: &Value, name: Option<String>) -> LuaError {
+    LuaError::Builtin(match name {
+        Some(name) => format!(
+            "attempt to {} {} (a {} value)",
+            operation,
+            name,
+            value.type_name()
+        ),
+        None => format!("attempt to {} a {} value", operation, value.type_name()),
+    })
+}
+
+fn compare_error(a: &Value, b: &Value) -> LuaError {
+    let (a, b) = (a.type_name(), b.type_name());
+    LuaError::Builtin(if a == b {
+        format!("attempt to compare two {} values", a)
+    } else {
+        format!("attempt to compare {} with {}", a, b)
+    })
+}
+
+fn arithmetic(operator: BinaryOp, a: f64, b: f64) -> f64 {
+    match operator {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        BinaryOp::Mod => a - (a / b).floor() * b,
+        BinaryOp::Pow => a.powf(b),
+        _ => unreachable!(),
+    }
+}
+
+impl<'h> Lua<'h> {
+    pub fn new(host: &'h mut dyn Host) -> Self {
+        let mut lua = Lua {
+            globals: TableRef::new(),
+            string_metatable: None,
+            host,
+            random_state: 0,
+            depth: 0,
+            ticks: 0,
+            call_sites: Vec::new(),
+        };
+        stdlib::open(&mut lua);
+        lua
+    }
+
+    // A compiled chunk as a function.
+    pub fn load(&self, body: Arc<FunctionBody>) -> Value {
+        Value::closure(body, Vec::new())
+    }
+
+    // The line `error` blames at `level`: 1 is where it was called, 2 where
+    // the function calling it was called, and so on.
+    pub fn call_site(&self, level: usize) -> Option<(Arc<str>, u32)> {
+        if level == 0 || level > self.call_sites.len() {
+            return None;
+        }
+        self.call_sites.get(self.call_sites.len() - level).cloned()
+    }
+
+    fn tick(&mut self) -> Result<(), LuaError> {
+        self.ticks += 1;
+        if self.ticks >= CHECK_INTERVAL {
+            self.ticks = 0;
+            if let Some(message) = self.host.interrupted() {
+                return Err(LuaError::Interrupted(message));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn metatable(&self, value: &Value) -> Option<TableRef> {
+        match value {
+            Value::Table(table) => table.metatable(),
+            Value::String(_) => self.string_metatable.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn metamethod(&self, value: &Value, event: &str) -> Value {
+        self.metatable(value)
+            .map(|metatable| metatable.get_field(event))
+            .unwrap_or_default()
+    }
+
+    pub fn call(&mut self, function: &Value, mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match function {
+            Value::Function(Function::Lua(closure)) => self.call_closure(&Rc::clone(closure), args),
+            Value::Function(Function::Native(native)) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(LuaError::Builtin("stack overflow".to_string()));
+                }
+                let native = Rc::clone(native);
+                self.depth += 1;
+                let result = (native.func)(self, &native.upvalues, args);
+                self.depth -= 1;
+                result
+            }
+            _ => {
+                let handler = self.metamethod(function, "__call");
+                if !matches!(handler, Value::Function(_)) {
+                    return Err(type_error("call", function, None));
+                }
+                args.insert(0, function.clone());
+                self.call(&handler, args)
+            }
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: &Rc<Closure>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, LuaError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(LuaError::Builtin("stack overflow".to_string()));
+        }
+        self.tick()?;
+        let body = &closure.body;
+        let placeholder = Rc::new(RefCell::new(Value::Nil));
+        let mut slots = vec![placeholder; body.slot_count];
+        let mut args = args.into_iter();
+        for slot in slots.iter_mut().take(body.parameters) {
+            *slot = Rc::new(RefCell::new(args.next().unwrap_or_default()));
+        }
+        let varargs = if body.is_vararg {
+            args.collect()
+        } else {
+            Vec::new()
+        };
+        let mut frame = Frame {
+            slots,
+            varargs,
+            upvalues: &closure.upvalues,
+            source: &body.source,
+        };
+        self.depth += 1;
+        let flow = self.exec_block(&mut frame, &body.block);
+        self.depth -= 1;
+        match flow? {
+            Flow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // Calls from Lua code, where errors from builtins get their position.
+    fn call_at(
+        &mut self,
+        frame: &Frame,
+        line: u32,
+        function: &Value,
+        args: Vec<Value>,
+        name: impl FnOnce() -> Option<String>,
+    ) -> Result<Vec<Value>, LuaError> {
+        if !matches!(function, Value::Function(_))
+            && !matches!(self.metamethod(function, "__call"), Value::Function(_))
+        {
+            return Err(self.located(frame, line, type_error("call", function, name())));
+        }
+        self.call_sites.push((Arc::clone(frame.source), line));
+        let result = self.call(function, args);
+        self.call_sites.pop();
+        result.map_err(|error| self.located(frame, line, error))
+    }
+
+    // Errors from builtins are positioned where Lua code ran into them.
+    fn located(&self, frame: &Frame, line: u32, error: LuaError) -> LuaError {
+        match error {
+            LuaError::Builtin(message) => LuaError::Raised(
+                Value::string(format!("{}:{}: {}", frame.source, line, message)),
+                line,
+            ),
+            error => error,
+        }
+    }
+
+    pub fn index(&mut self, object: &Value, key: &Value) -> Result<Value, LuaError> {
+        self.index_named(object, key, || None)
+    }
+
+    fn index_named(
+        &mut self,
+        object: &Value,
+        key: &Value,
+        name: impl FnOnce() -> Option<String>,
+    ) -> Result<Value, LuaError> {
+        let mut object = object.clone();
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match &object {
+                Value::Table(table) => {
+                    let value = table.get(key);
+                    if !value.is_nil() {
+                        return Ok(value);
+                    }
+                    match table.metatable() {
+                        Some(metatable) => metatable.get_field("__index"),
+                        None => return Ok(Value::Nil),
+                    }
+                }
+                _ => {
+                    let handler = self.metamethod(&object, "__index");
+                    if handler.is_nil() {
+                        return Err(type_error("index", &object, name()));
+                    }
+                    handler
+                }
+            };
+            match handler {
+                Value::Nil => return Ok(Value::Nil),
+                Value::Function(_) => {
+                    return Ok(first(self.call(&handler, vec![object, key.clone()])?))
+                }
+                handler => object = handler,
+            }
+        }
+        Err(LuaError::Builtin("loop in gettable".to_string()))
+    }
+
+    pub fn set_index(&mut self, object: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+        self.set_index_named(object, key, value, || None)
+    }
+
+    fn set_index_named(
+        &mut self,
+        object: &Value,
+        key: Value,
+        value: Value,
+        name: impl FnOnce() -> Option<String>,
+    ) -> Result<(), LuaError> {
+        let mut object = object.clone();
+        for _ in 0..MAX_TAG_LOOP {
+            let handler = match &object {
+                Value::Table(table) => {
+                    let handler = table
+                        .metatable()
+                        .map(|metatable| metatable.get_field("__newindex"))
+                        .unwrap_or_default();
+                    if handler.is_nil() || !table.get(&key).is_nil() {
+                        return table.set(key, value).map_err(LuaError::Builtin);
+                    }
+                    handler
+                }
+                _ => {
+                    let handler = self.metamethod(&object, "__newindex");
+                    if handler.is_nil() {
+                        return Err(type_error("index", &object, name()));
+                    }
+                    handler
+                }
+            };
+            if let Value::Function(_) = handler {
+                self.call(&handler, vec![object, key, value])?;
+                return Ok(());
+            }
+            object = handler;
+        }
+        Err(LuaError::Builtin("loop in settable".to_string()))
+    }
+
+    pub fn tostring(&mut self, value: &Value) -> Result<LuaString, LuaError> {
+        let handler = self.metamethod(value, "__tostring");
+        if !handler.is_nil() {
+            return match first(self.call(&handler, vec![value.clone()])?) {
+                Value::String(string) => Ok(string),
+                Value::Number(number) => Ok(Value::Number(number).to_lua_string().unwrap()),
+                _ => Err(LuaError::Builtin(
+                    "'__tostring' must return a string".to_string(),
+                )),
+            };
+        }
+        match value {
+            Value::String(string) => Ok(Rc::clone(string)),
+            _ => Ok(Rc::from(value.to_string().as_bytes())),
+        }
+    }
+
+    pub fn equals(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        if a.raw_equals(b) {
+            return Ok(true);
+        }
+        if let (Value::Table(_), Value::Table(_)) = (a, b) {
+            let handler = self.metamethod(a, "__eq");
+            if handler.is_nil() || !handler.raw_equals(&self.metamethod(b, "__eq")) {
+                return Ok(false);
+            }
+            return Ok(first(self.call(&handler, vec![a.clone(), b.clone()])?).is_truthy());
+        }
+        Ok(false)
+    }
+
+    // Both operands must share the comparison metamethod.
+    fn order_metamethod(
+        &mut self,
+        a: &Value,
+        b: &Value,
+        event: &str,
+    ) -> Result<Option<bool>, LuaError> {
+        if a.type_name() != b.type_name() {
+            return Ok(None);
+        }
+        let handler = self.metamethod(a, event);
+        if handler.is_nil() || !handler.raw_equals(&self.metamethod(b, event)) {
+            return Ok(None);
+        }
+        Ok(Some(
+            first(self.call(&handler, vec![a.clone(), b.clone()])?).is_truthy(),
+        ))
+    }
+
+    pub fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            (Value::String(a), Value::String(b)) => Ok(a < b),
+            _ => self
+                .order_metamethod(a, b, "__lt")?
+                .ok_or_else(|| compare_error(a, b)),
+        }
+    }
+
+    pub fn less_equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(a <= b),
+            (Value::String(a), Value::String(b)) => Ok(a <= b),
+            _ => {
+                if let Some(result) = self.order_metamethod(a, b, "__le")? {
+                    return Ok(result);
+                }
+                match self.order_metamethod(b, a, "__lt")? {
+                    Some(result) => Ok(!result),
+                    None => Err(compare_error(a, b)),
+                }
+            }
+        }
+    }
+
+    fn arithmetic(
+        &mut self,
+        operator: BinaryOp,
+        a: Value,
+        b: Value,
+        names: (&Expr, &Expr),
+    ) -> Result<Value, LuaError> {
+        if let (Some(x), Some(y)) = (a.to_number(), b.to_number()) {
+            return Ok(Value::Number(arithmetic(operator, x, y)));
+        }
+        let event = match operator {
+            BinaryOp::Add => "__add",
+            BinaryOp::Sub => "__sub",
+            BinaryOp::Mul => "__mul",
+            BinaryOp::Div => "__div",
+            BinaryOp::Mod => "__mod",
+            _ => "__pow",
+        };
+        let mut handler = self.metamethod(&a, event);
+        if handler.is_nil() {
+            handler = self.metamethod(&b, event);
+        }
+        if !handler.is_nil() {
+            return Ok(first(self.call(&handler, vec![a, b])?));
+        }
+        let (value, name) = if a.to_number().is_none() {
+            (&a, names.0)
+        } else {
+            (&b, names.1)
+        };
+        Err(type_error("perform arithmetic on", value, describe(name)))
+    }
+
+    fn concatenate(
+        &mut self,
+        a: Value,
+        b: Value,
+        names: (&Expr, &Expr),
+    ) -> Result<Value, LuaError> {
+        if let (Some(x), Some(y)) = (a.to_lua_string(), b.to_lua_string()) {
+            let mut joined = Vec::with_capacity(x.len() + y.len());
+            joined.extend_from_slice(&x);
+            joined.extend_from_slice(&y);
+            return Ok(Value::string(joined));
+        }
+        let mut handler = self.metamethod(&a, "__concat");
+        if handler.is_nil() {
+            handler = self.metamethod(&b, "__concat");
+        }
+        if !handler.is_nil() {
+            return Ok(first(self.call(&handler, vec![a, b])?));
+        }
+        let (value, name) = if matches!(a, Value::String(_) | Value::Number(_)) {
+            (&b, names.1)
+        } else {
+            (&a, names.0)
+        };
+        Err(type_error("concatenate", value, describe(name)))
+    }
+
+    fn binary(
+        &mut self,
+        operator: BinaryOp,
+        a: Value,
+        b: Value,
+        names: (&Expr, &Expr),
+    ) -> Result<Value, LuaError> {
+        Ok(match operator {
+            BinaryOp::Add
+            | BinaryOp::Sub
+            | BinaryOp::Mul
+            | BinaryOp::Div
+            | BinaryOp::Mod
+            | BinaryOp::Pow => return self.arithmetic(operator, a, b, names),
+            BinaryOp::Concat => return self.concatenate(a, b, names),
+            BinaryOp::Eq => Value::Boolean(self.equals(&a, &b)?),
+            BinaryOp::Ne => Value::Boolean(!self.equals(&a, &b)?),
+            BinaryOp::Lt => Value::Boolean(self.less_than(&a, &b)?),
+            BinaryOp::Le => Value::Boolean(self.less_equal(&a, &b)?),
+            BinaryOp::Gt => Value::Boolean(self.less_than(&b, &a)?),
+            BinaryOp::Ge => Value::Boolean(self.less_equal(&b, &a)?),
+        })
+    }
+
+    fn unary(&mut self, operator: UnaryOp, value: Value, name: &Expr) -> Result<Value, LuaError> {
+        match operator {
+            UnaryOp::Not => Ok(Value::Boolean(!value.is_truthy())),
+            UnaryOp::Neg => {
+                if let Some(number) = value.to_number() {
+                    return Ok(Value::Number(-number));
+                }
+                let handler = self.metamethod(&value, "__unm");
+                if handler.is_nil() {
+                    return Err(type_error("perform arithmetic on", &value, describe(name)));
+                }
+                Ok(first(self.call(&handler, vec![value.clone(), value])?))
+            }
+            UnaryOp::Len => match &value {
+                Value::String(string) => Ok(Value::Number(string.len() as f64)),
+                Value::Table(table) => Ok(Value::Number(table.len() as f64)),
+                _ => {
+                    let handler = self.metamethod(&value, "__len");
+                    if handler.is_nil() {
+                        return Err(type_error("get length of", &value, describe(name)));
+                    }
+                    Ok(first(self.call(&handler, vec![value.clone(), value])?))
+                }
+            },
+        }
+    }
+
+    fn closure(&self, frame: &Frame, body: &Arc<FunctionBody>) -> Value {
+        let upvalues = body
+            .captures
+            .iter()
+            .map(|capture| match capture {
+                Capture::Local(slot) => Rc::clone(&frame.slots[*slot]),
+                Capture::Upvalue(index) => Rc::clone(&frame.upvalues[*index]),
+            })
+            .collect();
+        Value::closure(Arc::clone(body), upvalues)
+    }
+
+    fn global(&mut self, name: &[u8]) -> Result<Value, LuaError> {
+        let value = self.globals.0.borrow().get_field(name);
+        if !value.is_nil() || self.globals.metatable().is_none() {
+            return Ok(value);
+        }
+        let globals = Value::Table(self.globals.clone());
+        self.index(&globals, &Value::string(name))
+    }
+
+    fn exec_block(&mut self, frame: &mut Frame, block: &[Stat]) -> Result<Flow, LuaError> {
+        for statement in block {
+            match self.exec(frame, statement)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // Runs a loop body, telling whether the loop goes on.
+    fn exec_loop_body(
+        &mut self,
+        frame: &mut Frame,
+        block: &[Stat],
+    ) -> Result<Option<Flow>, LuaError> {
+        self.tick()?;
+        match self.exec_block(frame, block)? {
+            Flow::Normal => Ok(None),
+            Flow::Break => Ok(Some(Flow::Normal)),
+            flow => Ok(Some(flow)),
+        }
+    }
+
+    fn exec(&mut self, frame: &mut Frame, statement: &Stat) -> Result<Flow, LuaError> {
+        match statement {
+            Stat::Local { slots, values } => {
+                let mut values =
+                    if slots.len() == 1 && values.len() == 1 && !values[0].is_multi() {
+                        vec![self.eval(frame, &values[0])?]
+                    } else {
+                        self.eval_list(frame, values)?
+                    }
+                    .into_iter();
+                for slot in slots {
+                    frame.declare(*slot, values.next().unwrap_or_default());
+                }
+            }
+            Stat::LocalFunction { slot, body } => {
+                frame.slots[*slot] = Rc::new(RefCell::new(Value::Nil));
+                let function = self.closure(frame, body);
+                *frame.slots[*slot].borrow_mut() = function;
+            }
+            Stat::Assign { targets, values } => self.assign(frame, targets, values)?,
+            Stat::Call(call) => {
+                self.eval_multi(frame, call)?;
+            }
+            Stat::Do(block) => return self.exec_block(frame, block),
+            Stat::While { condition, block } => {
+                while self.eval(frame, condition)?.is_truthy() {
+                    if let Some(flow) = self.exec_loop_body(frame, block)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Stat::Repeat { block, condition } => loop {
+                if let Some(flow) = self.exec_loop_body(frame, block)? {
+                    return Ok(flow);
+                }
+                if self.eval(frame, condition)?.is_truthy() {
+                    break;
+                }
+            },
+            Stat::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, block) in branches {
+                    if self.eval(frame, condition)?.is_truthy() {
+                        return self.exec_block(frame, block);
+                    }
+                }
+                if let Some(block) = otherwise {
+                    return self.exec_block(frame, block);
+                }
+            }
+            Stat::NumericFor {
+                slot,
+                start,
+                limit,
+                step,
+                block,
+                line,
+            } => {
+                let mut number = |lua: &mut Self, expression: &Expr, what: &str| {
+                    lua.eval(frame, expression)?.to_number().ok_or_else(|| {
+                        lua.located(
+                            frame,
+                            *line,
+                            LuaError::Builtin(format!("'for' {} must be a number", what)),
+                        )
+                    })
+                };
+                let start = number(self, start, "initial value")?;
+                let limit = number(self, limit, "limit")?;
+                let step = match step {
+                    Some(step) => number(self, step, "step")?,
+                    None => 1.0,
+                };
+                let mut index = start;
+                while (step > 0.0 && index <= limit) || (step <= 0.0 && index >= limit) {
+                    frame.declare(*slot, Value::Number(index));
+                    if let Some(flow) = self.exec_loop_body(frame, block)? {
+                        return Ok(flow);
+                    }
+                    index += step;
+                }
+            }
+            Stat::GenericFor {
+                slots,
+                values,
+                block,
+                line,
+            } => {
+                let mut values = self.eval_list(frame, values)?.into_iter();
+                let iterator = values.next().unwrap_or_default();
+                let state = values.next().unwrap_or_default();
+                let mut control = values.next().unwrap_or_default();
+                loop {
+                    let results = self.call_at(
+                        frame,
+                        *line,
+                        &iterator,
+                        vec![state.clone(), control.clone()],
+                        || None,
+                    )?;
+                    let mut results = results.into_iter();
+                    control = results.next().unwrap_or_default();
+                    if control.is_nil() {
+                        break;
+                    }
+                    frame.declare(slots[0], control.clone());
+                    for slot in &slots[1..] {
+                        frame.declare(*slot, results.next().unwrap_or_default());
+                    }
+                    if let Some(flow) = self.exec_loop_body(frame, block)? {
+                        return Ok(flow);
+                    }
+                }
+            }
+            Stat::Return(values) => {
+                return Ok(Flow::Return(self.eval_list(frame, values)?));
+            }
+            Stat::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    // Tables and keys are evaluated before the values, which are assigned
+    // from right to left like Lua does.
+    fn assign(
+        &mut self,
+        frame: &mut Frame,
+        targets: &[Target],
+        values: &[Expr],
+    ) -> Result<(), LuaError> {
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Target::Index { table, key, .. } => {
+                    Some((self.eval(frame, table)?, self.eval(frame, key)?))
+                }
+                _ => None,
+            });
+        }
+        let mut values = if targets.len() == 1 && values.len() == 1 && !values[0].is_multi() {
+            vec![self.eval(frame, &values[0])?]
+        } else {
+            self.eval_list(frame, values)?
+        };
+        values.resize(targets.len(), Value::Nil);
+        for ((target, place), value) in targets.iter().zip(places).zip(values).rev() {
+            match (target, place) {
+                (Target::Local(slot), _) => *frame.slots[*slot].borrow_mut() = value,
+                (Target::Upvalue(index), _) => *frame.upvalues[*index].borrow_mut() = value,
+                (Target::Global(name, line), _) => {
+                    let globals = Value::Table(self.globals.clone());
+                    self.set_index(&globals, Value::string(name), value)
+                        .map_err(|error| self.located(frame, *line, error))?;
+                }
+                (Target::Index { table, line, .. }, Some((object, key))) => self
+                    .set_index_named(&object, key, value, || describe(table))
+                    .map_err(|error| self.located(frame, *line, error))?,
+                _ => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_list(
+        &mut self,
+        frame: &mut Frame,
+        expressions: &[Expr],
+    ) -> Result<Vec<Value>, LuaError> {
+        let mut values = Vec::with_capacity(expressions.len());
+        for (i, expression) in expressions.iter().enumerate() {
+            if i + 1 == expressions.len() && expression.is_multi() {
+                values.extend(self.eval_multi(frame, expression)?);
+            } else {
+                values.push(self.eval(frame, expression)?);
+            }
+        }
+        Ok(values)
+    }
+
+    // All the values of a call or `...`.
+    fn eval_multi(&mut self, frame: &mut Frame, expression: &Expr) -> Result<Vec<Value>, LuaError> {
+        match expression {
+            Expr::Vararg => Ok(frame.varargs.clone()),
+            Expr::Call(function, args, line) => {
+                let callee = self.eval(frame, function)?;
+                let args = self.eval_list(frame, args)?;
+                self.call_at(frame, *line, &callee, args, || describe(function))
+            }
+            Expr::Method(object, method, args, line) => {
+                let object_value = self.eval(frame, object)?;
+                let callee = self
+                    .index_field(&object_value, method, || describe(object))
+                    .map_err(|error| self.located(frame, *line, error))?;
+                let mut arguments = Vec::with_capacity(args.len() + 1);
+                arguments.push(object_value);
+                arguments.extend(self.eval_list(frame, args)?);
+                self.call_at(frame, *line, &callee, arguments, || {
+                    Some(format!("method '{}'", String::from_utf8_lossy(method)))
+                })
+            }
+            _ => Ok(vec![self.eval(frame, expression)?]),
+        }
+    }
+
+    // Indexing with a constant string, without allocating the key when the
+    // table has it.
+    fn index_field(
+        &mut self,
+        object: &Value,
+        name: &[u8],
+        describe: impl FnOnce() -> Option<String>,
+    ) -> Result<Value, LuaError> {
+        if let Value::Table(table) = object {
+            let value = table.0.borrow().get_field(name);
+            if !value.is_nil() || table.metatable().is_none() {
+                return Ok(value);
+            }
+        }
+        self.index_named(object, &Value::string(name), describe)
+    }
+
+    fn eval(&mut self, frame: &mut Frame, expression: &Expr) -> Result<Value, LuaError> {
+        match expression {
+            Expr::Nil => Ok(Value::Nil),
+            Expr::True => Ok(Value::Boolean(true)),
+            Expr::False => Ok(Value::Boolean(false)),
+            Expr::Number(number) => Ok(Value::Number(*number)),
+            Expr::String(string) => Ok(Value::string(string)),
+            Expr::Vararg => Ok(frame.varargs.first().cloned().unwrap_or_default()),
+            Expr::Function(body) => Ok(self.closure(frame, body)),
+            Expr::Local(slot, _) => Ok(frame.slots[*slot].borrow().clone()),
+            Expr::Upvalue(index, _) => Ok(frame.upvalues[*index].borrow().clone()),
+            Expr::Global(name, line) => self
+                .global(name)
+                .map_err(|error| self.located(frame, *line, error)),
+            Expr::Index(object, key, line) => {
+                let object_value = self.eval(frame, object)?;
+                let result = match &**key {
+                    Expr::String(name) => {
+                        self.index_field(&object_value, name, || describe(object))
+                    }
+                    key => {
+                        let key = self.eval(frame, key)?;
+                        self.index_named(&object_value, &key, || describe(object))
+                    }
+                };
+                result.map_err(|error| self.located(frame, *line, error))
+            }
+            Expr::Call(..) | Expr::Method(..) => Ok(first(self.eval_multi(frame, expression)?)),
+            Expr::Binary(operator, left, right, line) => {
+                let a = self.eval(frame, left)?;
+                let b = self.eval(frame, right)?;
+                if let (Value::Number(x), Value::Number(y)) = (&a, &b) {
+                    match operator {
+                        BinaryOp::Add
+                        | BinaryOp::Sub
+                        | BinaryOp::Mul
+                        | BinaryOp::Div
+                        | BinaryOp::Mod
+                        | BinaryOp::Pow => return Ok(Value::Number(arithmetic(*operator, *x, *y))),
+                        BinaryOp::Eq => return Ok(Value::Boolean(x == y)),
+                        BinaryOp::Lt => return Ok(Value::Boolean(x < y)),
+                        _ => {}
+                    }
+                }
+                self.binary(*operator, a, b, (left, right))
+                    .map_err(|error| self.located(frame, *line, error))
+            }
+            Expr::And(left, right) => {
+                let value = self.eval(frame, left)?;
+                if !value.is_truthy() {
+                    return Ok(value);
+                }
+                self.eval(frame, right)
+            }
+            Expr::Or(left, right) => {
+                let value = self.eval(frame, left)?;
+                if value.is_truthy() {
+                    return Ok(value);
+                }
+                self.eval(frame, right)
+            }
+            Expr::Unary(operator, operand, line) => {
+                let value = self.eval(frame, operand)?;
+                self.unary(*operator, value, operand)
+                    .map_err(|error| self.located(frame, *line, error))
+            }
+            Expr::Table(fields, line) => {
+                let table = TableRef::new();
+                let mut index = 1;
+                for (i, field) in fields.iter().enumerate() {
+                    match field {
+                        Field::Positional(value) if i + 1 == fields.len() && value.is_multi() => {
+                            for value in self.eval_multi(frame, value)? {
+                                table.set(Value::Number(index as f64), value).unwrap();
+                                index += 1;
+                            }
+                        }
+                        Field::Positional(value) => {
+                            let value = self.eval(frame, value)?;
+                            table.set(Value::Number(index as f64), value).unwrap();
+                            index += 1;
+                        }
+                        Field::Named(key, value) => {
+                            let key = self.eval(frame, key)?;
+                            let value = self.eval(frame, value)?;
+                            table.set(key, value).map_err(|error| {
+                                self.located(frame, *line, LuaError::Builtin(error))
+                            })?;
+                        }
+                    }
+                }
+                Ok(Value::Table(table))
+            }
+            Expr::Paren(inner) => self.eval(frame, inner),
+        }
+    }
+}
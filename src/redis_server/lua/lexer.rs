@@ -0,0 +1,372 @@
+use super::value::parse_number;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(String),
+    String(Vec<u8>),
+    Number(f64),
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Hash,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Ellipsis,
+    Eof,
+}
+
+fn keyword(name: &str) -> Option<Token> {
+    Some(match name {
+        "and" => Token::And,
+        "break" => Token::Break,
+        "do" => Token::Do,
+        "else" => Token::Else,
+        "elseif" => Token::Elseif,
+        "end" => Token::End,
+        "false" => Token::False,
+        "for" => Token::For,
+        "function" => Token::Function,
+        "if" => Token::If,
+        "in" => Token::In,
+        "local" => Token::Local,
+        "nil" => Token::Nil,
+        "not" => Token::Not,
+        "or" => Token::Or,
+        "repeat" => Token::Repeat,
+        "return" => Token::Return,
+        "then" => Token::Then,
+        "true" => Token::True,
+        "until" => Token::Until,
+        "while" => Token::While,
+        _ => return None,
+    })
+}
+
+// A token with what error messages show of it.
+#[derive(Debug, Clone)]
+pub struct Lexeme {
+    pub token: Token,
+    pub line: u32,
+    pub text: String,
+}
+
+pub struct Lexer<'a> {
+    source: &'a [u8],
+    position: usize,
+    line: u32,
+    chunk_name: &'a str,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a [u8], chunk_name: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+            line: 1,
+            chunk_name,
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Lexeme>, String> {
+        let mut lexemes = Vec::new();
+        loop {
+            let lexeme = self.next_lexeme()?;
+            let end = lexeme.token == Token::Eof;
+            lexemes.push(lexeme);
+            if end {
+                return Ok(lexemes);
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.source.get(self.position + offset).copied()
+    }
+
+    fn error(&self, message: &str, near: &str) -> String {
+        format!(
+            "{}:{}: {} near '{}'",
+            self.chunk_name, self.line, message, near
+        )
+    }
+
+    fn text_from(&self, start: usize) -> String {
+        String::from_utf8_lossy(&self.source[start..self.position]).into_owned()
+    }
+
+    // Skips a newline sequence: \n, \r, \n\r or \r\n.
+    fn skip_newline(&mut self) {
+        let first = self.peek();
+        self.position += 1;
+        if matches!(self.peek(), Some(next @ (b'\n' | b'\r')) if Some(next) != first) {
+            self.position += 1;
+        }
+        self.line += 1;
+    }
+
+    // The level of a long bracket `[==[` starting here, if it is one.
+    fn long_bracket_level(&self) -> Option<usize> {
+        let bracket = self.peek()?;
+        let mut level = 0;
+        while self.peek_at(1 + level) == Some(b'=') {
+            level += 1;
+        }
+        (self.peek_at(1 + level) == Some(bracket)).then_some(level)
+    }
+
+    fn read_long_string(&mut self, level: usize) -> Result<Vec<u8>, String> {
+        self.position += level + 2;
+        if matches!(self.peek(), Some(b'\n' | b'\r')) {
+            self.skip_newline();
+        }
+        let mut content = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unfinished long string", "<eof>")),
+                Some(b']') if self.long_bracket_level() == Some(level) => {
+                    self.position += level + 2;
+                    return Ok(content);
+                }
+                Some(b'\n' | b'\r') => {
+                    self.skip_newline();
+                    content.push(b'\n');
+                }
+                Some(byte) => {
+                    content.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn read_string(&mut self, quote: u8, start: usize) -> Result<Vec<u8>, String> {
+        self.position += 1;
+        let mut content = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unfinished string", "<eof>")),
+                Some(b'\n' | b'\r') => {
+                    return Err(self.error("unfinished string", &self.text_from(start)))
+                }
+                Some(byte) if byte == quote => {
+                    self.position += 1;
+                    return Ok(content);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let Some(escaped) = self.peek() else {
+                        continue;
+                    };
+                    match escaped {
+                        b'a' => content.push(0x07),
+                        b'b' => content.push(0x08),
+                        b'f' => content.push(0x0C),
+                        b'n' => content.push(b'\n'),
+                        b'r' => content.push(b'\r'),
+                        b't' => content.push(b'\t'),
+                        b'v' => content.push(0x0B),
+                        b'\n' | b'\r' => {
+                            content.push(b'\n');
+                            self.skip_newline();
+                            continue;
+                        }
+                        b'0'..=b'9' => {
+                            let mut value: u32 = 0;
+                            let mut digits = 0;
+                            while digits < 3
+                                && self.peek().is_some_and(|byte| byte.is_ascii_digit())
+                            {
+                                value = value * 10 + (self.peek().unwrap() - b'0') as u32;
+                                self.position += 1;
+                                digits += 1;
+                            }
+                            if value > 255 {
+                                return Err(
+                                    self.error("escape sequence too large", &self.text_from(start))
+                                );
+                            }
+                            content.push(value as u8);
+                            continue;
+                        }
+                        other => content.push(other),
+                    }
+                    self.position += 1;
+                }
+                Some(byte) => {
+                    content.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn read_number(&mut self, start: usize) -> Result<f64, String> {
+        while self
+            .peek()
+            .is_some_and(|byte| byte.is_ascii_digit() || byte == b'.')
+        {
+            self.position += 1;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.position += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+        }
+        while self
+            .peek()
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.')
+        {
+            self.position += 1;
+        }
+        parse_number(&self.source[start..self.position])
+            .ok_or_else(|| self.error("malformed number", &self.text_from(start)))
+    }
+
+    fn next_lexeme(&mut self) -> Result<Lexeme, String> {
+        loop {
+            match self.peek() {
+                Some(b'\n' | b'\r') => self.skip_newline(),
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                Some(b'-') if self.peek_at(1) == Some(b'-') => {
+                    self.position += 2;
+                    if self.peek() == Some(b'[') {
+                        if let Some(level) = self.long_bracket_level() {
+                            self.read_long_string(level)?;
+                            continue;
+                        }
+                    }
+                    while self
+                        .peek()
+                        .is_some_and(|byte| byte != b'\n' && byte != b'\r')
+                    {
+                        self.position += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let start = self.position;
+        let line = self.line;
+        let Some(byte) = self.peek() else {
+            return Ok(Lexeme {
+                token: Token::Eof,
+                line,
+                text: "<eof>".to_string(),
+            });
+        };
+        let token = match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                while self
+                    .peek()
+                    .is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+                {
+                    self.position += 1;
+                }
+                let name = self.text_from(start);
+                keyword(&name).unwrap_or(Token::Name(name))
+            }
+            b'0'..=b'9' => Token::Number(self.read_number(start)?),
+            b'.' if self.peek_at(1).is_some_and(|byte| byte.is_ascii_digit()) => {
+                Token::Number(self.read_number(start)?)
+            }
+            b'"' | b'\'' => Token::String(self.read_string(byte, start)?),
+            b'[' if self.long_bracket_level().is_some() => {
+                let level = self.long_bracket_level().unwrap();
+                Token::String(self.read_long_string(level)?)
+            }
+            _ => {
+                let two = [byte, self.peek_at(1).unwrap_or(0)];
+                let (token, length) = match &two {
+                    b"==" => (Token::Eq, 2),
+                    b"~=" => (Token::Ne, 2),
+                    b"<=" => (Token::Le, 2),
+                    b">=" => (Token::Ge, 2),
+                    b".." if self.peek_at(2) == Some(b'.') => (Token::Ellipsis, 3),
+                    b".." => (Token::Concat, 2),
+                    _ => (
+                        match byte {
+                            b'+' => Token::Plus,
+                            b'-' => Token::Minus,
+                            b'*' => Token::Star,
+                            b'/' => Token::Slash,
+                            b'%' => Token::Percent,
+                            b'^' => Token::Caret,
+                            b'#' => Token::Hash,
+                            b'<' => Token::Lt,
+                            b'>' => Token::Gt,
+                            b'=' => Token::Assign,
+                            b'(' => Token::LeftParen,
+                            b')' => Token::RightParen,
+                            b'{' => Token::LeftBrace,
+                            b'}' => Token::RightBrace,
+                            b'[' => Token::LeftBracket,
+                            b']' => Token::RightBracket,
+                            b';' => Token::Semicolon,
+                            b':' => Token::Colon,
+                            b',' => Token::Comma,
+                            b'.' => Token::Dot,
+                            _ => {
+                                let near = (byte as char).to_string();
+                                return Err(self.error("unexpected symbol", &near));
+                            }
+                        },
+                        1,
+                    ),
+                };
+                self.position += length;
+                token
+            }
+        };
+        Ok(Lexeme {
+            token,
+            line,
+            text: self.text_from(start),
+        })
+    }
+}
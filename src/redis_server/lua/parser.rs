@@ -0,0 +1,758 @@
+use std::sync::Arc;
+
+use super::{
+    ast::{BinaryOp, Block, Capture, Expr, Field, FunctionBody, Stat, Target, UnaryOp},
+    lexer::{Lexeme, Lexer, Token},
+};
+
+// Like LUAI_MAXCCALLS, LUAI_MAXVARS and LUAI_MAXUPVALUES.
+const MAX_SYNTAX_LEVELS: usize = 200;
+const MAX_LOCALS: usize = 200;
+const MAX_UPVALUES: usize = 60;
+
+const UNARY_PRIORITY: u8 = 8;
+
+enum Operator {
+    Binary(BinaryOp),
+    And,
+    Or,
+}
+
+// The operator a token stands for, with its left and right priorities.
+fn binary_operator(token: &Token) -> Option<(Operator, u8, u8)> {
+    Some(match token {
+        Token::Or => (Operator::Or, 1, 1),
+        Token::And => (Operator::And, 2, 2),
+        Token::Lt => (Operator::Binary(BinaryOp::Lt), 3, 3),
+        Token::Gt => (Operator::Binary(BinaryOp::Gt), 3, 3),
+        Token::Le => (Operator::Binary(BinaryOp::Le), 3, 3),
+        Token::Ge => (Operator::Binary(BinaryOp::Ge), 3, 3),
+        Token::Ne => (Operator::Binary(BinaryOp::Ne), 3, 3),
+        Token::Eq => (Operator::Binary(BinaryOp::Eq), 3, 3),
+        // Right associative.
+        Token::Concat => (Operator::Binary(BinaryOp::Concat), 5, 4),
+        Token::Plus => (Operator::Binary(BinaryOp::Add), 6, 6),
+        Token::Minus => (Operator::Binary(BinaryOp::Sub), 6, 6),
+        Token::Star => (Operator::Binary(BinaryOp::Mul), 7, 7),
+        Token::Slash => (Operator::Binary(BinaryOp::Div), 7, 7),
+        Token::Percent => (Operator::Binary(BinaryOp::Mod), 7, 7),
+        Token::Caret => (Operator::Binary(BinaryOp::Pow), 10, 9),
+        _ => return None,
+    })
+}
+
+// The function being compiled. Active locals take the slots in the order
+// they were declared, slots are reused once their block ends.
+#[derive(Default)]
+struct FunctionState {
+    line: u32,
+    is_vararg: bool,
+    actives: Vec<(String, usize)>,
+    slot_count: usize,
+    captures: Vec<Capture>,
+    loops: usize,
+}
+
+pub struct Parser {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+    chunk_name: Arc<str>,
+    functions: Vec<FunctionState>,
+    levels: usize,
+}
+
+// Compiles a chunk into the body of a vararg function without parameters.
+pub fn parse(source: &[u8], chunk_name: &str) -> Result<Arc<FunctionBody>, String> {
+    let lexemes = Lexer::new(source, chunk_name).tokenize()?;
+    let mut parser = Parser {
+        lexemes,
+        position: 0,
+        chunk_name: Arc::from(chunk_name),
+        functions: vec![FunctionState {
+            is_vararg: true,
+            ..Default::default()
+        }],
+        levels: 0,
+    };
+    let block = parser.statements()?;
+    if parser.token() != &Token::Eof {
+        return Err(parser.error_near("'<eof>' expected"));
+    }
+    let state = parser.functions.pop().unwrap();
+    Ok(Arc::new(FunctionBody {
+        source: parser.chunk_name,
+        parameters: 0,
+        is_vararg: true,
+        block,
+        slot_count: state.slot_count,
+        captures: Vec::new(),
+    }))
+}
+
+impl Parser {
+    fn token(&self) -> &Token {
+        &self.lexemes[self.position].token
+    }
+
+    fn peek_token(&self) -> &Token {
+        &self.lexemes[(self.position + 1).min(self.lexemes.len() - 1)].token
+    }
+
+    fn line(&self) -> u32 {
+        self.lexemes[self.position].line
+    }
+
+    fn advance(&mut self) {
+        if self.position + 1 < self.lexemes.len() {
+            self.position += 1;
+        }
+    }
+
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{}:{}: {}", self.chunk_name, self.line(), message)
+    }
+
+    fn error_near(&self, message: &str) -> String {
+        format!(
+            "{}:{}: {} near '{}'",
+            self.chunk_name,
+            self.line(),
+            message,
+            self.lexemes[self.position].text
+        )
+    }
+
+    fn check(&mut self, token: Token, text: &str) -> Result<(), String> {
+        if self.token() != &token {
+            return Err(self.error_near(&format!("'{}' expected", text)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    // Expects the token closing what was opened at `line`.
+    fn check_match(
+        &mut self,
+        token: Token,
+        text: &str,
+        opener: &str,
+        line: u32,
+    ) -> Result<(), String> {
+        if self.token() == &token {
+            self.advance();
+            return Ok(());
+        }
+        if line == self.line() {
+            Err(self.error_near(&format!("'{}' expected", text)))
+        } else {
+            Err(self.error_near(&format!(
+                "'{}' expected (to close '{}' at line {})",
+                text, opener, line
+            )))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.token() {
+            Token::Name(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error_near("<name> expected")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.levels += 1;
+        if self.levels > MAX_SYNTAX_LEVELS {
+            return Err(self.error("chunk has too many syntax levels"));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.levels -= 1;
+    }
+
+    fn limit_error(&self, what: &str, limit: usize) -> String {
+        let line = self.functions.last().unwrap().line;
+        let function = if self.functions.len() == 1 {
+            "main function".to_string()
+        } else {
+            format!("function at line {}", line)
+        };
+        self.error(&format!("{} has more than {} {}", function, limit, what))
+    }
+
+    fn declare(&mut self, name: String) -> Result<usize, String> {
+        if self.functions.last().unwrap().actives.len() >= MAX_LOCALS {
+            return Err(self.limit_error("local variables", MAX_LOCALS));
+        }
+        let function = self.function();
+        let slot = function.actives.len();
+        function.actives.push((name, slot));
+        function.slot_count = function.slot_count.max(slot + 1);
+        Ok(slot)
+    }
+
+    // Finds a name as a local of the function at `level` or as one of its
+    // upvalues, capturing it from the enclosing functions if needed.
+    fn resolve(&mut self, level: usize, name: &str) -> Result<Option<Capture>, String> {
+        let function = &self.functions[level];
+        if let Some((_, slot)) = function
+            .actives
+            .iter()
+            .rev()
+            .find(|(active, _)| active == name)
+        {
+            return Ok(Some(Capture::Local(*slot)));
+        }
+        if level == 0 {
+            return Ok(None);
+        }
+        let Some(capture) = self.resolve(level - 1, name)? else {
+            return Ok(None);
+        };
+        let captures = &self.functions[level].captures;
+        if let Some(index) = captures.iter().position(|existing| *existing == capture) {
+            return Ok(Some(Capture::Upvalue(index)));
+        }
+        if captures.len() >= MAX_UPVALUES {
+            return Err(self.limit_error("upvalues", MAX_UPVALUES));
+        }
+        self.functions[level].captures.push(capture);
+        Ok(Some(Capture::Upvalue(
+            self.functions[level].captures.len() - 1,
+        )))
+    }
+
+    // Called right after the name was read.
+    fn variable(&mut self, name: String) -> Result<Expr, String> {
+        let level = self.functions.len() - 1;
+        let line = self.lexemes[self.position - 1].line;
+        Ok(match self.resolve(level, &name)? {
+            Some(Capture::Local(slot)) => Expr::Local(slot, name.into()),
+            Some(Capture::Upvalue(index)) => Expr::Upvalue(index, name.into()),
+            None => Expr::Global(name.into_bytes().into(), line),
+        })
+    }
+
+    fn block_follows(&self) -> bool {
+        matches!(
+            self.token(),
+            Token::Else | Token::Elseif | Token::End | Token::Until | Token::Eof
+        )
+    }
+
+    // A block in its own scope.
+    fn block(&mut self) -> Result<Block, String> {
+        let active = self.function().actives.len();
+        let block = self.statements()?;
+        self.function().actives.truncate(active);
+        Ok(block)
+    }
+
+    // Statements up to the end of a block, `return` and `break` can only be
+    // the last one.
+    fn statements(&mut self) -> Result<Block, String> {
+        let mut block = Vec::new();
+        while !self.block_follows() {
+            let last = matches!(self.token(), Token::Return | Token::Break);
+            block.push(self.statement()?);
+            if self.token() == &Token::Semicolon {
+                self.advance();
+            }
+            if last {
+                break;
+            }
+        }
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<Stat, String> {
+        self.enter()?;
+        let line = self.line();
+        let statement = match self.token() {
+            Token::If => self.if_statement(line),
+            Token::While => {
+                self.advance();
+                let condition = self.expression()?;
+                self.check(Token::Do, "do")?;
+                let block = self.loop_block()?;
+                self.check_match(Token::End, "end", "while", line)?;
+                Ok(Stat::While { condition, block })
+            }
+            Token::Do => {
+                self.advance();
+                let block = self.block()?;
+                self.check_match(Token::End, "end", "do", line)?;
+                Ok(Stat::Do(block))
+            }
+            Token::For => self.for_statement(line),
+            Token::Repeat => {
+                self.advance();
+                // The condition sees the block's locals.
+                let active = self.function().actives.len();
+                self.function().loops += 1;
+                let block = self.statements()?;
+                self.function().loops -= 1;
+                self.check_match(Token::Until, "until", "repeat", line)?;
+                let condition = self.expression()?;
+                self.function().actives.truncate(active);
+                Ok(Stat::Repeat { block, condition })
+            }
+            Token::Function => self.function_statement(line),
+            Token::Local => {
+                self.advance();
+                if self.token() == &Token::Function {
+                    self.advance();
+                    let name = self.name()?;
+                    // Declared first so that the function can call itself.
+                    let slot = self.declare(name)?;
+                    let body = self.function_body(false, line)?;
+                    Ok(Stat::LocalFunction { slot, body })
+                } else {
+                    self.local_statement()
+                }
+            }
+            Token::Return => {
+                self.advance();
+                let values = if self.block_follows() || self.token() == &Token::Semicolon {
+                    Vec::new()
+                } else {
+                    self.expression_list()?
+                };
+                Ok(Stat::Return(values))
+            }
+            Token::Break => {
+                self.advance();
+                if self.function().loops == 0 {
+                    return Err(self.error_near("no loop to break"));
+                }
+                Ok(Stat::Break)
+            }
+            _ => self.expression_statement(),
+        };
+        self.leave();
+        statement
+    }
+
+    fn loop_block(&mut self) -> Result<Block, String> {
+        self.function().loops += 1;
+        let block = self.block();
+        self.function().loops -= 1;
+        block
+    }
+
+    fn if_statement(&mut self, line: u32) -> Result<Stat, String> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+        loop {
+            // Skips `if` or `elseif`.
+            self.advance();
+            let condition = self.expression()?;
+            self.check(Token::Then, "then")?;
+            branches.push((condition, self.block()?));
+            match self.token() {
+                Token::Elseif => continue,
+                Token::Else => {
+                    self.advance();
+                    otherwise = Some(self.block()?);
+                    break;
+                }
+                _ => break,
+            }
+        }
+        self.check_match(Token::End, "end", "if", line)?;
+        Ok(Stat::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn for_statement(&mut self, line: u32) -> Result<Stat, String> {
+        self.advance();
+        let first = self.name()?;
+        let active = self.function().actives.len();
+        let statement = match self.token() {
+            Token::Assign => {
+                self.advance();
+                let start = self.expression()?;
+                self.check(Token::Comma, ",")?;
+                let limit = self.expression()?;
+                let step = if self.token() == &Token::Comma {
+                    self.advance();
+                    Some(self.expression()?)
+                } else {
+                    None
+                };
+                self.check(Token::Do, "do")?;
+                let slot = self.declare(first)?;
+                let block = self.loop_block()?;
+                Stat::NumericFor {
+                    slot,
+                    start,
+                    limit,
+                    step,
+                    block,
+                    line,
+                }
+            }
+            Token::Comma | Token::In => {
+                let mut names = vec![first];
+                while self.token() == &Token::Comma {
+                    self.advance();
+                    names.push(self.name()?);
+                }
+                self.check(Token::In, "in")?;
+                let values = self.expression_list()?;
+                self.check(Token::Do, "do")?;
+                let mut slots = Vec::with_capacity(names.len());
+                for name in names {
+                    slots.push(self.declare(name)?);
+                }
+                let block = self.loop_block()?;
+                Stat::GenericFor {
+                    slots,
+                    values,
+                    block,
+                    line,
+                }
+            }
+            _ => return Err(self.error_near("'=' or 'in' expected")),
+        };
+        self.function().actives.truncate(active);
+        self.check_match(Token::End, "end", "for", line)?;
+        Ok(statement)
+    }
+
+    // `function a.b.c:m() end` assigns to `a.b.c.m` with `self` as the first
+    // parameter.
+    fn function_statement(&mut self, line: u32) -> Result<Stat, String> {
+        self.advance();
+        let name = self.name()?;
+        let mut target = self.variable(name)?;
+        let mut is_method = false;
+        loop {
+            let key_line = self.line();
+            match self.token() {
+                Token::Dot | Token::Colon => {
+                    is_method = self.token() == &Token::Colon;
+                    self.advance();
+                    let key = self.name()?;
+                    target = Expr::Index(
+                        Box::new(target),
+                        Box::new(Expr::String(key.into_bytes().into())),
+                        key_line,
+                    );
+                    if is_method {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let body = self.function_body(is_method, line)?;
+        Ok(Stat::Assign {
+            targets: vec![self.target(target)?],
+            values: vec![Expr::Function(body)],
+        })
+    }
+
+    fn local_statement(&mut self) -> Result<Stat, String> {
+        let mut names = vec![self.name()?];
+        while self.token() == &Token::Comma {
+            self.advance();
+            names.push(self.name()?);
+        }
+        // The values can't see the new locals yet.
+        let values = if self.token() == &Token::Assign {
+            self.advance();
+            self.expression_list()?
+        } else {
+            Vec::new()
+        };
+        let mut slots = Vec::with_capacity(names.len());
+        for name in names {
+            slots.push(self.declare(name)?);
+        }
+        Ok(Stat::Local { slots, values })
+    }
+
+    fn expression_statement(&mut self) -> Result<Stat, String> {
+        let expression = self.suffixed_expression()?;
+        if matches!(self.token(), Token::Assign | Token::Comma) {
+            let mut targets = vec![self.target(expression)?];
+            while self.token() == &Token::Comma {
+                self.advance();
+                let expression = self.suffixed_expression()?;
+                targets.push(self.target(expression)?);
+            }
+            self.check(Token::Assign, "=")?;
+            let values = self.expression_list()?;
+            return Ok(Stat::Assign { targets, values });
+        }
+        // Anything else than a call is the start of an assignment.
+        if !matches!(expression, Expr::Call(..) | Expr::Method(..)) {
+            self.target(expression)?;
+            return Err(self.error_near("'=' expected"));
+        }
+        Ok(Stat::Call(expression))
+    }
+
+    fn target(&self, expression: Expr) -> Result<Target, String> {
+        Ok(match expression {
+            Expr::Local(slot, _) => Target::Local(slot),
+            Expr::Upvalue(index, _) => Target::Upvalue(index),
+            Expr::Global(name, line) => Target::Global(name, line),
+            Expr::Index(table, key, line) => Target::Index {
+                table: *table,
+                key: *key,
+                line,
+            },
+            _ => return Err(self.error_near("syntax error")),
+        })
+    }
+
+    fn function_body(&mut self, is_method: bool, line: u32) -> Result<Arc<FunctionBody>, String> {
+        self.functions.push(FunctionState {
+            line,
+            ..Default::default()
+        });
+        let mut parameters = 0;
+        if is_method {
+            self.declare("self".to_string())?;
+            parameters += 1;
+        }
+        self.check(Token::LeftParen, "(")?;
+        if self.token() != &Token::RightParen {
+            loop {
+                match self.token() {
+                    Token::Name(_) => {
+                        let name = self.name()?;
+                        self.declare(name)?;
+                        parameters += 1;
+                    }
+                    Token::Ellipsis => {
+                        self.advance();
+                        self.function().is_vararg = true;
+                        break;
+                    }
+                    _ => return Err(self.error_near("<name> expected")),
+                }
+                if self.token() != &Token::Comma {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.check(Token::RightParen, ")")?;
+        let block = self.statements()?;
+        self.check_match(Token::End, "end", "function", line)?;
+        let state = self.functions.pop().unwrap();
+        Ok(Arc::new(FunctionBody {
+            source: Arc::clone(&self.chunk_name),
+            parameters,
+            is_vararg: state.is_vararg,
+            block,
+            slot_count: state.slot_count,
+            captures: state.captures,
+        }))
+    }
+
+    fn expression_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut expressions = vec![self.expression()?];
+        while self.token() == &Token::Comma {
+            self.advance();
+            expressions.push(self.expression()?);
+        }
+        Ok(expressions)
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.subexpression(0)
+    }
+
+    // Operators binding tighter than `limit`, by precedence climbing.
+    fn subexpression(&mut self, limit: u8) -> Result<Expr, String> {
+        self.enter()?;
+        let line = self.line();
+        let unary = match self.token() {
+            Token::Not => Some(UnaryOp::Not),
+            Token::Minus => Some(UnaryOp::Neg),
+            Token::Hash => Some(UnaryOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(operator) => {
+                self.advance();
+                match (operator, self.subexpression(UNARY_PRIORITY)?) {
+                    (UnaryOp::Neg, Expr::Number(number)) => Expr::Number(-number),
+                    (operator, operand) => Expr::Unary(operator, Box::new(operand), line),
+                }
+            }
+            None => self.simple_expression()?,
+        };
+        while let Some((operator, left_priority, right_priority)) = binary_operator(self.token()) {
+            if left_priority <= limit {
+                break;
+            }
+            let line = self.line();
+            self.advance();
+            let right = Box::new(self.subexpression(right_priority)?);
+            let operand = Box::new(left);
+            left = match operator {
+                Operator::Binary(operator) => Expr::Binary(operator, operand, right, line),
+                Operator::And => Expr::And(operand, right),
+                Operator::Or => Expr::Or(operand, right),
+            };
+        }
+        self.leave();
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        let expression = match self.token() {
+            Token::Number(number) => Expr::Number(*number),
+            Token::String(string) => Expr::String(string.clone().into()),
+            Token::Nil => Expr::Nil,
+            Token::True => Expr::True,
+            Token::False => Expr::False,
+            Token::Ellipsis => {
+                if !self.function().is_vararg {
+                    return Err(self.error_near("cannot use '...' outside a vararg function"));
+                }
+                Expr::Vararg
+            }
+            Token::LeftBrace => return self.table(),
+            Token::Function => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body(false, line)?));
+            }
+            _ => return self.suffixed_expression(),
+        };
+        self.advance();
+        Ok(expression)
+    }
+
+    fn primary_expression(&mut self) -> Result<Expr, String> {
+        match self.token() {
+            Token::Name(_) => {
+                let name = self.name()?;
+                self.variable(name)
+            }
+            Token::LeftParen => {
+                let line = self.line();
+                self.advance();
+                let expression = self.expression()?;
+                self.check_match(Token::RightParen, ")", "(", line)?;
+                Ok(Expr::Paren(Box::new(expression)))
+            }
+            _ => Err(self.error_near("unexpected symbol")),
+        }
+    }
+
+    // A primary expression followed by fields, indexes and calls.
+    fn suffixed_expression(&mut self) -> Result<Expr, String> {
+        let mut expression = self.primary_expression()?;
+        loop {
+            let line = self.line();
+            match self.token() {
+                Token::Dot => {
+                    self.advance();
+                    let key = self.name()?;
+                    expression = Expr::Index(
+                        Box::new(expression),
+                        Box::new(Expr::String(key.into_bytes().into())),
+                        line,
+                    );
+                }
+                Token::LeftBracket => {
+                    self.advance();
+                    let key = self.expression()?;
+                    self.check(Token::RightBracket, "]")?;
+                    expression = Expr::Index(Box::new(expression), Box::new(key), line);
+                }
+                Token::Colon => {
+                    self.advance();
+                    let method = self.name()?;
+                    let arguments = self.arguments()?;
+                    expression = Expr::Method(
+                        Box::new(expression),
+                        method.into_bytes().into(),
+                        arguments,
+                        line,
+                    );
+                }
+                Token::LeftParen | Token::String(_) | Token::LeftBrace => {
+                    let arguments = self.arguments()?;
+                    expression = Expr::Call(Box::new(expression), arguments, line);
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Expr>, String> {
+        let line = self.line();
+        match self.token() {
+            Token::LeftParen => {
+                if line != self.lexemes[self.position - 1].line {
+                    return Err(self.error_near("ambiguous syntax (function call x new statement)"));
+                }
+                self.advance();
+                let arguments = if self.token() == &Token::RightParen {
+                    Vec::new()
+                } else {
+                    self.expression_list()?
+                };
+                self.check_match(Token::RightParen, ")", "(", line)?;
+                Ok(arguments)
+            }
+            Token::LeftBrace => Ok(vec![self.table()?]),
+            Token::String(string) => {
+                let argument = Expr::String(string.clone().into());
+                self.advance();
+                Ok(vec![argument])
+            }
+            _ => Err(self.error_near("function arguments expected")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        self.check(Token::LeftBrace, "{")?;
+        let mut fields = Vec::new();
+        while self.token() != &Token::RightBrace {
+            let field = match self.token() {
+                Token::Name(_) if self.peek_token() == &Token::Assign => {
+                    let key = self.name()?;
+                    self.advance();
+                    Field::Named(Expr::String(key.into_bytes().into()), self.expression()?)
+                }
+                Token::LeftBracket => {
+                    self.advance();
+                    let key = self.expression()?;
+                    self.check(Token::RightBracket, "]")?;
+                    self.check(Token::Assign, "=")?;
+                    Field::Named(key, self.expression()?)
+                }
+                _ => Field::Positional(self.expression()?),
+            };
+            fields.push(field);
+            if matches!(self.token(), Token::Comma | Token::Semicolon) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.check_match(Token::RightBrace, "}", "{", line)?;
+        Ok(Expr::Table(fields, line))
+    }
+}
@@ -0,0 +1,398 @@
+// Lua patterns, following lstrlib.c.
+pub const MAX_CAPTURES: usize = 32;
+
+const ESCAPE: u8 = b'%';
+pub const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+// Capture lengths that aren't lengths.
+const UNFINISHED: isize = -1;
+const POSITION: isize = -2;
+
+// Patterns recursing deeper than this are "too complex", like in Lua 5.2.
+const MAX_RECURSION: usize = 200;
+
+pub enum Captured<'a> {
+    String(&'a [u8]),
+    Position(usize),
+}
+
+pub struct Matcher<'a> {
+    source: &'a [u8],
+    pattern: &'a [u8],
+    level: usize,
+    captures: [(usize, isize); MAX_CAPTURES],
+    depth: usize,
+}
+
+fn class_matches(byte: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => byte.is_ascii_alphabetic(),
+        b'c' => byte.is_ascii_control(),
+        b'd' => byte.is_ascii_digit(),
+        b'l' => byte.is_ascii_lowercase(),
+        b'p' => byte.is_ascii_punctuation(),
+        // isspace also counts the vertical tab.
+        b's' => byte.is_ascii_whitespace() || byte == 0x0B,
+        b'u' => byte.is_ascii_uppercase(),
+        b'w' => byte.is_ascii_alphanumeric(),
+        b'x' => byte.is_ascii_hexdigit(),
+        b'z' => byte == 0,
+        _ => return class == byte,
+    };
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(source: &'a [u8], pattern: &'a [u8]) -> Self {
+        Self {
+            source,
+            pattern,
+            level: 0,
+            captures: [(0, 0); MAX_CAPTURES],
+            depth: 0,
+        }
+    }
+
+    pub fn source(&self) -> &'a [u8] {
+        self.source
+    }
+
+    // Where a match of the pattern from `pattern_start` at `start` ends.
+    pub fn find_at(&mut self, start: usize, pattern_start: usize) -> Result<Option<usize>, String> {
+        self.level = 0;
+        self.depth = 0;
+        self.matches(start, pattern_start)
+    }
+
+    fn pattern_byte(&self, position: usize) -> Option<u8> {
+        self.pattern.get(position).copied()
+    }
+
+    // The end of the single character class starting at `position`.
+    fn class_end(&self, mut position: usize) -> Result<usize, String> {
+        let byte = self.pattern[position];
+        position += 1;
+        match byte {
+            ESCAPE => {
+                if position >= self.pattern.len() {
+                    return Err("malformed pattern (ends with '%')".to_string());
+                }
+                Ok(position + 1)
+            }
+            b'[' => {
+                if self.pattern_byte(position) == Some(b'^') {
+                    position += 1;
+                }
+                // The first ']' is part of the set.
+                loop {
+                    let Some(byte) = self.pattern_byte(position) else {
+                        return Err("malformed pattern (missing ']')".to_string());
+                    };
+                    position += 1;
+                    if byte == ESCAPE && position < self.pattern.len() {
+                        position += 1;
+                    }
+                    if self.pattern_byte(position) == Some(b']') {
+                        return Ok(position + 1);
+                    }
+                }
+            }
+            _ => Ok(position),
+        }
+    }
+
+    // `start` is at '[' and `end` at the closing ']'.
+    fn bracket_class_matches(&self, byte: u8, mut start: usize, end: usize) -> bool {
+        let mut found = true;
+        if self.pattern_byte(start + 1) == Some(b'^') {
+            found = false;
+            start += 1;
+        }
+        let mut position = start + 1;
+        while position < end {
+            let current = self.pattern[position];
+            if current == ESCAPE {
+                position += 1;
+                if class_matches(byte, self.pattern[position]) {
+                    return found;
+                }
+            } else if self.pattern_byte(position + 1) == Some(b'-') && position + 2 < end {
+                if current <= byte && byte <= self.pattern[position + 2] {
+                    return found;
+                }
+                position += 2;
+            } else if current == byte {
+                return found;
+            }
+            position += 1;
+        }
+        !found
+    }
+
+    fn single_matches(&self, position: usize, class: usize, class_end: usize) -> bool {
+        let Some(&byte) = self.source.get(position) else {
+            return false;
+        };
+        match self.pattern[class] {
+            b'.' => true,
+            ESCAPE => class_matches(byte, self.pattern[class + 1]),
+            b'[' => self.bracket_class_matches(byte, class, class_end - 1),
+            other => other == byte,
+        }
+    }
+
+    fn matches(&mut self, source: usize, pattern: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+        if self.depth > MAX_RECURSION {
+            return Err("pattern too complex".to_string());
+        }
+        let result = self.match_here(source, pattern);
+        self.depth -= 1;
+        result
+    }
+
+    fn match_here(
+        &mut self,
+        mut source: usize,
+        mut pattern: usize,
+    ) -> Result<Option<usize>, String> {
+        loop {
+            let Some(byte) = self.pattern_byte(pattern) else {
+                return Ok(Some(source));
+            };
+            match byte {
+                b'(' => {
+                    return if self.pattern_byte(pattern + 1) == Some(b')') {
+                        self.start_capture(source, pattern + 2, POSITION)
+                    } else {
+                        self.start_capture(source, pattern + 1, UNFINISHED)
+                    };
+                }
+                b')' => return self.end_capture(source, pattern + 1),
+                b'$' if pattern + 1 == self.pattern.len() => {
+                    return Ok((source == self.source.len()).then_some(source));
+                }
+                ESCAPE if self.pattern_byte(pattern + 1) == Some(b'b') => {
+                    match self.match_balance(source, pattern + 2)? {
+                        Some(end) => {
+                            source = end;
+                            pattern += 4;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                ESCAPE if self.pattern_byte(pattern + 1) == Some(b'f') => {
+                    pattern += 2;
+                    if self.pattern_byte(pattern) != Some(b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let end = self.class_end(pattern)?;
+                    let previous = if source == 0 {
+                        0
+                    } else {
+                        self.source[source - 1]
+                    };
+                    let current = self.source.get(source).copied().unwrap_or(0);
+                    if self.bracket_class_matches(previous, pattern, end - 1)
+                        || !self.bracket_class_matches(current, pattern, end - 1)
+                    {
+                        return Ok(None);
+                    }
+                    pattern = end;
+                    continue;
+                }
+                ESCAPE
+                    if self
+                        .pattern_byte(pattern + 1)
+                        .is_some_and(|byte| byte.is_ascii_digit()) =>
+                {
+                    let index = self.check_capture(self.pattern[pattern + 1])?;
+                    let (start, length) = self.captures[index];
+                    let length = length as usize;
+                    if self.source.len() - source >= length
+                        && self.source[start..start + length]
+                            == self.source[source..source + length]
+                    {
+                        source += length;
+                        pattern += 2;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                _ => {}
+            }
+
+            let end = self.class_end(pattern)?;
+            let matched = self.single_matches(source, pattern, end);
+            match self.pattern_byte(end) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(result) = self.matches(source + 1, end + 1)? {
+                            return Ok(Some(result));
+                        }
+                    }
+                    pattern = end + 1;
+                }
+                Some(b'*') => return self.max_expand(source, pattern, end),
+                Some(b'+') => {
+                    return if matched {
+                        self.max_expand(source + 1, pattern, end)
+                    } else {
+                        Ok(None)
+                    };
+                }
+                Some(b'-') => return self.min_expand(source, pattern, end),
+                _ => {
+                    if !matched {
+                        return Ok(None);
+                    }
+                    source += 1;
+                    pattern = end;
+                }
+            }
+        }
+    }
+
+    fn max_expand(
+        &mut self,
+        source: usize,
+        class: usize,
+        end: usize,
+    ) -> Result<Option<usize>, String> {
+        let mut count = 0;
+        while self.single_matches(source + count, class, end) {
+            count += 1;
+        }
+        loop {
+            if let Some(result) = self.matches(source + count, end + 1)? {
+                return Ok(Some(result));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(
+        &mut self,
+        mut source: usize,
+        class: usize,
+        end: usize,
+    ) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(result) = self.matches(source, end + 1)? {
+                return Ok(Some(result));
+            }
+            if self.single_matches(source, class, end) {
+                source += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn match_balance(&self, source: usize, pattern: usize) -> Result<Option<usize>, String> {
+        let (Some(open), Some(close)) =
+            (self.pattern_byte(pattern), self.pattern_byte(pattern + 1))
+        else {
+            return Err("unbalanced pattern".to_string());
+        };
+        if self.source.get(source) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (position, &byte) in self.source.iter().enumerate().skip(source + 1) {
+            if byte == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(position + 1));
+                }
+            } else if byte == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn start_capture(
+        &mut self,
+        source: usize,
+        pattern: usize,
+        kind: isize,
+    ) -> Result<Option<usize>, String> {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+        self.captures[self.level] = (source, kind);
+        self.level += 1;
+        let result = self.matches(source, pattern)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, source: usize, pattern: usize) -> Result<Option<usize>, String> {
+        let Some(index) = (0..self.level)
+            .rev()
+            .find(|index| self.captures[*index].1 == UNFINISHED)
+        else {
+            return Err("invalid pattern capture".to_string());
+        };
+        self.captures[index].1 = (source - self.captures[index].0) as isize;
+        let result = self.matches(source, pattern)?;
+        if result.is_none() {
+            self.captures[index].1 = UNFINISHED;
+        }
+        Ok(result)
+    }
+
+    fn check_capture(&self, digit: u8) -> Result<usize, String> {
+        let index = (digit as usize).wrapping_sub(b'1' as usize);
+        if index >= self.level || self.captures[index].1 == UNFINISHED {
+            return Err("invalid capture index".to_string());
+        }
+        Ok(index)
+    }
+
+    // The captures made, or the whole match between `start` and `end` if
+    // the pattern has none.
+    pub fn captures(
+        &self,
+        start: usize,
+        end: usize,
+        whole_match: bool,
+    ) -> Result<Vec<Captured<'a>>, String> {
+        let count = if self.level == 0 && whole_match {
+            1
+        } else {
+            self.level
+        };
+        (0..count)
+            .map(|index| self.capture(index, start, end))
+            .collect()
+    }
+
+    pub fn capture(&self, index: usize, start: usize, end: usize) -> Result<Captured<'a>, String> {
+        if index >= self.level {
+            if index == 0 {
+                return Ok(Captured::String(&self.source[start..end]));
+            }
+            return Err("invalid capture index".to_string());
+        }
+        let (capture_start, length) = self.captures[index];
+        match length {
+            UNFINISHED => Err("unfinished capture".to_string()),
+            POSITION => Ok(Captured::Position(capture_start + 1)),
+            length => Ok(Captured::String(
+                &self.source[capture_start..capture_start + length as usize],
+            )),
+        }
+    }
+}
@@ -0,0 +1,873 @@
+use std::{sync::OnceLock, time::Instant};
+
+use super::{
+    cjson, cmsgpack,
+    interpreter::{first, Lua},
+    parser, strings, structs,
+    value::{LuaString, NativeFn, TableRef, Value},
+    LuaError,
+};
+
+// Like LUAI_MAXCSTACK, the most values `unpack` returns.
+const MAX_RESULTS: i64 = 8000;
+
+// Redis's rand48, so that math.random gives the same numbers everywhere.
+const RAND48_MAX: u64 = i32::MAX as u64;
+
+pub fn open(lua: &mut Lua) {
+    let globals = lua.globals.clone();
+    register(
+        &globals,
+        &[
+            ("assert", assert),
+            ("collectgarbage", collectgarbage),
+            ("error", error),
+            ("getmetatable", getmetatable),
+            ("ipairs", ipairs),
+            ("loadstring", loadstring),
+            ("next", next),
+            ("pairs", pairs),
+            ("pcall", pcall),
+            ("print", print),
+            ("rawequal", rawequal),
+            ("rawget", rawget),
+            ("rawset", rawset),
+            ("select", select),
+            ("setmetatable", setmetatable),
+            ("tonumber", tonumber),
+            ("tostring", tostring),
+            ("type", type_),
+            ("unpack", unpack),
+            ("xpcall", xpcall),
+        ],
+    );
+    globals.set_field("_G", Value::Table(globals.clone()));
+    globals.set_field("_VERSION", Value::string("Lua 5.1"));
+
+    let table = TableRef::new();
+    register(
+        &table,
+        &[
+            ("concat", concat),
+            ("getn", getn),
+            ("insert", insert),
+            ("maxn", maxn),
+            ("remove", remove),
+            ("sort", sort),
+        ],
+    );
+    globals.set_field("table", Value::Table(table));
+
+    let math = TableRef::new();
+    register(
+        &math,
+        &[
+            ("abs", |_, _, args| math_function(args, "abs", f64::abs)),
+            ("acos", |_, _, args| math_function(args, "acos", f64::acos)),
+            ("asin", |_, _, args| math_function(args, "asin", f64::asin)),
+            ("atan", |_, _, args| math_function(args, "atan", f64::atan)),
+            ("atan2", atan2),
+            ("ceil", |_, _, args| math_function(args, "ceil", f64::ceil)),
+            ("cos", |_, _, args| math_function(args, "cos", f64::cos)),
+            ("cosh", |_, _, args| math_function(args, "cosh", f64::cosh)),
+            ("deg", |_, _, args| {
+                math_function(args, "deg", f64::to_degrees)
+            }),
+            ("exp", |_, _, args| math_function(args, "exp", f64::exp)),
+            ("floor", |_, _, args| {
+                math_function(args, "floor", f64::floor)
+            }),
+            ("fmod", fmod),
+            ("frexp", frexp),
+            ("ldexp", ldexp),
+            ("log", |_, _, args| math_function(args, "log", f64::ln)),
+            ("log10", |_, _, args| {
+                math_function(args, "log10", f64::log10)
+            }),
+            ("max", max),
+            ("min", min),
+            ("mod", fmod),
+            ("modf", modf),
+            ("pow", pow),
+            ("rad", |_, _, args| {
+                math_function(args, "rad", f64::to_radians)
+            }),
+            ("random", random),
+            ("randomseed", randomseed),
+            ("sin", |_, _, args| math_function(args, "sin", f64::sin)),
+            ("sinh", |_, _, args| math_function(args, "sinh", f64::sinh)),
+            ("sqrt", |_, _, args| math_function(args, "sqrt", f64::sqrt)),
+            ("tan", |_, _, args| math_function(args, "tan", f64::tan)),
+            ("tanh", |_, _, args| math_function(args, "tanh", f64::tanh)),
+        ],
+    );
+    math.set_field("pi", Value::Number(std::f64::consts::PI));
+    math.set_field("huge", Value::Number(f64::INFINITY));
+    globals.set_field("math", Value::Table(math));
+    seed_random(lua, 0);
+
+    // Only the clock, scripts can't reach the system.
+    let os = TableRef::new();
+    register(&os, &[("clock", clock)]);
+    globals.set_field("os", Value::Table(os));
+
+    let bit = TableRef::new();
+    register(
+        &bit,
+        &[
+            ("arshift", |_, _, args| {
+                shift(args, "arshift", |x, n| x >> n)
+            }),
+            ("band", |_, _, args| fold_bits(args, "band", |a, b| a & b)),
+            ("bnot", |_, _, args| {
+                Ok(vec![bits_value(!check_bits(&args, 1, "bnot")?)])
+            }),
+            ("bor", |_, _, args| fold_bits(args, "bor", |a, b| a | b)),
+            ("bswap", |_, _, args| {
+                Ok(vec![bits_value(
+                    check_bits(&args, 1, "bswap")?.swap_bytes(),
+                )])
+            }),
+            ("bxor", |_, _, args| fold_bits(args, "bxor", |a, b| a ^ b)),
+            ("lshift", |_, _, args| shift(args, "lshift", |x, n| x << n)),
+            ("rol", |_, _, args| {
+                shift(args, "rol", |x, n| x.rotate_left(n as u32))
+            }),
+            ("ror", |_, _, args| {
+                shift(args, "ror", |x, n| x.rotate_right(n as u32))
+            }),
+            ("rshift", |_, _, args| {
+                shift(args, "rshift", |x, n| ((x as u32) >> n) as i32)
+            }),
+            ("tobit", |_, _, args| {
+                Ok(vec![bits_value(check_bits(&args, 1, "tobit")?)])
+            }),
+            ("tohex", tohex),
+        ],
+    );
+    globals.set_field("bit", Value::Table(bit));
+
+    // Scripts using coroutines get told, rather than an error about
+    // indexing nil.
+    let coroutine = TableRef::new();
+    register(
+        &coroutine,
+        &[
+            ("create", no_coroutines),
+            ("resume", no_coroutines),
+            ("running", no_coroutines),
+            ("status", no_coroutines),
+            ("wrap", no_coroutines),
+            ("yield", no_coroutines),
+        ],
+    );
+    globals.set_field("coroutine", Value::Table(coroutine));
+
+    strings::open(lua);
+    cjson::open(lua);
+    cmsgpack::open(lua);
+    structs::open(lua);
+}
+
+pub fn register(table: &TableRef, functions: &[(&'static str, NativeFn)]) {
+    for (name, function) in functions {
+        table.set_field(name, Value::native(*function));
+    }
+}
+
+// Argument checks, with the messages of lauxlib.
+
+pub fn argument(args: &[Value], position: usize) -> Value {
+    args.get(position - 1).cloned().unwrap_or_default()
+}
+
+pub fn argument_error(position: usize, function: &str, message: &str) -> LuaError {
+    LuaError::Builtin(format!(
+        "bad argument #{} to '{}' ({})",
+        position, function, message
+    ))
+}
+
+pub fn expected(args: &[Value], position: usize, function: &str, expected: &str) -> LuaError {
+    let got = args
+        .get(position - 1)
+        .map_or("no value", |value| value.type_name());
+    argument_error(
+        position,
+        function,
+        &format!("{} expected, got {}", expected, got),
+    )
+}
+
+pub fn check_any(args: &[Value], position: usize, function: &str) -> Result<Value, LuaError> {
+    args.get(position - 1)
+        .cloned()
+        .ok_or_else(|| argument_error(position, function, "value expected"))
+}
+
+pub fn check_table(args: &[Value], position: usize, function: &str) -> Result<TableRef, LuaError> {
+    match args.get(position - 1) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(expected(args, position, function, "table")),
+    }
+}
+
+pub fn check_number(args: &[Value], position: usize, function: &str) -> Result<f64, LuaError> {
+    args.get(position - 1)
+        .and_then(Value::to_number)
+        .ok_or_else(|| expected(args, position, function, "number"))
+}
+
+pub fn check_integer(args: &[Value], position: usize, function: &str) -> Result<i64, LuaError> {
+    Ok(check_number(args, position, function)? as i64)
+}
+
+pub fn optional_integer(
+    args: &[Value],
+    position: usize,
+    function: &str,
+    default: i64,
+) -> Result<i64, LuaError> {
+    match args.get(position - 1) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_integer(args, position, function),
+    }
+}
+
+pub fn check_string(
+    args: &[Value],
+    position: usize,
+    function: &str,
+) -> Result<LuaString, LuaError> {
+    args.get(position - 1)
+        .and_then(Value::to_lua_string)
+        .ok_or_else(|| expected(args, position, function, "string"))
+}
+
+// The base library.
+
+fn assert(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let value = check_any(&args, 1, "assert")?;
+    if !value.is_truthy() {
+        let message = match args.get(1) {
+            Some(message) => match message.to_lua_string() {
+                Some(message) => String::from_utf8_lossy(&message).into_owned(),
+                None => return Err(expected(&args, 2, "assert", "string")),
+            },
+            None => "assertion failed!".to_string(),
+        };
+        return Err(LuaError::Builtin(message));
+    }
+    Ok(args)
+}
+
+fn collectgarbage(_: &mut Lua, _: &[Value], _: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Number(0.0)])
+}
+
+// Strings are prefixed with where `error` was called, or where the
+// function calling it was called for level 2.
+fn error(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut value = argument(&args, 1);
+    let level = optional_integer(&args, 2, "error", 1)?;
+    let line = lua.call_site(1).map_or(0, |(_, line)| line);
+    if let Value::String(message) = &value {
+        if level > 0 {
+            if let Some((source, line)) = lua.call_site(level as usize) {
+                let mut positioned = format!("{}:{}: ", source, line).into_bytes();
+                positioned.extend_from_slice(message);
+                value = Value::string(positioned);
+            }
+        }
+    }
+    Err(LuaError::Raised(value, line))
+}
+
+fn getmetatable(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let value = check_any(&args, 1, "getmetatable")?;
+    let Some(metatable) = lua.metatable(&value) else {
+        return Ok(vec![Value::Nil]);
+    };
+    let protected = metatable.get_field("__metatable");
+    if !protected.is_nil() {
+        return Ok(vec![protected]);
+    }
+    Ok(vec![Value::Table(metatable)])
+}
+
+fn setmetatable(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "setmetatable")?;
+    let metatable = match argument(&args, 2) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => return Err(argument_error(2, "setmetatable", "nil or table expected")),
+    };
+    if table
+        .metatable()
+        .is_some_and(|current| !current.get_field("__metatable").is_nil())
+    {
+        return Err(LuaError::Builtin(
+            "cannot change a protected metatable".to_string(),
+        ));
+    }
+    if table.0.borrow().readonly {
+        return Err(LuaError::Builtin(
+            "Attempt to modify a readonly table".to_string(),
+        ));
+    }
+    table.0.borrow_mut().metatable = metatable;
+    Ok(vec![Value::Table(table)])
+}
+
+fn ipairs(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "ipairs")?;
+    Ok(vec![
+        Value::native(ipairs_next),
+        Value::Table(table),
+        Value::Number(0.0),
+    ])
+}
+
+fn ipairs_next(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "ipairs")?;
+    let index = check_integer(&args, 2, "ipairs")? + 1;
+    let value = table.get(&Value::Number(index as f64));
+    if value.is_nil() {
+        return Ok(vec![Value::Nil]);
+    }
+    Ok(vec![Value::Number(index as f64), value])
+}
+
+fn next(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "next")?;
+    let entry = table
+        .0
+        .borrow()
+        .next(&argument(&args, 2))
+        .map_err(LuaError::Builtin)?;
+    Ok(match entry {
+        Some((key, value)) => vec![key, value],
+        None => vec![Value::Nil],
+    })
+}
+
+fn pairs(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "pairs")?;
+    Ok(vec![Value::native(next), Value::Table(table), Value::Nil])
+}
+
+// The message of an error caught by pcall, interruptions aren't caught.
+fn caught(error: LuaError) -> Result<Value, LuaError> {
+    match error {
+        LuaError::Raised(value, _) => Ok(value),
+        LuaError::Builtin(message) => Ok(Value::string(message)),
+        interrupted => Err(interrupted),
+    }
+}
+
+fn pcall(lua: &mut Lua, _: &[Value], mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    check_any(&args, 1, "pcall")?;
+    let function = args.remove(0);
+    match lua.call(&function, args) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(error) => Ok(vec![Value::Boolean(false), caught(error)?]),
+    }
+}
+
+fn xpcall(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let function = check_any(&args, 1, "xpcall")?;
+    let handler = check_any(&args, 2, "xpcall")?;
+    match lua.call(&function, Vec::new()) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(error) => {
+            let message = caught(error)?;
+            let handled = match lua.call(&handler, vec![message]) {
+                Ok(values) => first(values),
+                Err(error) => caught(error)?,
+            };
+            Ok(vec![Value::Boolean(false), handled])
+        }
+    }
+}
+
+// The values are still converted, `__tostring` errors included, but the
+// server has no console for scripts to write to.
+fn print(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    for value in &args {
+        lua.tostring(value)?;
+    }
+    Ok(Vec::new())
+}
+
+fn rawequal(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let a = check_any(&args, 1, "rawequal")?;
+    let b = check_any(&args, 2, "rawequal")?;
+    Ok(vec![Value::Boolean(a.raw_equals(&b))])
+}
+
+fn rawget(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "rawget")?;
+    let key = check_any(&args, 2, "rawget")?;
+    Ok(vec![table.get(&key)])
+}
+
+fn rawset(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "rawset")?;
+    let key = check_any(&args, 2, "rawset")?;
+    let value = check_any(&args, 3, "rawset")?;
+    table.set(key, value).map_err(LuaError::Builtin)?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(_: &mut Lua, _: &[Value], mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let count = args.len() as i64;
+    if let Value::String(selector) = argument(&args, 1) {
+        if selector.first() == Some(&b'#') {
+            return Ok(vec![Value::Number((count - 1) as f64)]);
+        }
+    }
+    let mut index = check_integer(&args, 1, "select")?;
+    if index < 0 {
+        index += count;
+    } else if index > count {
+        index = count;
+    }
+    if index < 1 {
+        return Err(argument_error(1, "select", "index out of range"));
+    }
+    Ok(args.split_off(index as usize))
+}
+
+fn tonumber(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let base = optional_integer(&args, 2, "tonumber", 10)?;
+    if base == 10 {
+        let value = check_any(&args, 1, "tonumber")?;
+        return Ok(vec![value.to_number().map_or(Value::Nil, Value::Number)]);
+    }
+    if !(2..=36).contains(&base) {
+        return Err(argument_error(2, "tonumber", "base out of range"));
+    }
+    let text = check_string(&args, 1, "tonumber")?;
+    let text = String::from_utf8_lossy(&text);
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+    let mut number = 0.0;
+    for digit in digits.chars() {
+        match digit.to_digit(base as u32) {
+            Some(digit) => number = number * base as f64 + digit as f64,
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+    Ok(vec![Value::Number(if negative { -number } else { number })])
+}
+
+fn tostring(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let value = check_any(&args, 1, "tostring")?;
+    Ok(vec![Value::String(lua.tostring(&value)?)])
+}
+
+fn type_(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let value = check_any(&args, 1, "type")?;
+    Ok(vec![Value::string(value.type_name())])
+}
+
+fn unpack(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "unpack")?;
+    let start = optional_integer(&args, 2, "unpack", 1)?;
+    let end = optional_integer(&args, 3, "unpack", table.len() as i64)?;
+    if start > end {
+        return Ok(Vec::new());
+    }
+    if end - start >= MAX_RESULTS {
+        return Err(LuaError::Builtin("too many results to unpack".to_string()));
+    }
+    Ok((start..=end)
+        .map(|index| table.get(&Value::Number(index as f64)))
+        .collect())
+}
+
+// Chunks are named after their first line, like `[string "return 1"]`.
+fn chunk_name(source: &[u8]) -> String {
+    let line = source
+        .split(|byte| *byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    if line.len() < source.len() || line.len() > 45 {
+        let short: String = line.chars().take(42).collect();
+        format!("[string \"{}...\"]", short)
+    } else {
+        format!("[string \"{}\"]", line)
+    }
+}
+
+fn loadstring(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let source = check_string(&args, 1, "loadstring")?;
+    let name = match argument(&args, 2) {
+        Value::Nil => chunk_name(&source),
+        _ => {
+            let name = check_string(&args, 2, "loadstring")?;
+            let name = String::from_utf8_lossy(&name).into_owned();
+            match name.strip_prefix(['=', '@']) {
+                Some(name) => name.to_string(),
+                None => chunk_name(name.as_bytes()),
+            }
+        }
+    };
+    match parser::parse(&source, &name) {
+        Ok(body) => Ok(vec![lua.load(body)]),
+        Err(message) => Ok(vec![Value::Nil, Value::string(message)]),
+    }
+}
+
+// The table library.
+
+fn getn(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Number(
+        check_table(&args, 1, "getn")?.len() as f64
+    )])
+}
+
+fn concat(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "concat")?;
+    let separator = match argument(&args, 2) {
+        Value::Nil => None,
+        _ => Some(check_string(&args, 2, "concat")?),
+    };
+    let start = optional_integer(&args, 3, "concat", 1)?;
+    let end = optional_integer(&args, 4, "concat", table.len() as i64)?;
+    let mut result = Vec::new();
+    let mut index = start;
+    while index <= end {
+        let Some(string) = table.get(&Value::Number(index as f64)).to_lua_string() else {
+            return Err(LuaError::Builtin(format!(
+                "invalid value (at index {}) in table for 'concat'",
+                index
+            )));
+        };
+        result.extend_from_slice(&string);
+        if index < end {
+            if let Some(separator) = &separator {
+                result.extend_from_slice(separator);
+            }
+        }
+        index += 1;
+    }
+    Ok(vec![Value::string(result)])
+}
+
+fn insert(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "insert")?;
+    let mut end = table.len() as i64 + 1;
+    let (position, value) = match args.len() {
+        2 => (end, args[1].clone()),
+        3 => {
+            let position = check_integer(&args, 2, "insert")?;
+            end = end.max(position);
+            for index in (position + 1..=end).rev() {
+                let previous = table.get(&Value::Number((index - 1) as f64));
+                table
+                    .set(Value::Number(index as f64), previous)
+                    .map_err(LuaError::Builtin)?;
+            }
+            (position, args[2].clone())
+        }
+        _ => {
+            return Err(LuaError::Builtin(
+                "wrong number of arguments to 'insert'".to_string(),
+            ))
+        }
+    };
+    table
+        .set(Value::Number(position as f64), value)
+        .map_err(LuaError::Builtin)?;
+    Ok(Vec::new())
+}
+
+fn maxn(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "maxn")?;
+    let mut max: f64 = 0.0;
+    let mut key = Value::Nil;
+    while let Some((next, _)) = table.0.borrow().next(&key).map_err(LuaError::Builtin)? {
+        if let Value::Number(number) = next {
+            max = max.max(number);
+        }
+        key = next;
+    }
+    Ok(vec![Value::Number(max)])
+}
+
+fn remove(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "remove")?;
+    let end = table.len() as i64;
+    let position = optional_integer(&args, 2, "remove", end)?;
+    if !(1..=end).contains(&position) {
+        return Ok(Vec::new());
+    }
+    let removed = table.get(&Value::Number(position as f64));
+    for index in position..end {
+        let next = table.get(&Value::Number((index + 1) as f64));
+        table
+            .set(Value::Number(index as f64), next)
+            .map_err(LuaError::Builtin)?;
+    }
+    table
+        .set(Value::Number(end as f64), Value::Nil)
+        .map_err(LuaError::Builtin)?;
+    Ok(vec![removed])
+}
+
+// A merge sort, the comparison can fail.
+fn sort(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let table = check_table(&args, 1, "sort")?;
+    let comparator = match argument(&args, 2) {
+        Value::Nil => None,
+        function @ Value::Function(_) => Some(function),
+        _ => return Err(expected(&args, 2, "sort", "function")),
+    };
+    let length = table.len();
+    let mut values: Vec<Value> = (1..=length)
+        .map(|index| table.get(&Value::Number(index as f64)))
+        .collect();
+    let mut less = |a: &Value, b: &Value| -> Result<bool, LuaError> {
+        match &comparator {
+            Some(function) => {
+                Ok(first(lua.call(function, vec![a.clone(), b.clone()])?).is_truthy())
+            }
+            None => lua.less_than(a, b),
+        }
+    };
+    let mut buffer = values.clone();
+    let mut width = 1;
+    while width < length {
+        let mut start = 0;
+        while start < length {
+            let middle = (start + width).min(length);
+            let end = (start + 2 * width).min(length);
+            let (mut left, mut right) = (start, middle);
+            for slot in buffer.iter_mut().take(end).skip(start) {
+                let take_right =
+                    left >= middle || (right < end && less(&values[right], &values[left])?);
+                if take_right {
+                    *slot = values[right].clone();
+                    right += 1;
+                } else {
+                    *slot = values[left].clone();
+                    left += 1;
+                }
+            }
+            start = end;
+        }
+        std::mem::swap(&mut values, &mut buffer);
+        width *= 2;
+    }
+    for (index, value) in values.into_iter().enumerate() {
+        table
+            .set(Value::Number((index + 1) as f64), value)
+            .map_err(LuaError::Builtin)?;
+    }
+    Ok(Vec::new())
+}
+
+// The math library.
+
+fn math_function(
+    args: Vec<Value>,
+    name: &str,
+    function: fn(f64) -> f64,
+) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Number(function(check_number(&args, 1, name)?))])
+}
+
+fn atan2(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let y = check_number(&args, 1, "atan2")?;
+    let x = check_number(&args, 2, "atan2")?;
+    Ok(vec![Value::Number(y.atan2(x))])
+}
+
+fn fmod(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let a = check_number(&args, 1, "fmod")?;
+    let b = check_number(&args, 2, "fmod")?;
+    Ok(vec![Value::Number(a % b)])
+}
+
+fn pow(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let a = check_number(&args, 1, "pow")?;
+    let b = check_number(&args, 2, "pow")?;
+    Ok(vec![Value::Number(a.powf(b))])
+}
+
+fn modf(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let number = check_number(&args, 1, "modf")?;
+    if number.is_infinite() {
+        return Ok(vec![Value::Number(number), Value::Number(0.0)]);
+    }
+    Ok(vec![
+        Value::Number(number.trunc()),
+        Value::Number(number.fract()),
+    ])
+}
+
+fn frexp(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let number = check_number(&args, 1, "frexp")?;
+    if number == 0.0 || !number.is_finite() {
+        return Ok(vec![Value::Number(number), Value::Number(0.0)]);
+    }
+    let mut exponent = number.abs().log2().floor() as i32 + 1;
+    let mut mantissa = number / 2f64.powi(exponent);
+    // Rounding of log2 can be off by one.
+    if mantissa.abs() >= 1.0 {
+        mantissa /= 2.0;
+        exponent += 1;
+    } else if mantissa.abs() < 0.5 {
+        mantissa *= 2.0;
+        exponent -= 1;
+    }
+    Ok(vec![
+        Value::Number(mantissa),
+        Value::Number(exponent as f64),
+    ])
+}
+
+fn ldexp(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mantissa = check_number(&args, 1, "ldexp")?;
+    let exponent = check_integer(&args, 2, "ldexp")?;
+    Ok(vec![Value::Number(mantissa * 2f64.powi(exponent as i32))])
+}
+
+fn max(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut max = check_number(&args, 1, "max")?;
+    for position in 2..=args.len() {
+        let number = check_number(&args, position, "max")?;
+        if number > max {
+            max = number;
+        }
+    }
+    Ok(vec![Value::Number(max)])
+}
+
+fn min(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut min = check_number(&args, 1, "min")?;
+    for position in 2..=args.len() {
+        let number = check_number(&args, position, "min")?;
+        if number < min {
+            min = number;
+        }
+    }
+    Ok(vec![Value::Number(min)])
+}
+
+pub fn seed_random(lua: &mut Lua, seed: i32) {
+    lua.random_state = ((seed as u32 as u64) << 16) | 0x330E;
+}
+
+fn next_random(lua: &mut Lua) -> u64 {
+    lua.random_state = (0x5DEECE66D_u64
+        .wrapping_mul(lua.random_state)
+        .wrapping_add(0xB))
+        & ((1 << 48) - 1);
+    lua.random_state >> 17
+}
+
+fn random(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let fraction = (next_random(lua) % RAND48_MAX) as f64 / RAND48_MAX as f64;
+    let number = match args.len() {
+        0 => fraction,
+        1 => {
+            let upper = check_integer(&args, 1, "random")?;
+            if upper < 1 {
+                return Err(argument_error(1, "random", "interval is empty"));
+            }
+            (fraction * upper as f64).floor() + 1.0
+        }
+        2 => {
+            let lower = check_integer(&args, 1, "random")?;
+            let upper = check_integer(&args, 2, "random")?;
+            if lower > upper {
+                return Err(argument_error(2, "random", "interval is empty"));
+            }
+            (fraction * (upper - lower + 1) as f64).floor() + lower as f64
+        }
+        _ => return Err(LuaError::Builtin("wrong number of arguments".to_string())),
+    };
+    Ok(vec![Value::Number(number)])
+}
+
+fn randomseed(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let seed = check_integer(&args, 1, "randomseed")?;
+    seed_random(lua, seed as i32);
+    Ok(Vec::new())
+}
+
+fn clock(_: &mut Lua, _: &[Value], _: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    static STARTED: OnceLock<Instant> = OnceLock::new();
+    let started = STARTED.get_or_init(Instant::now);
+    Ok(vec![Value::Number(started.elapsed().as_secs_f64())])
+}
+
+fn no_coroutines(_: &mut Lua, _: &[Value], _: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Err(LuaError::Builtin(
+        "coroutines are not supported".to_string(),
+    ))
+}
+
+// The bit library, on 32 bit integers like LuaBitOp.
+
+fn check_bits(args: &[Value], position: usize, function: &str) -> Result<i32, LuaError> {
+    let number = check_number(args, position, function)?;
+    Ok(number.round().rem_euclid(4294967296.0) as u32 as i32)
+}
+
+fn bits_value(bits: i32) -> Value {
+    Value::Number(bits as f64)
+}
+
+fn fold_bits(
+    args: Vec<Value>,
+    function: &str,
+    operation: fn(i32, i32) -> i32,
+) -> Result<Vec<Value>, LuaError> {
+    let mut result = check_bits(&args, 1, function)?;
+    for position in 2..=args.len() {
+        result = operation(result, check_bits(&args, position, function)?);
+    }
+    Ok(vec![bits_value(result)])
+}
+
+fn shift(
+    args: Vec<Value>,
+    function: &str,
+    operation: fn(i32, i32) -> i32,
+) -> Result<Vec<Value>, LuaError> {
+    let bits = check_bits(&args, 1, function)?;
+    let count = check_bits(&args, 2, function)? & 31;
+    Ok(vec![bits_value(operation(bits, count))])
+}
+
+fn tohex(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let bits = check_bits(&args, 1, "tohex")? as u32;
+    let digits = match argument(&args, 2) {
+        Value::Nil => 8,
+        _ => check_bits(&args, 2, "tohex")?,
+    };
+    let uppercase = digits < 0;
+    let count = digits.unsigned_abs().min(8) as usize;
+    let hex = if uppercase {
+        format!("{:08X}", bits)
+    } else {
+        format!("{:08x}", bits)
+    };
+    Ok(vec![Value::string(&hex[8 - count..])])
+}
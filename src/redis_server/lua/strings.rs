@@ -0,0 +1,614 @@
+use super::{
+    interpreter::{first, Lua},
+    pattern::{Captured, Matcher, SPECIALS},
+    stdlib::{
+        argument, argument_error, check_integer, check_number, check_string, optional_integer,
+        register,
+    },
+    value::{format_general, TableRef, Value},
+    LuaError,
+};
+
+// Strings longer than this can't be built by `rep` or `format`.
+const MAX_STRING_SIZE: usize = 512 * 1024 * 1024;
+
+pub fn open(lua: &mut Lua) {
+    let string = TableRef::new();
+    register(
+        &string,
+        &[
+            ("byte", byte),
+            ("char", char),
+            ("find", find),
+            ("format", format),
+            ("gmatch", gmatch),
+            ("gsub", gsub),
+            ("len", len),
+            ("lower", lower),
+            ("match", match_),
+            ("rep", rep),
+            ("reverse", reverse),
+            ("sub", sub),
+            ("upper", upper),
+        ],
+    );
+    // Strings index the library, like ("x"):upper().
+    let metatable = TableRef::new();
+    metatable.set_field("__index", Value::Table(string.clone()));
+    lua.string_metatable = Some(metatable);
+    lua.globals.set_field("string", Value::Table(string));
+}
+
+// A position relative to the end when negative, like posrelat.
+fn relative(position: i64, length: usize) -> i64 {
+    if position < 0 {
+        length as i64 + position + 1
+    } else {
+        position
+    }
+}
+
+fn byte(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let string = check_string(&args, 1, "byte")?;
+    let start = relative(optional_integer(&args, 2, "byte", 1)?, string.len());
+    let end = relative(optional_integer(&args, 3, "byte", start)?, string.len());
+    let start = start.max(1) as usize;
+    let end = end.min(string.len() as i64);
+    if end < start as i64 {
+        return Ok(Vec::new());
+    }
+    Ok(string[start - 1..end as usize]
+        .iter()
+        .map(|byte| Value::Number(*byte as f64))
+        .collect())
+}
+
+fn char(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for position in 1..=args.len() {
+        let code = check_integer(&args, position, "char")?;
+        if !(0..=255).contains(&code) {
+            return Err(argument_error(position, "char", "invalid value"));
+        }
+        bytes.push(code as u8);
+    }
+    Ok(vec![Value::string(bytes)])
+}
+
+fn len(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::Number(
+        check_string(&args, 1, "len")?.len() as f64
+    )])
+}
+
+fn lower(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::string(
+        check_string(&args, 1, "lower")?.to_ascii_lowercase(),
+    )])
+}
+
+fn upper(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![Value::string(
+        check_string(&args, 1, "upper")?.to_ascii_uppercase(),
+    )])
+}
+
+fn reverse(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let mut bytes = check_string(&args, 1, "reverse")?.to_vec();
+    bytes.reverse();
+    Ok(vec![Value::string(bytes)])
+}
+
+fn rep(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let string = check_string(&args, 1, "rep")?;
+    let count = check_integer(&args, 2, "rep")?;
+    if count <= 0 {
+        return Ok(vec![Value::string("")]);
+    }
+    if string.len().saturating_mul(count as usize) > MAX_STRING_SIZE {
+        return Err(LuaError::Builtin("resulting string too large".to_string()));
+    }
+    Ok(vec![Value::string(string.repeat(count as usize))])
+}
+
+fn sub(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let string = check_string(&args, 1, "sub")?;
+    let start = relative(check_integer(&args, 2, "sub")?, string.len()).max(1);
+    let end =
+        relative(optional_integer(&args, 3, "sub", -1)?, string.len()).min(string.len() as i64);
+    if start > end {
+        return Ok(vec![Value::string("")]);
+    }
+    Ok(vec![Value::string(
+        &string[start as usize - 1..end as usize],
+    )])
+}
+
+fn captured(capture: Captured) -> Value {
+    match capture {
+        Captured::String(string) => Value::string(string),
+        Captured::Position(position) => Value::Number(position as f64),
+    }
+}
+
+fn find_or_match(args: Vec<Value>, find: bool) -> Result<Vec<Value>, LuaError> {
+    let name = if find { "find" } else { "match" };
+    let source = check_string(&args, 1, name)?;
+    let pattern = check_string(&args, 2, name)?;
+    let start = relative(optional_integer(&args, 3, name, 1)?, source.len()) - 1;
+    let start = start.clamp(0, source.len() as i64) as usize;
+
+    let plain =
+        argument(&args, 4).is_truthy() || !pattern.iter().any(|byte| SPECIALS.contains(byte));
+    if find && plain {
+        let found = if pattern.is_empty() {
+            Some(start)
+        } else {
+            source[start..]
+                .windows(pattern.len())
+                .position(|window| window == &pattern[..])
+                .map(|position| start + position)
+        };
+        return Ok(match found {
+            Some(position) => vec![
+                Value::Number((position + 1) as f64),
+                Value::Number((position + pattern.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+
+    let anchored = pattern.first() == Some(&b'^');
+    let pattern_start = anchored as usize;
+    let mut matcher = Matcher::new(&source, &pattern);
+    let mut position = start;
+    loop {
+        if let Some(end) = matcher
+            .find_at(position, pattern_start)
+            .map_err(LuaError::Builtin)?
+        {
+            if find {
+                let mut values = vec![
+                    Value::Number((position + 1) as f64),
+                    Value::Number(end as f64),
+                ];
+                for capture in matcher
+                    .captures(position, end, false)
+                    .map_err(LuaError::Builtin)?
+                {
+                    values.push(captured(capture));
+                }
+                return Ok(values);
+            }
+            return Ok(matcher
+                .captures(position, end, true)
+                .map_err(LuaError::Builtin)?
+                .into_iter()
+                .map(captured)
+                .collect());
+        }
+        position += 1;
+        if position > source.len() || anchored {
+            return Ok(vec![Value::Nil]);
+        }
+    }
+}
+
+fn find(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    find_or_match(args, true)
+}
+
+fn match_(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    find_or_match(args, false)
+}
+
+// The iterator keeps the string, the pattern and where to go on from.
+fn gmatch(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let source = check_string(&args, 1, "gmatch")?;
+    let pattern = check_string(&args, 2, "gmatch")?;
+    let state = TableRef::new();
+    state.set_field("position", Value::Number(0.0));
+    Ok(vec![Value::native_with(
+        gmatch_iterator,
+        vec![
+            Value::String(source),
+            Value::String(pattern),
+            Value::Table(state),
+        ],
+    )])
+}
+
+fn gmatch_iterator(_: &mut Lua, upvalues: &[Value], _: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let (Value::String(source), Value::String(pattern), Value::Table(state)) =
+        (&upvalues[0], &upvalues[1], &upvalues[2])
+    else {
+        unreachable!()
+    };
+    let mut position = state.get_field("position").to_number().unwrap_or(0.0) as usize;
+    let mut matcher = Matcher::new(source, pattern);
+    while position <= source.len() {
+        if let Some(end) = matcher.find_at(position, 0).map_err(LuaError::Builtin)? {
+            // Empty matches move on by one.
+            let next = if end == position { end + 1 } else { end };
+            state.set_field("position", Value::Number(next as f64));
+            return Ok(matcher
+                .captures(position, end, true)
+                .map_err(LuaError::Builtin)?
+                .into_iter()
+                .map(captured)
+                .collect());
+        }
+        position += 1;
+    }
+    state.set_field("position", Value::Number(position as f64));
+    Ok(vec![Value::Nil])
+}
+
+fn gsub(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let source = check_string(&args, 1, "gsub")?;
+    let pattern = check_string(&args, 2, "gsub")?;
+    let replacement = argument(&args, 3);
+    if !matches!(
+        replacement,
+        Value::Number(_) | Value::String(_) | Value::Function(_) | Value::Table(_)
+    ) {
+        return Err(argument_error(3, "gsub", "string/function/table expected"));
+    }
+    let max = optional_integer(&args, 4, "gsub", source.len() as i64 + 1)?;
+    let anchored = pattern.first() == Some(&b'^');
+    let pattern_start = anchored as usize;
+
+    let mut matcher = Matcher::new(&source, &pattern);
+    let mut result = Vec::with_capacity(source.len());
+    let mut position = 0;
+    let mut count = 0;
+    while count < max {
+        let end = matcher
+            .find_at(position, pattern_start)
+            .map_err(LuaError::Builtin)?;
+        if let Some(end) = end {
+            count += 1;
+            add_replacement(lua, &matcher, &replacement, position, end, &mut result)?;
+        }
+        match end {
+            Some(end) if end > position => position = end,
+            _ if position < source.len() => {
+                result.push(source[position]);
+                position += 1;
+            }
+            _ => break,
+        }
+        if anchored {
+            break;
+        }
+    }
+    result.extend_from_slice(&source[position.min(source.len())..]);
+    Ok(vec![Value::string(result), Value::Number(count as f64)])
+}
+
+fn add_replacement(
+    lua: &mut Lua,
+    matcher: &Matcher,
+    replacement: &Value,
+    start: usize,
+    end: usize,
+    result: &mut Vec<u8>,
+) -> Result<(), LuaError> {
+    let whole = &matcher.source()[start..end];
+    let value = match replacement {
+        Value::String(_) | Value::Number(_) => {
+            let replacement = replacement.to_lua_string().unwrap();
+            let mut bytes = replacement.iter();
+            while let Some(&byte) = bytes.next() {
+                if byte != b'%' {
+                    result.push(byte);
+                    continue;
+                }
+                match bytes.next() {
+                    Some(b'0') => result.extend_from_slice(whole),
+                    Some(&digit) if digit.is_ascii_digit() => {
+                        let capture = matcher
+                            .capture((digit - b'1') as usize, start, end)
+                            .map_err(LuaError::Builtin)?;
+                        result.extend_from_slice(&captured(capture).to_lua_string().unwrap());
+                    }
+                    Some(&other) => result.push(other),
+                    None => {}
+                }
+            }
+            return Ok(());
+        }
+        Value::Function(_) => {
+            let captures = matcher
+                .captures(start, end, true)
+                .map_err(LuaError::Builtin)?
+                .into_iter()
+                .map(captured)
+                .collect();
+            first(lua.call(replacement, captures)?)
+        }
+        _ => {
+            let key = captured(matcher.capture(0, start, end).map_err(LuaError::Builtin)?);
+            lua.index(replacement, &key)?
+        }
+    };
+    match value {
+        Value::Nil | Value::Boolean(false) => result.extend_from_slice(whole),
+        Value::String(_) | Value::Number(_) => {
+            result.extend_from_slice(&value.to_lua_string().unwrap())
+        }
+        other => {
+            return Err(LuaError::Builtin(format!(
+                "invalid replacement value (a {})",
+                other.type_name()
+            )))
+        }
+    }
+    Ok(())
+}
+
+// The flags, width and precision of a conversion in `format`.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, sign: &str, prefix: &str, digits: &str, numeric: bool) -> String {
+        let length = sign.len() + prefix.len() + digits.len();
+        if length >= self.width {
+            return format!("{}{}{}", sign, prefix, digits);
+        }
+        let fill = self.width - length;
+        if self.left {
+            format!("{}{}{}{}", sign, prefix, digits, " ".repeat(fill))
+        } else if self.zero && numeric {
+            format!("{}{}{}{}", sign, prefix, "0".repeat(fill), digits)
+        } else {
+            format!("{}{}{}{}", " ".repeat(fill), sign, prefix, digits)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+// C's "%e": at least two exponent digits.
+fn format_exponent(number: f64, precision: usize, uppercase: bool) -> String {
+    let formatted = format!("{:.*e}", precision, number);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    let formatted = format!("{}e{}{:02}", mantissa, sign, exponent.abs());
+    if uppercase {
+        formatted.to_uppercase()
+    } else {
+        formatted
+    }
+}
+
+fn format_float(spec: &Spec, conversion: u8, number: f64) -> String {
+    let uppercase = conversion.is_ascii_uppercase();
+    let negative = number.is_sign_negative() && !number.is_nan();
+    let magnitude = number.abs();
+    let digits = if !number.is_finite() {
+        let text = if number.is_nan() { "nan" } else { "inf" };
+        if uppercase {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        }
+    } else {
+        let precision = spec.precision.unwrap_or(6);
+        match conversion {
+            b'f' | b'F' => format!("{:.*}", precision, magnitude),
+            b'e' | b'E' => format_exponent(magnitude, precision, uppercase),
+            _ => format_general(magnitude, precision, uppercase),
+        }
+    };
+    spec.pad(spec.sign(negative), "", &digits, number.is_finite())
+}
+
+fn format_integer(spec: &Spec, conversion: u8, number: i64) -> String {
+    let (negative, digits, prefix) = match conversion {
+        b'd' | b'i' => (number < 0, number.unsigned_abs().to_string(), ""),
+        b'u' => (false, (number as u64).to_string(), ""),
+        b'o' => (
+            false,
+            format!("{:o}", number as u64),
+            if spec.alternate { "0" } else { "" },
+        ),
+        b'x' => (
+            false,
+            format!("{:x}", number as u64),
+            if spec.alternate { "0x" } else { "" },
+        ),
+        _ => (
+            false,
+            format!("{:X}", number as u64),
+            if spec.alternate { "0X" } else { "" },
+        ),
+    };
+    let digits = match spec.precision {
+        Some(precision) if digits.len() < precision => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        Some(0) if number == 0 => String::new(),
+        _ => digits,
+    };
+    let sign = if conversion == b'd' || conversion == b'i' {
+        spec.sign(negative)
+    } else {
+        ""
+    };
+    spec.pad(sign, prefix, &digits, spec.precision.is_none())
+}
+
+fn quoted(string: &[u8], result: &mut Vec<u8>) {
+    result.push(b'"');
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' | b'\n' => {
+                result.push(b'\\');
+                result.push(byte);
+            }
+            b'\r' => result.extend_from_slice(b"\\r"),
+            0 => result.extend_from_slice(b"\\000"),
+            _ => result.push(byte),
+        }
+    }
+    result.push(b'"');
+}
+
+fn format(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let template = check_string(&args, 1, "format")?;
+    let mut result = Vec::with_capacity(template.len());
+    let mut position = 0;
+    let mut argument_position = 1;
+    while position < template.len() {
+        let byte = template[position];
+        position += 1;
+        if byte != b'%' {
+            result.push(byte);
+            continue;
+        }
+        if template.get(position) == Some(&b'%') {
+            result.push(b'%');
+            position += 1;
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        let flags_start = position;
+        while let Some(&flag) = template.get(position) {
+            match flag {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            position += 1;
+        }
+        if position - flags_start > 5 {
+            return Err(LuaError::Builtin(
+                "invalid format (repeated flags)".to_string(),
+            ));
+        }
+        let digits = |position: &mut usize| {
+            let start = *position;
+            while template.get(*position).is_some_and(u8::is_ascii_digit) {
+                *position += 1;
+            }
+            let count = *position - start;
+            let value = std::str::from_utf8(&template[start..*position])
+                .unwrap()
+                .parse()
+                .unwrap_or(0);
+            (count, value)
+        };
+        let (width_digits, width) = digits(&mut position);
+        spec.width = width;
+        let mut precision_digits = 0;
+        if template.get(position) == Some(&b'.') {
+            position += 1;
+            let (count, precision) = digits(&mut position);
+            precision_digits = count;
+            spec.precision = Some(precision);
+        }
+        if width_digits > 2 || precision_digits > 2 {
+            return Err(LuaError::Builtin(
+                "invalid format (width or precision too long)".to_string(),
+            ));
+        }
+
+        let Some(&conversion) = template.get(position) else {
+            return Err(LuaError::Builtin(
+                "invalid option '%' to 'format'".to_string(),
+            ));
+        };
+        position += 1;
+        argument_position += 1;
+        if argument_position > args.len()
+            && matches!(
+                conversion,
+                b'c' | b'd'
+                    | b'i'
+                    | b'o'
+                    | b'u'
+                    | b'x'
+                    | b'X'
+                    | b'e'
+                    | b'E'
+                    | b'f'
+                    | b'g'
+                    | b'G'
+                    | b'q'
+                    | b's'
+            )
+        {
+            return Err(argument_error(argument_position, "format", "no value"));
+        }
+        match conversion {
+            b'c' => result.push(check_number(&args, argument_position, "format")? as i64 as u8),
+            b'd' | b'i' | b'o' | b'u' | b'x' | b'X' => {
+                let number = check_number(&args, argument_position, "format")?;
+                result
+                    .extend_from_slice(format_integer(&spec, conversion, number as i64).as_bytes());
+            }
+            b'e' | b'E' | b'f' | b'g' | b'G' => {
+                let number = check_number(&args, argument_position, "format")?;
+                result.extend_from_slice(format_float(&spec, conversion, number).as_bytes());
+            }
+            b'q' => quoted(
+                &check_string(&args, argument_position, "format")?,
+                &mut result,
+            ),
+            b's' => {
+                let string = check_string(&args, argument_position, "format")?;
+                let string = match spec.precision {
+                    Some(precision) if precision < string.len() => &string[..precision],
+                    _ => &string[..],
+                };
+                if string.len() < spec.width {
+                    let fill = vec![b' '; spec.width - string.len()];
+                    if spec.left {
+                        result.extend_from_slice(string);
+                        result.extend_from_slice(&fill);
+                    } else {
+                        result.extend_from_slice(&fill);
+                        result.extend_from_slice(string);
+                    }
+                } else {
+                    result.extend_from_slice(string);
+                }
+            }
+            other => {
+                return Err(LuaError::Builtin(format!(
+                    "invalid option '%{}' to 'format'",
+                    other as char
+                )))
+            }
+        }
+        if result.len() > MAX_STRING_SIZE {
+            return Err(LuaError::Builtin("resulting string too large".to_string()));
+        }
+    }
+    Ok(vec![Value::string(result)])
+}
@@ -0,0 +1,309 @@
+use super::{
+    interpreter::Lua,
+    stdlib::{argument_error, check_number, check_string, optional_integer, register},
+    value::{TableRef, Value},
+    LuaError,
+};
+
+// The struct library, packing values into binary strings like lua_struct
+// does on a 64 bit little endian machine.
+const MAX_INT_SIZE: usize = 32;
+const MAX_ALIGN: usize = 8;
+
+pub fn open(lua: &mut Lua) {
+    let table = TableRef::new();
+    register(
+        &table,
+        &[("pack", pack), ("size", size), ("unpack", unpack)],
+    );
+    lua.globals.set_field("struct", Value::Table(table));
+}
+
+fn is_integer(option: u8) -> bool {
+    matches!(
+        option,
+        b'b' | b'B' | b'h' | b'H' | b'l' | b'L' | b'T' | b'i' | b'I'
+    )
+}
+
+// A format string being read, its endianness and alignment change as the
+// options setting them are read.
+struct Format<'a> {
+    format: &'a [u8],
+    position: usize,
+    little_endian: bool,
+    align: usize,
+    function: &'static str,
+}
+
+impl<'a> Format<'a> {
+    fn new(format: &'a [u8], function: &'static str) -> Self {
+        Format {
+            format,
+            position: 0,
+            little_endian: true,
+            align: 1,
+            function,
+        }
+    }
+
+    // The next option with the size of what it packs.
+    fn next(&mut self) -> Result<Option<(u8, usize)>, LuaError> {
+        let Some(&option) = self.format.get(self.position) else {
+            return Ok(None);
+        };
+        self.position += 1;
+        let size = match option {
+            b'b' | b'B' | b'x' => 1,
+            b'h' | b'H' => 2,
+            b'f' => 4,
+            b'l' | b'L' | b'T' | b'd' => 8,
+            b'c' => self.number(1)?,
+            b'i' | b'I' => {
+                let size = self.number(4)?;
+                if size > MAX_INT_SIZE {
+                    return Err(LuaError::Builtin(format!(
+                        "integral size {} is larger than limit of {}",
+                        size, MAX_INT_SIZE
+                    )));
+                }
+                size
+            }
+            _ => 0,
+        };
+        Ok(Some((option, size)))
+    }
+
+    // The number following an option, `default` if there is none.
+    fn number(&mut self, default: usize) -> Result<usize, LuaError> {
+        let mut number = None;
+        while let Some(digit) = self
+            .format
+            .get(self.position)
+            .filter(|byte| byte.is_ascii_digit())
+        {
+            number = Some(
+                number
+                    .unwrap_or(0usize)
+                    .checked_mul(10)
+                    .and_then(|number| number.checked_add((digit - b'0') as usize))
+                    .filter(|&number| number <= i32::MAX as usize)
+                    .ok_or_else(|| LuaError::Builtin("integral size overflow".to_string()))?,
+            );
+            self.position += 1;
+        }
+        Ok(number.unwrap_or(default))
+    }
+
+    // The padding that aligns a value of `size` at `offset`.
+    fn padding(&self, offset: usize, option: u8, size: usize) -> usize {
+        if size == 0 || option == b'c' {
+            return 0;
+        }
+        let size = size.min(self.align);
+        (size - (offset & (size - 1))) & (size - 1)
+    }
+
+    // The options that don't stand for a value.
+    fn control(&mut self, option: u8) -> Result<(), LuaError> {
+        match option {
+            b' ' => {}
+            b'>' => self.little_endian = false,
+            b'<' => self.little_endian = true,
+            b'!' => {
+                let align = self.number(MAX_ALIGN)?;
+                if !align.is_power_of_two() {
+                    return Err(LuaError::Builtin(format!(
+                        "alignment {} is not a power of 2",
+                        align
+                    )));
+                }
+                self.align = align;
+            }
+            _ => {
+                return Err(argument_error(
+                    1,
+                    self.function,
+                    &format!("invalid format option '{}'", char::from(option)),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn pack(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let format = check_string(&args, 1, "pack")?;
+    let mut format = Format::new(&format, "pack");
+    let mut packed = Vec::new();
+    let mut position = 2;
+    while let Some((option, size)) = format.next()? {
+        packed.resize(packed.len() + format.padding(packed.len(), option, size), 0);
+        match option {
+            option if is_integer(option) => {
+                let number = check_number(&args, position, "pack")?;
+                position += 1;
+                // Negative numbers in two's complement.
+                let mut value = if number < 0.0 {
+                    number as i64 as u64
+                } else {
+                    number as u64
+                };
+                let mut bytes = vec![0; size];
+                for byte in &mut bytes {
+                    *byte = value as u8;
+                    value >>= 8;
+                }
+                if !format.little_endian {
+                    bytes.reverse();
+                }
+                packed.extend_from_slice(&bytes);
+            }
+            b'x' => packed.push(0),
+            b'f' => {
+                let number = check_number(&args, position, "pack")? as f32;
+                position += 1;
+                packed.extend_from_slice(&if format.little_endian {
+                    number.to_le_bytes()
+                } else {
+                    number.to_be_bytes()
+                });
+            }
+            b'd' => {
+                let number = check_number(&args, position, "pack")?;
+                position += 1;
+                packed.extend_from_slice(&if format.little_endian {
+                    number.to_le_bytes()
+                } else {
+                    number.to_be_bytes()
+                });
+            }
+            b'c' | b's' => {
+                let string = check_string(&args, position, "pack")?;
+                // "c0" and "s" take the whole string.
+                let size = if size == 0 { string.len() } else { size };
+                if string.len() < size {
+                    return Err(argument_error(position, "pack", "string too short"));
+                }
+                position += 1;
+                packed.extend_from_slice(&string[..size]);
+                if option == b's' {
+                    packed.push(0);
+                }
+            }
+            _ => format.control(option)?,
+        }
+    }
+    Ok(vec![Value::string(packed)])
+}
+
+// The values `format` packed in `data` from the optional 1-based offset,
+// then the offset following them.
+fn unpack(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let format = check_string(&args, 1, "unpack")?;
+    let data = check_string(&args, 2, "unpack")?;
+    let offset = optional_integer(&args, 3, "unpack", 1)?;
+    if offset < 1 {
+        return Err(argument_error(3, "unpack", "offset must be 1 or greater"));
+    }
+    let mut position = offset as usize - 1;
+    let mut format = Format::new(&format, "unpack");
+    let mut values = Vec::new();
+    let fits = |position: usize, size: usize| size <= data.len() && position <= data.len() - size;
+    let too_short = || argument_error(2, "unpack", "data string too short");
+    while let Some((option, mut size)) = format.next()? {
+        position += format.padding(position, option, size);
+        if !fits(position, size) {
+            return Err(too_short());
+        }
+        let bytes = &data[position..position + size];
+        match option {
+            option if is_integer(option) => {
+                let mut value = 0u64;
+                let mut read = |byte: &u8| value = (value << 8) | *byte as u64;
+                if format.little_endian {
+                    bytes.iter().rev().for_each(&mut read);
+                } else {
+                    bytes.iter().for_each(&mut read);
+                }
+                let number = if option.is_ascii_lowercase() {
+                    let sign = u64::MAX.checked_shl(size as u32 * 8 - 1).unwrap_or(0);
+                    if value & sign != 0 {
+                        value |= sign;
+                    }
+                    value as i64 as f64
+                } else {
+                    value as f64
+                };
+                values.push(Value::Number(number));
+            }
+            b'x' => {}
+            b'f' => {
+                let bytes = bytes.try_into().unwrap();
+                values.push(Value::Number(if format.little_endian {
+                    f32::from_le_bytes(bytes)
+                } else {
+                    f32::from_be_bytes(bytes)
+                } as f64));
+            }
+            b'd' => {
+                let bytes = bytes.try_into().unwrap();
+                values.push(Value::Number(if format.little_endian {
+                    f64::from_le_bytes(bytes)
+                } else {
+                    f64::from_be_bytes(bytes)
+                }));
+            }
+            b'c' => {
+                // "c0" takes the length from the value before it.
+                if size == 0 {
+                    size = match values.last().and_then(Value::to_number) {
+                        Some(length) => length as usize,
+                        None => {
+                            return Err(LuaError::Builtin(
+                                "format 'c0' needs a previous size".to_string(),
+                            ))
+                        }
+                    };
+                    values.pop();
+                    if !fits(position, size) {
+                        return Err(too_short());
+                    }
+                }
+                values.push(Value::string(&data[position..position + size]));
+            }
+            b's' => {
+                let Some(length) = data[position..].iter().position(|&byte| byte == 0) else {
+                    return Err(LuaError::Builtin("unfinished string in data".to_string()));
+                };
+                values.push(Value::string(&data[position..position + length]));
+                size = length + 1;
+            }
+            _ => format.control(option)?,
+        }
+        position += size;
+    }
+    values.push(Value::Number((position + 1) as f64));
+    Ok(values)
+}
+
+fn size(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let format = check_string(&args, 1, "size")?;
+    let mut format = Format::new(&format, "size");
+    let mut total = 0;
+    while let Some((option, size)) = format.next()? {
+        total += format.padding(total, option, size);
+        if option == b's' {
+            return Err(argument_error(1, "size", "options 's' has no fixed size"));
+        }
+        if option == b'c' && size == 0 {
+            return Err(argument_error(1, "size", "options 'c0' has no fixed size"));
+        }
+        // Other letters are values of no size here.
+        if !option.is_ascii_alphanumeric() {
+            format.control(option)?;
+        }
+        total += size;
+    }
+    Ok(vec![Value::Number(total as f64)])
+}
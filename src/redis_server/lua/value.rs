@@ -0,0 +1,568 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::{Rc, Weak},
+    sync::Arc,
+};
+
+use super::{ast::FunctionBody, interpreter::Lua, LuaError};
+
+// Lua strings are byte strings.
+pub type LuaString = Rc<[u8]>;
+
+pub type Cell = Rc<RefCell<Value>>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(LuaString),
+    Table(TableRef),
+    Function(Function),
+    // cjson.null, a NULL light userdata in Lua CJSON.
+    Null,
+}
+
+impl Value {
+    pub fn string(bytes: impl AsRef<[u8]>) -> Value {
+        Value::String(Rc::from(bytes.as_ref()))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    // Only nil and false are false.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+            Value::Null => "userdata",
+        }
+    }
+
+    // Numbers, and strings that read as one, like arithmetic does.
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::String(string) => parse_number(string),
+            _ => None,
+        }
+    }
+
+    // Strings, and numbers formatted like `tostring`, like concatenation does.
+    pub fn to_lua_string(&self) -> Option<LuaString> {
+        match self {
+            Value::String(string) => Some(Rc::clone(string)),
+            Value::Number(number) => Some(Rc::from(format_number(*number).as_bytes())),
+            _ => None,
+        }
+    }
+
+    // Raw equality, without metamethods.
+    pub fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(&a.0, &b.0),
+            (Value::Function(a), Value::Function(b)) => a.address() == b.address(),
+            (Value::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+
+    // What `tostring` shows when there is no __tostring metamethod.
+    pub fn address(&self) -> usize {
+        match self {
+            Value::Table(table) => Rc::as_ptr(&table.0) as *const u8 as usize,
+            Value::Function(function) => function.address(),
+            _ => 0,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(boolean) => write!(f, "{}", boolean),
+            Value::Number(number) => write!(f, "{}", format_number(*number)),
+            Value::String(string) => write!(f, "{}", String::from_utf8_lossy(string)),
+            Value::Table(_) => write!(f, "table: {:#010x}", self.address()),
+            Value::Function(_) => write!(f, "function: {:#010x}", self.address()),
+            // How glibc prints a NULL "%p".
+            Value::Null => write!(f, "userdata: (nil)"),
+        }
+    }
+}
+
+// Like Lua's `lua_str2number`: decimal with an optional exponent or
+// hexadecimal, surrounded by any amount of whitespace.
+pub fn parse_number(bytes: &[u8]) -> Option<f64> {
+    let text = std::str::from_utf8(bytes)
+        .ok()?
+        .trim_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        if hex.is_empty() || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = hex.bytes().fold(0.0, |value, digit| {
+            value * 16.0 + (digit as char).to_digit(16).unwrap() as f64
+        });
+        return Some(if negative { -value } else { value });
+    }
+    // Rust accepts "inf" and "nan", Lua doesn't.
+    let valid = !unsigned.is_empty()
+        && unsigned
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || matches!(byte, b'.' | b'e' | b'E' | b'+' | b'-'))
+        && unsigned
+            .bytes()
+            .next()
+            .is_some_and(|byte| byte != b'+' && byte != b'-');
+    if !valid {
+        return None;
+    }
+    let value: f64 = unsigned.parse().ok()?;
+    Some(if negative { -value } else { value })
+}
+
+// Numbers print with "%.14g", like LUA_NUMBER_FMT.
+pub fn format_number(number: f64) -> String {
+    format_general(number, 14, false)
+}
+
+// C's "%.{precision}g", uppercase for "%G".
+pub fn format_general(number: f64, precision: usize, uppercase: bool) -> String {
+    let formatted = if number.is_nan() {
+        if number.is_sign_negative() {
+            "-nan".to_string()
+        } else {
+            "nan".to_string()
+        }
+    } else if number.is_infinite() {
+        if number < 0.0 {
+            "-inf".to_string()
+        } else {
+            "inf".to_string()
+        }
+    } else if number == 0.0 {
+        if number.is_sign_negative() {
+            "-0".to_string()
+        } else {
+            "0".to_string()
+        }
+    } else {
+        let precision = precision.max(1);
+        let scientific = format!("{:.*e}", precision - 1, number);
+        let (mantissa, exponent) = scientific.split_once('e').unwrap();
+        let exponent: i32 = exponent.parse().unwrap();
+        if exponent < -4 || exponent >= precision as i32 {
+            let mantissa = trim_fraction(mantissa);
+            let sign = if exponent < 0 { '-' } else { '+' };
+            format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+        } else {
+            let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+            trim_fraction(&format!("{:.*}", decimals, number)).to_string()
+        }
+    };
+    if uppercase {
+        formatted.to_uppercase()
+    } else {
+        formatted
+    }
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Rc<Closure>),
+    Native(Rc<NativeFunction>),
+}
+
+impl Function {
+    fn address(&self) -> usize {
+        match self {
+            Function::Lua(closure) => Rc::as_ptr(closure) as *const u8 as usize,
+            Function::Native(native) => Rc::as_ptr(native) as *const u8 as usize,
+        }
+    }
+}
+
+pub struct Closure {
+    pub body: Arc<FunctionBody>,
+    pub upvalues: Vec<Cell>,
+}
+
+// Weak references to whatever can end up in a reference cycle, like a
+// table holding itself or a local function capturing its own local.
+struct Registry<T> {
+    items: Vec<Weak<T>>,
+    next_prune: usize,
+}
+
+impl<T> Registry<T> {
+    const fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            next_prune: 64,
+        }
+    }
+
+    fn register(&mut self, item: &Rc<T>) {
+        if self.items.len() >= self.next_prune {
+            self.items.retain(|item| item.strong_count() > 0);
+            self.next_prune = (self.items.len() * 2).max(64);
+        }
+        self.items.push(Rc::downgrade(item));
+    }
+
+    fn take(&mut self) -> Vec<Rc<T>> {
+        let items = std::mem::take(&mut self.items);
+        self.next_prune = 64;
+        items.iter().filter_map(Weak::upgrade).collect()
+    }
+}
+
+thread_local! {
+    static TABLES: RefCell<Registry<RefCell<Table>>> = const { RefCell::new(Registry::new()) };
+    static CLOSURES: RefCell<Registry<Closure>> = const { RefCell::new(Registry::new()) };
+}
+
+// Empties every table and closure still alive on this thread so that
+// cycles between them get dropped, once the interpreter is done.
+pub fn break_cycles() {
+    let tables = TABLES.with(|tables| tables.borrow_mut().take());
+    let closures = CLOSURES.with(|closures| closures.borrow_mut().take());
+    let mut contents = Vec::new();
+    for table in &tables {
+        contents.push(std::mem::take(&mut *table.borrow_mut()));
+    }
+    for closure in &closures {
+        for upvalue in &closure.upvalues {
+            contents.push(Table {
+                array: vec![std::mem::take(&mut *upvalue.borrow_mut())],
+                ..Default::default()
+            });
+        }
+    }
+    drop(contents);
+}
+
+impl Value {
+    pub fn closure(body: Arc<FunctionBody>, upvalues: Vec<Cell>) -> Value {
+        let closure = Rc::new(Closure { body, upvalues });
+        CLOSURES.with(|closures| closures.borrow_mut().register(&closure));
+        Value::Function(Function::Lua(closure))
+    }
+}
+
+// Builtins get the values they were created with, then the arguments.
+pub type NativeFn = fn(&mut Lua, &[Value], Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+pub struct NativeFunction {
+    pub func: NativeFn,
+    pub upvalues: Vec<Value>,
+}
+
+impl Value {
+    pub fn native(func: NativeFn) -> Value {
+        Value::native_with(func, Vec::new())
+    }
+
+    pub fn native_with(func: NativeFn, upvalues: Vec<Value>) -> Value {
+        Value::Function(Function::Native(Rc::new(NativeFunction { func, upvalues })))
+    }
+}
+
+#[derive(Clone)]
+pub struct TableRef(pub Rc<RefCell<Table>>);
+
+impl TableRef {
+    pub fn new() -> Self {
+        let table = Rc::new(RefCell::new(Table::default()));
+        TABLES.with(|tables| tables.borrow_mut().register(&table));
+        TableRef(table)
+    }
+
+    pub fn from_values(values: Vec<Value>) -> Self {
+        let table = TableRef::new();
+        {
+            let mut inner = table.0.borrow_mut();
+            for (i, value) in values.into_iter().enumerate() {
+                inner.set(Value::Number((i + 1) as f64), value).unwrap();
+            }
+        }
+        table
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        self.0.borrow().get(key)
+    }
+
+    pub fn get_field(&self, name: &str) -> Value {
+        self.0.borrow().get_field(name.as_bytes())
+    }
+
+    pub fn set(&self, key: Value, value: Value) -> Result<(), String> {
+        self.0.borrow_mut().set(key, value)
+    }
+
+    pub fn set_field(&self, name: &str, value: Value) {
+        self.0.borrow_mut().set(Value::string(name), value).unwrap();
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn metatable(&self) -> Option<TableRef> {
+        self.0.borrow().metatable.clone()
+    }
+}
+
+// Hash part keys other than strings, which have their own map so that they
+// can be looked up by `&[u8]`.
+#[derive(PartialEq, Eq, Hash)]
+enum Key {
+    Boolean(bool),
+    Number(u64),
+    Reference(usize),
+}
+
+// An array part for keys 1..n and a hash part keeping insertion order, so
+// that `next` can carry on from any key without scanning.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    strings: HashMap<LuaString, usize>,
+    others: HashMap<Key, usize>,
+    entries: Vec<(Value, Value)>,
+    removed: usize,
+    pub metatable: Option<TableRef>,
+    // Scripts can't change the tables of the libraries.
+    pub readonly: bool,
+}
+
+// The array index `number` stands for, if it is a positive integer.
+fn array_index(number: f64) -> Option<usize> {
+    (number >= 1.0 && number.fract() == 0.0 && number < usize::MAX as f64)
+        .then_some(number as usize)
+}
+
+impl Table {
+    fn hash_position(&self, key: &Value) -> Option<usize> {
+        match key {
+            Value::String(string) => self.strings.get(string).copied(),
+            Value::Nil => None,
+            _ => self.others.get(&Self::other_key(key)).copied(),
+        }
+    }
+
+    fn other_key(key: &Value) -> Key {
+        match key {
+            Value::Boolean(boolean) => Key::Boolean(*boolean),
+            // -0 and 0 are the same key.
+            Value::Number(number) => Key::Number((*number + 0.0).to_bits()),
+            _ => Key::Reference(key.address()),
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        if let Value::Number(number) = key {
+            if let Some(index) = array_index(*number) {
+                if index <= self.array.len() {
+                    return self.array[index - 1].clone();
+                }
+            }
+        }
+        match self.hash_position(key) {
+            Some(position) => self.entries[position].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_field(&self, name: &[u8]) -> Value {
+        match self.strings.get(name) {
+            Some(position) => self.entries[*position].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), String> {
+        if self.readonly {
+            return Err("Attempt to modify a readonly table".to_string());
+        }
+        match &key {
+            Value::Nil => return Err("table index is nil".to_string()),
+            Value::Number(number) if number.is_nan() => {
+                return Err("table index is NaN".to_string())
+            }
+            Value::Number(number) => {
+                if let Some(index) = array_index(*number) {
+                    if index <= self.array.len() {
+                        self.array[index - 1] = value;
+                        // No trailing nils, the length is the array's.
+                        while self.array.last().is_some_and(Value::is_nil) {
+                            self.array.pop();
+                        }
+                        return Ok(());
+                    }
+                    if index == self.array.len() + 1 && !value.is_nil() {
+                        self.remove_from_hash(&key);
+                        self.array.push(value);
+                        self.migrate_to_array();
+                        return Ok(());
+                    }
+                }
+            }
+            _ => {}
+        }
+        match self.hash_position(&key) {
+            Some(position) => {
+                match (self.entries[position].1.is_nil(), value.is_nil()) {
+                    (false, true) => self.removed += 1,
+                    (true, false) => self.removed -= 1,
+                    _ => {}
+                }
+                self.entries[position].1 = value;
+            }
+            None if value.is_nil() => {}
+            None => {
+                if self.removed > 16 && self.removed * 2 > self.entries.len() {
+                    self.compact();
+                }
+                let position = self.entries.len();
+                match &key {
+                    Value::String(string) => {
+                        self.strings.insert(Rc::clone(string), position);
+                    }
+                    _ => {
+                        self.others.insert(Self::other_key(&key), position);
+                    }
+                }
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_from_hash(&mut self, key: &Value) {
+        if let Some(position) = self.hash_position(key) {
+            if !self.entries[position].1.is_nil() {
+                self.entries[position].1 = Value::Nil;
+                self.removed += 1;
+            }
+        }
+    }
+
+    // Moves the keys following the array part from the hash part.
+    fn migrate_to_array(&mut self) {
+        loop {
+            let next = Value::Number((self.array.len() + 1) as f64);
+            let Some(position) = self.hash_position(&next) else {
+                return;
+            };
+            let value = std::mem::take(&mut self.entries[position].1);
+            if value.is_nil() {
+                return;
+            }
+            self.removed += 1;
+            self.array.push(value);
+        }
+    }
+
+    // Drops removed entries. Only done when adding a key, which Lua doesn't
+    // allow while traversing a table anyway.
+    fn compact(&mut self) {
+        let entries = std::mem::take(&mut self.entries);
+        self.strings.clear();
+        self.others.clear();
+        self.removed = 0;
+        for (key, value) in entries {
+            if value.is_nil() {
+                continue;
+            }
+            let position = self.entries.len();
+            match &key {
+                Value::String(string) => {
+                    self.strings.insert(Rc::clone(string), position);
+                }
+                _ => {
+                    self.others.insert(Self::other_key(&key), position);
+                }
+            }
+            self.entries.push((key, value));
+        }
+    }
+
+    // A border, the array part's length since it has no trailing nils.
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    // The entry following `key`, array part first.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, String> {
+        let mut array_start = 0;
+        let mut hash_start = 0;
+        match key {
+            Value::Nil => {}
+            Value::Number(number)
+                if array_index(*number).is_some_and(|index| index <= self.array.len()) =>
+            {
+                array_start = *number as usize;
+            }
+            _ => match self.hash_position(key) {
+                Some(position) => {
+                    array_start = self.array.len();
+                    hash_start = position + 1;
+                }
+                // Was at the end of the array part, which shrank when it
+                // was set to nil.
+                None if matches!(key, Value::Number(number) if array_index(*number).is_some()) => {
+                    array_start = self.array.len();
+                }
+                None => return Err("invalid key to 'next'".to_string()),
+            },
+        }
+        for index in array_start..self.array.len() {
+            if !self.array[index].is_nil() {
+                return Ok(Some((
+                    Value::Number((index + 1) as f64),
+                    self.array[index].clone(),
+                )));
+            }
+        }
+        Ok(self.entries[hash_start.min(self.entries.len())..]
+            .iter()
+            .find(|(_, value)| !value.is_nil())
+            .cloned())
+    }
+}
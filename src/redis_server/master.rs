@@ -1,28 +1,56 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{fs, net::SocketAddr, sync::Arc, time::Duration};
 
 use tokio::{
-    io::AsyncWriteExt,
+    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
 };
 
-use crate::redis_server::{
-    connection_handler, encode_resp_bulk_string, encode_simple_string, handle_connection,
-    hex_to_binary, replica,
-};
+use crate::redis_server::{bind, handle_connection};
 
-use super::{timed_hashmap::TimedHashMap, ConnectionHandler};
+use super::{
+    acl,
+    config::Config,
+    execute_keyspace_command, rdb,
+    resp::{Argument, Protocol, RespValue},
+    server_state::ServerState,
+    string, Command, ConnectionHandler,
+};
 
-pub async fn start_master(port: &str, replication_id: String) {
-    let listener: TcpListener = TcpListener::bind(port).await.unwrap();
-    println!("Master started on port: {}", port);
+pub async fn start_master(config: Config, replication_id: String) {
+    let listeners = bind(&config).await;
+    let aclfile = Some(config.aclfile.clone()).filter(|aclfile| !aclfile.is_empty());
+    let requirepass = Some(config.requirepass.clone()).filter(|password| !password.is_empty());
+    let state = Arc::new(ServerState::new(config));
+    acl::configure(&state, aclfile, requirepass);
+    load_snapshot(&state);
+    tokio::spawn(expire_keys_periodically(Arc::clone(&state)));
+    tokio::spawn(save_periodically(Arc::clone(&state)));
+
+    let mut accepting = Vec::new();
+    for listener in listeners {
+        println!("Master started on: {}", listener.local_addr().unwrap());
+        accepting.push(tokio::spawn(accept_connections(
+            listener,
+            Arc::clone(&state),
+            replication_id.clone(),
+        )));
+    }
+    for task in accepting {
+        let _ = task.await;
+    }
+}
 
+async fn accept_connections(
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    replication_id: String,
+) {
     loop {
         match listener.accept().await {
             Ok((socket, _)) => {
                 let handler = MasterConnectionHandler {
-                    timed_hashmap: TimedHashMap::new(),
+                    state: Arc::clone(&state),
                     replication_id: replication_id.clone(),
-                    replicas: Vec::new(),
                 };
                 tokio::spawn(async move {
                     if let Err(e) = handle_connection(socket, handler).await {
@@ -52,191 +80,165 @@ pub async fn start_master(port: &str, replication_id: String) {
 //     }
 // }
 
+// Like Redis at its default hz of 10, the active expire cycle gets at most a
+// quarter of the time between two.
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+// Active expiry, so that keys nobody reads again still go away (and fire
+// their `expired` event) shortly after their TTL. Replicas only follow the
+// master, which sends them a DEL for each.
+async fn expire_keys_periodically(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        interval.tick().await;
+        // Skipped while a script holds the keyspace, rather than blocking a
+        // worker thread until it ends.
+        let Ok(mut keyspace) = state.keyspace.try_lock() else {
+            continue;
+        };
+        keyspace.active_expire_cycle(ACTIVE_EXPIRE_BUDGET);
+        state.propagate_effects(&mut keyspace);
+        state.publish_notifications(&mut keyspace);
+        state.invalidate_modified(&mut keyspace, None);
+    }
+}
+
+// The save points of the config: a BGSAVE once some point has seen its
+// number of changes within its number of seconds since the last save.
+async fn save_periodically(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let save = state.config.lock().unwrap().save.clone();
+        let Ok(mut keyspace) = state.keyspace.try_lock() else {
+            continue;
+        };
+        let due = save.iter().any(|point| {
+            keyspace.dirty() >= point.changes
+                && keyspace.last_save().elapsed() >= Duration::from_secs(point.seconds)
+        });
+        if due {
+            println!(
+                "{} changes since the last save. Saving...",
+                keyspace.dirty()
+            );
+            rdb::background_save(&mut keyspace);
+        }
+    }
+}
+
+fn load_snapshot(state: &ServerState) {
+    let mut keyspace = state.keyspace.lock().unwrap();
+    let path = keyspace.dbfilename().to_string();
+    let Ok(bytes) = fs::read(&path) else {
+        return;
+    };
+    match rdb::load(&mut keyspace, &bytes) {
+        Ok(loaded) => println!("Loaded {} keys from {}.", loaded, path),
+        Err(e) => eprintln!("Failed to load {}: {}", path, e),
+    }
+}
+
 struct MasterConnectionHandler {
-    timed_hashmap: TimedHashMap<String, String>,
+    state: Arc<ServerState>,
     replication_id: String,
-    replicas: Vec<String>,
 }
 
 impl ConnectionHandler for MasterConnectionHandler {
-    async fn handle_echo(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let response: String = encode_resp_bulk_string(&decoded_str[1]);
-        if stream.write_all(response.as_bytes()).await.is_ok() {
-            Ok(())
-        } else {
-            println!("Master: unable to send response back in ECHO command.");
-            Err("Master: unable to send successful ECHO response".into())
-        }
+    fn state(&self) -> &Arc<ServerState> {
+        &self.state
     }
 
-    async fn handle_ping(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn role(&self) -> &'static str {
+        "master"
+    }
+
+    fn handle_echo(&mut self, decoded_str: &[Argument]) -> RespValue {
+        RespValue::bulk(&decoded_str[1])
+    }
+
+    fn handle_ping(&mut self) -> RespValue {
         println!("Master: received PING.");
-        let response = encode_simple_string("PONG");
-        if stream.write_all(response.as_bytes()).await.is_ok() {
-            println!("Master: sent PONG.");
-            Ok(())
-        } else {
-            eprintln!("Master: failed to reply with PONG.");
-            Err("Master: failed to send PONG response.".into())
-        }
+        RespValue::SimpleString("PONG".to_string())
     }
 
-    async fn handle_set(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        match decoded_str.len() {
-            3 => {
-                println!("Inserting into key-value store with no expiry...");
-                // TODO: handle situation with more than one replica
-                let mut replica_stream = TcpStream::connect(self.replicas[0]).await.unwrap();
-                self.timed_hashmap.insert(
-                    decoded_str[1].to_owned(),
-                    decoded_str[2].to_owned(),
-                    None,
-                );
-                let response = encode_simple_string("OK");
-                println!("Successfully inserted into key-value store with no expiry.");
-                if stream.write_all(response.as_bytes()).await.is_ok() {
-                    Ok(())
-                } else {
-                    Err("Master: unable to send OK response in SET command.".into())
-                }
-            }
-            5 => {
-                println!("Inserting into key-value store with expiry");
-                // TODO: handle situation with more than one replica
-                let mut replica_stream = TcpStream::connect(self.replicas[0]).await.unwrap();
-                let milliseconds: u64 = decoded_str[4].to_owned().parse().unwrap();
-                let ttl: Duration = Duration::from_millis(milliseconds);
-
-                self.timed_hashmap.insert(
-                    decoded_str[1].to_owned(),
-                    decoded_str[2].to_owned(),
-                    Some(ttl),
-                );
-
-                println!("Successfully inserted into key-value store with expiry.");
-                if stream.write_all("+OK\r\n".as_bytes()).await.is_ok() {
-                    Ok(())
-                } else {
-                    Err("Master: unable to send OK response in SET command".into())
-                }
-            }
-            _ => Err("Unable to insert into key-value store.".into()),
-        }
+    fn handle_set(&mut self, db: &mut usize, decoded_str: &[Argument]) -> RespValue {
+        println!("Inserting into key-value store...");
+        execute_keyspace_command(
+            &self.state,
+            db,
+            &Command::Set,
+            string::handle_set,
+            decoded_str,
+        )
     }
 
-    async fn handle_get(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn handle_get(&mut self, db: &mut usize, decoded_str: &[Argument]) -> RespValue {
         println!("Entering into GET command...");
-        println!("Removing expired entries...");
-        self.timed_hashmap.remove_expired_entries();
-
-        if let Some(key) = self.timed_hashmap.get(&decoded_str[1]) {
-            let response: String = encode_resp_bulk_string(key);
-            if stream.write_all(response.as_bytes()).await.is_ok() {
-                Ok(())
-            } else {
-                Err("Master: unable to send successful response in GET commmand.")
-            }
-        } else {
-            match stream.write_all("$-1\r\n".as_bytes()).await {
-                Ok(_) => Ok(()),
-                Err(_) => Err("Master: null bulk string.".into()),
-            }
-        }
+        execute_keyspace_command(
+            &self.state,
+            db,
+            &Command::Get,
+            string::handle_get,
+            decoded_str,
+        )
     }
 
-    async fn handle_info(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Master: entering INFO command...");
-        let encoded_role: String = encode_resp_bulk_string("master");
-        let encoded_master_replid =
-            encode_resp_bulk_string(format!("master_replid:{}", self.replication_id).as_str());
+    fn replication_info(&self) -> String {
         // TODO: Modify to use dynamic offset values
-        let encoded_master_repl_offset: String = encode_resp_bulk_string("master_repl_offset:0");
-        let response: String = format!(
-            "{}{}{}",
-            encoded_role, encoded_master_replid, encoded_master_repl_offset
-        );
-
-        if stream
-            .write_all(encode_resp_bulk_string(response.as_str()).as_bytes())
-            .await
-            .is_ok()
-        {
-            Ok(())
-        } else {
-            Err("Master: failed to send successful response in INFO command.".into())
-        }
+        format!(
+            "# Replication\r\nrole:master\r\nmaster_replid:{}\r\nmaster_repl_offset:0\r\n",
+            self.replication_id
+        )
     }
 
-    async fn handle_replconf(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn handle_replconf(&mut self) -> RespValue {
         println!("Master: received REPLCONF...");
-        let response: String = encode_simple_string("OK");
-        if stream.write_all(response.as_bytes()).await.is_ok() {
-            Ok(())
-        } else {
-            Err("Master: failed to send successful response in REPLCONF command.".into())
-        }
+        RespValue::ok()
     }
 
     async fn handle_psync(
         &mut self,
         stream: &mut TcpStream,
-        replication_id: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        // TODO: create method to keep track of replica address
         let peer_socket_address: SocketAddr = stream.peer_addr().unwrap();
         println!("Socket address of replica is: {}", peer_socket_address);
-        // TODO: assign / add replica address to 'replicas'
 
-        //TODO: Swap out '0' with offset
-        let response = encode_simple_string(format!("FULLRESYNC {} 0", replication_id).as_str());
-        if let Err(e) = stream.write_all(response.as_bytes()).await {
-            eprintln!("PSYNC: Failed to write to to client: {}", e);
-        }
+        // The snapshot and the registration happen under the same lock, the
+        // replica then receives every write made after the snapshot.
+        let (snapshot, mut writes) = {
+            let mut keyspace = self.state.keyspace.lock().unwrap();
+            keyspace.forget_replicated_database();
+            (rdb::encode(&keyspace), self.state.register_replica())
+        };
 
-        let hex_rdb = "524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
-        match hex_to_binary(hex_rdb) {
-            Ok(binary) => {
-                // binary now contains the RDB file contents as a Vec<u8>
-                let length = binary.len();
-                let response = format!("${}{}", length, String::from("\r\n"));
-                let response_bytes = response.as_bytes();
-
-                if stream.write(response_bytes).await.is_err() {
-                    println!("Unable to write length prefix into buffer.");
-                }
-                if stream.write(binary.as_slice()).await.is_err() {
-                    println!("Unable to write RDB data into buffer.");
-                }
-                if stream.flush().await.is_err() {
-                    eprint!("Unable to flush.")
-                };
-                Ok(())
-            }
-
-            Err(err) => {
-                eprintln!("Error parsing hexadecimal string: {}", err);
-                Err("Master: error parsing hexadecimal string.".into())
+        //TODO: Swap out '0' with offset
+        let response = RespValue::simple(format!("FULLRESYNC {} 0", self.replication_id));
+        stream.write_all(&response.encode(Protocol::Resp2)).await?;
+        stream
+            .write_all(format!("${}\r\n", snapshot.len()).as_bytes())
+            .await?;
+        stream.write_all(&snapshot).await?;
+        stream.flush().await?;
+
+        // From now on the connection is a replication link: forward writes
+        // until the replica goes away. What it sends back (REPLCONF ACK) is
+        // not used yet.
+        let mut buf = [0; 1024];
+        loop {
+            tokio::select! {
+                write = writes.recv() => match write {
+                    Some(write) => stream.write_all(&write).await?,
+                    None => return Ok(()),
+                },
+                read = stream.read(&mut buf) => match read {
+                    Ok(0) => {
+                        println!("Replica {} disconnected.", peer_socket_address);
+                        return Ok(());
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Err(e.into()),
+                },
             }
         }
     }
@@ -0,0 +1,130 @@
+// Keyspace event classes, one bit each in the `notify-keyspace-events`
+// setting, with the same letters as Redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventClass {
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    Zset,
+    Expired,
+    Evicted,
+    Stream,
+    KeyMiss,
+    Module,
+    New,
+}
+
+const KEYSPACE: u32 = 1 << 0;
+const KEYEVENT: u32 = 1 << 1;
+
+impl EventClass {
+    fn flag(self) -> u32 {
+        1 << (2 + self as u32)
+    }
+}
+
+// What the `A` alias stands for, key misses and new keys are opt-in.
+const ALL: [EventClass; 10] = [
+    EventClass::Generic,
+    EventClass::String,
+    EventClass::List,
+    EventClass::Set,
+    EventClass::Hash,
+    EventClass::Zset,
+    EventClass::Expired,
+    EventClass::Evicted,
+    EventClass::Stream,
+    EventClass::Module,
+];
+
+const LETTERS: [(char, EventClass); 12] = [
+    ('g', EventClass::Generic),
+    ('$', EventClass::String),
+    ('l', EventClass::List),
+    ('s', EventClass::Set),
+    ('h', EventClass::Hash),
+    ('z', EventClass::Zset),
+    ('x', EventClass::Expired),
+    ('e', EventClass::Evicted),
+    ('t', EventClass::Stream),
+    ('d', EventClass::Module),
+    ('n', EventClass::New),
+    ('m', EventClass::KeyMiss),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NotifyFlags(u32);
+
+impl NotifyFlags {
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    // Parses a `notify-keyspace-events` value such as "KEA" or "Ex$".
+    pub fn parse(input: &str) -> Option<Self> {
+        let mut bits = 0;
+        for letter in input.chars() {
+            bits |= match letter {
+                'A' => ALL.iter().fold(0, |bits, class| bits | class.flag()),
+                'K' => KEYSPACE,
+                'E' => KEYEVENT,
+                _ => LETTERS
+                    .iter()
+                    .find(|(candidate, _)| *candidate == letter)?
+                    .1
+                    .flag(),
+            };
+        }
+        Some(Self(bits))
+    }
+
+    // Notifications go out when the class is enabled and at least one of the
+    // keyspace or keyevent channels is.
+    pub fn wants(self, class: EventClass) -> Option<(bool, bool)> {
+        if self.0 & class.flag() == 0 || self.0 & (KEYSPACE | KEYEVENT) == 0 {
+            return None;
+        }
+        Some((self.0 & KEYSPACE != 0, self.0 & KEYEVENT != 0))
+    }
+}
+
+impl std::fmt::Display for NotifyFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let all = ALL.iter().fold(0, |bits, class| bits | class.flag());
+        let mut letters = String::new();
+        if self.0 & all == all {
+            letters.push('A');
+        }
+        for (letter, class) in LETTERS {
+            let covered_by_all = self.0 & all == all && ALL.contains(&class);
+            if self.0 & class.flag() != 0 && !covered_by_all && class != EventClass::KeyMiss {
+                letters.push(letter);
+            }
+        }
+        if self.0 & KEYSPACE != 0 {
+            letters.push('K');
+        }
+        if self.0 & KEYEVENT != 0 {
+            letters.push('E');
+        }
+        if self.0 & EventClass::KeyMiss.flag() != 0 {
+            letters.push('m');
+        }
+        write!(f, "{}", letters)
+    }
+}
+
+// An event recorded by a command, published once the command is done.
+#[derive(Debug)]
+pub struct KeyspaceEvent {
+    pub db: usize,
+    pub class: EventClass,
+    pub event: &'static str,
+    pub key: String,
+}
@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use super::{
+    glob::glob_match,
+    keyspace::Keyspace,
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    slot::key_hash_slot,
+    wrong_number_of_arguments,
+};
+
+type Subscribers = HashMap<u64, UnboundedSender<RespValue>>;
+
+// Server-wide registry of who listens to what. Messages are built once and
+// pushed into each subscriber's channel, the connection writes them out
+// whenever it is idle.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<String, Subscribers>>,
+    patterns: Mutex<HashMap<String, Subscribers>>,
+    // Sharded channels are grouped by the hash slot of their name, a server
+    // owns every slot unless it runs as part of a cluster.
+    shard_channels: Mutex<HashMap<u16, HashMap<String, Subscribers>>>,
+    // Every connection, for messages meant for one of them like client side
+    // caching invalidations.
+    connections: Mutex<Subscribers>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the number of clients that received the message.
+    pub fn publish(&self, channel: &str, message: &str) -> usize {
+        let mut receivers = 0;
+        if let Some(subscribers) = self.channels.lock().unwrap().get(channel) {
+            let encoded = encode_message(&["message", channel, message]);
+            for sender in subscribers.values() {
+                if sender.send(encoded.clone()).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+        for (pattern, subscribers) in self.patterns.lock().unwrap().iter() {
+            if !glob_match(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+            let encoded = encode_message(&["pmessage", pattern, channel, message]);
+            for sender in subscribers.values() {
+                if sender.send(encoded.clone()).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+        receivers
+    }
+
+    // Sharded messages only reach subscribers of the exact channel, patterns
+    // don't apply.
+    pub fn publish_shard(&self, channel: &str, message: &str) -> usize {
+        let slot = key_hash_slot(channel.as_bytes());
+        let shard_channels = self.shard_channels.lock().unwrap();
+        let Some(subscribers) = shard_channels
+            .get(&slot)
+            .and_then(|channels| channels.get(channel))
+        else {
+            return 0;
+        };
+        let encoded = encode_message(&["smessage", channel, message]);
+        subscribers
+            .values()
+            .filter(|sender| sender.send(encoded.clone()).is_ok())
+            .count()
+    }
+
+    // Sends a message to one connection.
+    pub fn send_to(&self, id: u64, message: RespValue) -> bool {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|sender| sender.send(message).is_ok())
+    }
+
+    pub fn is_subscribed(&self, id: u64, channel: &str) -> bool {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(channel)
+            .is_some_and(|subscribers| subscribers.contains_key(&id))
+    }
+
+    fn subscribe(
+        registry: &Mutex<HashMap<String, Subscribers>>,
+        name: &str,
+        id: u64,
+        sender: &UnboundedSender<RespValue>,
+    ) {
+        registry
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(id, sender.clone());
+    }
+
+    // Channels without subscribers are dropped from the registry.
+    fn unsubscribe(registry: &Mutex<HashMap<String, Subscribers>>, name: &str, id: u64) {
+        let mut registry = registry.lock().unwrap();
+        if let Some(subscribers) = registry.get_mut(name) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                registry.remove(name);
+            }
+        }
+    }
+
+    fn subscribe_shard(&self, channel: &str, id: u64, sender: &UnboundedSender<RespValue>) {
+        self.shard_channels
+            .lock()
+            .unwrap()
+            .entry(key_hash_slot(channel.as_bytes()))
+            .or_default()
+            .entry(channel.to_string())
+            .or_default()
+            .insert(id, sender.clone());
+    }
+
+    fn unsubscribe_shard(&self, channel: &str, id: u64) {
+        let slot = key_hash_slot(channel.as_bytes());
+        let mut shard_channels = self.shard_channels.lock().unwrap();
+        if let Some(channels) = shard_channels.get_mut(&slot) {
+            if let Some(subscribers) = channels.get_mut(channel) {
+                subscribers.remove(&id);
+                if subscribers.is_empty() {
+                    channels.remove(channel);
+                }
+            }
+            if channels.is_empty() {
+                shard_channels.remove(&slot);
+            }
+        }
+    }
+}
+
+// Pushed messages are plain arrays of bulk strings.
+fn encode_message(fields: &[&str]) -> RespValue {
+    RespValue::bulks(fields)
+}
+
+// The subscription side of a connection. Dropping it (the connection went
+// away) removes every subscription it still holds.
+pub struct Subscriber {
+    id: u64,
+    state: Arc<ServerState>,
+    sender: UnboundedSender<RespValue>,
+    pub receiver: UnboundedReceiver<RespValue>,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    shard_channels: HashSet<String>,
+}
+
+impl Subscriber {
+    pub fn new(state: &Arc<ServerState>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let id = state.next_client_id();
+        state
+            .pubsub
+            .connections
+            .lock()
+            .unwrap()
+            .insert(id, sender.clone());
+        Self {
+            id,
+            state: Arc::clone(state),
+            sender,
+            receiver,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    // Channels, patterns and shard channels, as CLIENT LIST shows them.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.channels.len(),
+            self.patterns.len(),
+            self.shard_channels.len(),
+        )
+    }
+
+    // While subscribed, a connection only accepts a handful of commands.
+    pub fn is_subscribed(&self) -> bool {
+        self.count() > 0 || !self.shard_channels.is_empty()
+    }
+
+    // Sharded subscriptions are counted apart from the others.
+    fn subscription_reply(&self, kind: &str, name: Option<&str>) -> RespValue {
+        let count = if matches!(kind, "ssubscribe" | "sunsubscribe") {
+            self.shard_channels.len()
+        } else {
+            self.count()
+        };
+        RespValue::Array(vec![
+            RespValue::bulk(kind),
+            name.map_or(RespValue::Null, RespValue::bulk),
+            RespValue::Integer(count as i64),
+        ])
+    }
+
+    fn subscribe_channel(&mut self, channel: &str) {
+        if self.channels.insert(channel.to_string()) {
+            PubSub::subscribe(&self.state.pubsub.channels, channel, self.id, &self.sender);
+        }
+    }
+
+    fn unsubscribe_channel(&mut self, channel: &str) {
+        if self.channels.remove(channel) {
+            PubSub::unsubscribe(&self.state.pubsub.channels, channel, self.id);
+        }
+    }
+
+    fn subscribe_pattern(&mut self, pattern: &str) {
+        if self.patterns.insert(pattern.to_string()) {
+            PubSub::subscribe(&self.state.pubsub.patterns, pattern, self.id, &self.sender);
+        }
+    }
+
+    fn unsubscribe_pattern(&mut self, pattern: &str) {
+        if self.patterns.remove(pattern) {
+            PubSub::unsubscribe(&self.state.pubsub.patterns, pattern, self.id);
+        }
+    }
+
+    fn subscribe_shard_channel(&mut self, channel: &str) {
+        if self.shard_channels.insert(channel.to_string()) {
+            self.state
+                .pubsub
+                .subscribe_shard(channel, self.id, &self.sender);
+        }
+    }
+
+    fn unsubscribe_shard_channel(&mut self, channel: &str) {
+        if self.shard_channels.remove(channel) {
+            self.state.pubsub.unsubscribe_shard(channel, self.id);
+        }
+    }
+
+    // Drops every subscription, as RESET does.
+    pub fn reset(&mut self) {
+        for channel in self.shard_channels.clone() {
+            self.unsubscribe_shard_channel(&channel);
+        }
+        for channel in self.channels.clone() {
+            self.unsubscribe_channel(&channel);
+        }
+        for pattern in self.patterns.clone() {
+            self.unsubscribe_pattern(&pattern);
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.reset();
+        self.state
+            .pubsub
+            .connections
+            .lock()
+            .unwrap()
+            .remove(&self.id);
+    }
+}
+
+// SUBSCRIBE channel [channel ...]
+pub fn handle_subscribe(subscriber: &mut Subscriber, decoded_str: &[Argument]) -> Vec<RespValue> {
+    if decoded_str.len() < 2 {
+        return vec![wrong_number_of_arguments("subscribe")];
+    }
+    let mut response = Vec::new();
+    for channel in &decoded_str[1..] {
+        subscriber.subscribe_channel(channel);
+        response.push(subscriber.subscription_reply("subscribe", Some(channel)));
+    }
+    response
+}
+
+// UNSUBSCRIBE [channel ...], every channel when none is given.
+pub fn handle_unsubscribe(subscriber: &mut Subscriber, decoded_str: &[Argument]) -> Vec<RespValue> {
+    let mut channels: Vec<String> = decoded_str[1..].iter().map(Argument::to_string).collect();
+    if channels.is_empty() {
+        channels = subscriber.channels.iter().cloned().collect();
+        if channels.is_empty() {
+            return vec![subscriber.subscription_reply("unsubscribe", None)];
+        }
+    }
+    let mut response = Vec::new();
+    for channel in &channels {
+        subscriber.unsubscribe_channel(channel);
+        response.push(subscriber.subscription_reply("unsubscribe", Some(channel)));
+    }
+    response
+}
+
+// PSUBSCRIBE pattern [pattern ...]
+pub fn handle_psubscribe(subscriber: &mut Subscriber, decoded_str: &[Argument]) -> Vec<RespValue> {
+    if decoded_str.len() < 2 {
+        return vec![wrong_number_of_arguments("psubscribe")];
+    }
+    let mut response = Vec::new();
+    for pattern in &decoded_str[1..] {
+        subscriber.subscribe_pattern(pattern);
+        response.push(subscriber.subscription_reply("psubscribe", Some(pattern)));
+    }
+    response
+}
+
+// PUNSUBSCRIBE [pattern ...], every pattern when none is given.
+pub fn handle_punsubscribe(
+    subscriber: &mut Subscriber,
+    decoded_str: &[Argument],
+) -> Vec<RespValue> {
+    let mut patterns: Vec<String> = decoded_str[1..].iter().map(Argument::to_string).collect();
+    if patterns.is_empty() {
+        patterns = subscriber.patterns.iter().cloned().collect();
+        if patterns.is_empty() {
+            return vec![subscriber.subscription_reply("punsubscribe", None)];
+        }
+    }
+    let mut response = Vec::new();
+    for pattern in &patterns {
+        subscriber.unsubscribe_pattern(pattern);
+        response.push(subscriber.subscription_reply("punsubscribe", Some(pattern)));
+    }
+    response
+}
+
+// SSUBSCRIBE shardchannel [shardchannel ...]
+pub fn handle_ssubscribe(subscriber: &mut Subscriber, decoded_str: &[Argument]) -> Vec<RespValue> {
+    if decoded_str.len() < 2 {
+        return vec![wrong_number_of_arguments("ssubscribe")];
+    }
+    let mut response = Vec::new();
+    for channel in &decoded_str[1..] {
+        subscriber.subscribe_shard_channel(channel);
+        response.push(subscriber.subscription_reply("ssubscribe", Some(channel)));
+    }
+    response
+}
+
+// SUNSUBSCRIBE [shardchannel ...], every sharded channel when none is given.
+pub fn handle_sunsubscribe(
+    subscriber: &mut Subscriber,
+    decoded_str: &[Argument],
+) -> Vec<RespValue> {
+    let mut channels: Vec<String> = decoded_str[1..].iter().map(Argument::to_string).collect();
+    if channels.is_empty() {
+        channels = subscriber.shard_channels.iter().cloned().collect();
+        if channels.is_empty() {
+            return vec![subscriber.subscription_reply("sunsubscribe", None)];
+        }
+    }
+    let mut response = Vec::new();
+    for channel in &channels {
+        subscriber.unsubscribe_shard_channel(channel);
+        response.push(subscriber.subscription_reply("sunsubscribe", Some(channel)));
+    }
+    response
+}
+
+// PUBLISH channel message. Replicas get the message through the replication
+// stream so that their own subscribers see it too.
+pub fn handle_publish(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    let mut keyspace = state.keyspace.lock().unwrap();
+    let response = publish_locked(state, &mut keyspace, decoded_str);
+    state.propagate_effects(&mut keyspace);
+    response
+}
+
+// PUBLISH with the keyspace already locked, as inside EXEC.
+pub fn publish_locked(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("publish");
+    }
+    let receivers = state.pubsub.publish(&decoded_str[1], &decoded_str[2]);
+    keyspace.propagate(decoded_str.to_vec());
+    RespValue::Integer(receivers as i64)
+}
+
+// SPUBLISH shardchannel message. Like PUBLISH, replicas deliver it to their
+// own subscribers when it reaches them through the replication stream.
+pub fn handle_spublish(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    let mut keyspace = state.keyspace.lock().unwrap();
+    let response = spublish_locked(state, &mut keyspace, decoded_str);
+    state.propagate_effects(&mut keyspace);
+    response
+}
+
+pub fn spublish_locked(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("spublish");
+    }
+    let receivers = state.pubsub.publish_shard(&decoded_str[1], &decoded_str[2]);
+    keyspace.propagate(decoded_str.to_vec());
+    RespValue::Integer(receivers as i64)
+}
+
+fn encode_matching_channels<'a>(
+    channels: impl Iterator<Item = &'a String>,
+    pattern: Option<&Argument>,
+) -> RespValue {
+    let elements: Vec<RespValue> = channels
+        .filter(|channel| {
+            pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), channel.as_bytes()))
+        })
+        .map(RespValue::bulk)
+        .collect();
+    RespValue::Array(elements)
+}
+
+// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+//   | SHARDCHANNELS [pattern] | SHARDNUMSUB [shardchannel ...]
+pub fn handle_pubsub(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("pubsub");
+    }
+    let subcommand = decoded_str[1].to_lowercase();
+    match (subcommand.as_str(), decoded_str.len()) {
+        ("channels", 2 | 3) => {
+            let channels = state.pubsub.channels.lock().unwrap();
+            encode_matching_channels(channels.keys(), decoded_str.get(2))
+        }
+        ("shardchannels", 2 | 3) => {
+            let shard_channels = state.pubsub.shard_channels.lock().unwrap();
+            encode_matching_channels(
+                shard_channels.values().flat_map(HashMap::keys),
+                decoded_str.get(2),
+            )
+        }
+        ("numsub", _) => {
+            let channels = state.pubsub.channels.lock().unwrap();
+            let mut elements = Vec::new();
+            for channel in &decoded_str[2..] {
+                let count = channels.get(channel.as_str()).map_or(0, HashMap::len);
+                elements.push(RespValue::bulk(channel));
+                elements.push(RespValue::Integer(count as i64));
+            }
+            RespValue::Array(elements)
+        }
+        ("shardnumsub", _) => {
+            let shard_channels = state.pubsub.shard_channels.lock().unwrap();
+            let mut elements = Vec::new();
+            for channel in &decoded_str[2..] {
+                let count = shard_channels
+                    .get(&key_hash_slot(channel.as_bytes()))
+                    .and_then(|channels| channels.get(channel.as_str()))
+                    .map_or(0, HashMap::len);
+                elements.push(RespValue::bulk(channel));
+                elements.push(RespValue::Integer(count as i64));
+            }
+            RespValue::Array(elements)
+        }
+        ("numpat", 2) => RespValue::Integer(state.pubsub.patterns.lock().unwrap().len() as i64),
+        _ => RespValue::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try PUBSUB HELP.",
+            decoded_str[1]
+        )),
+    }
+}
@@ -0,0 +1,38 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+// Seeds the generator from the clock and the address of a stack value so that
+// each thread ends up with a different sequence.
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0);
+    let local = 0u8;
+    let address = &local as *const u8 as u64;
+    (nanos ^ address.rotate_left(32)) | 1
+}
+
+// xorshift64* - fast and good enough for picking random members, never use it
+// for anything security related.
+pub fn random_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    })
+}
+
+// Returns a random index in the range [0, upper).
+pub fn random_index(upper: usize) -> usize {
+    (random_u64() % upper as u64) as usize
+}
@@ -0,0 +1,846 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    functions::{self, Libraries},
+    keyspace::Keyspace,
+    listpack::{self, ListpackEntry},
+    redis_value::{parse_canonical_i64, RedisValue},
+    resp::{Argument, RespValue},
+    set::RedisSet,
+    sorted_set::SortedSet,
+    stream::{
+        Consumer, ConsumerGroup, PendingEntry, Stream, StreamFields, StreamId,
+        STREAM_NODE_MAX_ENTRIES,
+    },
+    string::RedisString,
+    wrong_number_of_arguments,
+};
+
+// Where SAVE and BGSAVE write the snapshot, and where it is loaded from at
+// startup, relative to the working directory.
+pub const DEFAULT_FILENAME: &str = "dump.rdb";
+
+const RDB_VERSION: u32 = 11;
+pub const REDIS_VERSION: &str = "7.2.0";
+
+const TYPE_STRING: u8 = 0;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+// Flags of the entries in a stream listpack node.
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+fn unix_time_in_milliseconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// CRC-64/Jones as used by Redis to checksum RDB files (reflected, poly
+// 0xad93d23594c935a9).
+fn crc64(bytes: &[u8]) -> u64 {
+    const POLY: u64 = 0x95AC_9329_AC4B_C9B5;
+    let mut crc: u64 = 0;
+    for byte in bytes {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn push_length(out: &mut Vec<u8>, length: u64) {
+    if length < 1 << 6 {
+        out.push(length as u8);
+    } else if length < 1 << 14 {
+        out.push(0x40 | (length >> 8) as u8);
+        out.push(length as u8);
+    } else if length <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(length as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&length.to_be_bytes());
+    }
+}
+
+fn push_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    // Small integers are stored in their binary form, like Redis does.
+    if bytes.len() <= 11 {
+        if let Some(value) = std::str::from_utf8(bytes)
+            .ok()
+            .and_then(parse_canonical_i64)
+        {
+            if let Ok(value) = i8::try_from(value) {
+                out.push(0xC0 | ENCODING_INT8);
+                out.push(value as u8);
+                return;
+            } else if let Ok(value) = i16::try_from(value) {
+                out.push(0xC0 | ENCODING_INT16);
+                out.extend_from_slice(&value.to_le_bytes());
+                return;
+            } else if let Ok(value) = i32::try_from(value) {
+                out.push(0xC0 | ENCODING_INT32);
+                out.extend_from_slice(&value.to_le_bytes());
+                return;
+            }
+        }
+    }
+    push_length(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+// IDs are stored as two big endian integers so that they sort bytewise.
+fn push_raw_stream_id(out: &mut Vec<u8>, id: &StreamId) {
+    out.extend_from_slice(&id.ms.to_be_bytes());
+    out.extend_from_slice(&id.seq.to_be_bytes());
+}
+
+fn listpack_string(input: &str) -> ListpackEntry {
+    match parse_canonical_i64(input) {
+        Some(value) => ListpackEntry::Integer(value),
+        None => ListpackEntry::String(input.as_bytes().to_vec()),
+    }
+}
+
+// Lays entries out the way Redis' stream listpack nodes do: a master entry
+// holding the fields of the first entry, then every entry as deltas from
+// the master ID, omitting field names when they match the master ones.
+fn encode_stream_node(entries: &[(&StreamId, &StreamFields)]) -> (StreamId, Vec<u8>) {
+    let master_id = *entries[0].0;
+    let master_fields: Vec<&str> = entries[0]
+        .1
+        .iter()
+        .map(|(field, _)| field.as_str())
+        .collect();
+
+    let mut node = vec![
+        ListpackEntry::Integer(entries.len() as i64),
+        ListpackEntry::Integer(0),
+        ListpackEntry::Integer(master_fields.len() as i64),
+    ];
+    node.extend(master_fields.iter().map(|field| listpack_string(field)));
+    node.push(ListpackEntry::Integer(0));
+
+    for (id, fields) in entries {
+        let same_fields = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(&master_fields)
+                .all(|((field, _), master_field)| field == master_field);
+        let flags = if same_fields {
+            STREAM_ITEM_FLAG_SAMEFIELDS
+        } else {
+            0
+        };
+        node.push(ListpackEntry::Integer(flags));
+        node.push(ListpackEntry::Integer(
+            id.ms.wrapping_sub(master_id.ms) as i64
+        ));
+        node.push(ListpackEntry::Integer(
+            id.seq.wrapping_sub(master_id.seq) as i64
+        ));
+        if same_fields {
+            node.extend(fields.iter().map(|(_, value)| listpack_string(value)));
+            node.push(ListpackEntry::Integer(fields.len() as i64 + 3));
+        } else {
+            node.push(ListpackEntry::Integer(fields.len() as i64));
+            for (field, value) in fields.iter() {
+                node.push(listpack_string(field));
+                node.push(listpack_string(value));
+            }
+            node.push(ListpackEntry::Integer(fields.len() as i64 * 2 + 4));
+        }
+    }
+    (master_id, listpack::encode(&node))
+}
+
+fn push_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let entries: Vec<(&StreamId, &StreamFields)> = stream.entries().collect();
+    let nodes: Vec<&[(&StreamId, &StreamFields)]> =
+        entries.chunks(STREAM_NODE_MAX_ENTRIES).collect();
+    push_length(out, nodes.len() as u64);
+    for node in nodes {
+        let (master_id, listpack) = encode_stream_node(node);
+        let mut key = Vec::with_capacity(16);
+        push_raw_stream_id(&mut key, &master_id);
+        push_string(out, &key);
+        push_string(out, &listpack);
+    }
+
+    push_length(out, stream.len() as u64);
+    for id in [
+        stream.last_id(),
+        stream.first_id(),
+        stream.max_deleted_entry_id(),
+    ] {
+        push_length(out, id.ms);
+        push_length(out, id.seq);
+    }
+    push_length(out, stream.entries_added());
+
+    push_length(out, stream.groups().len() as u64);
+    for (name, group) in stream.groups() {
+        push_string(out, name.as_bytes());
+        push_length(out, group.last_id.ms);
+        push_length(out, group.last_id.seq);
+        // -1, as Redis stores an unknown counter.
+        push_length(out, group.entries_read.unwrap_or(u64::MAX));
+
+        push_length(out, group.pending.len() as u64);
+        for (id, entry) in &group.pending {
+            push_raw_stream_id(out, id);
+            out.extend_from_slice(&entry.delivery_time.to_le_bytes());
+            push_length(out, entry.delivery_count);
+        }
+        push_length(out, group.consumers.len() as u64);
+        for (name, consumer) in &group.consumers {
+            push_string(out, name.as_bytes());
+            out.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            let active_time = consumer.active_time.map_or(-1, |time| time as i64);
+            out.extend_from_slice(&active_time.to_le_bytes());
+            push_length(out, consumer.pending.len() as u64);
+            for id in &consumer.pending {
+                push_raw_stream_id(out, id);
+            }
+        }
+    }
+}
+
+fn push_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.push(OPCODE_AUX);
+    push_string(out, key.as_bytes());
+    push_string(out, value.as_bytes());
+}
+
+// Each library is saved as its code, loading runs it again.
+fn push_functions(out: &mut Vec<u8>, libraries: &Libraries) {
+    for library in libraries.iter() {
+        out.push(OPCODE_FUNCTION2);
+        push_string(out, library.code.as_bytes());
+    }
+}
+
+// The FUNCTION DUMP payload: the libraries as in an RDB file, then the RDB
+// version and a checksum, like DUMP.
+pub fn dump_functions(libraries: &Libraries) -> Vec<u8> {
+    let mut out = Vec::new();
+    push_functions(&mut out, libraries);
+    out.extend_from_slice(&(RDB_VERSION as u16).to_le_bytes());
+    let checksum = crc64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+// Reads back the library codes of a FUNCTION DUMP payload.
+pub fn read_function_dump(payload: &[u8]) -> Result<Vec<String>, String> {
+    let wrong_payload = || "ERR payload version or checksum are wrong".to_string();
+    let Some(content_len) = payload.len().checked_sub(10) else {
+        return Err(wrong_payload());
+    };
+    let version = u16::from_le_bytes([payload[content_len], payload[content_len + 1]]);
+    let checksum = u64::from_le_bytes(payload[content_len + 2..].try_into().unwrap());
+    if version as u32 > RDB_VERSION || checksum != crc64(&payload[..content_len + 2]) {
+        return Err(wrong_payload());
+    }
+    let mut reader = Reader {
+        bytes: &payload[..content_len],
+        pos: 0,
+    };
+    let mut codes = Vec::new();
+    while reader.pos < content_len {
+        if reader.read_u8()? != OPCODE_FUNCTION2 {
+            return Err("ERR given type is not a function".to_string());
+        }
+        codes.push(
+            reader
+                .read_utf8()
+                .map_err(|error| format!("ERR {}", error))?,
+        );
+    }
+    Ok(codes)
+}
+
+// Serializes the keyspace as an RDB file.
+pub fn encode(keyspace: &Keyspace) -> Vec<u8> {
+    let mut out: Vec<u8> = format!("REDIS{:04}", RDB_VERSION).into_bytes();
+    push_aux(&mut out, "redis-ver", REDIS_VERSION);
+    push_aux(&mut out, "redis-bits", "64");
+    push_aux(
+        &mut out,
+        "ctime",
+        &(unix_time_in_milliseconds() / 1000).to_string(),
+    );
+    push_aux(&mut out, "aof-base", "0");
+    push_functions(&mut out, keyspace.functions());
+
+    let now = Instant::now();
+    let now_unix = unix_time_in_milliseconds();
+    // Empty databases are left out.
+    for db in 0..keyspace.databases() {
+        let entries: Vec<_> = keyspace.iter_database(db).collect();
+        if entries.is_empty() {
+            continue;
+        }
+        out.push(OPCODE_SELECTDB);
+        push_length(&mut out, db as u64);
+        out.push(OPCODE_RESIZEDB);
+        push_length(&mut out, entries.len() as u64);
+        push_length(
+            &mut out,
+            entries
+                .iter()
+                .filter(|(_, _, expiration)| expiration.is_some())
+                .count() as u64,
+        );
+
+        for (key, value, expiration) in entries {
+            if let Some(expiration) = expiration {
+                let expire_at =
+                    now_unix + expiration.saturating_duration_since(now).as_millis() as u64;
+                out.push(OPCODE_EXPIRETIME_MS);
+                out.extend_from_slice(&expire_at.to_le_bytes());
+            }
+            match value {
+                RedisValue::String(value) => {
+                    out.push(TYPE_STRING);
+                    push_string(&mut out, key.as_bytes());
+                    push_string(&mut out, &value.as_bytes());
+                }
+                RedisValue::Set(set) => {
+                    out.push(TYPE_SET);
+                    push_string(&mut out, key.as_bytes());
+                    let members = set.members();
+                    push_length(&mut out, members.len() as u64);
+                    for member in members {
+                        push_string(&mut out, member.as_bytes());
+                    }
+                }
+                RedisValue::SortedSet(sorted_set) => {
+                    out.push(TYPE_ZSET_2);
+                    push_string(&mut out, key.as_bytes());
+                    let entries = sorted_set.entries();
+                    push_length(&mut out, entries.len() as u64);
+                    // Highest scores first, so that loading appends to the tail.
+                    for (member, score) in entries.iter().rev() {
+                        push_string(&mut out, member.as_bytes());
+                        out.extend_from_slice(&score.to_le_bytes());
+                    }
+                }
+                RedisValue::Stream(stream) => {
+                    out.push(TYPE_STREAM_LISTPACKS_3);
+                    push_string(&mut out, key.as_bytes());
+                    push_stream(&mut out, stream);
+                }
+            }
+        }
+    }
+
+    out.push(OPCODE_EOF);
+    let checksum = crc64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+enum Length {
+    Plain(u64),
+    // String stored with a special encoding: an integer or LZF compressed.
+    Encoded(u8),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| "unexpected end of RDB file".to_string())?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u64_le(&mut self) -> Result<u64, String> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_u64_be(&mut self) -> Result<u64, String> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_length_or_encoding(&mut self) -> Result<Length, String> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok(Length::Plain((first & 0x3F) as u64)),
+            1 => Ok(Length::Plain(
+                (((first & 0x3F) as u64) << 8) | self.read_u8()? as u64,
+            )),
+            2 => match first {
+                0x80 => {
+                    let bytes = self.read_bytes(4)?;
+                    Ok(Length::Plain(
+                        u32::from_be_bytes(bytes.try_into().unwrap()) as u64
+                    ))
+                }
+                0x81 => Ok(Length::Plain(self.read_u64_be()?)),
+                _ => Err(format!("invalid RDB length encoding {:#04x}", first)),
+            },
+            _ => Ok(Length::Encoded(first & 0x3F)),
+        }
+    }
+
+    fn read_length(&mut self) -> Result<u64, String> {
+        match self.read_length_or_encoding()? {
+            Length::Plain(length) => Ok(length),
+            Length::Encoded(_) => Err("unexpected encoded length in RDB file".to_string()),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>, String> {
+        match self.read_length_or_encoding()? {
+            Length::Plain(length) => Ok(self.read_bytes(length as usize)?.to_vec()),
+            Length::Encoded(ENCODING_INT8) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENCODING_INT16) => {
+                let bytes = self.read_bytes(2)?;
+                Ok(i16::from_le_bytes(bytes.try_into().unwrap())
+                    .to_string()
+                    .into_bytes())
+            }
+            Length::Encoded(ENCODING_INT32) => {
+                let bytes = self.read_bytes(4)?;
+                Ok(i32::from_le_bytes(bytes.try_into().unwrap())
+                    .to_string()
+                    .into_bytes())
+            }
+            Length::Encoded(ENCODING_LZF) => {
+                let compressed_len = self.read_length()? as usize;
+                let len = self.read_length()? as usize;
+                lzf_decompress(self.read_bytes(compressed_len)?, len)
+            }
+            Length::Encoded(encoding) => Err(format!("unknown RDB string encoding {}", encoding)),
+        }
+    }
+
+    fn read_utf8(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(&self.read_string()?).into_owned())
+    }
+
+    fn read_raw_stream_id(&mut self) -> Result<StreamId, String> {
+        Ok(StreamId {
+            ms: self.read_u64_be()?,
+            seq: self.read_u64_be()?,
+        })
+    }
+
+    fn read_length_stream_id(&mut self) -> Result<StreamId, String> {
+        Ok(StreamId {
+            ms: self.read_length()?,
+            seq: self.read_length()?,
+        })
+    }
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    let corrupt = || "corrupt LZF compressed string".to_string();
+    let mut output: Vec<u8> = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < input.len() {
+        let control = input[pos] as usize;
+        pos += 1;
+        if control < 32 {
+            let literal = input.get(pos..pos + control + 1).ok_or_else(corrupt)?;
+            output.extend_from_slice(literal);
+            pos += control + 1;
+        } else {
+            let mut length = control >> 5;
+            if length == 7 {
+                length += *input.get(pos).ok_or_else(corrupt)? as usize;
+                pos += 1;
+            }
+            let offset = ((control & 0x1F) << 8) + *input.get(pos).ok_or_else(corrupt)? as usize;
+            pos += 1;
+            let start = output.len().checked_sub(offset + 1).ok_or_else(corrupt)?;
+            // Back references may overlap what they produce.
+            for i in 0..length + 2 {
+                output.push(output[start + i]);
+            }
+        }
+    }
+    if output.len() != len {
+        return Err(corrupt());
+    }
+    Ok(output)
+}
+
+fn read_intset(blob: &[u8]) -> Result<RedisSet, String> {
+    let corrupt = || "corrupt intset".to_string();
+    let header = blob.get(..8).ok_or_else(corrupt)?;
+    let width = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    let values = blob.get(8..8 + width * len).ok_or_else(corrupt)?;
+    let members = values.chunks(width).map(|chunk| {
+        match width {
+            2 => i16::from_le_bytes(chunk.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(chunk.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(chunk.try_into().unwrap()),
+        }
+        .to_string()
+    });
+    if !matches!(width, 2 | 4 | 8) {
+        return Err(corrupt());
+    }
+    Ok(members.collect())
+}
+
+fn read_stream(reader: &mut Reader, version: u8) -> Result<Stream, String> {
+    let corrupt = || "corrupt stream listpack".to_string();
+    let mut entries: BTreeMap<StreamId, StreamFields> = BTreeMap::new();
+    let nodes = reader.read_length()?;
+    for _ in 0..nodes {
+        let master_key = reader.read_string()?;
+        let master_id = Reader {
+            bytes: &master_key,
+            pos: 0,
+        }
+        .read_raw_stream_id()?;
+        let node = listpack::decode(&reader.read_string()?)?;
+        let mut node = node.into_iter();
+        let next_integer = |node: &mut std::vec::IntoIter<ListpackEntry>| {
+            node.next()
+                .and_then(|entry| entry.as_integer())
+                .ok_or_else(corrupt)
+        };
+        let next_string = |node: &mut std::vec::IntoIter<ListpackEntry>| {
+            node.next()
+                .map(|entry| String::from_utf8_lossy(&entry.into_bytes()).into_owned())
+                .ok_or_else(corrupt)
+        };
+
+        let count = next_integer(&mut node)?;
+        let deleted = next_integer(&mut node)?;
+        let master_field_count = next_integer(&mut node)?;
+        let master_fields: Vec<String> = (0..master_field_count)
+            .map(|_| next_string(&mut node))
+            .collect::<Result<_, _>>()?;
+        next_integer(&mut node)?;
+
+        for _ in 0..count + deleted {
+            let flags = next_integer(&mut node)?;
+            let id = StreamId {
+                ms: master_id.ms.wrapping_add(next_integer(&mut node)? as u64),
+                seq: master_id.seq.wrapping_add(next_integer(&mut node)? as u64),
+            };
+            let fields: StreamFields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                master_fields
+                    .iter()
+                    .map(|field| Ok((field.clone(), next_string(&mut node)?)))
+                    .collect::<Result<_, String>>()?
+            } else {
+                let field_count = next_integer(&mut node)?;
+                (0..field_count)
+                    .map(|_| Ok((next_string(&mut node)?, next_string(&mut node)?)))
+                    .collect::<Result<_, String>>()?
+            };
+            next_integer(&mut node)?;
+            if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                entries.insert(id, fields);
+            }
+        }
+    }
+
+    reader.read_length()?;
+    let last_id = reader.read_length_stream_id()?;
+    let (max_deleted_entry_id, entries_added) = if version >= TYPE_STREAM_LISTPACKS_2 {
+        reader.read_length_stream_id()?;
+        let max_deleted_entry_id = reader.read_length_stream_id()?;
+        (max_deleted_entry_id, reader.read_length()?)
+    } else {
+        (StreamId::MIN, entries.len() as u64)
+    };
+
+    let mut groups: BTreeMap<String, ConsumerGroup> = BTreeMap::new();
+    for _ in 0..reader.read_length()? {
+        let name = reader.read_utf8()?;
+        let group_last_id = reader.read_length_stream_id()?;
+        let entries_read = if version >= TYPE_STREAM_LISTPACKS_2 {
+            Some(reader.read_length()?).filter(|entries_read| *entries_read != u64::MAX)
+        } else {
+            None
+        };
+        let mut group = ConsumerGroup::new(group_last_id, entries_read);
+        for _ in 0..reader.read_length()? {
+            let id = reader.read_raw_stream_id()?;
+            let delivery_time = reader.read_u64_le()?;
+            let delivery_count = reader.read_length()?;
+            group.pending.insert(
+                id,
+                PendingEntry {
+                    consumer: String::new(),
+                    delivery_time,
+                    delivery_count,
+                },
+            );
+        }
+        for _ in 0..reader.read_length()? {
+            let consumer_name = reader.read_utf8()?;
+            let seen_time = reader.read_u64_le()?;
+            let active_time = if version >= TYPE_STREAM_LISTPACKS_3 {
+                Some(reader.read_u64_le()? as i64).filter(|time| *time >= 0)
+            } else {
+                None
+            };
+            let mut consumer = Consumer {
+                seen_time,
+                active_time: active_time.map(|time| time as u64),
+                ..Consumer::default()
+            };
+            for _ in 0..reader.read_length()? {
+                let id = reader.read_raw_stream_id()?;
+                let entry = group
+                    .pending
+                    .get_mut(&id)
+                    .ok_or_else(|| "consumer pending entry missing from group PEL".to_string())?;
+                entry.consumer = consumer_name.clone();
+                consumer.pending.insert(id);
+            }
+            group.consumers.insert(consumer_name, consumer);
+        }
+        groups.insert(name, group);
+    }
+
+    Ok(Stream::restore(
+        entries,
+        last_id,
+        max_deleted_entry_id,
+        entries_added,
+        groups,
+    ))
+}
+
+fn read_value(reader: &mut Reader, value_type: u8) -> Result<RedisValue, String> {
+    match value_type {
+        TYPE_STRING => Ok(RedisValue::String(RedisString::new(reader.read_string()?))),
+        TYPE_SET => {
+            let len = reader.read_length()?;
+            let members: Vec<String> = (0..len)
+                .map(|_| reader.read_utf8())
+                .collect::<Result<_, _>>()?;
+            Ok(RedisValue::Set(members.into_iter().collect()))
+        }
+        TYPE_SET_INTSET => Ok(RedisValue::Set(read_intset(&reader.read_string()?)?)),
+        TYPE_SET_LISTPACK => {
+            let members = listpack::decode(&reader.read_string()?)?;
+            Ok(RedisValue::Set(
+                members
+                    .into_iter()
+                    .map(|member| String::from_utf8_lossy(&member.into_bytes()).into_owned())
+                    .collect(),
+            ))
+        }
+        TYPE_ZSET_2 => {
+            let mut sorted_set = SortedSet::new();
+            for _ in 0..reader.read_length()? {
+                let member = reader.read_utf8()?;
+                let score = f64::from_bits(reader.read_u64_le()?);
+                sorted_set.insert(member, score);
+            }
+            Ok(RedisValue::SortedSet(sorted_set))
+        }
+        TYPE_ZSET_LISTPACK => {
+            let mut sorted_set = SortedSet::new();
+            let entries = listpack::decode(&reader.read_string()?)?;
+            for pair in entries.chunks(2) {
+                let [member, score] = pair else {
+                    return Err("corrupt sorted set listpack".to_string());
+                };
+                let member = String::from_utf8_lossy(&member.clone().into_bytes()).into_owned();
+                let score = String::from_utf8_lossy(&score.clone().into_bytes())
+                    .parse::<f64>()
+                    .map_err(|_| "corrupt sorted set listpack".to_string())?;
+                sorted_set.insert(member, score);
+            }
+            Ok(RedisValue::SortedSet(sorted_set))
+        }
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            Ok(RedisValue::Stream(read_stream(reader, value_type)?))
+        }
+        _ => Err(format!("unsupported RDB value type {}", value_type)),
+    }
+}
+
+// Loads an RDB file into the keyspace, returns how many keys were read.
+// Keys already expired are skipped.
+pub fn load(keyspace: &mut Keyspace, bytes: &[u8]) -> Result<usize, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let magic = reader.read_bytes(9)?;
+    if !magic.starts_with(b"REDIS") {
+        return Err("wrong signature trying to load DB from file".to_string());
+    }
+
+    let now = unix_time_in_milliseconds();
+    let mut loaded = 0;
+    let mut database = 0;
+    let mut expire_at: Option<u64> = None;
+    loop {
+        let opcode = reader.read_u8()?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            OPCODE_RESIZEDB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            OPCODE_SELECTDB => {
+                database = reader.read_length()? as usize;
+                if database < keyspace.databases() {
+                    keyspace.select(database);
+                }
+            }
+            OPCODE_EXPIRETIME_MS => expire_at = Some(reader.read_u64_le()?),
+            OPCODE_EXPIRETIME => {
+                let bytes = reader.read_bytes(4)?;
+                expire_at = Some(u32::from_le_bytes(bytes.try_into().unwrap()) as u64 * 1000);
+            }
+            OPCODE_IDLE => {
+                reader.read_length()?;
+            }
+            OPCODE_FREQ => {
+                reader.read_u8()?;
+            }
+            OPCODE_FUNCTION2 => {
+                let code = reader.read_utf8()?;
+                let library = functions::load_library(&code)
+                    .map_err(|error| format!("failed loading a function library: {}", error))?;
+                keyspace.functions_mut().insert(library, true)?;
+            }
+            OPCODE_MODULE_AUX => return Err("module data is not supported".to_string()),
+            value_type => {
+                let key = reader.read_utf8()?;
+                let value = read_value(&mut reader, value_type)?;
+                let ttl = expire_at
+                    .take()
+                    .map(|expire_at| expire_at.checked_sub(now).map(Duration::from_millis));
+                // Keys of databases beyond the configured ones are left out.
+                match ttl {
+                    _ if database >= keyspace.databases() => {}
+                    Some(None) | Some(Some(Duration::ZERO)) => {}
+                    Some(Some(ttl)) => {
+                        keyspace.insert(key, value, Some(ttl));
+                        loaded += 1;
+                    }
+                    None => {
+                        keyspace.insert(key, value, None);
+                        loaded += 1;
+                    }
+                }
+            }
+        }
+    }
+    // Loading is not a write, there is nothing to notify.
+    keyspace.take_notifications();
+    keyspace.select(0);
+
+    // Files written with `rdbchecksum no` carry a zero checksum.
+    let content_len = reader.pos;
+    if let Ok(checksum) = reader.read_u64_le() {
+        if checksum != 0 && checksum != crc64(&bytes[..content_len]) {
+            return Err("wrong RDB checksum".to_string());
+        }
+    }
+    Ok(loaded)
+}
+
+// Writes through a temporary file so that a crash never leaves a truncated
+// snapshot behind.
+pub fn save(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension(format!("tmp-{}", std::process::id()));
+    fs::write(&temporary, bytes)?;
+    fs::rename(&temporary, path)
+}
+
+// SAVE
+pub fn handle_save(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 1 {
+        return wrong_number_of_arguments("save");
+    }
+    match save(Path::new(keyspace.dbfilename()), &encode(keyspace)) {
+        Ok(()) => {
+            keyspace.saved();
+            RespValue::ok()
+        }
+        Err(error) => {
+            eprintln!("Failed saving the DB: {}", error);
+            RespValue::error("ERR")
+        }
+    }
+}
+
+// BGSAVE [SCHEDULE]
+pub fn handle_bgsave(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    match decoded_str.get(1) {
+        None => {}
+        Some(option) if decoded_str.len() == 2 && option.eq_ignore_ascii_case("schedule") => {}
+        Some(_) => return RespValue::error("ERR syntax error"),
+    }
+    background_save(keyspace);
+    RespValue::simple("Background saving started")
+}
+
+// The snapshot is taken right away, only writing it to disk happens in the
+// background.
+pub fn background_save(keyspace: &mut Keyspace) {
+    let snapshot = encode(keyspace);
+    let path = PathBuf::from(keyspace.dbfilename());
+    keyspace.saved();
+    std::thread::spawn(move || {
+        if let Err(error) = save(&path, &snapshot) {
+            eprintln!("Background saving error: {}", error);
+        }
+    });
+}
@@ -0,0 +1,33 @@
+use super::{set::RedisSet, sorted_set::SortedSet, stream::Stream, string::RedisString};
+
+pub const WRONGTYPE_ERROR: &str =
+    "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+// Every value stored in the keyspace; commands check the variant and answer
+// with WRONGTYPE when a key holds a different kind of value.
+#[derive(Debug)]
+pub enum RedisValue {
+    String(RedisString),
+    Set(RedisSet),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+// Parses an integer only when it is in canonical form (no sign on zero, no
+// leading zeros or spaces), mirroring Redis' `string2ll`. Values that round
+// trip through this function can be stored as integers without changing how
+// they are printed back to clients.
+pub fn parse_canonical_i64(input: &str) -> Option<i64> {
+    let bytes = input.as_bytes();
+    if bytes.is_empty() || bytes.len() > 20 {
+        return None;
+    }
+    if bytes == b"0" {
+        return Some(0);
+    }
+    let digits = bytes.strip_prefix(b"-").unwrap_or(bytes);
+    match digits.first() {
+        Some(b'1'..=b'9') if digits.iter().all(u8::is_ascii_digit) => input.parse().ok(),
+        _ => None,
+    }
+}
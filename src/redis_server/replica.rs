@@ -1,15 +1,21 @@
-use std::time::Duration;
+use std::sync::Arc;
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
-    sync::RwLock,
 };
 
-use crate::redis_server::{encode_simple_string, handle_connection};
+use crate::redis_server::{bind, handle_connection};
 
 use super::{
-    encode_resp_array, encode_resp_bulk_string, timed_hashmap::TimedHashMap, ConnectionHandler,
+    acl,
+    config::Config,
+    execute_keyspace_command, functions,
+    keyspace::Keyspace,
+    rdb,
+    resp::{Argument, Protocol, RespValue},
+    server_state::ServerState,
+    string, Command, ConnectionHandler,
 };
 
 // pub async fn start_replica(master_address: &str, address: &str, replication_id: String) {
@@ -31,22 +37,56 @@ use super::{
 //         }
 //     }
 // }
-pub async fn start_replica(master_address: &str, address: &str, replication_id: String) {
-    let listener = TcpListener::bind(address).await.unwrap();
-    println!("Replica started on port: {}", address);
+pub async fn start_replica(config: Config, replication_id: String) {
+    let listeners = bind(&config).await;
+    let (host, port) = config.replicaof.clone().expect("replicas have a master");
+    let master_address = format!("{}:{}", host, port);
+    let address = format!("{}:{}", config.bind[0], config.port);
+    println!("Replica started on port: {}", config.port);
+
+    let mut master_stream: TcpStream = TcpStream::connect(&master_address).await.unwrap();
+    let aclfile = Some(config.aclfile.clone()).filter(|aclfile| !aclfile.is_empty());
+    let requirepass = Some(config.requirepass.clone()).filter(|password| !password.is_empty());
+    let state = Arc::new(ServerState::new(config));
+    acl::configure(&state, aclfile, requirepass);
+    match send_handshake_to_master(&mut master_stream, &address).await {
+        Ok((snapshot, pending)) => {
+            match rdb::load(&mut state.keyspace.lock().unwrap(), &snapshot) {
+                Ok(loaded) => println!("Loaded {} keys from master snapshot.", loaded),
+                Err(e) => eprintln!("Failed to load master snapshot: {}", e),
+            }
+            tokio::spawn(follow_master(master_stream, pending, Arc::clone(&state)));
+        }
+        Err(e) => eprintln!("Failed to synchronize with master: {}", e),
+    }
 
-    let mut master_stream: TcpStream = TcpStream::connect(master_address).await.unwrap();
-    let mut shared_master_stream = send_handshake_to_master(&mut master_stream, address).await;
-    let shared_master_stream = RwLock::new(master_stream);
+    let mut accepting = Vec::new();
+    for listener in listeners {
+        accepting.push(tokio::spawn(accept_connections(
+            listener,
+            Arc::clone(&state),
+            replication_id.clone(),
+            master_address.clone(),
+        )));
+    }
+    for task in accepting {
+        let _ = task.await;
+    }
+}
 
+async fn accept_connections(
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    replication_id: String,
+    master_address: String,
+) {
     loop {
         match listener.accept().await {
             Ok((socket, _)) => {
-                let replication_id: String = replication_id.clone();
                 let handler = SlaveConnectionHandler {
-                    timed_hashmap: TimedHashMap::new(),
+                    state: Arc::clone(&state),
                     replication_id: replication_id.clone(),
-                    master_address: master_address.to_string().clone(),
+                    master_address: master_address.clone(),
                 };
                 tokio::spawn(async move {
                     if let Err(e) = handle_connection(socket, handler).await {
@@ -60,182 +100,109 @@ pub async fn start_replica(master_address: &str, address: &str, replication_id:
 }
 
 struct SlaveConnectionHandler {
-    timed_hashmap: TimedHashMap<String, String>,
+    state: Arc<ServerState>,
     replication_id: String,
     master_address: String,
 }
 
 impl ConnectionHandler for SlaveConnectionHandler {
-    async fn handle_echo(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let response: String = encode_resp_bulk_string(&decoded_str[1]);
-        if stream.write_all(response.as_bytes()).await.is_ok() {
-            Ok(())
-        } else {
-            println!("Master: unable to send response back in ECHO command.");
-            Err("Master: unable to send successful ECHO response".into())
-        }
+    fn state(&self) -> &Arc<ServerState> {
+        &self.state
     }
 
-    async fn handle_ping(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Replica: received PING.");
-        let response = encode_resp_array(&["PONG"]);
-        if stream.write_all(response.as_bytes()).await.is_ok() {
-            println!("Replica: sent PONG.");
-            Ok(())
-        } else {
-            eprintln!("Replica: failed to reply with PONG.");
-            Err("Replica: failed to send PONG response.".into())
-        }
+    fn role(&self) -> &'static str {
+        "replica"
     }
 
-    async fn handle_set(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        match decoded_str.len() {
-            3 => {
-                println!("Inserting into key-value store with no expiry...");
-                // TODO: handle situation with more than one replica
-                let mut replica_stream = TcpStream::connect(&self.master_address).await.unwrap();
-                self.timed_hashmap.insert(
-                    decoded_str[1].to_owned(),
-                    decoded_str[2].to_owned(),
-                    None,
-                );
-                let response = encode_simple_string("OK");
-                println!("Successfully inserted into key-value store with no expiry.");
-                if stream.write_all(response.as_bytes()).await.is_ok() {
-                    Ok(())
-                } else {
-                    Err("Replica: unable to send OK response in SET command.".into())
-                }
-            }
-            5 => {
-                println!("Inserting into key-value store with expiry");
-                // TODO: use same stream as handshake
-                let mut replica_stream = TcpStream::connect(&self.master_address).await.unwrap();
-                let milliseconds: u64 = decoded_str[4].to_owned().parse().unwrap();
-                let ttl: Duration = Duration::from_millis(milliseconds);
-
-                self.timed_hashmap.insert(
-                    decoded_str[1].to_owned(),
-                    decoded_str[2].to_owned(),
-                    Some(ttl),
-                );
-
-                println!("Successfully inserted into key-value store with expiry.");
-                if stream.write_all("+OK\r\n".as_bytes()).await.is_ok() {
-                    Ok(())
-                } else {
-                    Err("Replica: unable to send OK response in SET command".into())
-                }
-            }
-            _ => Err("Unable to insert into key-value store.".into()),
-        }
+    fn handle_echo(&mut self, decoded_str: &[Argument]) -> RespValue {
+        RespValue::bulk(&decoded_str[1])
     }
 
-    async fn handle_get(
-        &mut self,
-        decoded_str: &[String],
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Entering into GET command...");
-        println!("Removing expired entries...");
-        self.timed_hashmap.remove_expired_entries();
+    fn handle_ping(&mut self) -> RespValue {
+        println!("Replica: received PING.");
+        RespValue::Array(vec![RespValue::bulk("PONG")])
+    }
 
-        if let Some(key) = self.timed_hashmap.get(&decoded_str[1]) {
-            let response: String = encode_resp_bulk_string(key);
-            if stream.write_all(response.as_bytes()).await.is_ok() {
-                Ok(())
-            } else {
-                Err("Replica: unable to send successful response in GET commmand.".into())
-            }
-        } else {
-            match stream.write_all("$-1\r\n".as_bytes()).await {
-                Ok(_) => Ok(()),
-                Err(_) => Err("Replica: null bulk string.".into()),
-            }
-        }
+    fn handle_set(&mut self, db: &mut usize, decoded_str: &[Argument]) -> RespValue {
+        println!("Inserting into key-value store...");
+        execute_keyspace_command(
+            &self.state,
+            db,
+            &Command::Set,
+            string::handle_set,
+            decoded_str,
+        )
     }
 
-    async fn handle_info(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        println!("Master: entering INFO command...");
-        let encoded_role: String = encode_resp_bulk_string("master");
-        let encoded_master_replid =
-            encode_resp_bulk_string(format!("master_replid:{}", self.replication_id).as_str());
-        // TODO: Modify to use dynamic offset values
-        let encoded_master_repl_offset: String = encode_resp_bulk_string("master_repl_offset:0");
-        let response: String = format!(
-            "{}{}{}",
-            encoded_role, encoded_master_replid, encoded_master_repl_offset
-        );
+    fn handle_get(&mut self, db: &mut usize, decoded_str: &[Argument]) -> RespValue {
+        println!("Entering into GET command...");
+        execute_keyspace_command(
+            &self.state,
+            db,
+            &Command::Get,
+            string::handle_get,
+            decoded_str,
+        )
+    }
 
-        if stream
-            .write_all(encode_resp_bulk_string(response.as_str()).as_bytes())
-            .await
-            .is_ok()
-        {
-            Ok(())
-        } else {
-            Err("Master: failed to send successful response in INFO command.".into())
-        }
+    fn replication_info(&self) -> String {
+        let (host, port) = self
+            .master_address
+            .rsplit_once(':')
+            .unwrap_or((&self.master_address, ""));
+        format!(
+            "# Replication\r\nrole:slave\r\nmaster_host:{}\r\nmaster_port:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:0\r\n",
+            host, port, self.replication_id
+        )
     }
 
-    async fn handle_replconf(
-        &mut self,
-        stream: &mut TcpStream,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    fn handle_replconf(&mut self) -> RespValue {
         todo!()
     }
 
     async fn handle_psync(
         &mut self,
-        stream: &mut TcpStream,
-        replication_id: &str,
+        _stream: &mut TcpStream,
     ) -> Result<(), Box<dyn std::error::Error>> {
         todo!()
     }
 }
 
-async fn send_handshake_to_master<'a>(stream: &'a mut TcpStream, port: &str) -> &'a mut TcpStream {
+// Returns the RDB snapshot sent by the master, along with whatever was
+// received after it: the beginning of the replication stream.
+async fn send_handshake_to_master(
+    stream: &mut TcpStream,
+    port: &str,
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error>> {
     send_ping_to_master(stream).await;
     send_replconf_to_master(stream, port).await;
-    send_psync_to_master(stream).await;
-    stream
+    send_psync_to_master(stream).await
 }
 
 async fn send_ping_to_master(stream: &mut TcpStream) {
     println!("Sending PING to master...");
-    let ping = encode_resp_array(&["PING"]);
-    if let Err(e) = stream.write_all(ping.as_bytes()).await {
+    let ping = RespValue::bulks(["PING"]);
+    if let Err(e) = stream.write_all(&ping.encode(Protocol::Resp2)).await {
         eprintln!("Failed to send Ping to master with error: {:?}", e);
     }
 
     let ping_response = read_from_stream(stream).await;
-    println!("PING response: {}", ping_response);
+    println!("PING response: {:?}", ping_response);
 }
 
 // Notifies master what port replica is lstening on & notifying the master of its capabilities
 async fn send_replconf_to_master(stream: &mut TcpStream, port: &str) {
     println!("Sending REPLCONF with port information to master...");
-    let replconf_listening_port = encode_resp_array(&[
+    let replconf_listening_port = RespValue::bulks([
         "REPLCONF",
         "listening-port",
-        &get_port_from_address(port).as_str(),
+        get_port_from_address(port).as_str(),
     ]);
 
-    if let Err(e) = stream.write_all(replconf_listening_port.as_bytes()).await {
+    if let Err(e) = stream
+        .write_all(&replconf_listening_port.encode(Protocol::Resp2))
+        .await
+    {
         eprintln!(
             "Failed to send replconf_listening_port to master with error: {}",
             e
@@ -244,13 +211,16 @@ async fn send_replconf_to_master(stream: &mut TcpStream, port: &str) {
 
     let replconf_listening_port_response = read_from_stream(stream).await;
     println!(
-        "REPLCONF port information response: {}",
+        "REPLCONF port information response: {:?}",
         replconf_listening_port_response
     );
 
     println!("Sending REPLCONF with capabilities to master...");
-    let replconf_capabilities = encode_resp_array(&["REPLCONF", "capa", "psync2"]);
-    if let Err(e) = stream.write_all(replconf_capabilities.as_bytes()).await {
+    let replconf_capabilities = RespValue::bulks(["REPLCONF", "capa", "psync2"]);
+    if let Err(e) = stream
+        .write_all(&replconf_capabilities.encode(Protocol::Resp2))
+        .await
+    {
         eprintln!(
             "Failed to send replconf_capabilities to master with error: {}",
             e
@@ -259,23 +229,152 @@ async fn send_replconf_to_master(stream: &mut TcpStream, port: &str) {
 
     let replconf_capabilities_response = read_from_stream(stream).await;
     println!(
-        "REPLCONF capabilities response: {}",
+        "REPLCONF capabilities response: {:?}",
         replconf_capabilities_response
     );
 }
 
 // Used to synchronize with the state of the replica with master
-async fn send_psync_to_master(stream: &mut TcpStream) {
+async fn send_psync_to_master(
+    stream: &mut TcpStream,
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error>> {
     // First command (after PSYNC) should be ID of master or ? if it is first time connecting
     // Second command is the offset of the master or -1 if it is first time connecting to master
     println!("Sending PSYNC to master...");
-    let replconf_psync = encode_resp_array(&["PSYNC", "?", "-1"]);
-    if let Err(e) = stream.write_all(replconf_psync.as_bytes()).await {
-        eprintln!("Failed to send replconf_psync to master with error: {}", e);
+    let replconf_psync = RespValue::bulks(["PSYNC", "?", "-1"]);
+    stream
+        .write_all(&replconf_psync.encode(Protocol::Resp2))
+        .await?;
+
+    // +FULLRESYNC <replid> <offset>\r\n then $<length>\r\n and the RDB
+    // payload, which has no trailing CRLF.
+    let mut received: Vec<u8> = Vec::new();
+    let line_end = read_until_crlf(stream, &mut received, 0).await?;
+    println!(
+        "PSYNC response: {}",
+        String::from_utf8_lossy(&received[..line_end])
+    );
+    let header_end = read_until_crlf(stream, &mut received, line_end + 2).await?;
+    let length: usize = std::str::from_utf8(&received[line_end + 2..header_end])?
+        .strip_prefix('$')
+        .ok_or("Expected the RDB payload after FULLRESYNC.")?
+        .parse()?;
+    let start = header_end + 2;
+    let mut buf = [0; 4096];
+    while received.len() < start + length {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err("Master closed the connection during synchronization.".into());
+        }
+        received.extend_from_slice(&buf[..n]);
+    }
+    let pending = received.split_off(start + length);
+    Ok((received.split_off(start), pending))
+}
+
+// Reads until a CRLF shows up after `from`, returns its position.
+async fn read_until_crlf(
+    stream: &mut TcpStream,
+    received: &mut Vec<u8>,
+    from: usize,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut buf = [0; 4096];
+    loop {
+        if let Some(position) = received
+            .get(from..)
+            .and_then(|unread| unread.windows(2).position(|window| window == b"\r\n"))
+        {
+            return Ok(from + position);
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err("Master closed the connection during synchronization.".into());
+        }
+        received.extend_from_slice(&buf[..n]);
+    }
+}
+
+// Applies the writes streamed by the master, silently. Only REPLCONF GETACK
+// gets an answer: the number of bytes processed so far. Writes between MULTI
+// and EXEC are held back and applied together.
+async fn follow_master(mut stream: TcpStream, mut received: Vec<u8>, state: Arc<ServerState>) {
+    let mut offset: usize = 0;
+    let mut buf = [0; 4096];
+    let mut transaction: Option<Vec<Vec<Argument>>> = None;
+    // The database the master last selected in the stream.
+    let mut db = 0;
+    loop {
+        while let Ok(Some((request, consumed))) = RespValue::decode(&received) {
+            received.drain(..consumed);
+            let decoded_str = request.into_command().unwrap_or_default();
+            let is_getack = decoded_str.len() >= 2
+                && decoded_str[0].eq_ignore_ascii_case("replconf")
+                && decoded_str[1].eq_ignore_ascii_case("getack");
+            if is_getack {
+                let ack = RespValue::bulks(["REPLCONF", "ACK", offset.to_string().as_str()]);
+                if let Err(e) = stream.write_all(&ack.encode(Protocol::Resp2)).await {
+                    eprintln!("Failed to acknowledge master offset: {}", e);
+                }
+            } else if decoded_str.is_empty() {
+            } else if decoded_str[0].eq_ignore_ascii_case("multi") {
+                transaction = Some(Vec::new());
+            } else if decoded_str[0].eq_ignore_ascii_case("exec") {
+                apply_writes_from_master(&state, &mut db, &transaction.take().unwrap_or_default());
+            } else if let Some(queued) = transaction.as_mut() {
+                queued.push(decoded_str);
+            } else {
+                apply_writes_from_master(&state, &mut db, &[decoded_str]);
+            }
+            offset += consumed;
+        }
+        match stream.read(&mut buf).await {
+            Ok(0) => {
+                eprintln!("Master closed the replication link.");
+                return;
+            }
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(e) => {
+                eprintln!("Error reading from master: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+fn apply_writes_from_master(state: &ServerState, db: &mut usize, writes: &[Vec<Argument>]) {
+    {
+        let mut keyspace = state.lock_keyspace(*db);
+        for decoded_str in writes {
+            apply_write_from_master(state, &mut keyspace, decoded_str);
+        }
+        *db = keyspace.selected();
     }
+    state.keyspace_written.notify_waiters();
+}
 
-    let psync_response = read_from_stream(stream).await;
-    println!("PSYNC response: {}", psync_response);
+fn apply_write_from_master(state: &ServerState, keyspace: &mut Keyspace, decoded_str: &[Argument]) {
+    let command = Command::from_name(&decoded_str[0]);
+    if let Some(keyspace_handler) = command.keyspace_handler() {
+        keyspace_handler(keyspace, decoded_str);
+        keyspace.take_propagated();
+    } else if let Command::Set = command {
+        string::handle_set(keyspace, decoded_str);
+    } else if let Command::Function = command {
+        functions::function_locked(state, keyspace, decoded_str);
+        keyspace.take_propagated();
+    } else if let (Command::Publish, Some(channel), Some(message)) =
+        (&command, decoded_str.get(1), decoded_str.get(2))
+    {
+        state.pubsub.publish(channel, message);
+    } else if let (Command::Spublish, Some(channel), Some(message)) =
+        (&command, decoded_str.get(1), decoded_str.get(2))
+    {
+        state.pubsub.publish_shard(channel, message);
+    } else if !matches!(command, Command::Ping) {
+        eprintln!("Ignoring '{}' sent by master.", decoded_str[0]);
+    }
+    state.publish_notifications(keyspace);
+    state.invalidate_modified(keyspace, None);
 }
 
 // TODO: Remove (doesn't work)
@@ -293,12 +392,25 @@ async fn read_response_from_master(
     Ok(response.to_string())
 }
 
-async fn read_from_stream(stream: &mut TcpStream) -> String {
+// Reads the master's reply to a handshake command.
+async fn read_from_stream(stream: &mut TcpStream) -> RespValue {
     let mut buf = [0; 1024];
-    let n = stream.read(&mut buf).await.unwrap();
-    let received = String::from_utf8_lossy(&buf[..n]).to_string();
-    println!("Received: {}", received);
-    received
+    let mut received: Vec<u8> = Vec::new();
+    loop {
+        match RespValue::decode(&received) {
+            Ok(Some((reply, _))) => {
+                println!("Received: {:?}", reply);
+                return reply;
+            }
+            Ok(None) => {}
+            Err(e) => return RespValue::Error(format!("ERR Protocol error: {}", e)),
+        }
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return RespValue::Error("ERR Master closed the connection".to_string());
+        }
+        received.extend_from_slice(&buf[..n]);
+    }
 }
 
 fn get_port_from_address(address: &str) -> String {
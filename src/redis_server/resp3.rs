@@ -0,0 +1,176 @@
+use super::{
+    resp::{Argument, RespValue},
+    Command,
+};
+
+// Replies are built for RESP2. Connections that switched to RESP3 with HELLO
+// get them upgraded here, with the richer types where Redis uses them.
+
+// Scores are sent as bulk strings in RESP2.
+fn double(reply: RespValue) -> RespValue {
+    match reply {
+        RespValue::BulkString(bytes) => match std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|text| text.parse().ok())
+        {
+            Some(number) => RespValue::Double(number),
+            None => RespValue::BulkString(bytes),
+        },
+        reply => reply,
+    }
+}
+
+// [member, score, member, score, ...] becomes [[member, score], ...].
+fn score_pairs(elements: Vec<RespValue>) -> RespValue {
+    let mut elements = elements.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(member), Some(score)) = (elements.next(), elements.next()) {
+        pairs.push(RespValue::Array(vec![member, double(score)]));
+    }
+    RespValue::Array(pairs)
+}
+
+// [key, value, key, value, ...] becomes a map.
+fn map(elements: Vec<RespValue>) -> RespValue {
+    let mut elements = elements.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+        pairs.push((key, value));
+    }
+    RespValue::Map(pairs)
+}
+
+fn has_option(decoded_str: &[Argument], option: &str) -> bool {
+    decoded_str
+        .iter()
+        .skip(2)
+        .any(|argument| argument.eq_ignore_ascii_case(option))
+}
+
+fn upgrade_reply(command: &Command, decoded_str: &[Argument], reply: RespValue) -> RespValue {
+    let subcommand = decoded_str.get(1).map(|name| name.to_lowercase());
+    match (command, reply) {
+        (Command::Info, RespValue::BulkString(text)) => {
+            RespValue::Verbatim("txt".to_string(), text)
+        }
+        (Command::Zscore | Command::Zincrby, reply) => double(reply),
+        (Command::Zadd, reply) if has_option(decoded_str, "incr") => double(reply),
+        (Command::Geodist, reply) => double(reply),
+        (Command::Geopos, RespValue::Array(positions)) => RespValue::Array(
+            positions
+                .into_iter()
+                .map(|position| match position {
+                    RespValue::Array(coordinates) => {
+                        RespValue::Array(coordinates.into_iter().map(double).collect())
+                    }
+                    position => position,
+                })
+                .collect(),
+        ),
+        (Command::Zrange | Command::Zrandmember, RespValue::Array(elements))
+            if has_option(decoded_str, "withscores") =>
+        {
+            score_pairs(elements)
+        }
+        (Command::Zrank | Command::Zrevrank, RespValue::Array(mut elements))
+            if elements.len() == 2 =>
+        {
+            let score = elements.pop().map(double).unwrap();
+            elements.push(score);
+            RespValue::Array(elements)
+        }
+        // With a count, pairs, without, a single member and its score.
+        (Command::Zpopmin | Command::Zpopmax, RespValue::Array(elements)) => {
+            match (decoded_str.len() > 2, score_pairs(elements)) {
+                (false, RespValue::Array(mut pairs)) if pairs.len() == 1 => pairs.pop().unwrap(),
+                (_, pairs) => pairs,
+            }
+        }
+        (Command::Bzpopmin | Command::Bzpopmax, RespValue::Array(mut elements))
+            if elements.len() == 3 =>
+        {
+            let score = elements.pop().map(double).unwrap();
+            elements.push(score);
+            RespValue::Array(elements)
+        }
+        (
+            Command::Smembers | Command::Sinter | Command::Sunion | Command::Sdiff,
+            RespValue::Array(elements),
+        ) => RespValue::Set(elements),
+        (Command::Config, RespValue::Array(elements)) if subcommand.as_deref() == Some("get") => {
+            map(elements)
+        }
+        // Stream names map to their entries.
+        (Command::Xread | Command::Xreadgroup, RespValue::Array(streams)) => RespValue::Map(
+            streams
+                .into_iter()
+                .filter_map(|stream| match stream {
+                    RespValue::Array(mut pair) if pair.len() == 2 => {
+                        let entries = pair.pop().unwrap();
+                        Some((pair.pop().unwrap(), entries))
+                    }
+                    _ => None,
+                })
+                .collect(),
+        ),
+        (Command::Xinfo, RespValue::Array(elements)) => match subcommand.as_deref() {
+            Some("stream") => map(elements),
+            _ => RespValue::Array(
+                elements
+                    .into_iter()
+                    .map(|element| match element {
+                        RespValue::Array(fields) => map(fields),
+                        element => element,
+                    })
+                    .collect(),
+            ),
+        },
+        (
+            Command::Subscribe
+            | Command::Unsubscribe
+            | Command::Psubscribe
+            | Command::Punsubscribe
+            | Command::Ssubscribe
+            | Command::Sunsubscribe,
+            RespValue::Array(elements),
+        ) => RespValue::Push(elements),
+        (_, reply) => reply,
+    }
+}
+
+// Upgrades the replies of a command. Some commands, like SUBSCRIBE, reply
+// more than once.
+pub fn upgrade(
+    command: &Command,
+    decoded_str: &[Argument],
+    replies: Vec<RespValue>,
+) -> Vec<RespValue> {
+    replies
+        .into_iter()
+        .map(|reply| upgrade_reply(command, decoded_str, reply))
+        .collect()
+}
+
+// EXEC replies with what each queued command replied.
+pub fn upgrade_exec(queued: &[Vec<Argument>], reply: RespValue) -> RespValue {
+    match reply {
+        RespValue::Array(replies) if replies.len() == queued.len() => RespValue::Array(
+            queued
+                .iter()
+                .zip(replies)
+                .map(|(decoded_str, reply)| {
+                    upgrade_reply(&Command::from_name(&decoded_str[0]), decoded_str, reply)
+                })
+                .collect(),
+        ),
+        reply => reply,
+    }
+}
+
+// Published messages are push data in RESP3.
+pub fn push(message: RespValue) -> RespValue {
+    match message {
+        RespValue::Array(elements) => RespValue::Push(elements),
+        message => message,
+    }
+}
@@ -0,0 +1,712 @@
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::Notify, time::timeout_at};
+
+use super::{
+    client::Client,
+    execute_queued_command,
+    keyspace::Keyspace,
+    lua::{
+        self, argument, check_any, check_string, format_number, register, FunctionBody, Host, Lua,
+        LuaError, TableRef, Value,
+    },
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    sha1::sha1_hex,
+    wrong_number_of_arguments, Command,
+};
+
+// Like Redis's busy-reply-threshold: past this, other clients are told a
+// script is running instead of waiting for it.
+const BUSY_REPLY_THRESHOLD: Duration = Duration::from_secs(5);
+
+// Scripts run on their own thread, deep Lua recursion needs a deep stack.
+const SCRIPT_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// Nested tables a script may return, past this it likely returned a cycle.
+const MAX_REPLY_DEPTH: usize = 1000;
+
+const CHUNK_NAME: &str = "user_script";
+
+const KILLED: &str = "ERR Script killed by user with SCRIPT KILL...";
+
+// A compiled script with the flags of its shebang line, if it had one.
+#[derive(Debug)]
+struct Script {
+    body: Arc<FunctionBody>,
+    no_writes: bool,
+}
+
+// The script cache and the state of the script running, if any. The keyspace
+// stays locked while a script runs, so there is at most one.
+#[derive(Debug)]
+pub struct Scripts {
+    cache: Mutex<HashMap<String, Arc<Script>>>,
+    running: Mutex<Option<Instant>>,
+    finished: Notify,
+    // Whether the running script wrote to the keyspace, it can't be killed
+    // then.
+    wrote: AtomicBool,
+    kill_requested: AtomicBool,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        Self {
+            cache: Mutex::new(HashMap::new()),
+            running: Mutex::new(None),
+            finished: Notify::new(),
+            wrote: AtomicBool::new(false),
+            kill_requested: AtomicBool::new(false),
+        }
+    }
+
+    // Waits for the running script, if any, to finish. Returns true when it
+    // is still running after the busy threshold.
+    pub async fn wait_until_idle(&self) -> bool {
+        loop {
+            let finished = self.finished.notified();
+            let Some(started) = *self.running.lock().unwrap() else {
+                return false;
+            };
+            let deadline = started + BUSY_REPLY_THRESHOLD;
+            if Instant::now() >= deadline {
+                return true;
+            }
+            let _ = timeout_at(deadline.into(), finished).await;
+        }
+    }
+
+    fn start(&self) {
+        self.wrote.store(false, Ordering::Relaxed);
+        self.kill_requested.store(false, Ordering::Relaxed);
+        *self.running.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn finish(&self) {
+        *self.running.lock().unwrap() = None;
+        self.finished.notify_waiters();
+    }
+
+    fn kill(&self) -> RespValue {
+        if self.running.lock().unwrap().is_none() {
+            return RespValue::error("NOTBUSY No scripts in execution right now.");
+        }
+        if self.wrote.load(Ordering::Relaxed) {
+            return RespValue::error(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. You can only wait the script termination.",
+            );
+        }
+        self.kill_requested.store(true, Ordering::Relaxed);
+        RespValue::ok()
+    }
+
+    // Compiles a script, or finds it already compiled, and returns its SHA1.
+    fn load(&self, source: &str) -> Result<(String, Arc<Script>), RespValue> {
+        let sha = sha1_hex(source.as_bytes());
+        if let Some(script) = self.cache.lock().unwrap().get(&sha) {
+            return Ok((sha, Arc::clone(script)));
+        }
+        let script = Arc::new(compile(source)?);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(sha.clone(), Arc::clone(&script));
+        Ok((sha, script))
+    }
+
+    fn get(&self, sha: &str) -> Option<Arc<Script>> {
+        self.cache.lock().unwrap().get(&sha.to_lowercase()).cloned()
+    }
+}
+
+// Whether a command may run while a script is busy: only the ones killing
+// it.
+pub fn allowed_when_busy(command: &Command, decoded_str: &[Argument]) -> bool {
+    matches!(command, Command::Script | Command::Function)
+        && decoded_str
+            .get(1)
+            .is_some_and(|subcommand| subcommand.eq_ignore_ascii_case("kill"))
+}
+
+// SCRIPT KILL and FUNCTION KILL.
+pub fn handle_kill(state: &ServerState) -> RespValue {
+    state.scripts.kill()
+}
+
+// Reads the `#!lua flags=...` line scripts may start with. It is commented
+// out rather than removed so that line numbers don't change.
+fn compile(source: &str) -> Result<Script, RespValue> {
+    let mut no_writes = false;
+    let mut code = source.as_bytes().to_vec();
+    if let Some(shebang) = source.strip_prefix("#!") {
+        let line = shebang.lines().next().unwrap_or_default();
+        let mut parts = line.split_whitespace();
+        let engine = parts.next().unwrap_or_default();
+        if engine != "lua" {
+            return Err(RespValue::error(format!(
+                "ERR Unexpected engine in script shebang: {}",
+                engine
+            )));
+        }
+        for part in parts {
+            let Some(flags) = part.strip_prefix("flags=") else {
+                return Err(RespValue::error(format!(
+                    "ERR Unknown lua shebang option: {}",
+                    part
+                )));
+            };
+            for flag in flags.split(',').filter(|flag| !flag.is_empty()) {
+                match flag {
+                    "no-writes" => no_writes = true,
+                    "allow-oom" | "allow-stale" | "no-cluster" | "allow-cross-slot-keys" => {}
+                    _ => {
+                        return Err(RespValue::error(format!(
+                            "ERR Unexpected flag in script shebang: {}",
+                            flag
+                        )))
+                    }
+                }
+            }
+        }
+        code[..2].copy_from_slice(b"--");
+    }
+    let body = lua::compile(&code, CHUNK_NAME).map_err(|error| {
+        RespValue::error(format!(
+            "ERR Error compiling script (new function): {}",
+            error
+        ))
+    })?;
+    Ok(Script { body, no_writes })
+}
+
+// EVAL, FCALL and friends, run by `execute` with the keyspace locked for
+// the whole script, starting in the database of `client`. Scripts run off
+// the runtime so that other clients can still be told the server is busy,
+// or kill them.
+pub async fn handle_script_command(
+    state: &Arc<ServerState>,
+    client: &Client,
+    decoded_str: &[Argument],
+    execute: fn(&ServerState, &mut Keyspace, &Client, &[Argument]) -> RespValue,
+) -> RespValue {
+    let state = Arc::clone(state);
+    let client = client.clone();
+    let decoded_str = decoded_str.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut keyspace = state.lock_keyspace(client.db);
+        let response = execute(&state, &mut keyspace, &client, &decoded_str);
+        state.propagate_effects(&mut keyspace);
+        response
+    })
+    .await
+    .unwrap_or_else(|_| RespValue::error("ERR Script crashed"))
+}
+
+// Splits `command name numkeys [key ...] [arg ...]` into keys and arguments.
+pub fn keys_and_arguments(
+    decoded_str: &[Argument],
+) -> Result<(&[Argument], &[Argument]), RespValue> {
+    if decoded_str.len() < 3 {
+        return Err(wrong_number_of_arguments(&decoded_str[0].to_lowercase()));
+    }
+    let Ok(numkeys) = decoded_str[2].parse::<i64>() else {
+        return Err(RespValue::error(
+            "ERR value is not an integer or out of range",
+        ));
+    };
+    if numkeys < 0 {
+        return Err(RespValue::error("ERR Number of keys can't be negative"));
+    }
+    if numkeys as usize > decoded_str.len() - 3 {
+        return Err(RespValue::error(
+            "ERR Number of keys can't be greater than number of args",
+        ));
+    }
+    Ok(decoded_str[3..].split_at(numkeys as usize))
+}
+
+// EVAL script numkeys [key ...] [arg ...], EVALSHA sha1 numkeys ... and
+// their read-only variants.
+pub fn eval_locked(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    client: &Client,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let command = Command::from_name(&decoded_str[0]);
+    let (keys, argv) = match keys_and_arguments(decoded_str) {
+        Ok(split) => split,
+        Err(error) => return error,
+    };
+    let (sha, script) = match command {
+        Command::Evalsha | Command::EvalshaRo => match state.scripts.get(&decoded_str[1]) {
+            Some(script) => (decoded_str[1].to_lowercase(), script),
+            None => return RespValue::error("NOSCRIPT No matching script. Please use EVAL."),
+        },
+        _ => match state.scripts.load(&decoded_str[1]) {
+            Ok(loaded) => loaded,
+            Err(error) => return error,
+        },
+    };
+    let read_only = script.no_writes || matches!(command, Command::EvalRo | Command::EvalshaRo);
+    run_locked(
+        state,
+        keyspace,
+        client,
+        read_only,
+        &sha,
+        CHUNK_NAME,
+        |lua| {
+            lua.globals
+                .set_field("KEYS", Value::Table(string_table(keys)));
+            lua.globals
+                .set_field("ARGV", Value::Table(string_table(argv)));
+            protect_globals(lua);
+            let function = lua.load(Arc::clone(&script.body));
+            lua.call(&function, Vec::new())
+        },
+    )
+}
+
+pub fn string_table(values: &[Argument]) -> TableRef {
+    TableRef::from_values(values.iter().map(Value::string).collect())
+}
+
+// Runs Lua code against the locked keyspace and turns what it returns into
+// a reply. Errors name the script by `name`, its SHA1 or function name. The
+// commands it calls are checked against the ACL rules of `client`'s user.
+pub fn run_locked<F>(
+    state: &ServerState,
+    keyspace: &mut Keyspace,
+    client: &Client,
+    read_only: bool,
+    name: &str,
+    chunk_name: &str,
+    run: F,
+) -> RespValue
+where
+    F: FnOnce(&mut Lua) -> Result<Vec<Value>, LuaError> + Send,
+{
+    state.scripts.start();
+    let response = on_script_stack(|| {
+        let mut host = ScriptHost {
+            state,
+            keyspace,
+            client,
+            read_only,
+        };
+        let mut lua = Lua::new(&mut host);
+        open_redis(&lua);
+        match run(&mut lua) {
+            Ok(values) => reply_from_lua(&lua::first(values), 0),
+            Err(error) => error_reply(error, name, chunk_name),
+        }
+    });
+    state.scripts.finish();
+    response
+}
+
+// Runs `run` on a thread with a stack deep enough for Lua.
+pub fn on_script_stack<R: Send>(run: impl FnOnce() -> R + Send) -> R {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(SCRIPT_STACK_SIZE)
+            .spawn_scoped(scope, run)
+            .expect("failed to start the script thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+// What `redis.call` reaches: commands run as if queued in a transaction.
+struct ScriptHost<'a> {
+    state: &'a ServerState,
+    keyspace: &'a mut Keyspace,
+    // Who ran the script.
+    client: &'a Client,
+    read_only: bool,
+}
+
+impl Host for ScriptHost<'_> {
+    fn call(&mut self, args: &[Vec<u8>]) -> RespValue {
+        let decoded_str: Vec<Argument> = args.iter().cloned().map(Argument::new).collect();
+        let command = Command::from_name(&decoded_str[0]);
+        let error = if let Command::Unknown = command {
+            Some("ERR Unknown Redis command called from script")
+        } else if !command.allowed_in_script() {
+            Some("ERR This Redis command is not allowed from script")
+        } else if !command.accepts_argument_count(decoded_str.len()) {
+            Some("ERR Wrong number of args calling Redis command from script")
+        } else if command.is_write() && self.read_only {
+            Some("ERR Write commands are not allowed from read-only scripts.")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            return RespValue::error(error);
+        }
+        let checked = self.state.acl.lock().unwrap().check_command(
+            self.client,
+            "lua",
+            &command,
+            &decoded_str,
+        );
+        if let Err(error) = checked {
+            return RespValue::Error(error);
+        }
+        let response = execute_queued_command(self.state, self.keyspace, self.client, &decoded_str);
+        if command.is_write() && !response.is_error() {
+            self.state.scripts.wrote.store(true, Ordering::Relaxed);
+        }
+        response
+    }
+
+    fn interrupted(&mut self) -> Option<String> {
+        self.state
+            .scripts
+            .kill_requested
+            .load(Ordering::Relaxed)
+            .then(|| KILLED.to_string())
+    }
+}
+
+// Scripts can't define globals nor change the libraries, reading a global
+// that doesn't exist is an error too.
+pub fn protect_globals(lua: &Lua) {
+    let metatable = TableRef::new();
+    register(&metatable, &[("__index", nonexistent_global)]);
+    let globals = lua.globals.clone();
+    let mut libraries = Vec::new();
+    let mut key = Value::Nil;
+    while let Ok(Some((next, value))) = globals.0.borrow().next(&key) {
+        let is_argument =
+            matches!(&next, Value::String(name) if &**name == b"KEYS" || &**name == b"ARGV");
+        match value {
+            Value::Table(library) if !is_argument && !Rc::ptr_eq(&library.0, &globals.0) => {
+                libraries.push(library)
+            }
+            _ => {}
+        }
+        key = next;
+    }
+    for library in libraries {
+        library.0.borrow_mut().readonly = true;
+    }
+    if let Some(string_metatable) = &lua.string_metatable {
+        string_metatable.0.borrow_mut().readonly = true;
+    }
+    let mut table = globals.0.borrow_mut();
+    table.metatable = Some(metatable);
+    table.readonly = true;
+}
+
+fn nonexistent_global(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let name = argument(&args, 2)
+        .to_lua_string()
+        .map(|name| String::from_utf8_lossy(&name).into_owned())
+        .unwrap_or_default();
+    Err(LuaError::Builtin(format!(
+        "Script attempted to access nonexistent global variable '{}'",
+        name
+    )))
+}
+
+pub fn open_redis(lua: &Lua) {
+    let redis = TableRef::new();
+    register(
+        &redis,
+        &[
+            ("call", call),
+            ("pcall", pcall),
+            ("sha1hex", sha1hex),
+            ("error_reply", error_reply_table),
+            ("status_reply", status_reply_table),
+            ("log", log),
+            // Scripts always replicate their effects.
+            ("set_repl", |_, _, _| Ok(Vec::new())),
+            ("replicate_commands", |_, _, _| {
+                Ok(vec![Value::Boolean(true)])
+            }),
+        ],
+    );
+    for (name, value) in [
+        ("LOG_DEBUG", 0),
+        ("LOG_VERBOSE", 1),
+        ("LOG_NOTICE", 2),
+        ("LOG_WARNING", 3),
+        ("REPL_NONE", 0),
+        ("REPL_AOF", 1),
+        ("REPL_SLAVE", 2),
+        ("REPL_REPLICA", 2),
+        ("REPL_ALL", 3),
+    ] {
+        redis.set_field(name, Value::Number(value as f64));
+    }
+    lua.globals.set_field("redis", Value::Table(redis));
+}
+
+fn status_table(field: &str, message: impl AsRef<[u8]>) -> Value {
+    let table = TableRef::new();
+    table.set_field(field, Value::string(message));
+    Value::Table(table)
+}
+
+// The `err` field of an error reply table.
+fn error_message(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Table(table) => match table.get_field("err") {
+            Value::String(message) => Some(message.to_vec()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Runs a command for `redis.call` and `redis.pcall`, errors come back as
+// error reply tables.
+fn run_command(lua: &mut Lua, args: &[Value]) -> Value {
+    if args.is_empty() {
+        return status_table(
+            "err",
+            "ERR Please specify at least one argument for this redis lib call",
+        );
+    }
+    let mut arguments = Vec::with_capacity(args.len());
+    for argument in args {
+        match argument {
+            Value::String(string) => arguments.push(string.to_vec()),
+            Value::Number(number) => arguments.push(format_number(*number).into_bytes()),
+            _ => {
+                return status_table(
+                    "err",
+                    "ERR Lua redis lib command arguments must be strings or integers",
+                )
+            }
+        }
+    }
+    let reply = lua.host.call(&arguments);
+    reply_to_lua(&reply)
+}
+
+fn call(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let reply = run_command(lua, &args);
+    if error_message(&reply).is_some() {
+        let line = lua.call_site(1).map_or(0, |(_, line)| line);
+        return Err(LuaError::Raised(reply, line));
+    }
+    Ok(vec![reply])
+}
+
+fn pcall(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    Ok(vec![run_command(lua, &args)])
+}
+
+fn sha1hex(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.len() != 1 {
+        return Err(LuaError::Builtin("wrong number of arguments".to_string()));
+    }
+    let string = check_string(&args, 1, "sha1hex")?;
+    Ok(vec![Value::string(sha1_hex(&string))])
+}
+
+fn error_reply_table(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let message = check_string(&args, 1, "error_reply")?;
+    Ok(vec![status_table("err", &*message)])
+}
+
+fn status_reply_table(_: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let message = check_string(&args, 1, "status_reply")?;
+    Ok(vec![status_table("ok", &*message)])
+}
+
+fn log(lua: &mut Lua, _: &[Value], args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    if args.len() < 2 {
+        return Err(LuaError::Builtin(
+            "redis.log() requires two arguments or more.".to_string(),
+        ));
+    }
+    let level = check_any(&args, 1, "log")?;
+    if !matches!(level.to_number(), Some(level) if (0.0..=3.0).contains(&level)) {
+        return Err(LuaError::Builtin("Invalid debug level.".to_string()));
+    }
+    // Checked like Redis does, there is no log file to write them to.
+    for argument in &args[1..] {
+        lua.tostring(argument)?;
+    }
+    Ok(Vec::new())
+}
+
+// RESP to Lua: integers become numbers, bulk strings strings, arrays tables,
+// nil replies false, status and error replies {ok=...} and {err=...} tables.
+fn reply_to_lua(reply: &RespValue) -> Value {
+    match reply {
+        RespValue::SimpleString(status) => status_table("ok", status),
+        RespValue::Error(message) => status_table("err", message),
+        RespValue::Integer(integer) => Value::Number(*integer as f64),
+        RespValue::BulkString(bytes) => Value::string(bytes),
+        RespValue::Array(elements) => Value::Table(TableRef::from_values(
+            elements.iter().map(reply_to_lua).collect(),
+        )),
+        RespValue::Null | RespValue::NullArray => Value::Boolean(false),
+        _ => Value::Nil,
+    }
+}
+
+// Lua to RESP: numbers become integers (truncated), true 1, false and nil
+// nil replies, tables arrays up to their first nil unless they have an
+// `err` or `ok` field.
+fn reply_from_lua(value: &Value, depth: usize) -> RespValue {
+    match value {
+        Value::String(string) => RespValue::bulk(string),
+        Value::Number(number) => RespValue::Integer(*number as i64),
+        Value::Boolean(true) => RespValue::Integer(1),
+        Value::Table(table) => {
+            if let Some(message) = error_message(value) {
+                return RespValue::error(single_line(&message));
+            }
+            if let Value::String(status) = table.get_field("ok") {
+                return RespValue::simple(single_line(&status));
+            }
+            if depth >= MAX_REPLY_DEPTH {
+                return RespValue::error("ERR reached lua stack limit");
+            }
+            let mut elements = Vec::new();
+            for index in 1.. {
+                let element = table.get(&Value::Number(index as f64));
+                if element.is_nil() {
+                    break;
+                }
+                elements.push(reply_from_lua(&element, depth + 1));
+            }
+            RespValue::Array(elements)
+        }
+        _ => RespValue::Null,
+    }
+}
+
+// Replies can't span lines.
+fn single_line(message: &[u8]) -> String {
+    String::from_utf8_lossy(message).replace(['\r', '\n'], " ")
+}
+
+// The message of an error raised while running Lua code, with the line it
+// was raised at when known.
+pub fn describe_error(error: LuaError) -> (String, Option<u32>) {
+    match error {
+        LuaError::Interrupted(message) => (message, None),
+        LuaError::Builtin(message) => (format!("ERR {}", message), None),
+        LuaError::Raised(value, line) => {
+            let message = match (&value, error_message(&value)) {
+                (_, Some(message)) => single_line(&message),
+                (Value::String(message), None) => format!("ERR {}", single_line(message)),
+                (Value::Number(number), None) => format!("ERR {}", format_number(*number)),
+                _ => "ERR unknown error".to_string(),
+            };
+            (message, Some(line))
+        }
+    }
+}
+
+// Errors raised by scripts name the script and the line they came from.
+fn error_reply(error: LuaError, name: &str, chunk_name: &str) -> RespValue {
+    if let LuaError::Interrupted(message) = error {
+        return RespValue::error(message);
+    }
+    let (message, line) = describe_error(error);
+    let location = match line {
+        Some(line) => format!(", on @{}:{}.", chunk_name, line),
+        None => String::new(),
+    };
+    RespValue::error(format!("{} script: {}{}", message, name, location))
+}
+
+// SCRIPT LOAD script | EXISTS sha1 [sha1 ...] | FLUSH [ASYNC|SYNC] | KILL
+pub fn handle_script(state: &ServerState, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("script");
+    }
+    let response = match decoded_str[1].to_lowercase().as_str() {
+        "load" if decoded_str.len() == 3 => match state.scripts.load(&decoded_str[2]) {
+            Ok((sha, _)) => RespValue::bulk(&sha),
+            Err(error) => error,
+        },
+        "exists" if decoded_str.len() >= 3 => {
+            let elements: Vec<RespValue> = decoded_str[2..]
+                .iter()
+                .map(|sha| RespValue::Integer(state.scripts.get(sha).is_some() as i64))
+                .collect();
+            RespValue::Array(elements)
+        }
+        "flush" if decoded_str.len() <= 3 => {
+            let mode = decoded_str.get(2).map(|mode| mode.to_lowercase());
+            if !matches!(mode.as_deref(), None | Some("async") | Some("sync")) {
+                return RespValue::error("ERR SCRIPT FLUSH only support SYNC|ASYNC option");
+            }
+            state.scripts.cache.lock().unwrap().clear();
+            RespValue::ok()
+        }
+        "kill" if decoded_str.len() == 2 => handle_kill(state),
+        _ => RespValue::error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try SCRIPT HELP.",
+            decoded_str[1]
+        )),
+    };
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        acl::handle_acl, config::Config, redis_value::RedisValue, string::RedisString,
+    };
+    use super::*;
+
+    fn arguments(words: &[&str]) -> Vec<Argument> {
+        words.iter().map(|word| Argument::from(*word)).collect()
+    }
+
+    #[test]
+    fn scripts_only_run_what_the_caller_may() {
+        let state = ServerState::new(Config::default());
+        let admin = Client::new(1, true, String::new(), String::new());
+        let setuser = arguments(&[
+            "ACL", "SETUSER", "eve", "on", "nopass", "~app:*", "+eval", "+get",
+        ]);
+        assert_eq!(handle_acl(&state, &admin, &setuser), RespValue::ok());
+
+        let mut eve = Client::new(2, true, String::new(), String::new());
+        eve.user = "eve".to_string();
+        let mut keyspace = Keyspace::new(1);
+        for key in ["app:1", "secret"] {
+            let value = RedisValue::String(RedisString::Integer(1));
+            keyspace.insert(key.to_string(), value, None);
+        }
+
+        let eval = |keyspace: &mut Keyspace, source: &str| {
+            eval_locked(&state, keyspace, &eve, &arguments(&["EVAL", source, "0"]))
+        };
+        assert_eq!(
+            eval(&mut keyspace, "return redis.call('GET', 'app:1')"),
+            RespValue::bulk(b"1")
+        );
+        for source in [
+            "return redis.call('GET', 'secret')",
+            "return redis.pcall('DEL', 'app:1')",
+        ] {
+            match eval(&mut keyspace, source) {
+                RespValue::Error(error) => assert!(error.starts_with("NOPERM"), "{error}"),
+                reply => panic!("{source} replied {reply:?}"),
+            }
+        }
+        assert!(keyspace.contains_key("app:1"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{
+    atomic::{AtomicU32, AtomicU64, Ordering},
+    Mutex, MutexGuard,
+};
+
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Notify,
+};
+
+use super::{
+    acl::Acl,
+    client::Clients,
+    config::Config,
+    keyspace::Keyspace,
+    notify::NotifyFlags,
+    pubsub::PubSub,
+    resp::{Argument, Protocol, RespValue},
+    scripting::Scripts,
+    tracking::Tracking,
+};
+
+// State shared by every connection of a server instance.
+#[derive(Debug)]
+pub struct ServerState {
+    pub config: Mutex<Config>,
+    pub keyspace: Mutex<Keyspace>,
+    // Signalled after each write command so that clients blocked on BZPOPMIN
+    // and friends look at their keys again.
+    pub keyspace_written: Notify,
+    // One channel per connected replica, fed with the encoded write commands
+    // to forward.
+    replicas: Mutex<Vec<UnboundedSender<Vec<u8>>>>,
+    pub pubsub: PubSub,
+    pub scripts: Scripts,
+    pub acl: Mutex<Acl>,
+    pub clients: Clients,
+    pub tracking: Mutex<Tracking>,
+    next_client_id: AtomicU64,
+    notify_keyspace_events: AtomicU32,
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> Self {
+        let mut keyspace = Keyspace::new(config.databases);
+        keyspace.set_eviction(config.eviction);
+        keyspace.set_dbfilename(&config.dbfilename);
+        let notify_keyspace_events = config.notify_keyspace_events.bits();
+        Self {
+            config: Mutex::new(config),
+            keyspace: Mutex::new(keyspace),
+            keyspace_written: Notify::new(),
+            replicas: Mutex::new(Vec::new()),
+            pubsub: PubSub::new(),
+            scripts: Scripts::new(),
+            acl: Mutex::new(Acl::new()),
+            clients: Clients::new(),
+            tracking: Mutex::new(Tracking::new()),
+            next_client_id: AtomicU64::new(1),
+            notify_keyspace_events: AtomicU32::new(notify_keyspace_events),
+        }
+    }
+
+    // Locks the keyspace with the database of the client running a command
+    // selected.
+    pub fn lock_keyspace(&self, db: usize) -> MutexGuard<'_, Keyspace> {
+        let mut keyspace = self.keyspace.lock().unwrap();
+        keyspace.select(db);
+        keyspace
+    }
+
+    pub fn notify_keyspace_events(&self) -> NotifyFlags {
+        NotifyFlags::from_bits(self.notify_keyspace_events.load(Ordering::Relaxed))
+    }
+
+    pub fn set_notify_keyspace_events(&self, flags: NotifyFlags) {
+        self.notify_keyspace_events
+            .store(flags.bits(), Ordering::Relaxed);
+    }
+
+    // Publishes the keyspace events recorded by the last command. Called with
+    // the keyspace locked so that events go out in the order of the writes.
+    pub fn publish_notifications(&self, keyspace: &mut Keyspace) {
+        let flags = self.notify_keyspace_events();
+        for event in keyspace.take_notifications() {
+            let Some((keyspace_channel, keyevent_channel)) = flags.wants(event.class) else {
+                continue;
+            };
+            if keyspace_channel {
+                let channel = format!("__keyspace@{}__:{}", event.db, event.key);
+                self.pubsub.publish(&channel, event.event);
+            }
+            if keyevent_channel {
+                let channel = format!("__keyevent@{}__:{}", event.db, event.event);
+                self.pubsub.publish(&channel, &event.key);
+            }
+        }
+    }
+
+    // Sends client side caching invalidations for the keys changed since the
+    // last call. Called with the keyspace locked, like
+    // `publish_notifications`. `writer` is the client whose command changed
+    // them, if any, for NOLOOP.
+    pub fn invalidate_modified(&self, keyspace: &mut Keyspace, writer: Option<u64>) {
+        let keys = keyspace.take_modified();
+        if keyspace.take_flushed() {
+            self.tracking.lock().unwrap().invalidate_all(self);
+        } else if !keys.is_empty() {
+            self.tracking.lock().unwrap().invalidate(self, keys, writer);
+        }
+    }
+
+    // Evicts keys until the data fits in maxmemory. Replicas, keyspace
+    // notifications and client side caching hear about the evictions like
+    // about any write. False when maxmemory-policy can't free enough.
+    pub fn free_memory(&self) -> bool {
+        let mut keyspace = self.keyspace.lock().unwrap();
+        let fits = keyspace.evict();
+        self.propagate_effects(&mut keyspace);
+        self.publish_notifications(&mut keyspace);
+        self.invalidate_modified(&mut keyspace, None);
+        fits
+    }
+
+    // Unique, increasing ID for each connection.
+    pub fn next_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Must be called with the keyspace locked, right after taking the
+    // snapshot sent to the replica, so that no write is missed or applied
+    // twice.
+    pub fn register_replica(&self) -> UnboundedReceiver<Vec<u8>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.replicas.lock().unwrap().push(sender);
+        receiver
+    }
+
+    // Forwards a write to every replica. Called with the keyspace locked for
+    // the same reason as `register_replica`.
+    pub fn propagate(&self, command: &[Argument]) {
+        let mut replicas = self.replicas.lock().unwrap();
+        if replicas.is_empty() {
+            return;
+        }
+        let encoded = RespValue::bulks(command).encode(Protocol::Resp2);
+        replicas.retain(|replica| replica.send(encoded.clone()).is_ok());
+    }
+
+    // Forwards the effects recorded in the keyspace since the keyspace was
+    // locked. Several of them go out as a MULTI/EXEC block, which replicas
+    // apply at once.
+    pub fn propagate_effects(&self, keyspace: &mut Keyspace) {
+        let effects = keyspace.take_propagated();
+        let atomic = effects.len() > 1;
+        if atomic {
+            self.propagate(&["MULTI".into()]);
+        }
+        for effect in effects {
+            self.propagate(&effect);
+        }
+        if atomic {
+            self.propagate(&["EXEC".into()]);
+        }
+    }
+}
@@ -0,0 +1,635 @@
+use super::{
+    glob::glob_match,
+    indexed_map::IndexedMap,
+    keyspace::Keyspace,
+    notify::EventClass,
+    parse_integer_argument, parse_random_count, parse_scan_options,
+    random::random_index,
+    redis_value::{parse_canonical_i64, RedisValue, WRONGTYPE_ERROR},
+    resp::{Argument, RespValue},
+    wrong_number_of_arguments,
+};
+
+// Same default as Redis' `set-max-intset-entries`.
+const SET_MAX_INTSET_ENTRIES: usize = 512;
+
+#[derive(Debug, Clone)]
+pub enum RedisSet {
+    // Sorted integers, used while every member is a canonical 64-bit integer
+    // and the set is small. Saves the per-member String allocation.
+    IntSet(Vec<i64>),
+    // With the heap memory its members take, kept up to date.
+    HashTable {
+        members: IndexedMap<String, ()>,
+        memory: usize,
+    },
+}
+
+impl RedisSet {
+    pub fn new() -> Self {
+        RedisSet::IntSet(Vec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RedisSet::IntSet(integers) => integers.len(),
+            RedisSet::HashTable { members, .. } => members.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        match self {
+            RedisSet::IntSet(integers) => parse_canonical_i64(member)
+                .is_some_and(|integer| integers.binary_search(&integer).is_ok()),
+            RedisSet::HashTable { members, .. } => members.contains_key(member),
+        }
+    }
+
+    pub fn insert(&mut self, member: String) -> bool {
+        if let RedisSet::IntSet(integers) = self {
+            if let Some(integer) = parse_canonical_i64(&member) {
+                match integers.binary_search(&integer) {
+                    Ok(_) => return false,
+                    Err(idx) if integers.len() < SET_MAX_INTSET_ENTRIES => {
+                        integers.insert(idx, integer);
+                        return true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            self.convert_to_hashtable();
+        }
+
+        match self {
+            RedisSet::HashTable { members, memory } => {
+                let size = member_size(&member);
+                let added = members.insert(member, ()).is_none();
+                if added {
+                    *memory += size;
+                }
+                added
+            }
+            RedisSet::IntSet(_) => unreachable!("intset was converted above"),
+        }
+    }
+
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self {
+            RedisSet::IntSet(integers) => match parse_canonical_i64(member)
+                .and_then(|integer| integers.binary_search(&integer).ok())
+            {
+                Some(idx) => {
+                    integers.remove(idx);
+                    true
+                }
+                None => false,
+            },
+            RedisSet::HashTable { members, memory } => match members.remove_entry(member) {
+                Some((member, _)) => {
+                    *memory -= member_size(&member);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    // Heap memory taken by the members.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            RedisSet::IntSet(integers) => integers.capacity() * size_of::<i64>(),
+            RedisSet::HashTable { memory, .. } => *memory,
+        }
+    }
+
+    pub fn members(&self) -> Vec<String> {
+        match self {
+            RedisSet::IntSet(integers) => integers.iter().map(i64::to_string).collect(),
+            RedisSet::HashTable { members, .. } => members.keys().cloned().collect(),
+        }
+    }
+
+    pub fn random_member(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        match self {
+            RedisSet::IntSet(integers) => Some(integers[random_index(integers.len())].to_string()),
+            RedisSet::HashTable { members, .. } => {
+                members.random().map(|(member, _)| member.clone())
+            }
+        }
+    }
+
+    pub fn pop_random(&mut self) -> Option<String> {
+        let member = self.random_member()?;
+        self.remove(&member);
+        Some(member)
+    }
+
+    fn convert_to_hashtable(&mut self) {
+        if let RedisSet::IntSet(integers) = self {
+            let mut members = IndexedMap::new();
+            let mut memory = 0;
+            for integer in integers.iter() {
+                let member = integer.to_string();
+                memory += member_size(&member);
+                members.insert(member, ());
+            }
+            *self = RedisSet::HashTable { members, memory };
+        }
+    }
+}
+
+fn member_size(member: &str) -> usize {
+    size_of::<String>() + member.len()
+}
+
+impl FromIterator<String> for RedisSet {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        let mut set = RedisSet::new();
+        for member in iter {
+            set.insert(member);
+        }
+        set
+    }
+}
+
+fn get_set<'a>(keyspace: &'a Keyspace, key: &str) -> Result<Option<&'a RedisSet>, RespValue> {
+    match keyspace.get(key) {
+        None => Ok(None),
+        Some(RedisValue::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+fn get_set_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut RedisSet>, RespValue> {
+    match keyspace.get_mut(key) {
+        None => Ok(None),
+        Some(RedisValue::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+fn get_or_create_set<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<&'a mut RedisSet, RespValue> {
+    if !keyspace.contains_key(key) {
+        keyspace.insert(key.to_string(), RedisValue::Set(RedisSet::new()), None);
+    }
+    match keyspace.get_mut(key) {
+        Some(RedisValue::Set(set)) => Ok(set),
+        _ => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+// Empty sets are never kept around, the key disappears with its last member.
+fn remove_if_empty(keyspace: &mut Keyspace, key: &str) {
+    if let Some(RedisValue::Set(set)) = keyspace.get(key) {
+        if set.is_empty() {
+            keyspace.remove(key);
+            keyspace.notify(EventClass::Generic, "del", key);
+        }
+    }
+}
+
+fn collect_sets<'a>(
+    keyspace: &'a Keyspace,
+    keys: &[Argument],
+) -> Result<Vec<Option<&'a RedisSet>>, RespValue> {
+    keys.iter().map(|key| get_set(keyspace, key)).collect()
+}
+
+fn intersection(sets: &[Option<&RedisSet>]) -> RedisSet {
+    let mut sets: Vec<&RedisSet> = match sets.iter().copied().collect::<Option<Vec<_>>>() {
+        Some(sets) => sets,
+        // A missing key is an empty set, so the intersection is empty as well.
+        None => return RedisSet::new(),
+    };
+    // Walking the smallest set keeps the number of lookups down.
+    sets.sort_by_key(|set| set.len());
+    match sets.split_first() {
+        Some((smallest, others)) => smallest
+            .members()
+            .into_iter()
+            .filter(|member| others.iter().all(|set| set.contains(member)))
+            .collect(),
+        None => RedisSet::new(),
+    }
+}
+
+fn union(sets: &[Option<&RedisSet>]) -> RedisSet {
+    sets.iter()
+        .flatten()
+        .flat_map(|set| set.members())
+        .collect()
+}
+
+fn difference(sets: &[Option<&RedisSet>]) -> RedisSet {
+    match sets.split_first() {
+        Some((Some(first), others)) => first
+            .members()
+            .into_iter()
+            .filter(|member| others.iter().flatten().all(|set| !set.contains(member)))
+            .collect(),
+        _ => RedisSet::new(),
+    }
+}
+
+fn encode_members(members: &[String]) -> RespValue {
+    RespValue::bulks(members)
+}
+
+fn run_algebra(
+    keyspace: &Keyspace,
+    keys: &[Argument],
+    operation: fn(&[Option<&RedisSet>]) -> RedisSet,
+) -> Result<RedisSet, RespValue> {
+    let sets = collect_sets(keyspace, keys)?;
+    Ok(operation(&sets))
+}
+
+fn handle_algebra(
+    keyspace: &Keyspace,
+    decoded_str: &[Argument],
+    command: &str,
+    operation: fn(&[Option<&RedisSet>]) -> RedisSet,
+) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments(command);
+    }
+    match run_algebra(keyspace, &decoded_str[1..], operation) {
+        Ok(result) => encode_members(&result.members()),
+        Err(error) => error,
+    }
+}
+
+// The *STORE variants overwrite the destination whatever type it held before,
+// and delete it when the result is empty.
+fn handle_algebra_store(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+    command: &'static str,
+    operation: fn(&[Option<&RedisSet>]) -> RedisSet,
+) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments(command);
+    }
+    let destination = &decoded_str[1];
+    let result = match run_algebra(keyspace, &decoded_str[2..], operation) {
+        Ok(result) => result,
+        Err(error) => return error,
+    };
+    let cardinality = result.len();
+    let existed = keyspace.remove(destination.as_str()).is_some();
+    if cardinality > 0 {
+        keyspace.insert(destination.to_string(), RedisValue::Set(result), None);
+        keyspace.notify(EventClass::Set, command, destination);
+    } else if existed {
+        keyspace.notify(EventClass::Generic, "del", destination);
+    }
+    RespValue::Integer(cardinality as i64)
+}
+
+pub fn handle_sadd(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("sadd");
+    }
+    let set = match get_or_create_set(keyspace, &decoded_str[1]) {
+        Ok(set) => set,
+        Err(error) => return error,
+    };
+    let added = decoded_str[2..]
+        .iter()
+        .filter(|member| set.insert(member.to_string()))
+        .count();
+    if added > 0 {
+        keyspace.notify(EventClass::Set, "sadd", &decoded_str[1]);
+    }
+    RespValue::Integer(added as i64)
+}
+
+pub fn handle_srem(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("srem");
+    }
+    let key = &decoded_str[1];
+    let removed = match get_set_mut(keyspace, key) {
+        Ok(Some(set)) => decoded_str[2..]
+            .iter()
+            .filter(|member| set.remove(member))
+            .count(),
+        Ok(None) => 0,
+        Err(error) => return error,
+    };
+    if removed > 0 {
+        keyspace.notify(EventClass::Set, "srem", key);
+    }
+    remove_if_empty(keyspace, key);
+    RespValue::Integer(removed as i64)
+}
+
+pub fn handle_smembers(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("smembers");
+    }
+    match get_set(keyspace, &decoded_str[1]) {
+        Ok(Some(set)) => encode_members(&set.members()),
+        Ok(None) => RespValue::Array(Vec::new()),
+        Err(error) => error,
+    }
+}
+
+pub fn handle_scard(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("scard");
+    }
+    match get_set(keyspace, &decoded_str[1]) {
+        Ok(set) => RespValue::Integer(set.map_or(0, RedisSet::len) as i64),
+        Err(error) => error,
+    }
+}
+
+pub fn handle_sismember(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("sismember");
+    }
+    match get_set(keyspace, &decoded_str[1]) {
+        Ok(set) => RespValue::Integer(set.is_some_and(|set| set.contains(&decoded_str[2])) as i64),
+        Err(error) => error,
+    }
+}
+
+pub fn handle_smismember(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("smismember");
+    }
+    match get_set(keyspace, &decoded_str[1]) {
+        Ok(set) => {
+            let replies: Vec<RespValue> = decoded_str[2..]
+                .iter()
+                .map(
+                    |member| RespValue::Integer(set.is_some_and(|set| set.contains(member)) as i64),
+                )
+                .collect();
+            RespValue::Array(replies)
+        }
+        Err(error) => error,
+    }
+}
+
+pub fn handle_sinter(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_algebra(keyspace, decoded_str, "sinter", intersection)
+}
+
+pub fn handle_sunion(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_algebra(keyspace, decoded_str, "sunion", union)
+}
+
+pub fn handle_sdiff(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_algebra(keyspace, decoded_str, "sdiff", difference)
+}
+
+pub fn handle_sinterstore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_algebra_store(keyspace, decoded_str, "sinterstore", intersection)
+}
+
+pub fn handle_sunionstore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_algebra_store(keyspace, decoded_str, "sunionstore", union)
+}
+
+pub fn handle_sdiffstore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_algebra_store(keyspace, decoded_str, "sdiffstore", difference)
+}
+
+// SINTERCARD numkeys key [key ...] [LIMIT limit]
+pub fn handle_sintercard(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("sintercard");
+    }
+    let numkeys = match parse_integer_argument(&decoded_str[1]) {
+        Ok(numkeys) if numkeys > 0 => numkeys as usize,
+        Ok(_) => return RespValue::error("ERR numkeys should be greater than 0"),
+        Err(error) => return error,
+    };
+    if numkeys > decoded_str.len() - 2 {
+        return RespValue::error("ERR Number of keys can't be greater than number of args");
+    }
+    let keys = &decoded_str[2..2 + numkeys];
+
+    let mut limit: usize = 0;
+    let mut options = decoded_str[2 + numkeys..].iter();
+    while let Some(option) = options.next() {
+        match (option.to_lowercase().as_str(), options.next()) {
+            ("limit", Some(value)) => match parse_integer_argument(value) {
+                Ok(value) if value >= 0 => limit = value as usize,
+                Ok(_) => return RespValue::error("ERR LIMIT can't be negative"),
+                Err(error) => return error,
+            },
+            _ => return RespValue::error("ERR syntax error"),
+        }
+    }
+
+    let sets = match collect_sets(keyspace, keys) {
+        Ok(sets) => sets,
+        Err(error) => return error,
+    };
+    let mut sets: Vec<&RedisSet> = match sets.into_iter().collect::<Option<Vec<_>>>() {
+        Some(sets) => sets,
+        None => return RespValue::Integer(0),
+    };
+    sets.sort_by_key(|set| set.len());
+
+    let mut cardinality: usize = 0;
+    for member in sets[0].members() {
+        if sets[1..].iter().all(|set| set.contains(&member)) {
+            cardinality += 1;
+            if limit != 0 && cardinality == limit {
+                break;
+            }
+        }
+    }
+    RespValue::Integer(cardinality as i64)
+}
+
+// SPOP key [count]
+pub fn handle_spop(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 && decoded_str.len() != 3 {
+        return wrong_number_of_arguments("spop");
+    }
+    let key = &decoded_str[1];
+    let count = match decoded_str
+        .get(2)
+        .map(|count| parse_integer_argument(count))
+    {
+        Some(Ok(count)) if count < 0 => {
+            return RespValue::error("ERR value is out of range, must be positive")
+        }
+        Some(Ok(count)) => Some(count as usize),
+        Some(Err(error)) => return error,
+        None => None,
+    };
+
+    let set = match get_set_mut(keyspace, key) {
+        Ok(set) => set,
+        Err(error) => return error,
+    };
+    let (response, popped) = match (set, count) {
+        (Some(set), None) => match set.pop_random() {
+            Some(member) => (RespValue::bulk(&member), vec![member]),
+            None => (RespValue::Null, Vec::new()),
+        },
+        (Some(set), Some(count)) => {
+            let popped: Vec<String> = (0..count).map_while(|_| set.pop_random()).collect();
+            (encode_members(&popped), popped)
+        }
+        (None, None) => (RespValue::Null, Vec::new()),
+        (None, Some(_)) => (RespValue::Array(Vec::new()), Vec::new()),
+    };
+    if !popped.is_empty() {
+        keyspace.notify(EventClass::Set, "spop", key);
+    }
+    remove_if_empty(keyspace, key);
+    // Members are picked at random, replicas are told which ones went away.
+    if !popped.is_empty() {
+        let mut effect = vec!["SREM".to_string(), key.to_string()];
+        effect.extend(popped);
+        keyspace.propagate(effect);
+    }
+    response
+}
+
+// SRANDMEMBER key [count]
+// A positive count returns distinct members, a negative one allows repeats.
+pub fn handle_srandmember(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 && decoded_str.len() != 3 {
+        return wrong_number_of_arguments("srandmember");
+    }
+    let count = match decoded_str.get(2).map(|count| parse_random_count(count)) {
+        Some(Ok(count)) => Some(count),
+        Some(Err(error)) => return error,
+        None => None,
+    };
+    let set = match get_set(keyspace, &decoded_str[1]) {
+        Ok(set) => set,
+        Err(error) => return error,
+    };
+
+    match (set, count) {
+        (Some(set), None) => match set.random_member() {
+            Some(member) => RespValue::bulk(&member),
+            None => RespValue::Null,
+        },
+        (Some(set), Some(count)) if count < 0 => {
+            let picked: Vec<String> = (0..count.unsigned_abs())
+                .filter_map(|_| set.random_member())
+                .collect();
+            encode_members(&picked)
+        }
+        (Some(set), Some(count)) => {
+            let mut members = set.members();
+            let count = (count as usize).min(members.len());
+            // Partial Fisher-Yates shuffle, only the first `count` slots matter.
+            for idx in 0..count {
+                let swap_with = idx + random_index(members.len() - idx);
+                members.swap(idx, swap_with);
+            }
+            members.truncate(count);
+            encode_members(&members)
+        }
+        (None, None) => RespValue::Null,
+        (None, Some(_)) => RespValue::Array(Vec::new()),
+    }
+}
+
+// SMOVE source destination member
+pub fn handle_smove(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("smove");
+    }
+    let (source, destination, member) = (&decoded_str[1], &decoded_str[2], &decoded_str[3]);
+
+    // Both keys are type checked before anything is modified.
+    if let Err(error) = get_set(keyspace, destination) {
+        return error;
+    }
+    let moved = match get_set_mut(keyspace, source) {
+        Ok(Some(set)) => {
+            if source == destination {
+                return RespValue::Integer(set.contains(member) as i64);
+            }
+            set.remove(member)
+        }
+        Ok(None) => false,
+        Err(error) => return error,
+    };
+    if !moved {
+        return RespValue::Integer(0);
+    }
+    keyspace.notify(EventClass::Set, "srem", source);
+    remove_if_empty(keyspace, source);
+
+    match get_or_create_set(keyspace, destination) {
+        Ok(set) => {
+            if set.insert(member.to_string()) {
+                keyspace.notify(EventClass::Set, "sadd", destination);
+            }
+            RespValue::Integer(1)
+        }
+        Err(error) => error,
+    }
+}
+
+// SSCAN key cursor [MATCH pattern] [COUNT count]
+// The cursor is an offset into the members sorted in a stable order. Small
+// intset encoded sets are returned in a single call, as Redis does.
+pub fn handle_sscan(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("sscan");
+    }
+    let cursor: usize = match decoded_str[2].parse() {
+        Ok(cursor) => cursor,
+        Err(_) => return RespValue::error("ERR invalid cursor"),
+    };
+
+    let (pattern, count) = match parse_scan_options(&decoded_str[3..]) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+
+    let set = match get_set(keyspace, &decoded_str[1]) {
+        Ok(set) => set,
+        Err(error) => return error,
+    };
+    let (next_cursor, page) = match set {
+        Some(RedisSet::IntSet(_)) => (0, set.map(RedisSet::members).unwrap_or_default()),
+        Some(set) => {
+            let mut members = set.members();
+            members.sort();
+            let end = cursor.saturating_add(count).min(members.len());
+            let page = members.get(cursor..end).map(<[String]>::to_vec);
+            let next_cursor = if end >= members.len() { 0 } else { end };
+            (next_cursor, page.unwrap_or_default())
+        }
+        None => (0, Vec::new()),
+    };
+
+    let page: Vec<String> = page
+        .into_iter()
+        .filter(|member| {
+            pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), member.as_bytes()))
+        })
+        .collect();
+    RespValue::Array(vec![
+        RespValue::bulk(next_cursor.to_string()),
+        encode_members(&page),
+    ])
+}
@@ -0,0 +1,619 @@
+use std::cmp::Ordering;
+
+use super::random::random_u64;
+
+// Same parameters as Redis' zskiplist: enough levels for 2^64 elements with
+// P = 1/4.
+const SKIPLIST_MAXLEVEL: usize = 32;
+const SKIPLIST_P: u64 = u64::MAX / 4;
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    // Number of nodes skipped by following `forward`, used to compute ranks.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: String,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+// Inclusive or exclusive score interval, as parsed from `(1.5` or `-inf`.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub max: f64,
+    pub min_exclusive: bool,
+    pub max_exclusive: bool,
+}
+
+impl ScoreRange {
+    pub fn gte_min(&self, score: f64) -> bool {
+        if self.min_exclusive {
+            score > self.min
+        } else {
+            score >= self.min
+        }
+    }
+
+    pub fn lte_max(&self, score: f64) -> bool {
+        if self.max_exclusive {
+            score < self.max
+        } else {
+            score <= self.max
+        }
+    }
+
+    pub fn contains(&self, score: f64) -> bool {
+        self.gte_min(score) && self.lte_max(score)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max || (self.min == self.max && (self.min_exclusive || self.max_exclusive))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LexBound {
+    NegativeInfinity,
+    PositiveInfinity,
+    Inclusive(String),
+    Exclusive(String),
+}
+
+// Lexicographical interval used by the BYLEX family, `[a`, `(a`, `-` and `+`.
+#[derive(Debug, Clone)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    pub fn gte_min(&self, member: &str) -> bool {
+        match &self.min {
+            LexBound::NegativeInfinity => true,
+            LexBound::PositiveInfinity => false,
+            LexBound::Inclusive(min) => member >= min.as_str(),
+            LexBound::Exclusive(min) => member > min.as_str(),
+        }
+    }
+
+    pub fn lte_max(&self, member: &str) -> bool {
+        match &self.max {
+            LexBound::NegativeInfinity => false,
+            LexBound::PositiveInfinity => true,
+            LexBound::Inclusive(max) => member <= max.as_str(),
+            LexBound::Exclusive(max) => member < max.as_str(),
+        }
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        self.gte_min(member) && self.lte_max(member)
+    }
+
+    fn is_empty(&self) -> bool {
+        match (&self.min, &self.max) {
+            (LexBound::PositiveInfinity, _) | (_, LexBound::NegativeInfinity) => true,
+            (LexBound::NegativeInfinity, _) | (_, LexBound::PositiveInfinity) => false,
+            (LexBound::Inclusive(min), LexBound::Inclusive(max)) => min > max,
+            (LexBound::Inclusive(min), LexBound::Exclusive(max))
+            | (LexBound::Exclusive(min), LexBound::Inclusive(max))
+            | (LexBound::Exclusive(min), LexBound::Exclusive(max)) => min >= max,
+        }
+    }
+}
+
+// Port of Redis' zskiplist. Nodes live in an arena and link to each other by
+// index; each level keeps the span it skips so rank lookups are O(log n).
+// Elements are ordered by score, then by member bytes.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    length: usize,
+    level: usize,
+    // What `memory_usage` reports, kept up to date as nodes come and go.
+    memory: usize,
+}
+
+fn precedes(score: f64, member: &str, other_score: f64, other_member: &str) -> bool {
+    match score.partial_cmp(&other_score) {
+        Some(Ordering::Less) => true,
+        Some(Ordering::Equal) => member < other_member,
+        _ => false,
+    }
+}
+
+// Heap memory taken by a node.
+fn node_size(node: &Node) -> usize {
+    size_of::<Node>() + node.member.capacity() + node.levels.capacity() * size_of::<Level>()
+}
+
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < SKIPLIST_MAXLEVEL && random_u64() < SKIPLIST_P {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        let head = Node {
+            member: String::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                SKIPLIST_MAXLEVEL
+            ],
+        };
+        Self {
+            memory: node_size(&head),
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: None,
+            length: 0,
+            level: 1,
+        }
+    }
+
+    // Heap memory taken by the nodes, freed ones included.
+    pub fn memory_usage(&self) -> usize {
+        self.memory
+    }
+
+    pub fn member(&self, node: usize) -> &str {
+        &self.nodes[node].member
+    }
+
+    pub fn score(&self, node: usize) -> f64 {
+        self.nodes[node].score
+    }
+
+    pub fn first(&self) -> Option<usize> {
+        self.nodes[HEAD].levels[0].forward
+    }
+
+    pub fn last(&self) -> Option<usize> {
+        self.tail
+    }
+
+    pub fn next(&self, node: usize) -> Option<usize> {
+        self.nodes[node].levels[0].forward
+    }
+
+    pub fn prev(&self, node: usize) -> Option<usize> {
+        self.nodes[node].backward
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        self.memory += node_size(&node);
+        match self.free.pop() {
+            Some(idx) => {
+                self.memory -= node_size(&self.nodes[idx]);
+                self.nodes[idx] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    // The caller guarantees the member is not already present.
+    pub fn insert(&mut self, score: f64, member: String) {
+        let mut update = [HEAD; SKIPLIST_MAXLEVEL];
+        let mut rank = [0usize; SKIPLIST_MAXLEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                let next_node = &self.nodes[next];
+                if !precedes(next_node.score, &next_node.member, score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.length;
+            }
+            self.level = level;
+        }
+
+        let x = self.allocate(Node {
+            member,
+            score,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                level
+            ],
+        });
+        for i in 0..level {
+            let previous = update[i];
+            self.nodes[x].levels[i].forward = self.nodes[previous].levels[i].forward;
+            self.nodes[previous].levels[i].forward = Some(x);
+            self.nodes[x].levels[i].span =
+                self.nodes[previous].levels[i].span - (rank[0] - rank[i]);
+            self.nodes[previous].levels[i].span = (rank[0] - rank[i]) + 1;
+        }
+        for (i, &previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[previous].levels[i].span += 1;
+        }
+
+        self.nodes[x].backward = if update[0] == HEAD {
+            None
+        } else {
+            Some(update[0])
+        };
+        match self.nodes[x].levels[0].forward {
+            Some(next) => self.nodes[next].backward = Some(x),
+            None => self.tail = Some(x),
+        }
+        self.length += 1;
+    }
+
+    pub fn delete(&mut self, score: f64, member: &str) -> bool {
+        let mut update = [HEAD; SKIPLIST_MAXLEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let next_node = &self.nodes[next];
+                if !precedes(next_node.score, &next_node.member, score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        match self.forward(x, 0) {
+            Some(candidate)
+                if self.nodes[candidate].score == score
+                    && self.nodes[candidate].member == member =>
+            {
+                self.delete_node(candidate, &update);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn delete_node(&mut self, x: usize, update: &[usize; SKIPLIST_MAXLEVEL]) {
+        for (i, &previous) in update.iter().enumerate().take(self.level) {
+            if self.nodes[previous].levels[i].forward == Some(x) {
+                self.nodes[previous].levels[i].span += self.nodes[x].levels[i].span;
+                self.nodes[previous].levels[i].span -= 1;
+                self.nodes[previous].levels[i].forward = self.nodes[x].levels[i].forward;
+            } else {
+                self.nodes[previous].levels[i].span -= 1;
+            }
+        }
+        match self.nodes[x].levels[0].forward {
+            Some(next) => self.nodes[next].backward = self.nodes[x].backward,
+            None => self.tail = self.nodes[x].backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.length -= 1;
+
+        self.memory -= node_size(&self.nodes[x]);
+        self.nodes[x].member = String::new();
+        self.nodes[x].levels = Vec::new();
+        self.memory += node_size(&self.nodes[x]);
+        self.free.push(x);
+    }
+
+    // 1-based rank of the element, 0 when it is not present.
+    pub fn rank(&self, score: f64, member: &str) -> usize {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let next_node = &self.nodes[next];
+                if !(precedes(next_node.score, &next_node.member, score, member)
+                    || (next_node.score == score && next_node.member == member))
+                {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].member == member {
+                return rank;
+            }
+        }
+        0
+    }
+
+    // Node at the given 1-based rank.
+    pub fn by_rank(&self, rank: usize) -> Option<usize> {
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.nodes[x].levels[i].span > rank {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return (x != HEAD).then_some(x);
+            }
+        }
+        None
+    }
+
+    pub fn first_in_score_range(&self, range: &ScoreRange) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if range.gte_min(self.nodes[next].score) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        self.forward(x, 0)
+            .filter(|&node| range.lte_max(self.nodes[node].score))
+    }
+
+    pub fn last_in_score_range(&self, range: &ScoreRange) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !range.lte_max(self.nodes[next].score) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        (x != HEAD && range.gte_min(self.nodes[x].score)).then_some(x)
+    }
+
+    pub fn first_in_lex_range(&self, range: &LexRange) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if range.gte_min(&self.nodes[next].member) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        self.forward(x, 0)
+            .filter(|&node| range.lte_max(&self.nodes[node].member))
+    }
+
+    pub fn last_in_lex_range(&self, range: &LexRange) -> Option<usize> {
+        if range.is_empty() {
+            return None;
+        }
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !range.lte_max(&self.nodes[next].member) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        (x != HEAD && range.gte_min(&self.nodes[x].member)).then_some(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Walks the list checking it against `expected`, sorted, through every
+    // way of reaching a node: level 0 links, backward links, ranks, and the
+    // spans of every level.
+    fn check(list: &SkipList, expected: &[(f64, String)]) {
+        assert_eq!(list.length, expected.len());
+        let mut node = list.first();
+        let mut previous = None;
+        for (position, (score, member)) in expected.iter().enumerate() {
+            let x = node.expect("list ended early");
+            assert_eq!((list.score(x), list.member(x)), (*score, member.as_str()));
+            assert_eq!(list.prev(x), previous);
+            assert_eq!(list.rank(*score, member), position + 1);
+            assert_eq!(list.by_rank(position + 1), Some(x));
+            previous = node;
+            node = list.next(x);
+        }
+        assert_eq!(node, None);
+        assert_eq!(list.last(), previous);
+        assert_eq!(
+            list.memory_usage(),
+            list.nodes.iter().map(node_size).sum::<usize>()
+        );
+
+        for i in 0..list.level {
+            let mut x = HEAD;
+            let mut rank = 0;
+            while let Some(next) = list.forward(x, i) {
+                rank += list.nodes[x].levels[i].span;
+                let (score, member) = (list.score(next), list.member(next));
+                assert_eq!(list.rank(score, member), rank, "span at level {}", i);
+                x = next;
+            }
+        }
+    }
+
+    fn sorted(mut elements: Vec<(f64, String)>) -> Vec<(f64, String)> {
+        elements.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+        elements
+    }
+
+    // Few distinct scores, so that members break most ties.
+    fn elements(count: usize) -> Vec<(f64, String)> {
+        (0..count)
+            .map(|i| ((i % 37) as f64, format!("member:{}", i * 7919 % count)))
+            .collect()
+    }
+
+    #[test]
+    fn ranks_and_spans_follow_inserts_and_deletes() {
+        let mut list = SkipList::new();
+        let elements = elements(2000);
+        for (score, member) in &elements {
+            list.insert(*score, member.clone());
+        }
+        check(&list, &sorted(elements.clone()));
+
+        let (deleted, kept): (Vec<_>, Vec<_>) = elements
+            .into_iter()
+            .enumerate()
+            .partition(|(i, _)| i % 3 != 0);
+        for (_, (score, member)) in &deleted {
+            assert!(list.delete(*score, member));
+        }
+        let kept: Vec<(f64, String)> = kept.into_iter().map(|(_, element)| element).collect();
+        check(&list, &sorted(kept.clone()));
+
+        // Freed nodes get reused.
+        let nodes = list.nodes.len();
+        for (_, (score, member)) in &deleted {
+            list.insert(*score, member.clone());
+        }
+        assert_eq!(list.nodes.len(), nodes);
+        let all: Vec<(f64, String)> = kept
+            .into_iter()
+            .chain(deleted.into_iter().map(|(_, element)| element))
+            .collect();
+        check(&list, &sorted(all));
+    }
+
+    #[test]
+    fn missing_elements_have_no_rank() {
+        let mut list = SkipList::new();
+        assert_eq!(list.by_rank(1), None);
+        list.insert(1.0, "a".to_string());
+        list.insert(2.0, "b".to_string());
+        assert_eq!(list.rank(1.0, "b"), 0);
+        assert_eq!(list.rank(3.0, "c"), 0);
+        assert!(!list.delete(2.0, "a"));
+        assert_eq!(list.by_rank(0), None);
+        assert_eq!(list.by_rank(3), None);
+        assert!(list.delete(1.0, "a") && list.delete(2.0, "b"));
+        check(&list, &[]);
+        assert_eq!(list.level, 1);
+    }
+
+    #[test]
+    fn score_ranges() {
+        let mut list = SkipList::new();
+        for i in 1..=10 {
+            list.insert(i as f64, format!("m{}", i));
+        }
+        let range = |min, max, min_exclusive, max_exclusive| ScoreRange {
+            min,
+            max,
+            min_exclusive,
+            max_exclusive,
+        };
+        let bounds = |range: &ScoreRange| {
+            let first = list
+                .first_in_score_range(range)
+                .map(|node| list.score(node));
+            let last = list.last_in_score_range(range).map(|node| list.score(node));
+            (first, last)
+        };
+        assert_eq!(
+            bounds(&range(3.0, 7.0, false, false)),
+            (Some(3.0), Some(7.0))
+        );
+        assert_eq!(bounds(&range(3.0, 7.0, true, true)), (Some(4.0), Some(6.0)));
+        assert_eq!(
+            bounds(&range(f64::NEG_INFINITY, f64::INFINITY, false, false)),
+            (Some(1.0), Some(10.0))
+        );
+        assert_eq!(bounds(&range(10.5, 20.0, false, false)), (None, None));
+        assert_eq!(bounds(&range(5.0, 5.0, true, false)), (None, None));
+        assert_eq!(bounds(&range(7.0, 3.0, false, false)), (None, None));
+    }
+
+    #[test]
+    fn lex_ranges() {
+        let mut list = SkipList::new();
+        for member in ["a", "b", "c", "d", "e"] {
+            list.insert(0.0, member.to_string());
+        }
+        let bounds = |min, max| {
+            let range = LexRange { min, max };
+            let first = list
+                .first_in_lex_range(&range)
+                .map(|node| list.member(node));
+            let last = list.last_in_lex_range(&range).map(|node| list.member(node));
+            (first, last)
+        };
+        assert_eq!(
+            bounds(LexBound::NegativeInfinity, LexBound::PositiveInfinity),
+            (Some("a"), Some("e"))
+        );
+        assert_eq!(
+            bounds(
+                LexBound::Exclusive("a".to_string()),
+                LexBound::Inclusive("c".to_string())
+            ),
+            (Some("b"), Some("c"))
+        );
+        assert_eq!(
+            bounds(
+                LexBound::Inclusive("bb".to_string()),
+                LexBound::Exclusive("e".to_string())
+            ),
+            (Some("c"), Some("d"))
+        );
+        assert_eq!(
+            bounds(
+                LexBound::Exclusive("c".to_string()),
+                LexBound::Exclusive("c".to_string())
+            ),
+            (None, None)
+        );
+        assert_eq!(
+            bounds(LexBound::PositiveInfinity, LexBound::PositiveInfinity),
+            (None, None)
+        );
+    }
+}
@@ -0,0 +1,26 @@
+// Cluster hash slots: CRC16 (XMODEM) of the key modulo 16384, hashing only
+// the `{tag}` part when the key has a non-empty one, like Redis Cluster.
+const SLOT_COUNT: u16 = 16384;
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|byte| *byte == b'{').and_then(|open| {
+        let close = key[open + 1..].iter().position(|byte| *byte == b'}')?;
+        (close > 0).then(|| &key[open + 1..open + 1 + close])
+    });
+    crc16(tag.unwrap_or(key)) % SLOT_COUNT
+}
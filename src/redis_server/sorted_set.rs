@@ -0,0 +1,1178 @@
+use std::collections::HashMap;
+
+use tokio::time::{timeout_at, Duration, Instant};
+
+use super::{
+    format_double,
+    glob::glob_match,
+    keyspace::Keyspace,
+    notify::EventClass,
+    parse_integer_argument, parse_random_count, parse_scan_options,
+    random::random_index,
+    redis_value::{RedisValue, WRONGTYPE_ERROR},
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    skiplist::{LexBound, LexRange, ScoreRange, SkipList},
+    wrong_number_of_arguments,
+};
+
+// Member to score map for O(1) lookups, plus a skiplist ordered by
+// (score, member) for ranks and ranges, the same layout as Redis' zset.
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    skiplist: SkipList,
+    // Heap memory taken by the members of `scores`, kept up to date.
+    scores_memory: usize,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self {
+            scores: HashMap::new(),
+            skiplist: SkipList::new(),
+            scores_memory: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    // Heap memory taken by the members, which the dictionary and the
+    // skiplist both keep a copy of.
+    pub fn memory_usage(&self) -> usize {
+        self.scores_memory + self.skiplist.memory_usage()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    // Adds the member or moves it to its new score, returns true when the
+    // member was not present before.
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        match self.scores.get(&member).copied() {
+            Some(current) => {
+                if current != score {
+                    self.skiplist.delete(current, &member);
+                    self.skiplist.insert(score, member.clone());
+                    self.scores.insert(member, score);
+                }
+                false
+            }
+            None => {
+                self.scores_memory += size_of::<(String, f64)>() + member.capacity();
+                self.skiplist.insert(score, member.clone());
+                self.scores.insert(member, score);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.scores_memory -= size_of::<(String, f64)>() + member.capacity();
+                self.skiplist.delete(score, &member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 0-based rank, counted from the highest score when `reverse` is set.
+    pub fn rank(&self, member: &str, reverse: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self.skiplist.rank(score, member);
+        if reverse {
+            Some(self.len() - rank)
+        } else {
+            Some(rank - 1)
+        }
+    }
+
+    fn entry(&self, node: usize) -> (String, f64) {
+        (
+            self.skiplist.member(node).to_string(),
+            self.skiplist.score(node),
+        )
+    }
+
+    fn step(&self, node: usize, reverse: bool) -> Option<usize> {
+        if reverse {
+            self.skiplist.prev(node)
+        } else {
+            self.skiplist.next(node)
+        }
+    }
+
+    // Walks from `start` in the requested direction while `in_range` holds,
+    // skipping `offset` elements and returning at most `limit` of them.
+    fn collect_from(
+        &self,
+        start: Option<usize>,
+        reverse: bool,
+        offset: usize,
+        limit: Option<usize>,
+        in_range: impl Fn(usize) -> bool,
+    ) -> Vec<(String, f64)> {
+        let mut node = start;
+        for _ in 0..offset {
+            node = node.and_then(|node| self.step(node, reverse));
+        }
+        let mut entries = Vec::new();
+        while let Some(current) = node {
+            if limit.is_some_and(|limit| entries.len() >= limit) || !in_range(current) {
+                break;
+            }
+            entries.push(self.entry(current));
+            node = self.step(current, reverse);
+        }
+        entries
+    }
+
+    // Inclusive 0-based ranks that are already clamped to the set.
+    pub fn range_by_rank(&self, start: usize, end: usize, reverse: bool) -> Vec<(String, f64)> {
+        let first = if reverse {
+            self.skiplist.by_rank(self.len() - start)
+        } else {
+            self.skiplist.by_rank(start + 1)
+        };
+        self.collect_from(first, reverse, 0, Some(end - start + 1), |_| true)
+    }
+
+    pub fn range_by_score(
+        &self,
+        range: &ScoreRange,
+        reverse: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(String, f64)> {
+        let first = if reverse {
+            self.skiplist.last_in_score_range(range)
+        } else {
+            self.skiplist.first_in_score_range(range)
+        };
+        self.collect_from(first, reverse, offset, limit, |node| {
+            range.contains(self.skiplist.score(node))
+        })
+    }
+
+    pub fn range_by_lex(
+        &self,
+        range: &LexRange,
+        reverse: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(String, f64)> {
+        let first = if reverse {
+            self.skiplist.last_in_lex_range(range)
+        } else {
+            self.skiplist.first_in_lex_range(range)
+        };
+        self.collect_from(first, reverse, offset, limit, |node| {
+            range.contains(self.skiplist.member(node))
+        })
+    }
+
+    pub fn count_in_score_range(&self, range: &ScoreRange) -> usize {
+        match (
+            self.skiplist.first_in_score_range(range),
+            self.skiplist.last_in_score_range(range),
+        ) {
+            (Some(first), Some(last)) => self.count_between(first, last),
+            _ => 0,
+        }
+    }
+
+    pub fn count_in_lex_range(&self, range: &LexRange) -> usize {
+        match (
+            self.skiplist.first_in_lex_range(range),
+            self.skiplist.last_in_lex_range(range),
+        ) {
+            (Some(first), Some(last)) => self.count_between(first, last),
+            _ => 0,
+        }
+    }
+
+    fn count_between(&self, first: usize, last: usize) -> usize {
+        let first_rank = self
+            .skiplist
+            .rank(self.skiplist.score(first), self.skiplist.member(first));
+        let last_rank = self
+            .skiplist
+            .rank(self.skiplist.score(last), self.skiplist.member(last));
+        last_rank + 1 - first_rank
+    }
+
+    pub fn pop(&mut self, max: bool) -> Option<(String, f64)> {
+        let node = if max {
+            self.skiplist.last()
+        } else {
+            self.skiplist.first()
+        }?;
+        let (member, score) = self.entry(node);
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    pub fn random_entry(&self) -> Option<(String, f64)> {
+        if self.is_empty() {
+            return None;
+        }
+        let node = self.skiplist.by_rank(random_index(self.len()) + 1)?;
+        Some(self.entry(node))
+    }
+
+    // Every element ordered by score, then member.
+    pub fn entries(&self) -> Vec<(String, f64)> {
+        self.collect_from(self.skiplist.first(), false, 0, None, |_| true)
+    }
+}
+
+// Parses a score the way Redis' `strtod` based parser does: `inf`, `+inf` and
+// `-inf` are fine, NaN is not.
+pub fn parse_score(input: &str) -> Option<f64> {
+    input.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+pub fn parse_score_range(min: &str, max: &str) -> Result<ScoreRange, RespValue> {
+    let parse_bound = |bound: &str| -> Option<(f64, bool)> {
+        match bound.strip_prefix('(') {
+            Some(bound) => parse_score(bound).map(|score| (score, true)),
+            None => parse_score(bound).map(|score| (score, false)),
+        }
+    };
+    match (parse_bound(min), parse_bound(max)) {
+        (Some((min, min_exclusive)), Some((max, max_exclusive))) => Ok(ScoreRange {
+            min,
+            max,
+            min_exclusive,
+            max_exclusive,
+        }),
+        _ => Err(RespValue::error("ERR min or max is not a float")),
+    }
+}
+
+pub fn parse_lex_range(min: &str, max: &str) -> Result<LexRange, RespValue> {
+    let parse_bound = |bound: &str| -> Option<LexBound> {
+        match bound.chars().next() {
+            Some('-') if bound.len() == 1 => Some(LexBound::NegativeInfinity),
+            Some('+') if bound.len() == 1 => Some(LexBound::PositiveInfinity),
+            Some('[') => Some(LexBound::Inclusive(bound[1..].to_string())),
+            Some('(') => Some(LexBound::Exclusive(bound[1..].to_string())),
+            _ => None,
+        }
+    };
+    match (parse_bound(min), parse_bound(max)) {
+        (Some(min), Some(max)) => Ok(LexRange { min, max }),
+        _ => Err(RespValue::error(
+            "ERR min or max not valid string range item",
+        )),
+    }
+}
+
+pub fn get_sorted_set<'a>(
+    keyspace: &'a Keyspace,
+    key: &str,
+) -> Result<Option<&'a SortedSet>, RespValue> {
+    match keyspace.get(key) {
+        None => Ok(None),
+        Some(RedisValue::SortedSet(sorted_set)) => Ok(Some(sorted_set)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+fn get_sorted_set_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut SortedSet>, RespValue> {
+    match keyspace.get_mut(key) {
+        None => Ok(None),
+        Some(RedisValue::SortedSet(sorted_set)) => Ok(Some(sorted_set)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+pub fn get_or_create_sorted_set<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<&'a mut SortedSet, RespValue> {
+    if !keyspace.contains_key(key) {
+        keyspace.insert(
+            key.to_string(),
+            RedisValue::SortedSet(SortedSet::new()),
+            None,
+        );
+    }
+    match keyspace.get_mut(key) {
+        Some(RedisValue::SortedSet(sorted_set)) => Ok(sorted_set),
+        _ => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+// Like sets, an empty sorted set removes its key.
+fn remove_if_empty(keyspace: &mut Keyspace, key: &str) {
+    if let Some(RedisValue::SortedSet(sorted_set)) = keyspace.get(key) {
+        if sorted_set.is_empty() {
+            keyspace.remove(key);
+            keyspace.notify(EventClass::Generic, "del", key);
+        }
+    }
+}
+
+pub fn encode_entries(entries: &[(String, f64)], with_scores: bool) -> RespValue {
+    let mut elements = Vec::with_capacity(entries.len() * 2);
+    for (member, score) in entries {
+        elements.push(RespValue::bulk(member));
+        if with_scores {
+            elements.push(RespValue::bulk(format_double(*score)));
+        }
+    }
+    RespValue::Array(elements)
+}
+
+// ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+pub fn handle_zadd(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments("zadd");
+    }
+    let key = &decoded_str[1];
+
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    let mut idx = 2;
+    while idx < decoded_str.len() {
+        match decoded_str[idx].to_lowercase().as_str() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "gt" => gt = true,
+            "lt" => lt = true,
+            "ch" => ch = true,
+            "incr" => incr = true,
+            _ => break,
+        }
+        idx += 1;
+    }
+
+    let elements = &decoded_str[idx..];
+    if elements.is_empty() || !elements.len().is_multiple_of(2) {
+        return RespValue::error("ERR syntax error");
+    }
+    if nx && xx {
+        return RespValue::error("ERR XX and NX options at the same time are not compatible");
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return RespValue::error(
+            "ERR GT, LT, and/or NX options at the same time are not compatible",
+        );
+    }
+    if incr && elements.len() > 2 {
+        return RespValue::error("ERR INCR option supports a single increment-element pair");
+    }
+
+    let mut pairs: Vec<(f64, &str)> = Vec::with_capacity(elements.len() / 2);
+    for pair in elements.chunks(2) {
+        match parse_score(&pair[0]) {
+            Some(score) => pairs.push((score, pair[1].as_str())),
+            None => return RespValue::error("ERR value is not a valid float"),
+        }
+    }
+
+    let sorted_set = match get_sorted_set_mut(keyspace, key) {
+        Ok(Some(sorted_set)) => sorted_set,
+        Ok(None) if xx => {
+            return if incr {
+                RespValue::Null
+            } else {
+                RespValue::Integer(0)
+            };
+        }
+        Ok(None) => match get_or_create_sorted_set(keyspace, key) {
+            Ok(sorted_set) => sorted_set,
+            Err(error) => return error,
+        },
+        Err(error) => return error,
+    };
+
+    let (mut added, mut updated) = (0, 0);
+    let mut incr_result: Option<f64> = None;
+    let mut error: Option<RespValue> = None;
+    for (score, member) in pairs {
+        match sorted_set.score(member) {
+            Some(current) => {
+                if nx {
+                    continue;
+                }
+                let new_score = if incr { current + score } else { score };
+                if new_score.is_nan() {
+                    error = Some(RespValue::error(
+                        "ERR resulting score is not a number (NaN)",
+                    ));
+                    break;
+                }
+                if (lt && new_score >= current) || (gt && new_score <= current) {
+                    continue;
+                }
+                if new_score != current {
+                    sorted_set.insert(member.to_owned(), new_score);
+                    updated += 1;
+                }
+                incr_result = Some(new_score);
+            }
+            None => {
+                if xx {
+                    continue;
+                }
+                sorted_set.insert(member.to_owned(), score);
+                added += 1;
+                incr_result = Some(score);
+            }
+        }
+    }
+    if added + updated > 0 {
+        keyspace.notify(EventClass::Zset, if incr { "zincr" } else { "zadd" }, key);
+    }
+    remove_if_empty(keyspace, key);
+
+    if let Some(error) = error {
+        return error;
+    }
+    if incr {
+        match incr_result {
+            Some(score) => RespValue::bulk(format_double(score)),
+            None => RespValue::Null,
+        }
+    } else if ch {
+        RespValue::Integer(added + updated)
+    } else {
+        RespValue::Integer(added)
+    }
+}
+
+// ZINCRBY key increment member
+pub fn handle_zincrby(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("zincrby");
+    }
+    let arguments = [
+        "zadd".into(),
+        decoded_str[1].to_owned(),
+        "incr".into(),
+        decoded_str[2].to_owned(),
+        decoded_str[3].to_owned(),
+    ];
+    handle_zadd(keyspace, &arguments)
+}
+
+pub fn handle_zcard(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("zcard");
+    }
+    match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => RespValue::Integer(sorted_set.map_or(0, SortedSet::len) as i64),
+        Err(error) => error,
+    }
+}
+
+pub fn handle_zscore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("zscore");
+    }
+    match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => match sorted_set.and_then(|sorted_set| sorted_set.score(&decoded_str[2]))
+        {
+            Some(score) => RespValue::bulk(format_double(score)),
+            None => RespValue::Null,
+        },
+        Err(error) => error,
+    }
+}
+
+pub fn handle_zrem(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("zrem");
+    }
+    let key = &decoded_str[1];
+    let removed = match get_sorted_set_mut(keyspace, key) {
+        Ok(Some(sorted_set)) => decoded_str[2..]
+            .iter()
+            .filter(|member| sorted_set.remove(member))
+            .count(),
+        Ok(None) => 0,
+        Err(error) => return error,
+    };
+    if removed > 0 {
+        keyspace.notify(EventClass::Zset, "zrem", key);
+    }
+    remove_if_empty(keyspace, key);
+    RespValue::Integer(removed as i64)
+}
+
+enum RangeKind {
+    Rank,
+    Score,
+    Lex,
+}
+
+// ZRANGE key start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+pub fn handle_zrange(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments("zrange");
+    }
+    let mut kind = RangeKind::Rank;
+    let mut reverse = false;
+    let mut with_scores = false;
+    let mut limit: Option<(i64, i64)> = None;
+
+    let mut idx = 4;
+    while idx < decoded_str.len() {
+        match decoded_str[idx].to_lowercase().as_str() {
+            "byscore" => kind = RangeKind::Score,
+            "bylex" => kind = RangeKind::Lex,
+            "rev" => reverse = true,
+            "withscores" => with_scores = true,
+            "limit" if idx + 2 < decoded_str.len() => {
+                let offset = match parse_integer_argument(&decoded_str[idx + 1]) {
+                    Ok(offset) => offset,
+                    Err(error) => return error,
+                };
+                let count = match parse_integer_argument(&decoded_str[idx + 2]) {
+                    Ok(count) => count,
+                    Err(error) => return error,
+                };
+                limit = Some((offset, count));
+                idx += 2;
+            }
+            _ => return RespValue::error("ERR syntax error"),
+        }
+        idx += 1;
+    }
+
+    if limit.is_some() && matches!(kind, RangeKind::Rank) {
+        return RespValue::error(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        );
+    }
+    if with_scores && matches!(kind, RangeKind::Lex) {
+        return RespValue::error(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX",
+        );
+    }
+
+    // With REV the range is given from the highest to the lowest element.
+    let (min, max) = if reverse && !matches!(kind, RangeKind::Rank) {
+        (&decoded_str[3], &decoded_str[2])
+    } else {
+        (&decoded_str[2], &decoded_str[3])
+    };
+    let (offset, count) = match limit {
+        Some((offset, _)) if offset < 0 => return RespValue::Array(Vec::new()),
+        Some((offset, count)) => (offset as usize, (count >= 0).then_some(count as usize)),
+        None => (0, None),
+    };
+
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(Some(sorted_set)) => sorted_set,
+        Ok(None) => {
+            // The range is still validated for missing keys.
+            let validation = match kind {
+                RangeKind::Rank => parse_integer_argument(min)
+                    .and(parse_integer_argument(max))
+                    .map(|_| ()),
+                RangeKind::Score => parse_score_range(min, max).map(|_| ()),
+                RangeKind::Lex => parse_lex_range(min, max).map(|_| ()),
+            };
+            return match validation {
+                Ok(()) => RespValue::Array(Vec::new()),
+                Err(error) => error,
+            };
+        }
+        Err(error) => return error,
+    };
+
+    let entries = match kind {
+        RangeKind::Rank => {
+            let (start, end) = match (parse_integer_argument(min), parse_integer_argument(max)) {
+                (Ok(start), Ok(end)) => (start, end),
+                (Err(error), _) | (_, Err(error)) => return error,
+            };
+            match normalize_rank_range(start, end, sorted_set.len()) {
+                Some((start, end)) => sorted_set.range_by_rank(start, end, reverse),
+                None => Vec::new(),
+            }
+        }
+        RangeKind::Score => match parse_score_range(min, max) {
+            Ok(range) => sorted_set.range_by_score(&range, reverse, offset, count),
+            Err(error) => return error,
+        },
+        RangeKind::Lex => match parse_lex_range(min, max) {
+            Ok(range) => sorted_set.range_by_lex(&range, reverse, offset, count),
+            Err(error) => return error,
+        },
+    };
+    encode_entries(&entries, with_scores)
+}
+
+// Turns possibly negative start/stop indexes into an inclusive range inside
+// a collection of `length` elements.
+pub fn normalize_rank_range(start: i64, end: i64, length: usize) -> Option<(usize, usize)> {
+    let length = length as i64;
+    let start = if start < 0 {
+        (start + length).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { end + length } else { end };
+    let end = end.min(length - 1);
+    if start > end || start >= length {
+        None
+    } else {
+        Some((start as usize, end as usize))
+    }
+}
+
+fn handle_rank(
+    keyspace: &Keyspace,
+    decoded_str: &[Argument],
+    command: &str,
+    reverse: bool,
+) -> RespValue {
+    if decoded_str.len() != 3 && decoded_str.len() != 4 {
+        return wrong_number_of_arguments(command);
+    }
+    let with_score = match decoded_str.get(3) {
+        Some(option) if option.eq_ignore_ascii_case("withscore") => true,
+        Some(_) => return RespValue::error("ERR syntax error"),
+        None => false,
+    };
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => sorted_set,
+        Err(error) => return error,
+    };
+    let member = &decoded_str[2];
+    match sorted_set.and_then(|sorted_set| {
+        let rank = sorted_set.rank(member, reverse)?;
+        Some((rank, sorted_set.score(member)?))
+    }) {
+        Some((rank, score)) if with_score => RespValue::Array(vec![
+            RespValue::Integer(rank as i64),
+            RespValue::bulk(format_double(score)),
+        ]),
+        Some((rank, _)) => RespValue::Integer(rank as i64),
+        None if with_score => RespValue::NullArray,
+        None => RespValue::Null,
+    }
+}
+
+// ZRANK key member [WITHSCORE]
+pub fn handle_zrank(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_rank(keyspace, decoded_str, "zrank", false)
+}
+
+// ZREVRANK key member [WITHSCORE]
+pub fn handle_zrevrank(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_rank(keyspace, decoded_str, "zrevrank", true)
+}
+
+pub fn handle_zcount(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("zcount");
+    }
+    let range = match parse_score_range(&decoded_str[2], &decoded_str[3]) {
+        Ok(range) => range,
+        Err(error) => return error,
+    };
+    match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => RespValue::Integer(
+            sorted_set.map_or(0, |sorted_set| sorted_set.count_in_score_range(&range)) as i64,
+        ),
+        Err(error) => error,
+    }
+}
+
+pub fn handle_zlexcount(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("zlexcount");
+    }
+    let range = match parse_lex_range(&decoded_str[2], &decoded_str[3]) {
+        Ok(range) => range,
+        Err(error) => return error,
+    };
+    match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => RespValue::Integer(
+            sorted_set.map_or(0, |sorted_set| sorted_set.count_in_lex_range(&range)) as i64,
+        ),
+        Err(error) => error,
+    }
+}
+
+fn handle_pop(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+    command: &'static str,
+    max: bool,
+) -> RespValue {
+    if decoded_str.len() != 2 && decoded_str.len() != 3 {
+        return wrong_number_of_arguments(command);
+    }
+    let key = &decoded_str[1];
+    let count = match decoded_str
+        .get(2)
+        .map(|count| parse_integer_argument(count))
+    {
+        Some(Ok(count)) if count < 0 => {
+            return RespValue::error("ERR value is out of range, must be positive")
+        }
+        Some(Ok(count)) => count as usize,
+        Some(Err(error)) => return error,
+        None => 1,
+    };
+    let popped: Vec<(String, f64)> = match get_sorted_set_mut(keyspace, key) {
+        Ok(Some(sorted_set)) => (0..count).map_while(|_| sorted_set.pop(max)).collect(),
+        Ok(None) => Vec::new(),
+        Err(error) => return error,
+    };
+    if !popped.is_empty() {
+        keyspace.notify(EventClass::Zset, command, key);
+    }
+    remove_if_empty(keyspace, key);
+    encode_entries(&popped, true)
+}
+
+// ZPOPMIN key [count]
+pub fn handle_zpopmin(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_pop(keyspace, decoded_str, "zpopmin", false)
+}
+
+// ZPOPMAX key [count]
+pub fn handle_zpopmax(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_pop(keyspace, decoded_str, "zpopmax", true)
+}
+
+// Parses the trailing timeout of blocking commands, in seconds with decimals.
+// Zero means block forever.
+pub fn parse_blocking_timeout(input: &str) -> Result<Option<Instant>, RespValue> {
+    match input.parse::<f64>() {
+        Ok(timeout) if timeout < 0.0 => Err(RespValue::error("ERR timeout is negative")),
+        Ok(0.0) => Ok(None),
+        Ok(timeout) if timeout.is_finite() => {
+            Ok(Some(Instant::now() + Duration::from_secs_f64(timeout)))
+        }
+        _ => Err(RespValue::error(
+            "ERR timeout is not a float or out of range",
+        )),
+    }
+}
+
+// Pops from the first non-empty sorted set among `keys`, recording the pop
+// as ZPOPMIN or ZPOPMAX for the replicas.
+fn pop_first(
+    keyspace: &mut Keyspace,
+    keys: &[Argument],
+    max: bool,
+) -> Result<Option<RespValue>, RespValue> {
+    for key in keys {
+        let popped = match get_sorted_set_mut(keyspace, key) {
+            Ok(Some(sorted_set)) => sorted_set.pop(max),
+            Ok(None) => None,
+            Err(error) => return Err(error),
+        };
+        if let Some((member, score)) = popped {
+            keyspace.notify(
+                EventClass::Zset,
+                if max { "zpopmax" } else { "zpopmin" },
+                key,
+            );
+            remove_if_empty(keyspace, key);
+            let pop = if max { "ZPOPMAX" } else { "ZPOPMIN" };
+            keyspace.propagate(vec![pop.to_string(), key.to_string()]);
+            return Ok(Some(RespValue::bulks([
+                key.as_str(),
+                &member,
+                &format_double(score),
+            ])));
+        }
+    }
+    Ok(None)
+}
+
+async fn handle_blocking_pop(
+    state: &ServerState,
+    db: usize,
+    decoded_str: &[Argument],
+    command: &str,
+    max: bool,
+) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments(command);
+    }
+    let deadline = match parse_blocking_timeout(&decoded_str[decoded_str.len() - 1]) {
+        Ok(deadline) => deadline,
+        Err(error) => return error,
+    };
+    let keys = &decoded_str[1..decoded_str.len() - 1];
+
+    loop {
+        // Registered before looking at the keys so that a write landing between
+        // the check and the wait still wakes us up.
+        let notified = state.keyspace_written.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        {
+            let mut keyspace = state.lock_keyspace(db);
+            match pop_first(&mut keyspace, keys, max) {
+                Ok(Some(response)) => {
+                    state.propagate_effects(&mut keyspace);
+                    state.publish_notifications(&mut keyspace);
+                    return response;
+                }
+                Ok(None) => {}
+                Err(error) => return error,
+            }
+        }
+
+        match deadline {
+            Some(deadline) => {
+                if timeout_at(deadline, notified).await.is_err() {
+                    return RespValue::NullArray;
+                }
+            }
+            None => notified.await,
+        }
+    }
+}
+
+// BZPOPMIN key [key ...] timeout
+pub async fn handle_bzpopmin(
+    state: &ServerState,
+    db: usize,
+    decoded_str: &[Argument],
+) -> RespValue {
+    handle_blocking_pop(state, db, decoded_str, "bzpopmin", false).await
+}
+
+// BZPOPMAX key [key ...] timeout
+pub async fn handle_bzpopmax(
+    state: &ServerState,
+    db: usize,
+    decoded_str: &[Argument],
+) -> RespValue {
+    handle_blocking_pop(state, db, decoded_str, "bzpopmax", true).await
+}
+
+// Inside a transaction there is nothing to wait for: blocking pops behave as
+// if their timeout had expired right away.
+fn handle_pop_without_blocking(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+    command: &str,
+    max: bool,
+) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments(command);
+    }
+    if let Err(error) = parse_blocking_timeout(&decoded_str[decoded_str.len() - 1]) {
+        return error;
+    }
+    match pop_first(keyspace, &decoded_str[1..decoded_str.len() - 1], max) {
+        Ok(Some(response)) => response,
+        Ok(None) => RespValue::NullArray,
+        Err(error) => error,
+    }
+}
+
+pub fn handle_bzpopmin_without_blocking(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    handle_pop_without_blocking(keyspace, decoded_str, "bzpopmin", false)
+}
+
+pub fn handle_bzpopmax_without_blocking(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    handle_pop_without_blocking(keyspace, decoded_str, "bzpopmax", true)
+}
+
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(&self, current: f64, score: f64) -> f64 {
+        match self {
+            Aggregate::Sum => {
+                let sum = current + score;
+                // inf + -inf, Redis settles on 0 rather than storing NaN.
+                if sum.is_nan() {
+                    0.0
+                } else {
+                    sum
+                }
+            }
+            Aggregate::Min => current.min(score),
+            Aggregate::Max => current.max(score),
+        }
+    }
+}
+
+// Plain sets can be used as inputs, every member then has a score of 1.
+fn read_weighted_source(
+    keyspace: &Keyspace,
+    key: &str,
+) -> Result<Option<Vec<(String, f64)>>, RespValue> {
+    match keyspace.get(key) {
+        None => Ok(None),
+        Some(RedisValue::SortedSet(sorted_set)) => Ok(Some(sorted_set.entries())),
+        Some(RedisValue::Set(set)) => Ok(Some(
+            set.members()
+                .into_iter()
+                .map(|member| (member, 1.0))
+                .collect(),
+        )),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+// ZUNIONSTORE / ZINTERSTORE destination numkeys key [key ...]
+//     [WEIGHTS weight [weight ...]] [AGGREGATE SUM|MIN|MAX]
+fn handle_store(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+    command: &'static str,
+    union: bool,
+) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments(command);
+    }
+    let destination = &decoded_str[1];
+    let numkeys = match parse_integer_argument(&decoded_str[2]) {
+        Ok(numkeys) if numkeys > 0 => numkeys as usize,
+        Ok(_) => {
+            return RespValue::error(
+                format!(
+                    "ERR at least 1 input key is needed for '{}' command",
+                    command
+                )
+                .as_str(),
+            )
+        }
+        Err(error) => return error,
+    };
+    if numkeys > decoded_str.len() - 3 {
+        return RespValue::error("ERR syntax error");
+    }
+    let keys = &decoded_str[3..3 + numkeys];
+
+    let mut weights: Vec<f64> = vec![1.0; numkeys];
+    let mut aggregate = Aggregate::Sum;
+    let mut idx = 3 + numkeys;
+    while idx < decoded_str.len() {
+        match decoded_str[idx].to_lowercase().as_str() {
+            "weights" if idx + numkeys < decoded_str.len() => {
+                for (weight_idx, weight) in weights.iter_mut().enumerate() {
+                    match parse_score(&decoded_str[idx + 1 + weight_idx]) {
+                        Some(value) => *weight = value,
+                        None => return RespValue::error("ERR weight value is not a float"),
+                    }
+                }
+                idx += numkeys;
+            }
+            "aggregate" if idx + 1 < decoded_str.len() => {
+                aggregate = match decoded_str[idx + 1].to_lowercase().as_str() {
+                    "sum" => Aggregate::Sum,
+                    "min" => Aggregate::Min,
+                    "max" => Aggregate::Max,
+                    _ => return RespValue::error("ERR syntax error"),
+                };
+                idx += 1;
+            }
+            _ => return RespValue::error("ERR syntax error"),
+        }
+        idx += 1;
+    }
+
+    let mut sources: Vec<Option<Vec<(String, f64)>>> = Vec::with_capacity(numkeys);
+    for key in keys {
+        match read_weighted_source(keyspace, key) {
+            Ok(source) => sources.push(source),
+            Err(error) => return error,
+        }
+    }
+
+    let weighted = |score: f64, weight: f64| {
+        let result = score * weight;
+        // 0 * inf is NaN, treated as 0 like Redis does.
+        if result.is_nan() {
+            0.0
+        } else {
+            result
+        }
+    };
+
+    let mut result = SortedSet::new();
+    if union {
+        let mut scores: HashMap<String, f64> = HashMap::new();
+        for (source, weight) in sources.iter().zip(weights.iter()) {
+            for (member, score) in source.iter().flatten() {
+                let score = weighted(*score, *weight);
+                scores
+                    .entry(member.to_owned())
+                    .and_modify(|current| *current = aggregate.apply(*current, score))
+                    .or_insert(score);
+            }
+        }
+        for (member, score) in scores {
+            result.insert(member, score);
+        }
+    } else if sources.iter().all(Option::is_some) {
+        let sources: Vec<HashMap<&str, f64>> = sources
+            .iter()
+            .flatten()
+            .map(|source| {
+                source
+                    .iter()
+                    .map(|(member, score)| (member.as_str(), *score))
+                    .collect()
+            })
+            .collect();
+        for (member, score) in &sources[0] {
+            let mut total = weighted(*score, weights[0]);
+            let mut in_all = true;
+            for (other, weight) in sources[1..].iter().zip(weights[1..].iter()) {
+                match other.get(member) {
+                    Some(other_score) => {
+                        total = aggregate.apply(total, weighted(*other_score, *weight))
+                    }
+                    None => {
+                        in_all = false;
+                        break;
+                    }
+                }
+            }
+            if in_all {
+                result.insert(member.to_string(), total);
+            }
+        }
+    }
+
+    let cardinality = result.len();
+    let existed = keyspace.remove(destination.as_str()).is_some();
+    if cardinality > 0 {
+        keyspace.insert(destination.to_string(), RedisValue::SortedSet(result), None);
+        keyspace.notify(EventClass::Zset, command, destination);
+    } else if existed {
+        keyspace.notify(EventClass::Generic, "del", destination);
+    }
+    RespValue::Integer(cardinality as i64)
+}
+
+pub fn handle_zunionstore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_store(keyspace, decoded_str, "zunionstore", true)
+}
+
+pub fn handle_zinterstore(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_store(keyspace, decoded_str, "zinterstore", false)
+}
+
+// ZRANDMEMBER key [count [WITHSCORES]]
+pub fn handle_zrandmember(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 || decoded_str.len() > 4 {
+        return wrong_number_of_arguments("zrandmember");
+    }
+    let count = match decoded_str.get(2).map(|count| parse_random_count(count)) {
+        Some(Ok(count)) => Some(count),
+        Some(Err(error)) => return error,
+        None => None,
+    };
+    let with_scores = match decoded_str.get(3) {
+        Some(option) if option.eq_ignore_ascii_case("withscores") => true,
+        Some(_) => return RespValue::error("ERR syntax error"),
+        None => false,
+    };
+    // The reply has twice as many elements, their count must not overflow.
+    if with_scores && count.is_some_and(|count| count.unsigned_abs() > (i64::MAX / 2) as u64) {
+        return RespValue::error("ERR value is out of range");
+    }
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => sorted_set,
+        Err(error) => return error,
+    };
+
+    match (sorted_set, count) {
+        (Some(sorted_set), None) => match sorted_set.random_entry() {
+            Some((member, _)) => RespValue::bulk(&member),
+            None => RespValue::Null,
+        },
+        (Some(sorted_set), Some(count)) if count < 0 => {
+            let picked: Vec<(String, f64)> = (0..count.unsigned_abs())
+                .filter_map(|_| sorted_set.random_entry())
+                .collect();
+            encode_entries(&picked, with_scores)
+        }
+        (Some(sorted_set), Some(count)) => {
+            let mut entries = sorted_set.entries();
+            let count = (count as usize).min(entries.len());
+            for idx in 0..count {
+                let swap_with = idx + random_index(entries.len() - idx);
+                entries.swap(idx, swap_with);
+            }
+            entries.truncate(count);
+            encode_entries(&entries, with_scores)
+        }
+        (None, None) => RespValue::Null,
+        (None, Some(_)) => RespValue::Array(Vec::new()),
+    }
+}
+
+// ZSCAN key cursor [MATCH pattern] [COUNT count]
+// The cursor is an offset in (score, member) order.
+pub fn handle_zscan(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("zscan");
+    }
+    let cursor: usize = match decoded_str[2].parse() {
+        Ok(cursor) => cursor,
+        Err(_) => return RespValue::error("ERR invalid cursor"),
+    };
+    let (pattern, count) = match parse_scan_options(&decoded_str[3..]) {
+        Ok(options) => options,
+        Err(error) => return error,
+    };
+    let sorted_set = match get_sorted_set(keyspace, &decoded_str[1]) {
+        Ok(sorted_set) => sorted_set,
+        Err(error) => return error,
+    };
+
+    let (next_cursor, page) = match sorted_set {
+        Some(sorted_set) => {
+            let entries = sorted_set.entries();
+            let end = cursor.saturating_add(count).min(entries.len());
+            let page = entries.get(cursor..end).map(<[(String, f64)]>::to_vec);
+            let next_cursor = if end >= entries.len() { 0 } else { end };
+            (next_cursor, page.unwrap_or_default())
+        }
+        None => (0, Vec::new()),
+    };
+    let page: Vec<(String, f64)> = page
+        .into_iter()
+        .filter(|(member, _)| {
+            pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), member.as_bytes()))
+        })
+        .collect();
+    RespValue::Array(vec![
+        RespValue::bulk(next_cursor.to_string()),
+        encode_entries(&page, true),
+    ])
+}
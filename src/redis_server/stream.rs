@@ -0,0 +1,2154 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    ops::Bound,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::{timeout_at, Duration, Instant};
+
+use super::{
+    keyspace::Keyspace,
+    notify::EventClass,
+    parse_integer_argument,
+    redis_value::{RedisValue, WRONGTYPE_ERROR},
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    wrong_number_of_arguments,
+};
+
+// Redis packs stream entries in listpack nodes of `stream-node-max-entries`
+// entries, approximate trimming only ever drops whole nodes.
+pub const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+const INVALID_ID_ERROR: &str = "ERR Invalid stream ID specified as stream command argument";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    // Parses `ms-seq`, or `ms` alone in which case `missing_seq` is used.
+    pub fn parse(input: &str, missing_seq: u64) -> Option<StreamId> {
+        match input.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: ms.parse().ok()?,
+                seq: seq.parse().ok()?,
+            }),
+            None => Some(StreamId {
+                ms: input.parse().ok()?,
+                seq: missing_seq,
+            }),
+        }
+    }
+
+    pub fn next(&self) -> Option<StreamId> {
+        if self.seq < u64::MAX {
+            Some(StreamId {
+                ms: self.ms,
+                seq: self.seq + 1,
+            })
+        } else if self.ms < u64::MAX {
+            Some(StreamId {
+                ms: self.ms + 1,
+                seq: 0,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+pub type StreamFields = Vec<(String, String)>;
+
+// An entry delivered to a consumer and not acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    // Unix time in milliseconds of the last delivery.
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Consumer {
+    // Last time the consumer issued a command against the group, and last
+    // time it was actually delivered or claimed entries.
+    pub seen_time: u64,
+    pub active_time: Option<u64>,
+    pub pending: BTreeSet<StreamId>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerGroup {
+    pub last_id: StreamId,
+    // Entries delivered to the group so far, used to compute its lag. None
+    // when it can't be known, e.g. after SETID to an arbitrary ID.
+    pub entries_read: Option<u64>,
+    // Group-wide pending entries list; each consumer also keeps the IDs it
+    // owns so that its history can be served without a scan.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_id,
+            entries_read,
+            ..Self::default()
+        }
+    }
+
+    // Looks the consumer up, creating it if needed; the flag tells whether
+    // it was created.
+    fn touch_consumer(&mut self, name: &str, now: u64) -> (&mut Consumer, bool) {
+        let created = !self.consumers.contains_key(name);
+        let consumer = self.consumers.entry(name.to_owned()).or_default();
+        consumer.seen_time = now;
+        (consumer, created)
+    }
+
+    // Makes `consumer` the owner of the pending entry `id`.
+    fn assign(&mut self, id: StreamId, consumer: &str, delivery_time: u64, delivery_count: u64) {
+        let entry = PendingEntry {
+            consumer: consumer.to_owned(),
+            delivery_time,
+            delivery_count,
+        };
+        if let Some(previous) = self.pending.insert(id, entry) {
+            if previous.consumer != consumer {
+                if let Some(previous) = self.consumers.get_mut(&previous.consumer) {
+                    previous.pending.remove(&id);
+                }
+            }
+        }
+        self.consumers
+            .entry(consumer.to_owned())
+            .or_default()
+            .pending
+            .insert(id);
+    }
+
+    fn acknowledge(&mut self, id: &StreamId) -> Option<PendingEntry> {
+        let entry = self.pending.remove(id)?;
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(id);
+        }
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    last_id: StreamId,
+    max_deleted_entry_id: StreamId,
+    entries_added: u64,
+    groups: BTreeMap<String, ConsumerGroup>,
+    // Heap memory taken by the entries, kept up to date.
+    entries_memory: usize,
+}
+
+// How XADD wants the ID of the new entry to be picked.
+#[derive(Debug, Clone, Copy)]
+pub enum IdSpec {
+    Auto,
+    AutoSequence(u64),
+    Explicit(StreamId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrimOptions {
+    pub strategy: TrimStrategy,
+    pub approximate: bool,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClaimOptions {
+    pub min_idle: u64,
+    pub delivery_time: u64,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub just_id: bool,
+}
+
+#[derive(Debug)]
+pub enum ClaimOutcome {
+    Claimed(PendingEntry),
+    // The entry was deleted from the stream, so it was dropped from the PEL.
+    Deleted(PendingEntry),
+    Skipped,
+}
+
+fn entry_size(fields: &StreamFields) -> usize {
+    size_of::<(StreamId, StreamFields)>()
+        + fields
+            .iter()
+            .map(|(field, value)| {
+                size_of::<(String, String)>() + field.capacity() + value.capacity()
+            })
+            .sum::<usize>()
+}
+
+fn now_in_milliseconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    // Heap memory taken by the entries and the consumer groups. Only the
+    // groups and their consumers are walked, pending entries are counted.
+    pub fn memory_usage(&self) -> usize {
+        let groups: usize = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                size_of::<(String, ConsumerGroup)>()
+                    + name.capacity()
+                    + group.pending.len() * size_of::<(StreamId, PendingEntry)>()
+                    + group
+                        .consumers
+                        .iter()
+                        .map(|(name, consumer)| {
+                            size_of::<(String, Consumer)>()
+                                + name.capacity()
+                                + consumer.pending.len() * size_of::<StreamId>()
+                        })
+                        .sum::<usize>()
+            })
+            .sum();
+        self.entries_memory + groups
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn max_deleted_entry_id(&self) -> StreamId {
+        self.max_deleted_entry_id
+    }
+
+    pub fn first_id(&self) -> StreamId {
+        self.entries
+            .first_key_value()
+            .map_or(StreamId::MIN, |(id, _)| *id)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&StreamId, &StreamFields)> {
+        self.entries.iter()
+    }
+
+    pub fn groups(&self) -> &BTreeMap<String, ConsumerGroup> {
+        &self.groups
+    }
+
+    // Rebuilds a stream from its persisted parts.
+    pub fn restore(
+        entries: BTreeMap<StreamId, StreamFields>,
+        last_id: StreamId,
+        max_deleted_entry_id: StreamId,
+        entries_added: u64,
+        groups: BTreeMap<String, ConsumerGroup>,
+    ) -> Self {
+        Self {
+            entries_memory: entries.values().map(entry_size).sum(),
+            entries,
+            last_id,
+            max_deleted_entry_id,
+            entries_added,
+            groups,
+        }
+    }
+
+    // Picks the ID of the next entry, it has to be strictly greater than the
+    // last one ever added, deleted entries included.
+    pub fn next_id(&self, spec: IdSpec) -> Result<StreamId, RespValue> {
+        let too_small = || {
+            RespValue::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            )
+        };
+        let id = match spec {
+            IdSpec::Auto => {
+                let ms = now_in_milliseconds();
+                if ms > self.last_id.ms {
+                    StreamId { ms, seq: 0 }
+                } else {
+                    self.last_id.next().ok_or_else(too_small)?
+                }
+            }
+            IdSpec::AutoSequence(ms) => {
+                if ms > self.last_id.ms {
+                    StreamId { ms, seq: 0 }
+                } else if ms == self.last_id.ms && self.last_id.seq < u64::MAX {
+                    StreamId {
+                        ms,
+                        seq: self.last_id.seq + 1,
+                    }
+                } else {
+                    return Err(too_small());
+                }
+            }
+            IdSpec::Explicit(id) => id,
+        };
+        if id == StreamId::MIN {
+            return Err(RespValue::error(
+                "ERR The ID specified in XADD must be greater than 0-0",
+            ));
+        }
+        if self.entries_added > 0 && id <= self.last_id {
+            return Err(too_small());
+        }
+        Ok(id)
+    }
+
+    pub fn add(&mut self, id: StreamId, fields: StreamFields) {
+        self.entries_memory += entry_size(&fields);
+        if let Some(replaced) = self.entries.insert(id, fields) {
+            self.entries_memory -= entry_size(&replaced);
+        }
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    pub fn delete(&mut self, id: &StreamId) -> bool {
+        if let Some(fields) = self.entries.remove(id) {
+            self.entries_memory -= entry_size(&fields);
+            if *id > self.max_deleted_entry_id {
+                self.max_deleted_entry_id = *id;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        reverse: bool,
+        count: Option<usize>,
+    ) -> Vec<(StreamId, StreamFields)> {
+        // BTreeMap::range panics on inverted or empty exclusive bounds.
+        match (start, end) {
+            (Bound::Included(start), Bound::Included(end)) if start > end => return Vec::new(),
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+                if start >= end =>
+            {
+                return Vec::new()
+            }
+            _ => {}
+        }
+        let range = self.entries.range((start, end));
+        let limit = count.unwrap_or(usize::MAX);
+        let cloned = |(id, fields): (&StreamId, &StreamFields)| (*id, fields.clone());
+        if reverse {
+            range.rev().take(limit).map(cloned).collect()
+        } else {
+            range.take(limit).map(cloned).collect()
+        }
+    }
+
+    // Returns how many entries were evicted from the head of the stream.
+    pub fn trim(&mut self, options: &TrimOptions) -> usize {
+        let candidates = match options.strategy {
+            TrimStrategy::MaxLen(max_len) => self.len().saturating_sub(max_len),
+            TrimStrategy::MinId(min_id) => self.entries.range(..min_id).count(),
+        };
+        let mut to_remove = if options.approximate {
+            candidates - candidates % STREAM_NODE_MAX_ENTRIES
+        } else {
+            candidates
+        };
+        if let Some(limit) = options.limit.filter(|limit| *limit > 0) {
+            to_remove = to_remove.min(limit - limit % STREAM_NODE_MAX_ENTRIES);
+        }
+
+        for _ in 0..to_remove {
+            if let Some((id, fields)) = self.entries.pop_first() {
+                self.entries_memory -= entry_size(&fields);
+                if id > self.max_deleted_entry_id {
+                    self.max_deleted_entry_id = id;
+                }
+            }
+        }
+        to_remove
+    }
+
+    // Whether an entry at or after `id` was ever deleted, in which case
+    // counting entries from there is unreliable.
+    fn has_tombstones_from(&self, id: &StreamId) -> bool {
+        !self.entries.is_empty()
+            && self.max_deleted_entry_id != StreamId::MIN
+            && self.max_deleted_entry_id >= *id
+    }
+
+    // Port of `streamEstimateDistanceFromFirstEverEntry`: how many entries
+    // were added up to `id`, when that can be told.
+    fn estimate_entries_read(&self, id: &StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.entries.is_empty() && *id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        if *id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if *id > self.last_id {
+            return None;
+        }
+        let first_id = self.first_id();
+        if self.max_deleted_entry_id == StreamId::MIN || self.max_deleted_entry_id < first_id {
+            if *id < first_id {
+                return Some(self.entries_added - self.len() as u64);
+            }
+            if *id == first_id {
+                return Some(self.entries_added - self.len() as u64 + 1);
+            }
+        }
+        None
+    }
+
+    // Entries added to the stream that the group has yet to read.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(entries_read) if !self.has_tombstones_from(&group.last_id) => Some(entries_read),
+            _ => self.estimate_entries_read(&group.last_id),
+        };
+        entries_read.map(|entries_read| self.entries_added.saturating_sub(entries_read))
+    }
+
+    pub fn create_group(&mut self, name: &str, group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        self.groups.insert(name.to_owned(), group);
+        true
+    }
+
+    // Serves `>`: entries after the group's last delivered ID. They become
+    // pending for the consumer unless `no_ack` is given.
+    pub fn deliver_new(
+        &mut self,
+        group_name: &str,
+        consumer: &str,
+        count: Option<usize>,
+        no_ack: bool,
+        now: u64,
+    ) -> Vec<(StreamId, StreamFields)> {
+        let Some(last_id) = self.groups.get(group_name).map(|group| group.last_id) else {
+            return Vec::new();
+        };
+        let entries = self.range(Bound::Excluded(last_id), Bound::Unbounded, false, count);
+        for (id, _) in &entries {
+            let entries_read = match self.groups[group_name].entries_read {
+                Some(entries_read) if !self.has_tombstones_from(id) => Some(entries_read + 1),
+                _ => self.estimate_entries_read(id),
+            };
+            let group = self.groups.get_mut(group_name).unwrap();
+            group.last_id = *id;
+            group.entries_read = entries_read;
+            if !no_ack {
+                group.assign(*id, consumer, now, 1);
+            }
+        }
+        if !entries.is_empty() {
+            if let Some(consumer) = self
+                .groups
+                .get_mut(group_name)
+                .and_then(|group| group.consumers.get_mut(consumer))
+            {
+                consumer.active_time = Some(now);
+            }
+        }
+        entries
+    }
+
+    // Serves an explicit ID: the consumer's own pending entries after
+    // `start`. Entries deleted meanwhile come back without fields.
+    pub fn deliver_history(
+        &mut self,
+        group_name: &str,
+        consumer: &str,
+        start: StreamId,
+        count: Option<usize>,
+        now: u64,
+    ) -> Vec<(StreamId, Option<StreamFields>)> {
+        let Some(group) = self.groups.get_mut(group_name) else {
+            return Vec::new();
+        };
+        let ids: Vec<StreamId> = match group.consumers.get(consumer) {
+            Some(consumer) => consumer
+                .pending
+                .range((Bound::Excluded(start), Bound::Unbounded))
+                .take(count.unwrap_or(usize::MAX))
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        };
+        ids.into_iter()
+            .map(|id| {
+                let fields = self.entries.get(&id).cloned();
+                if fields.is_some() {
+                    if let Some(entry) = group.pending.get_mut(&id) {
+                        entry.delivery_time = now;
+                        entry.delivery_count += 1;
+                    }
+                }
+                (id, fields)
+            })
+            .collect()
+    }
+
+    // Transfers the pending entry `id` to `consumer`, following XCLAIM's
+    // rules on idle time, FORCE and delivery counters.
+    pub fn claim(
+        &mut self,
+        group_name: &str,
+        consumer: &str,
+        id: StreamId,
+        options: &ClaimOptions,
+        now: u64,
+    ) -> ClaimOutcome {
+        let Some(group) = self.groups.get_mut(group_name) else {
+            return ClaimOutcome::Skipped;
+        };
+        if !self.entries.contains_key(&id) {
+            return match group.acknowledge(&id) {
+                Some(entry) => ClaimOutcome::Deleted(entry),
+                None => ClaimOutcome::Skipped,
+            };
+        }
+        let delivery_count = match group.pending.get(&id) {
+            Some(entry) => {
+                if options.min_idle > 0
+                    && now.saturating_sub(entry.delivery_time) < options.min_idle
+                {
+                    return ClaimOutcome::Skipped;
+                }
+                entry.delivery_count
+            }
+            // Same as a freshly created NACK in Redis.
+            None if options.force => 1,
+            None => return ClaimOutcome::Skipped,
+        };
+        let delivery_count = match options.retry_count {
+            Some(retry_count) => retry_count,
+            None if options.just_id => delivery_count,
+            None => delivery_count + 1,
+        };
+        group.touch_consumer(consumer, now).0.active_time = Some(now);
+        group.assign(id, consumer, options.delivery_time, delivery_count);
+        ClaimOutcome::Claimed(group.pending[&id].clone())
+    }
+}
+
+pub fn get_stream<'a>(keyspace: &'a Keyspace, key: &str) -> Result<Option<&'a Stream>, RespValue> {
+    match keyspace.get(key) {
+        None => Ok(None),
+        Some(RedisValue::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+pub fn get_stream_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut Stream>, RespValue> {
+    match keyspace.get_mut(key) {
+        None => Ok(None),
+        Some(RedisValue::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+pub fn parse_stream_id(input: &str) -> Result<StreamId, RespValue> {
+    StreamId::parse(input, 0).ok_or_else(|| RespValue::error(INVALID_ID_ERROR))
+}
+
+pub fn encode_entry(id: &StreamId, fields: &StreamFields) -> RespValue {
+    let mut flattened: Vec<&str> = Vec::with_capacity(fields.len() * 2);
+    for (field, value) in fields {
+        flattened.push(field);
+        flattened.push(value);
+    }
+    RespValue::Array(vec![
+        RespValue::bulk(id.to_string()),
+        RespValue::bulks(&flattened),
+    ])
+}
+
+pub fn encode_entries(entries: &[(StreamId, StreamFields)]) -> RespValue {
+    let encoded: Vec<RespValue> = entries
+        .iter()
+        .map(|(id, fields)| encode_entry(id, fields))
+        .collect();
+    RespValue::Array(encoded)
+}
+
+// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` starting at `idx`,
+// returns the options and the index of the first unparsed argument.
+fn parse_trim_options(
+    decoded_str: &[Argument],
+    idx: usize,
+) -> Result<(TrimOptions, usize), RespValue> {
+    let strategy = decoded_str[idx].to_lowercase();
+    let mut idx = idx + 1;
+    let mut approximate = false;
+    match decoded_str.get(idx).map(Argument::as_str) {
+        Some("~") => {
+            approximate = true;
+            idx += 1;
+        }
+        Some("=") => idx += 1,
+        _ => {}
+    }
+    let threshold = decoded_str
+        .get(idx)
+        .ok_or_else(|| RespValue::error("ERR syntax error"))?;
+    let strategy = match strategy.as_str() {
+        "maxlen" => match parse_integer_argument(threshold)? {
+            max_len if max_len < 0 => {
+                return Err(RespValue::error("ERR The MAXLEN argument must be >= 0."))
+            }
+            max_len => TrimStrategy::MaxLen(max_len as usize),
+        },
+        _ => TrimStrategy::MinId(parse_stream_id(threshold)?),
+    };
+    idx += 1;
+
+    let mut limit = None;
+    if decoded_str
+        .get(idx)
+        .is_some_and(|option| option.eq_ignore_ascii_case("limit"))
+    {
+        let count = decoded_str
+            .get(idx + 1)
+            .ok_or_else(|| RespValue::error("ERR syntax error"))?;
+        match parse_integer_argument(count)? {
+            count if count < 0 => {
+                return Err(RespValue::error("ERR The LIMIT argument must be >= 0."))
+            }
+            count => limit = Some(count as usize),
+        }
+        if !approximate {
+            return Err(RespValue::error(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option",
+            ));
+        }
+        idx += 2;
+    } else if approximate {
+        // Same default effort as Redis: 100 times the node size.
+        limit = Some(STREAM_NODE_MAX_ENTRIES * 100);
+    }
+
+    Ok((
+        TrimOptions {
+            strategy,
+            approximate,
+            limit,
+        },
+        idx,
+    ))
+}
+
+// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]]
+//     *|id field value [field value ...]
+pub fn handle_xadd(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 5 {
+        return wrong_number_of_arguments("xadd");
+    }
+    let key = &decoded_str[1];
+    let mut no_mkstream = false;
+    let mut trim: Option<TrimOptions> = None;
+
+    let mut idx = 2;
+    loop {
+        match decoded_str
+            .get(idx)
+            .map(|option| option.to_lowercase())
+            .as_deref()
+        {
+            Some("nomkstream") => {
+                no_mkstream = true;
+                idx += 1;
+            }
+            Some("maxlen") | Some("minid") => match parse_trim_options(decoded_str, idx) {
+                Ok((options, next)) => {
+                    trim = Some(options);
+                    idx = next;
+                }
+                Err(error) => return error,
+            },
+            _ => break,
+        }
+    }
+
+    let fields = match decoded_str.get(idx + 1..) {
+        Some(fields) if !fields.is_empty() && fields.len().is_multiple_of(2) => fields,
+        _ => return wrong_number_of_arguments("xadd"),
+    };
+    let id_spec = match decoded_str[idx].as_str() {
+        "*" => IdSpec::Auto,
+        id => match id.strip_suffix("-*") {
+            Some(ms) => match ms.parse::<u64>() {
+                Ok(ms) => IdSpec::AutoSequence(ms),
+                Err(_) => return RespValue::error(INVALID_ID_ERROR),
+            },
+            None => match parse_stream_id(id) {
+                Ok(id) => IdSpec::Explicit(id),
+                Err(error) => return error,
+            },
+        },
+    };
+
+    let stream = match get_stream_mut(keyspace, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) if no_mkstream => return RespValue::Null,
+        Ok(None) => {
+            keyspace.insert(key.to_string(), RedisValue::Stream(Stream::new()), None);
+            match get_stream_mut(keyspace, key) {
+                Ok(Some(stream)) => stream,
+                _ => return RespValue::error(WRONGTYPE_ERROR),
+            }
+        }
+        Err(error) => return error,
+    };
+
+    let id = match stream.next_id(id_spec) {
+        Ok(id) => id,
+        Err(error) => {
+            if stream.entries_added() == 0 {
+                keyspace.remove(key.as_str());
+            }
+            return error;
+        }
+    };
+    let fields: StreamFields = fields
+        .chunks(2)
+        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+        .collect();
+    stream.add(id, fields);
+    let trimmed = trim.map_or(0, |trim| stream.trim(&trim));
+    keyspace.notify(EventClass::Stream, "xadd", key);
+    if trimmed > 0 {
+        keyspace.notify(EventClass::Stream, "xtrim", key);
+    }
+    // Replicas get the ID that was picked rather than `*`.
+    let mut effect = decoded_str.to_vec();
+    effect[idx] = id.to_string().into();
+    keyspace.propagate(effect);
+    RespValue::bulk(id.to_string())
+}
+
+pub fn handle_xlen(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("xlen");
+    }
+    match get_stream(keyspace, &decoded_str[1]) {
+        Ok(stream) => RespValue::Integer(stream.map_or(0, Stream::len) as i64),
+        Err(error) => error,
+    }
+}
+
+// Range bounds accept `-`, `+`, incomplete IDs and `(` for exclusive bounds.
+fn parse_range_bound(input: &str, is_start: bool) -> Result<Bound<StreamId>, RespValue> {
+    match input {
+        "-" => Ok(Bound::Included(StreamId::MIN)),
+        "+" => Ok(Bound::Included(StreamId::MAX)),
+        _ => {
+            let missing_seq = if is_start { 0 } else { u64::MAX };
+            match input.strip_prefix('(') {
+                Some(id) => StreamId::parse(id, missing_seq)
+                    .map(Bound::Excluded)
+                    .ok_or_else(|| RespValue::error(INVALID_ID_ERROR)),
+                None => StreamId::parse(input, missing_seq)
+                    .map(Bound::Included)
+                    .ok_or_else(|| RespValue::error(INVALID_ID_ERROR)),
+            }
+        }
+    }
+}
+
+fn handle_range(
+    keyspace: &Keyspace,
+    decoded_str: &[Argument],
+    command: &str,
+    reverse: bool,
+) -> RespValue {
+    if decoded_str.len() != 4 && decoded_str.len() != 6 {
+        return wrong_number_of_arguments(command);
+    }
+    let (start, end) = if reverse {
+        (&decoded_str[3], &decoded_str[2])
+    } else {
+        (&decoded_str[2], &decoded_str[3])
+    };
+    let start = match parse_range_bound(start, true) {
+        Ok(start) => start,
+        Err(error) => return error,
+    };
+    let end = match parse_range_bound(end, false) {
+        Ok(end) => end,
+        Err(error) => return error,
+    };
+    let count = match decoded_str.get(4) {
+        Some(option) if option.eq_ignore_ascii_case("count") => {
+            match parse_integer_argument(&decoded_str[5]) {
+                Ok(count) if count < 0 => Some(0),
+                Ok(count) => Some(count as usize),
+                Err(error) => return error,
+            }
+        }
+        Some(_) => return RespValue::error("ERR syntax error"),
+        None => None,
+    };
+
+    match get_stream(keyspace, &decoded_str[1]) {
+        Ok(Some(stream)) => encode_entries(&stream.range(start, end, reverse, count)),
+        Ok(None) => RespValue::Array(Vec::new()),
+        Err(error) => error,
+    }
+}
+
+// XRANGE key start end [COUNT count]
+pub fn handle_xrange(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_range(keyspace, decoded_str, "xrange", false)
+}
+
+// XREVRANGE key end start [COUNT count]
+pub fn handle_xrevrange(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    handle_range(keyspace, decoded_str, "xrevrange", true)
+}
+
+// XDEL key id [id ...]
+pub fn handle_xdel(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("xdel");
+    }
+    let mut ids: Vec<StreamId> = Vec::with_capacity(decoded_str.len() - 2);
+    for id in &decoded_str[2..] {
+        match parse_stream_id(id) {
+            Ok(id) => ids.push(id),
+            Err(error) => return error,
+        }
+    }
+    let deleted = match get_stream_mut(keyspace, &decoded_str[1]) {
+        Ok(Some(stream)) => ids.iter().filter(|id| stream.delete(id)).count(),
+        Ok(None) => 0,
+        Err(error) => return error,
+    };
+    if deleted > 0 {
+        keyspace.notify(EventClass::Stream, "xdel", &decoded_str[1]);
+    }
+    RespValue::Integer(deleted as i64)
+}
+
+// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+pub fn handle_xtrim(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments("xtrim");
+    }
+    if !matches!(decoded_str[2].to_lowercase().as_str(), "maxlen" | "minid") {
+        return RespValue::error("ERR syntax error");
+    }
+    let options = match parse_trim_options(decoded_str, 2) {
+        Ok((options, next)) if next == decoded_str.len() => options,
+        Ok(_) => return RespValue::error("ERR syntax error"),
+        Err(error) => return error,
+    };
+    let trimmed = match get_stream_mut(keyspace, &decoded_str[1]) {
+        Ok(Some(stream)) => stream.trim(&options),
+        Ok(None) => 0,
+        Err(error) => return error,
+    };
+    if trimmed > 0 {
+        keyspace.notify(EventClass::Stream, "xtrim", &decoded_str[1]);
+    }
+    RespValue::Integer(trimmed as i64)
+}
+
+// Parses the `BLOCK milliseconds` option shared by XREAD and XREADGROUP.
+// Zero blocks forever.
+pub fn parse_block_milliseconds(input: &str) -> Result<Option<Instant>, RespValue> {
+    match parse_integer_argument(input) {
+        Ok(milliseconds) if milliseconds < 0 => Err(RespValue::error("ERR timeout is negative")),
+        Ok(0) => Ok(None),
+        Ok(milliseconds) => Ok(Some(
+            Instant::now() + Duration::from_millis(milliseconds as u64),
+        )),
+        Err(_) => Err(RespValue::error(
+            "ERR timeout is not an integer or out of range",
+        )),
+    }
+}
+
+struct XreadArguments<'a> {
+    count: Option<usize>,
+    // None without BLOCK, then the deadline, None to block forever.
+    block: Option<Option<Instant>>,
+    keys: &'a [Argument],
+    ids: &'a [Argument],
+}
+
+fn parse_xread(decoded_str: &[Argument]) -> Result<XreadArguments<'_>, RespValue> {
+    if decoded_str.len() < 4 {
+        return Err(wrong_number_of_arguments("xread"));
+    }
+    let mut count: Option<usize> = None;
+    let mut block: Option<Option<Instant>> = None;
+    let mut idx = 1;
+    let streams_idx = loop {
+        match decoded_str
+            .get(idx)
+            .map(|option| option.to_lowercase())
+            .as_deref()
+        {
+            Some("count") if idx + 1 < decoded_str.len() => {
+                match parse_integer_argument(&decoded_str[idx + 1])? {
+                    value if value > 0 => count = Some(value as usize),
+                    _ => count = None,
+                }
+                idx += 2;
+            }
+            Some("block") if idx + 1 < decoded_str.len() => {
+                block = Some(parse_block_milliseconds(&decoded_str[idx + 1])?);
+                idx += 2;
+            }
+            Some("streams") => break idx + 1,
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+    };
+
+    let arguments = &decoded_str[streams_idx..];
+    if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+        return Err(RespValue::error(
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
+        ));
+    }
+    let (keys, ids) = arguments.split_at(arguments.len() / 2);
+    Ok(XreadArguments {
+        count,
+        block,
+        keys,
+        ids,
+    })
+}
+
+// The IDs to read after, with `$` standing for the last entry of the stream.
+fn resolve_last_seen(
+    keyspace: &Keyspace,
+    arguments: &XreadArguments,
+) -> Result<Vec<StreamId>, RespValue> {
+    let mut last_seen: Vec<StreamId> = Vec::with_capacity(arguments.keys.len());
+    for (key, id) in arguments.keys.iter().zip(arguments.ids) {
+        let stream = get_stream(keyspace, key)?;
+        let id = match id.as_str() {
+            "$" => stream.map_or(StreamId::MIN, Stream::last_id),
+            id => parse_stream_id(id)?,
+        };
+        last_seen.push(id);
+    }
+    Ok(last_seen)
+}
+
+// One reply per stream that has entries after its ID.
+fn read_after(
+    keyspace: &Keyspace,
+    arguments: &XreadArguments,
+    last_seen: &[StreamId],
+) -> Result<Vec<RespValue>, RespValue> {
+    let mut replies: Vec<RespValue> = Vec::new();
+    for (key, last_seen) in arguments.keys.iter().zip(last_seen) {
+        let Some(stream) = get_stream(keyspace, key)? else {
+            continue;
+        };
+        let entries = stream.range(
+            Bound::Excluded(*last_seen),
+            Bound::Unbounded,
+            false,
+            arguments.count,
+        );
+        if !entries.is_empty() {
+            replies.push(RespValue::Array(vec![
+                RespValue::bulk(key),
+                encode_entries(&entries),
+            ]));
+        }
+    }
+    Ok(replies)
+}
+
+// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+pub async fn handle_xread(state: &ServerState, db: usize, decoded_str: &[Argument]) -> RespValue {
+    let arguments = match parse_xread(decoded_str) {
+        Ok(arguments) => arguments,
+        Err(error) => return error,
+    };
+    // `$` is resolved once, so that blocking waits for entries added after
+    // the call.
+    let last_seen = match resolve_last_seen(&state.lock_keyspace(db), &arguments) {
+        Ok(last_seen) => last_seen,
+        Err(error) => return error,
+    };
+
+    loop {
+        let notified = state.keyspace_written.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let replies = match read_after(&state.lock_keyspace(db), &arguments, &last_seen) {
+            Ok(replies) => replies,
+            Err(error) => return error,
+        };
+        if !replies.is_empty() {
+            return RespValue::Array(replies);
+        }
+
+        match arguments.block {
+            None => return RespValue::NullArray,
+            Some(Some(deadline)) => {
+                if timeout_at(deadline, notified).await.is_err() {
+                    return RespValue::NullArray;
+                }
+            }
+            Some(None) => notified.await,
+        }
+    }
+}
+
+// XREAD inside a transaction, BLOCK is ignored.
+pub fn handle_xread_without_blocking(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let replies = parse_xread(decoded_str).and_then(|arguments| {
+        let last_seen = resolve_last_seen(keyspace, &arguments)?;
+        read_after(keyspace, &arguments, &last_seen)
+    });
+    match replies {
+        Ok(replies) if replies.is_empty() => RespValue::NullArray,
+        Ok(replies) => RespValue::Array(replies),
+        Err(error) => error,
+    }
+}
+
+fn no_such_group(key: &str, group: &str) -> RespValue {
+    RespValue::error(
+        format!(
+            "NOGROUP No such consumer group '{}' for key name '{}'",
+            group, key
+        )
+        .as_str(),
+    )
+}
+
+fn no_such_key_or_group(key: &str, group: &str) -> RespValue {
+    RespValue::error(
+        format!(
+            "NOGROUP No such key '{}' or consumer group '{}'",
+            key, group
+        )
+        .as_str(),
+    )
+}
+
+fn encode_optional_integer(value: Option<u64>) -> RespValue {
+    match value {
+        Some(value) => RespValue::Integer(value as i64),
+        None => RespValue::Null,
+    }
+}
+
+// What replicas run to end up with the same pending entry as the master,
+// whatever the idle times on their side.
+fn claim_effect(
+    key: &str,
+    group_name: &str,
+    group: &ConsumerGroup,
+    consumer: &str,
+    id: &StreamId,
+    entry: &PendingEntry,
+) -> Vec<String> {
+    [
+        "XCLAIM",
+        key,
+        group_name,
+        consumer,
+        "0",
+        &id.to_string(),
+        "TIME",
+        &entry.delivery_time.to_string(),
+        "RETRYCOUNT",
+        &entry.delivery_count.to_string(),
+        "FORCE",
+        "JUSTID",
+        "LASTID",
+        &group.last_id.to_string(),
+    ]
+    .iter()
+    .map(|argument| argument.to_string())
+    .collect()
+}
+
+fn set_id_effect(key: &str, group_name: &str, group: &ConsumerGroup) -> Vec<String> {
+    let entries_read = group
+        .entries_read
+        .map_or("-1".to_string(), |entries_read| entries_read.to_string());
+    [
+        "XGROUP",
+        "SETID",
+        key,
+        group_name,
+        &group.last_id.to_string(),
+        "ENTRIESREAD",
+        &entries_read,
+    ]
+    .iter()
+    .map(|argument| argument.to_string())
+    .collect()
+}
+
+// Parses `id|$ [ENTRIESREAD entries-read]` of XGROUP CREATE and SETID,
+// `$` resolving to the last ID of the stream.
+fn parse_group_position(
+    stream: Option<&Stream>,
+    arguments: &[Argument],
+    allow_mkstream: bool,
+) -> Result<(StreamId, Option<u64>, bool), RespValue> {
+    let mut mkstream = false;
+    let mut entries_read: Option<u64> = None;
+    let mut options = arguments[1..].iter();
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "mkstream" if allow_mkstream => mkstream = true,
+            "entriesread" => {
+                let value = options
+                    .next()
+                    .ok_or_else(|| RespValue::error("ERR syntax error"))?;
+                entries_read = match parse_integer_argument(value)? {
+                    -1 => None,
+                    value if value < 0 => {
+                        return Err(RespValue::error(
+                            "ERR value for ENTRIESREAD must be positive or -1",
+                        ))
+                    }
+                    value => Some(value as u64),
+                };
+            }
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+    }
+    let id = match arguments[0].as_str() {
+        "$" => stream.map_or(StreamId::MIN, Stream::last_id),
+        id => parse_stream_id(id)?,
+    };
+    Ok((id, entries_read, mkstream))
+}
+
+// XGROUP CREATE key group id|$ [MKSTREAM] [ENTRIESREAD entries-read]
+// XGROUP SETID key group id|$ [ENTRIESREAD entries-read]
+// XGROUP DESTROY key group
+// XGROUP CREATECONSUMER key group consumer
+// XGROUP DELCONSUMER key group consumer
+pub fn handle_xgroup(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("xgroup");
+    }
+    let subcommand = decoded_str[1].to_lowercase();
+    let arity_ok = match subcommand.as_str() {
+        "create" => decoded_str.len() >= 5,
+        "setid" => decoded_str.len() >= 5,
+        "destroy" => decoded_str.len() == 4,
+        "createconsumer" | "delconsumer" => decoded_str.len() == 5,
+        _ => {
+            return RespValue::error(
+                format!(
+                    "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                    decoded_str[1]
+                )
+                .as_str(),
+            )
+        }
+    };
+    if !arity_ok {
+        return wrong_number_of_arguments(format!("xgroup|{}", subcommand).as_str());
+    }
+    let key = &decoded_str[2];
+    let group_name = &decoded_str[3];
+
+    let exists = match get_stream(keyspace, key) {
+        Ok(stream) => stream.is_some(),
+        Err(error) => return error,
+    };
+    if subcommand == "create" {
+        let (id, entries_read, mkstream) = match parse_group_position(
+            get_stream(keyspace, key).unwrap_or(None),
+            &decoded_str[4..],
+            true,
+        ) {
+            Ok(position) => position,
+            Err(error) => return error,
+        };
+        if !exists {
+            if !mkstream {
+                return RespValue::error(
+                    "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+                );
+            }
+            keyspace.insert(key.to_string(), RedisValue::Stream(Stream::new()), None);
+        }
+        let Ok(Some(stream)) = get_stream_mut(keyspace, key) else {
+            return RespValue::error(WRONGTYPE_ERROR);
+        };
+        return if stream.create_group(group_name, ConsumerGroup::new(id, entries_read)) {
+            keyspace.notify(EventClass::Stream, "xgroup-create", key);
+            RespValue::ok()
+        } else {
+            RespValue::error("BUSYGROUP Consumer Group name already exists")
+        };
+    }
+    if !exists {
+        return RespValue::error(
+            "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+        );
+    }
+
+    let position = if subcommand == "setid" {
+        match parse_group_position(
+            get_stream(keyspace, key).unwrap_or(None),
+            &decoded_str[4..],
+            false,
+        ) {
+            Ok(position) => Some(position),
+            Err(error) => return error,
+        }
+    } else {
+        None
+    };
+    let Ok(Some(stream)) = get_stream_mut(keyspace, key) else {
+        return RespValue::error(WRONGTYPE_ERROR);
+    };
+    if subcommand == "destroy" {
+        let destroyed = stream.groups.remove(group_name.as_str()).is_some();
+        if destroyed {
+            keyspace.notify(EventClass::Stream, "xgroup-destroy", key);
+        }
+        return RespValue::Integer(destroyed as i64);
+    }
+    let Some(group) = stream.groups.get_mut(group_name.as_str()) else {
+        return no_such_group(key, group_name);
+    };
+    let (response, event) = match (subcommand.as_str(), position) {
+        ("setid", Some((id, entries_read, _))) => {
+            group.last_id = id;
+            group.entries_read = entries_read;
+            (RespValue::ok(), Some("xgroup-setid"))
+        }
+        ("createconsumer", _) => {
+            let created = group
+                .touch_consumer(&decoded_str[4], now_in_milliseconds())
+                .1;
+            (
+                RespValue::Integer(created as i64),
+                created.then_some("xgroup-createconsumer"),
+            )
+        }
+        _ => match group.consumers.remove(decoded_str[4].as_str()) {
+            Some(consumer) => {
+                for id in &consumer.pending {
+                    group.pending.remove(id);
+                }
+                (
+                    RespValue::Integer(consumer.pending.len() as i64),
+                    Some("xgroup-delconsumer"),
+                )
+            }
+            None => (RespValue::Integer(0), None),
+        },
+    };
+    if let Some(event) = event {
+        keyspace.notify(EventClass::Stream, event, key);
+    }
+    response
+}
+
+// XACK key group id [id ...]
+pub fn handle_xack(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 4 {
+        return wrong_number_of_arguments("xack");
+    }
+    let mut ids: Vec<StreamId> = Vec::with_capacity(decoded_str.len() - 3);
+    for id in &decoded_str[3..] {
+        match parse_stream_id(id) {
+            Ok(id) => ids.push(id),
+            Err(error) => return error,
+        }
+    }
+    let group = match get_stream_mut(keyspace, &decoded_str[1]) {
+        Ok(Some(stream)) => stream.groups.get_mut(decoded_str[2].as_str()),
+        Ok(None) => None,
+        Err(error) => return error,
+    };
+    let Some(group) = group else {
+        return RespValue::Integer(0);
+    };
+    let acknowledged = ids
+        .iter()
+        .filter(|id| group.acknowledge(id).is_some())
+        .count();
+    RespValue::Integer(acknowledged as i64)
+}
+
+// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+pub fn handle_xpending(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("xpending");
+    }
+    let key = &decoded_str[1];
+    let group_name = &decoded_str[2];
+
+    let mut min_idle: Option<u64> = None;
+    let mut idx = 3;
+    if decoded_str
+        .get(idx)
+        .is_some_and(|option| option.eq_ignore_ascii_case("idle"))
+    {
+        match decoded_str
+            .get(idx + 1)
+            .map(|idle| parse_integer_argument(idle))
+        {
+            Some(Ok(idle)) => min_idle = Some(idle.max(0) as u64),
+            Some(Err(error)) => return error,
+            None => return RespValue::error("ERR syntax error"),
+        }
+        idx += 2;
+    }
+    let extended = match decoded_str.len() - idx {
+        0 if min_idle.is_none() => None,
+        3 | 4 => {
+            let start = match parse_range_bound(&decoded_str[idx], true) {
+                Ok(start) => start,
+                Err(error) => return error,
+            };
+            let end = match parse_range_bound(&decoded_str[idx + 1], false) {
+                Ok(end) => end,
+                Err(error) => return error,
+            };
+            let count = match parse_integer_argument(&decoded_str[idx + 2]) {
+                Ok(count) => count.max(0) as usize,
+                Err(error) => return error,
+            };
+            Some((start, end, count, decoded_str.get(idx + 3)))
+        }
+        _ => return RespValue::error("ERR syntax error"),
+    };
+
+    let group = match get_stream(keyspace, key) {
+        Ok(Some(stream)) => stream.groups.get(group_name.as_str()),
+        Ok(None) => None,
+        Err(error) => return error,
+    };
+    let Some(group) = group else {
+        return no_such_key_or_group(key, group_name);
+    };
+
+    let Some((start, end, count, consumer)) = extended else {
+        let (Some(first), Some(last)) = (
+            group.pending.first_key_value(),
+            group.pending.last_key_value(),
+        ) else {
+            return RespValue::Array(vec![
+                RespValue::Integer(0),
+                RespValue::Null,
+                RespValue::Null,
+                RespValue::NullArray,
+            ]);
+        };
+        let consumers: Vec<RespValue> = group
+            .consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| RespValue::bulks([name, &consumer.pending.len().to_string()]))
+            .collect();
+        return RespValue::Array(vec![
+            RespValue::Integer(group.pending.len() as i64),
+            RespValue::bulk(first.0.to_string()),
+            RespValue::bulk(last.0.to_string()),
+            RespValue::Array(consumers),
+        ]);
+    };
+
+    let empty = match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    };
+    if empty {
+        return RespValue::Array(Vec::new());
+    }
+    let now = now_in_milliseconds();
+    let entries: Vec<RespValue> = group
+        .pending
+        .range((start, end))
+        .filter(|(_, entry)| consumer.is_none_or(|consumer| entry.consumer == consumer.as_str()))
+        .filter(|(_, entry)| {
+            min_idle.is_none_or(|min_idle| now.saturating_sub(entry.delivery_time) >= min_idle)
+        })
+        .take(count)
+        .map(|(id, entry)| {
+            RespValue::Array(vec![
+                RespValue::bulk(id.to_string()),
+                RespValue::bulk(&entry.consumer),
+                RespValue::Integer(now.saturating_sub(entry.delivery_time) as i64),
+                RespValue::Integer(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+    RespValue::Array(entries)
+}
+
+fn parse_min_idle(input: &str, command: &str) -> Result<u64, RespValue> {
+    match input.parse::<i64>() {
+        Ok(min_idle) => Ok(min_idle.max(0) as u64),
+        Err(_) => Err(RespValue::error(
+            format!("ERR Invalid min-idle-time argument for {}", command).as_str(),
+        )),
+    }
+}
+
+// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+//     [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID]
+//     [LASTID lastid]
+pub fn handle_xclaim(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 6 {
+        return wrong_number_of_arguments("xclaim");
+    }
+    let key = &decoded_str[1];
+    let group_name = &decoded_str[2];
+    let consumer = &decoded_str[3];
+    let min_idle = match parse_min_idle(&decoded_str[4], "XCLAIM") {
+        Ok(min_idle) => min_idle,
+        Err(error) => return error,
+    };
+
+    let mut idx = 5;
+    let mut ids: Vec<StreamId> = Vec::new();
+    while let Some(id) = decoded_str.get(idx).and_then(|id| StreamId::parse(id, 0)) {
+        ids.push(id);
+        idx += 1;
+    }
+    if ids.is_empty() {
+        return RespValue::error(INVALID_ID_ERROR);
+    }
+
+    let now = now_in_milliseconds();
+    let mut options = ClaimOptions {
+        min_idle,
+        delivery_time: now,
+        retry_count: None,
+        force: false,
+        just_id: false,
+    };
+    let mut last_id: Option<StreamId> = None;
+    while idx < decoded_str.len() {
+        let option = decoded_str[idx].to_lowercase();
+        let value = decoded_str.get(idx + 1);
+        match (option.as_str(), value) {
+            ("force", _) => options.force = true,
+            ("justid", _) => options.just_id = true,
+            ("idle", Some(value)) => match parse_integer_argument(value) {
+                Ok(idle) => options.delivery_time = now.saturating_sub(idle.max(0) as u64),
+                Err(_) => return RespValue::error("ERR Invalid IDLE option argument for XCLAIM"),
+            },
+            ("time", Some(value)) => match parse_integer_argument(value) {
+                Ok(time) => options.delivery_time = time.max(0) as u64,
+                Err(_) => return RespValue::error("ERR Invalid TIME option argument for XCLAIM"),
+            },
+            ("retrycount", Some(value)) => match parse_integer_argument(value) {
+                Ok(count) => options.retry_count = Some(count.max(0) as u64),
+                Err(_) => {
+                    return RespValue::error("ERR Invalid RETRYCOUNT option argument for XCLAIM")
+                }
+            },
+            ("lastid", Some(value)) => match parse_stream_id(value) {
+                Ok(id) => last_id = Some(id),
+                Err(error) => return error,
+            },
+            _ => {
+                return RespValue::error(
+                    format!("ERR Unrecognized XCLAIM option '{}'", decoded_str[idx]).as_str(),
+                )
+            }
+        }
+        idx += if matches!(option.as_str(), "force" | "justid") {
+            1
+        } else {
+            2
+        };
+    }
+    // Entries are never considered pending for the future.
+    options.delivery_time = options.delivery_time.min(now);
+
+    let stream = match get_stream_mut(keyspace, key) {
+        Ok(Some(stream)) if stream.groups.contains_key(group_name.as_str()) => stream,
+        Ok(_) => return no_such_key_or_group(key, group_name),
+        Err(error) => return error,
+    };
+    let mut effects: Vec<Vec<String>> = Vec::new();
+    let group = stream.groups.get_mut(group_name.as_str()).unwrap();
+    if let Some(consumer) = group.consumers.get_mut(consumer.as_str()) {
+        consumer.seen_time = now;
+    }
+    let last_id_moved = last_id.is_some_and(|last_id| last_id > group.last_id);
+    if let Some(last_id) = last_id.filter(|_| last_id_moved) {
+        group.last_id = last_id;
+    }
+
+    let mut replies: Vec<RespValue> = Vec::new();
+    for id in ids {
+        match stream.claim(group_name, consumer, id, &options, now) {
+            ClaimOutcome::Claimed(entry) => {
+                if options.just_id {
+                    replies.push(RespValue::bulk(id.to_string()));
+                } else if let Some(fields) = stream.entries.get(&id) {
+                    replies.push(encode_entry(&id, fields));
+                }
+                let group = &stream.groups[group_name.as_str()];
+                effects.push(claim_effect(key, group_name, group, consumer, &id, &entry));
+            }
+            ClaimOutcome::Deleted(entry) => {
+                let group = &stream.groups[group_name.as_str()];
+                effects.push(claim_effect(key, group_name, group, consumer, &id, &entry));
+            }
+            ClaimOutcome::Skipped => {}
+        }
+    }
+    if effects.is_empty() && last_id_moved {
+        effects.push(set_id_effect(
+            key,
+            group_name,
+            &stream.groups[group_name.as_str()],
+        ));
+    }
+    for effect in effects {
+        keyspace.propagate(effect);
+    }
+    RespValue::Array(replies)
+}
+
+// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+pub fn handle_xautoclaim(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 6 {
+        return wrong_number_of_arguments("xautoclaim");
+    }
+    let key = &decoded_str[1];
+    let group_name = &decoded_str[2];
+    let consumer = &decoded_str[3];
+    let min_idle = match parse_min_idle(&decoded_str[4], "XAUTOCLAIM") {
+        Ok(min_idle) => min_idle,
+        Err(error) => return error,
+    };
+    let start = match parse_range_bound(&decoded_str[5], true) {
+        Ok(start) => start,
+        Err(error) => return error,
+    };
+
+    let mut count: usize = 100;
+    let mut just_id = false;
+    let mut idx = 6;
+    while idx < decoded_str.len() {
+        match decoded_str[idx].to_lowercase().as_str() {
+            "justid" => {
+                just_id = true;
+                idx += 1;
+            }
+            "count" if idx + 1 < decoded_str.len() => {
+                match parse_integer_argument(&decoded_str[idx + 1]) {
+                    // Same bound as Redis, which scans up to ten times COUNT
+                    // entries.
+                    Ok(value) if (1..=i64::MAX / 10).contains(&value) => count = value as usize,
+                    Ok(_) => return RespValue::error("ERR COUNT must be > 0"),
+                    Err(error) => return error,
+                }
+                idx += 2;
+            }
+            _ => return RespValue::error("ERR syntax error"),
+        }
+    }
+
+    let stream = match get_stream_mut(keyspace, key) {
+        Ok(Some(stream)) if stream.groups.contains_key(group_name.as_str()) => stream,
+        Ok(_) => return no_such_key_or_group(key, group_name),
+        Err(error) => return error,
+    };
+    let now = now_in_milliseconds();
+    let options = ClaimOptions {
+        min_idle,
+        delivery_time: now,
+        retry_count: None,
+        force: false,
+        just_id,
+    };
+    let group = stream.groups.get_mut(group_name.as_str()).unwrap();
+    if let Some(consumer) = group.consumers.get_mut(consumer.as_str()) {
+        consumer.seen_time = now;
+    }
+    let candidates: Vec<StreamId> = group
+        .pending
+        .range((start, Bound::Unbounded))
+        .map(|(id, _)| *id)
+        .take(count * 10 + 1)
+        .collect();
+
+    let mut claimed: Vec<RespValue> = Vec::new();
+    let mut deleted: Vec<RespValue> = Vec::new();
+    let mut effects: Vec<Vec<String>> = Vec::new();
+    let mut next = StreamId::MIN;
+    let mut attempts = count * 10;
+    let mut remaining = count;
+    let mut candidates = candidates.into_iter();
+    for id in candidates.by_ref() {
+        if attempts == 0 || remaining == 0 {
+            next = id;
+            break;
+        }
+        attempts -= 1;
+        match stream.claim(group_name, consumer, id, &options, now) {
+            ClaimOutcome::Claimed(entry) => {
+                remaining -= 1;
+                if just_id {
+                    claimed.push(RespValue::bulk(id.to_string()));
+                } else if let Some(fields) = stream.entries.get(&id) {
+                    claimed.push(encode_entry(&id, fields));
+                }
+                let group = &stream.groups[group_name.as_str()];
+                effects.push(claim_effect(key, group_name, group, consumer, &id, &entry));
+            }
+            ClaimOutcome::Deleted(entry) => {
+                deleted.push(RespValue::bulk(id.to_string()));
+                let group = &stream.groups[group_name.as_str()];
+                effects.push(claim_effect(key, group_name, group, consumer, &id, &entry));
+            }
+            ClaimOutcome::Skipped => {}
+        }
+    }
+    for effect in effects {
+        keyspace.propagate(effect);
+    }
+    RespValue::Array(vec![
+        RespValue::bulk(next.to_string()),
+        RespValue::Array(claimed),
+        RespValue::Array(deleted),
+    ])
+}
+
+fn encode_first_or_last_entry(stream: &Stream, last: bool) -> RespValue {
+    let entry = if last {
+        stream.entries.last_key_value()
+    } else {
+        stream.entries.first_key_value()
+    };
+    match entry {
+        Some((id, fields)) => encode_entry(id, fields),
+        None => RespValue::Null,
+    }
+}
+
+// Header fields shared by XINFO STREAM and its FULL form.
+fn encode_stream_summary(stream: &Stream) -> Vec<RespValue> {
+    let radix_tree_keys = stream.len().div_ceil(STREAM_NODE_MAX_ENTRIES);
+    vec![
+        RespValue::bulk("length"),
+        RespValue::Integer(stream.len() as i64),
+        RespValue::bulk("radix-tree-keys"),
+        RespValue::Integer(radix_tree_keys as i64),
+        RespValue::bulk("radix-tree-nodes"),
+        RespValue::Integer(radix_tree_keys as i64 + 1),
+        RespValue::bulk("last-generated-id"),
+        RespValue::bulk(stream.last_id.to_string()),
+        RespValue::bulk("max-deleted-entry-id"),
+        RespValue::bulk(stream.max_deleted_entry_id.to_string()),
+        RespValue::bulk("entries-added"),
+        RespValue::Integer(stream.entries_added as i64),
+        RespValue::bulk("recorded-first-entry-id"),
+        RespValue::bulk(stream.first_id().to_string()),
+    ]
+}
+
+fn encode_full_stream_info(stream: &Stream, count: Option<usize>) -> RespValue {
+    let limit = count.unwrap_or(usize::MAX);
+    let mut reply = encode_stream_summary(stream);
+    let entries = stream.range(Bound::Unbounded, Bound::Unbounded, false, count);
+    reply.push(RespValue::bulk("entries"));
+    reply.push(encode_entries(&entries));
+
+    let groups: Vec<RespValue> = stream
+        .groups
+        .iter()
+        .map(|(name, group)| {
+            let pending: Vec<RespValue> = group
+                .pending
+                .iter()
+                .take(limit)
+                .map(|(id, entry)| {
+                    RespValue::Array(vec![
+                        RespValue::bulk(id.to_string()),
+                        RespValue::bulk(&entry.consumer),
+                        RespValue::Integer(entry.delivery_time as i64),
+                        RespValue::Integer(entry.delivery_count as i64),
+                    ])
+                })
+                .collect();
+            let consumers: Vec<RespValue> = group
+                .consumers
+                .iter()
+                .map(|(consumer_name, consumer)| {
+                    let pending: Vec<RespValue> = consumer
+                        .pending
+                        .iter()
+                        .take(limit)
+                        .filter_map(|id| group.pending.get(id).map(|entry| (id, entry)))
+                        .map(|(id, entry)| {
+                            RespValue::Array(vec![
+                                RespValue::bulk(id.to_string()),
+                                RespValue::Integer(entry.delivery_time as i64),
+                                RespValue::Integer(entry.delivery_count as i64),
+                            ])
+                        })
+                        .collect();
+                    RespValue::Array(vec![
+                        RespValue::bulk("name"),
+                        RespValue::bulk(consumer_name),
+                        RespValue::bulk("seen-time"),
+                        RespValue::Integer(consumer.seen_time as i64),
+                        RespValue::bulk("active-time"),
+                        RespValue::Integer(consumer.active_time.map_or(-1, |time| time as i64)),
+                        RespValue::bulk("pel-count"),
+                        RespValue::Integer(consumer.pending.len() as i64),
+                        RespValue::bulk("pending"),
+                        RespValue::Array(pending),
+                    ])
+                })
+                .collect();
+            RespValue::Array(vec![
+                RespValue::bulk("name"),
+                RespValue::bulk(name),
+                RespValue::bulk("last-delivered-id"),
+                RespValue::bulk(group.last_id.to_string()),
+                RespValue::bulk("entries-read"),
+                encode_optional_integer(group.entries_read),
+                RespValue::bulk("lag"),
+                encode_optional_integer(stream.lag(group)),
+                RespValue::bulk("pel-count"),
+                RespValue::Integer(group.pending.len() as i64),
+                RespValue::bulk("pending"),
+                RespValue::Array(pending),
+                RespValue::bulk("consumers"),
+                RespValue::Array(consumers),
+            ])
+        })
+        .collect();
+    reply.push(RespValue::bulk("groups"));
+    reply.push(RespValue::Array(groups));
+    RespValue::Array(reply)
+}
+
+// XINFO STREAM key [FULL [COUNT count]]
+// XINFO GROUPS key
+// XINFO CONSUMERS key group
+pub fn handle_xinfo(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("xinfo");
+    }
+    let subcommand = decoded_str[1].to_lowercase();
+    let arity_ok = match subcommand.as_str() {
+        "stream" => decoded_str.len() >= 3,
+        "groups" => decoded_str.len() == 3,
+        "consumers" => decoded_str.len() == 4,
+        _ => {
+            return RespValue::error(
+                format!(
+                    "ERR unknown subcommand '{}'. Try XINFO HELP.",
+                    decoded_str[1]
+                )
+                .as_str(),
+            )
+        }
+    };
+    if !arity_ok {
+        return wrong_number_of_arguments(format!("xinfo|{}", subcommand).as_str());
+    }
+    let key = &decoded_str[2];
+    let stream = match get_stream(keyspace, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return RespValue::error("ERR no such key"),
+        Err(error) => return error,
+    };
+    let now = now_in_milliseconds();
+
+    match subcommand.as_str() {
+        "stream" => match decoded_str
+            .get(3)
+            .map(|option| option.to_lowercase())
+            .as_deref()
+        {
+            None => {
+                let mut reply = encode_stream_summary(stream);
+                reply.push(RespValue::bulk("groups"));
+                reply.push(RespValue::Integer(stream.groups.len() as i64));
+                reply.push(RespValue::bulk("first-entry"));
+                reply.push(encode_first_or_last_entry(stream, false));
+                reply.push(RespValue::bulk("last-entry"));
+                reply.push(encode_first_or_last_entry(stream, true));
+                RespValue::Array(reply)
+            }
+            Some("full") => {
+                let count = match &decoded_str[4..] {
+                    [] => Some(10),
+                    [option, count] if option.eq_ignore_ascii_case("count") => {
+                        match parse_integer_argument(count) {
+                            Ok(count) if count <= 0 => None,
+                            Ok(count) => Some(count as usize),
+                            Err(error) => return error,
+                        }
+                    }
+                    _ => return RespValue::error("ERR syntax error"),
+                };
+                encode_full_stream_info(stream, count)
+            }
+            Some(_) => RespValue::error("ERR syntax error"),
+        },
+        "groups" => {
+            let groups: Vec<RespValue> = stream
+                .groups
+                .iter()
+                .map(|(name, group)| {
+                    RespValue::Array(vec![
+                        RespValue::bulk("name"),
+                        RespValue::bulk(name),
+                        RespValue::bulk("consumers"),
+                        RespValue::Integer(group.consumers.len() as i64),
+                        RespValue::bulk("pending"),
+                        RespValue::Integer(group.pending.len() as i64),
+                        RespValue::bulk("last-delivered-id"),
+                        RespValue::bulk(group.last_id.to_string()),
+                        RespValue::bulk("entries-read"),
+                        encode_optional_integer(group.entries_read),
+                        RespValue::bulk("lag"),
+                        encode_optional_integer(stream.lag(group)),
+                    ])
+                })
+                .collect();
+            RespValue::Array(groups)
+        }
+        _ => {
+            let group_name = &decoded_str[3];
+            let Some(group) = stream.groups.get(group_name.as_str()) else {
+                return no_such_group(key, group_name);
+            };
+            let consumers: Vec<RespValue> = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| {
+                    let inactive = consumer
+                        .active_time
+                        .map_or(-1, |time| now.saturating_sub(time) as i64);
+                    RespValue::Array(vec![
+                        RespValue::bulk("name"),
+                        RespValue::bulk(name),
+                        RespValue::bulk("pending"),
+                        RespValue::Integer(consumer.pending.len() as i64),
+                        RespValue::bulk("idle"),
+                        RespValue::Integer(now.saturating_sub(consumer.seen_time) as i64),
+                        RespValue::bulk("inactive"),
+                        RespValue::Integer(inactive),
+                    ])
+                })
+                .collect();
+            RespValue::Array(consumers)
+        }
+    }
+}
+
+struct XreadgroupArguments<'a> {
+    group_name: &'a str,
+    consumer: &'a str,
+    count: Option<usize>,
+    block: Option<Option<Instant>>,
+    no_ack: bool,
+    keys: &'a [Argument],
+    // None stands for `>`, new entries.
+    starts: Vec<Option<StreamId>>,
+}
+
+fn parse_xreadgroup(decoded_str: &[Argument]) -> Result<XreadgroupArguments<'_>, RespValue> {
+    if decoded_str.len() < 7 {
+        return Err(wrong_number_of_arguments("xreadgroup"));
+    }
+    let mut group: Option<(&str, &str)> = None;
+    let mut count: Option<usize> = None;
+    let mut block: Option<Option<Instant>> = None;
+    let mut no_ack = false;
+    let mut idx = 1;
+    let streams_idx = loop {
+        match decoded_str
+            .get(idx)
+            .map(|option| option.to_lowercase())
+            .as_deref()
+        {
+            Some("group") if idx + 2 < decoded_str.len() => {
+                group = Some((decoded_str[idx + 1].as_str(), decoded_str[idx + 2].as_str()));
+                idx += 3;
+            }
+            Some("count") if idx + 1 < decoded_str.len() => {
+                match parse_integer_argument(&decoded_str[idx + 1])? {
+                    value if value > 0 => count = Some(value as usize),
+                    _ => count = None,
+                }
+                idx += 2;
+            }
+            Some("block") if idx + 1 < decoded_str.len() => {
+                block = Some(parse_block_milliseconds(&decoded_str[idx + 1])?);
+                idx += 2;
+            }
+            Some("noack") => {
+                no_ack = true;
+                idx += 1;
+            }
+            Some("streams") => break idx + 1,
+            _ => return Err(RespValue::error("ERR syntax error")),
+        }
+    };
+    let Some((group_name, consumer)) = group else {
+        return Err(RespValue::error("ERR Missing GROUP option for XREADGROUP"));
+    };
+
+    let arguments = &decoded_str[streams_idx..];
+    if arguments.is_empty() || !arguments.len().is_multiple_of(2) {
+        return Err(RespValue::error(
+            "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.",
+        ));
+    }
+    let (keys, ids) = arguments.split_at(arguments.len() / 2);
+    let mut starts: Vec<Option<StreamId>> = Vec::with_capacity(ids.len());
+    for id in ids {
+        match id.as_str() {
+            ">" => starts.push(None),
+            "$" => {
+                return Err(RespValue::error(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.",
+                ))
+            }
+            id => starts.push(Some(parse_stream_id(id)?)),
+        }
+    }
+    // Only reads of new entries can block, history is served right away.
+    if starts.iter().any(Option::is_some) {
+        block = None;
+    }
+    Ok(XreadgroupArguments {
+        group_name,
+        consumer,
+        count,
+        block,
+        no_ack,
+        keys,
+        starts,
+    })
+}
+
+fn no_such_key_or_group_to_read(key: &str, group: &str) -> RespValue {
+    RespValue::error(
+        format!(
+            "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+            key, group
+        )
+        .as_str(),
+    )
+}
+
+// Delivers entries to the consumer, one reply per stream. What changed in
+// the groups is recorded as effects, only once every stream has been read.
+fn read_group(
+    keyspace: &mut Keyspace,
+    arguments: &XreadgroupArguments,
+) -> Result<Vec<RespValue>, RespValue> {
+    let XreadgroupArguments {
+        group_name,
+        consumer,
+        count,
+        no_ack,
+        ..
+    } = *arguments;
+    let now = now_in_milliseconds();
+    let mut replies: Vec<RespValue> = Vec::new();
+    let mut effects: Vec<Vec<String>> = Vec::new();
+    let mut consumer_created_in: Vec<&Argument> = Vec::new();
+    for (key, start) in arguments.keys.iter().zip(&arguments.starts) {
+        let Some(stream) = get_stream_mut(keyspace, key)? else {
+            return Err(no_such_key_or_group_to_read(key, group_name));
+        };
+        let Some(group) = stream.groups.get_mut(group_name) else {
+            return Err(no_such_key_or_group_to_read(key, group_name));
+        };
+        if group.touch_consumer(consumer, now).1 {
+            consumer_created_in.push(key);
+            effects.push(
+                ["XGROUP", "CREATECONSUMER", key, group_name, consumer]
+                    .iter()
+                    .map(|argument| argument.to_string())
+                    .collect(),
+            );
+        }
+
+        match start {
+            None => {
+                let entries = stream.deliver_new(group_name, consumer, count, no_ack, now);
+                if entries.is_empty() {
+                    continue;
+                }
+                let group = &stream.groups[group_name];
+                if !no_ack {
+                    for (id, _) in &entries {
+                        effects.push(claim_effect(
+                            key,
+                            group_name,
+                            group,
+                            consumer,
+                            id,
+                            &group.pending[id],
+                        ));
+                    }
+                }
+                effects.push(set_id_effect(key, group_name, group));
+                replies.push(RespValue::Array(vec![
+                    RespValue::bulk(key),
+                    encode_entries(&entries),
+                ]));
+            }
+            Some(start) => {
+                let entries = stream.deliver_history(group_name, consumer, *start, count, now);
+                let entries: Vec<RespValue> = entries
+                    .iter()
+                    .map(|(id, fields)| match fields {
+                        Some(fields) => encode_entry(id, fields),
+                        None => RespValue::Array(vec![
+                            RespValue::bulk(id.to_string()),
+                            RespValue::NullArray,
+                        ]),
+                    })
+                    .collect();
+                replies.push(RespValue::Array(vec![
+                    RespValue::bulk(key),
+                    RespValue::Array(entries),
+                ]));
+            }
+        }
+    }
+    for effect in effects {
+        keyspace.propagate(effect);
+    }
+    for key in consumer_created_in {
+        keyspace.notify(EventClass::Stream, "xgroup-createconsumer", key);
+    }
+    Ok(replies)
+}
+
+// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+//     STREAMS key [key ...] id [id ...]
+pub async fn handle_xreadgroup(
+    state: &ServerState,
+    db: usize,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let arguments = match parse_xreadgroup(decoded_str) {
+        Ok(arguments) => arguments,
+        Err(error) => return error,
+    };
+
+    loop {
+        let notified = state.keyspace_written.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let replies = {
+            let mut keyspace = state.lock_keyspace(db);
+            let replies = match read_group(&mut keyspace, &arguments) {
+                Ok(replies) => replies,
+                Err(error) => return error,
+            };
+            state.propagate_effects(&mut keyspace);
+            state.publish_notifications(&mut keyspace);
+            replies
+        };
+        if !replies.is_empty() {
+            return RespValue::Array(replies);
+        }
+
+        match arguments.block {
+            None => return RespValue::NullArray,
+            Some(Some(deadline)) => {
+                if timeout_at(deadline, notified).await.is_err() {
+                    return RespValue::NullArray;
+                }
+            }
+            Some(None) => notified.await,
+        }
+    }
+}
+
+// XREADGROUP inside a transaction, BLOCK is ignored.
+pub fn handle_xreadgroup_without_blocking(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+) -> RespValue {
+    match parse_xreadgroup(decoded_str).and_then(|arguments| read_group(keyspace, &arguments)) {
+        Ok(replies) if replies.is_empty() => RespValue::NullArray,
+        Ok(replies) => RespValue::Array(replies),
+        Err(error) => error,
+    }
+}
@@ -0,0 +1,708 @@
+use std::{
+    borrow::Cow,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    keyspace::Keyspace,
+    notify::EventClass,
+    parse_integer_argument,
+    redis_value::{parse_canonical_i64, RedisValue, WRONGTYPE_ERROR},
+    resp::{Argument, RespValue},
+    sorted_set::parse_score,
+    wrong_number_of_arguments,
+};
+
+// Same limit as Redis' default `proto-max-bulk-len`.
+pub const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum RedisString {
+    // Canonical integers are kept as such, like Redis' `int` encoding, which
+    // spares counters an allocation and a parse on every increment.
+    Integer(i64),
+    // Any bytes, values are binary safe.
+    Raw(Vec<u8>),
+}
+
+impl RedisString {
+    pub fn new(value: Vec<u8>) -> Self {
+        match parse_integer_bytes(&value) {
+            Some(integer) => RedisString::Integer(integer),
+            None => RedisString::Raw(value),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Cow<'_, [u8]> {
+        match self {
+            RedisString::Integer(integer) => Cow::Owned(integer.to_string().into_bytes()),
+            RedisString::Raw(value) => Cow::Borrowed(value),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            RedisString::Integer(integer) => integer.to_string().into_bytes(),
+            RedisString::Raw(value) => value,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RedisString::Integer(integer) => integer.to_string().len(),
+            RedisString::Raw(value) => value.len(),
+        }
+    }
+
+    // Heap memory taken by the value, integers need none.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            RedisString::Integer(_) => 0,
+            RedisString::Raw(value) => value.capacity(),
+        }
+    }
+
+    // Bytes to edit in place, giving up the integer encoding if needed.
+    pub fn raw_bytes_mut(&mut self) -> &mut Vec<u8> {
+        if let RedisString::Integer(integer) = self {
+            *self = RedisString::Raw(integer.to_string().into_bytes());
+        }
+        match self {
+            RedisString::Raw(value) => value,
+            RedisString::Integer(_) => unreachable!("integer was converted above"),
+        }
+    }
+
+    fn as_integer(&self) -> Option<i64> {
+        match self {
+            RedisString::Integer(integer) => Some(*integer),
+            RedisString::Raw(value) => parse_integer_bytes(value),
+        }
+    }
+}
+
+fn parse_integer_bytes(bytes: &[u8]) -> Option<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(parse_canonical_i64)
+}
+
+pub fn get_string<'a>(
+    keyspace: &'a Keyspace,
+    key: &str,
+) -> Result<Option<&'a RedisString>, RespValue> {
+    match keyspace.get(key) {
+        None => Ok(None),
+        Some(RedisValue::String(value)) => Ok(Some(value)),
+        Some(_) => Err(RespValue::error(WRONGTYPE_ERROR)),
+    }
+}
+
+// Replaces the value of `key` while keeping its time to live, as every
+// command editing a string in place does.
+fn update_string(keyspace: &mut Keyspace, key: &str, value: RedisString) {
+    match keyspace.get_mut(key) {
+        Some(existing) => *existing = RedisValue::String(value),
+        None => keyspace.insert(key.to_string(), RedisValue::String(value), None),
+    }
+}
+
+fn encode_optional_string(value: Option<&RedisString>) -> RespValue {
+    match value {
+        Some(value) => RespValue::bulk(value.as_bytes()),
+        None => RespValue::Null,
+    }
+}
+
+fn check_string_length(offset: usize, append: usize) -> Result<(), RespValue> {
+    if offset.saturating_add(append) > MAX_STRING_LENGTH {
+        Err(RespValue::error(
+            "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid_expire_time(command: &str) -> RespValue {
+    RespValue::error(format!("ERR invalid expire time in '{}' command", command))
+}
+
+fn unix_time_in_milliseconds() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+// Turns the argument of EX/PX/EXAT/PXAT into a time to live. `None` means the
+// absolute time is already in the past, in which case the key goes away.
+fn parse_expire_time(
+    command: &str,
+    option: &str,
+    value: &str,
+) -> Result<Option<Duration>, RespValue> {
+    let value = parse_integer_argument(value)?;
+    let milliseconds = match option {
+        "ex" | "exat" => value.checked_mul(1000),
+        _ => Some(value),
+    };
+    let milliseconds = match milliseconds {
+        Some(milliseconds) if value > 0 => milliseconds,
+        _ => return Err(invalid_expire_time(command)),
+    };
+    let ttl = match option {
+        "exat" | "pxat" => milliseconds - unix_time_in_milliseconds(),
+        _ => milliseconds,
+    };
+    Ok((ttl > 0).then(|| Duration::from_millis(ttl as u64)))
+}
+
+// SET key value [EX seconds | PX milliseconds]
+pub fn handle_set(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    let ttl = match decoded_str.len() {
+        0..=2 => return wrong_number_of_arguments("set"),
+        3 => None,
+        5 => {
+            let option = decoded_str[3].to_lowercase();
+            if option != "ex" && option != "px" {
+                return RespValue::error("ERR syntax error");
+            }
+            match parse_expire_time("set", &option, &decoded_str[4]) {
+                Ok(ttl) => ttl,
+                Err(error) => return error,
+            }
+        }
+        _ => return RespValue::error("ERR syntax error"),
+    };
+    let key = &decoded_str[1];
+    keyspace.insert(
+        key.to_string(),
+        RedisValue::String(RedisString::new(decoded_str[2].as_bytes().to_vec())),
+        ttl,
+    );
+    keyspace.notify(EventClass::String, "set", key);
+    if ttl.is_some() {
+        keyspace.notify(EventClass::Generic, "expire", key);
+    }
+    RespValue::ok()
+}
+
+// GET key
+pub fn handle_get(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("get");
+    }
+    match get_string(keyspace, &decoded_str[1]) {
+        Ok(None) => {
+            keyspace.notify(EventClass::KeyMiss, "keymiss", &decoded_str[1]);
+            RespValue::Null
+        }
+        Ok(value) => encode_optional_string(value),
+        Err(error) => error,
+    }
+}
+
+fn increment_by(keyspace: &mut Keyspace, key: &str, increment: i64) -> RespValue {
+    let current = match get_string(keyspace, key) {
+        Ok(Some(value)) => match value.as_integer() {
+            Some(current) => current,
+            None => return RespValue::error("ERR value is not an integer or out of range"),
+        },
+        Ok(None) => 0,
+        Err(error) => return error,
+    };
+    let Some(updated) = current.checked_add(increment) else {
+        return RespValue::error("ERR increment or decrement would overflow");
+    };
+    update_string(keyspace, key, RedisString::Integer(updated));
+    keyspace.notify(EventClass::String, "incrby", key);
+    RespValue::Integer(updated)
+}
+
+pub fn handle_incr(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("incr");
+    }
+    increment_by(keyspace, &decoded_str[1], 1)
+}
+
+pub fn handle_decr(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("decr");
+    }
+    increment_by(keyspace, &decoded_str[1], -1)
+}
+
+pub fn handle_incrby(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("incrby");
+    }
+    match parse_integer_argument(&decoded_str[2]) {
+        Ok(increment) => increment_by(keyspace, &decoded_str[1], increment),
+        Err(error) => error,
+    }
+}
+
+pub fn handle_decrby(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("decrby");
+    }
+    match parse_integer_argument(&decoded_str[2]) {
+        Ok(i64::MIN) => RespValue::error("ERR decrement would overflow"),
+        Ok(decrement) => increment_by(keyspace, &decoded_str[1], -decrement),
+        Err(error) => error,
+    }
+}
+
+// Formats INCRBYFLOAT results the way Redis' human friendly `%.17Lf` does:
+// plain decimal notation that never switches to an exponent, at most 17
+// decimals and no trailing zeros.
+fn format_human_friendly(value: f64) -> String {
+    let shortest = format!("{}", value);
+    match shortest.split_once('.') {
+        Some((_, fraction)) if fraction.len() > 17 => {
+            let rounded = format!("{:.17}", value);
+            rounded
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        }
+        _ => shortest,
+    }
+}
+
+pub fn handle_incrbyfloat(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("incrbyfloat");
+    }
+    let key = &decoded_str[1];
+    let current = match get_string(keyspace, key) {
+        Ok(Some(value)) => match std::str::from_utf8(&value.as_bytes())
+            .ok()
+            .and_then(parse_score)
+        {
+            Some(current) => current,
+            None => return RespValue::error("ERR value is not a valid float"),
+        },
+        Ok(None) => 0.0,
+        Err(error) => return error,
+    };
+    let Some(increment) = parse_score(&decoded_str[2]) else {
+        return RespValue::error("ERR value is not a valid float");
+    };
+    let updated = current + increment;
+    if !updated.is_finite() {
+        return RespValue::error("ERR increment would produce NaN or Infinity");
+    }
+    let formatted = format_human_friendly(updated);
+    let response = RespValue::bulk(&formatted);
+    update_string(keyspace, key, RedisString::new(formatted.into_bytes()));
+    keyspace.notify(EventClass::String, "incrbyfloat", key);
+    response
+}
+
+pub fn handle_append(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("append");
+    }
+    let key = &decoded_str[1];
+    let mut value = match get_string(keyspace, key) {
+        Ok(Some(value)) => value.clone().into_bytes(),
+        Ok(None) => Vec::new(),
+        Err(error) => return error,
+    };
+    if let Err(error) = check_string_length(value.len(), decoded_str[2].len()) {
+        return error;
+    }
+    value.extend_from_slice(decoded_str[2].as_bytes());
+    let len = value.len();
+    update_string(keyspace, key, RedisString::new(value));
+    keyspace.notify(EventClass::String, "append", key);
+    RespValue::Integer(len as i64)
+}
+
+pub fn handle_strlen(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("strlen");
+    }
+    match get_string(keyspace, &decoded_str[1]) {
+        Ok(value) => RespValue::Integer(value.map_or(0, RedisString::len) as i64),
+        Err(error) => error,
+    }
+}
+
+// Resolves the inclusive `start`/`end` range of GETRANGE and BITCOUNT-like
+// commands over `len` units, negative indexes counting from the end. `None`
+// when the range is empty.
+pub fn clamp_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    if start < 0 && end < 0 && start > end {
+        return None;
+    }
+    let start = if start < 0 { len + start } else { start }.max(0);
+    let end = if end < 0 { len + end } else { end }.max(0).min(len - 1);
+    if len == 0 || start > end {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+pub fn handle_getrange(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("getrange");
+    }
+    let (start, end) = match (
+        parse_integer_argument(&decoded_str[2]),
+        parse_integer_argument(&decoded_str[3]),
+    ) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(error), _) | (_, Err(error)) => return error,
+    };
+    let bytes = match get_string(keyspace, &decoded_str[1]) {
+        Ok(Some(value)) => value.as_bytes(),
+        Ok(None) => return RespValue::bulk(b""),
+        Err(error) => return error,
+    };
+    match clamp_range(start, end, bytes.len()) {
+        Some((start, end)) => RespValue::bulk(&bytes[start..=end]),
+        None => RespValue::bulk(b""),
+    }
+}
+
+pub fn handle_setrange(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments("setrange");
+    }
+    let key = &decoded_str[1];
+    let patch = decoded_str[3].as_bytes();
+    let offset = match parse_integer_argument(&decoded_str[2]) {
+        Ok(offset) if offset >= 0 => offset as usize,
+        Ok(_) => return RespValue::error("ERR offset is out of range"),
+        Err(error) => return error,
+    };
+    let mut bytes = match get_string(keyspace, key) {
+        Ok(Some(value)) => value.clone().into_bytes(),
+        Ok(None) => Vec::new(),
+        Err(error) => return error,
+    };
+    // Setting nothing leaves the key as it is, and doesn't create it.
+    if patch.is_empty() {
+        return RespValue::Integer(bytes.len() as i64);
+    }
+    if let Err(error) = check_string_length(offset, patch.len()) {
+        return error;
+    }
+    // Whatever lies between the end of the string and the offset is zero
+    // padded.
+    if bytes.len() < offset + patch.len() {
+        bytes.resize(offset + patch.len(), 0);
+    }
+    bytes[offset..offset + patch.len()].copy_from_slice(patch);
+    let len = bytes.len();
+    update_string(keyspace, key, RedisString::new(bytes));
+    keyspace.notify(EventClass::String, "setrange", key);
+    RespValue::Integer(len as i64)
+}
+
+pub fn handle_getdel(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 2 {
+        return wrong_number_of_arguments("getdel");
+    }
+    let key = &decoded_str[1];
+    let response = match get_string(keyspace, key) {
+        Ok(value) => encode_optional_string(value),
+        Err(error) => return error,
+    };
+    if keyspace.remove(key).is_some() {
+        keyspace.notify(EventClass::Generic, "del", key);
+    }
+    response
+}
+
+pub fn handle_getex(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("getex");
+    }
+    let key = &decoded_str[1];
+
+    // `None` leaves the time to live alone, `Some(None)` is PERSIST.
+    let mut expiration: Option<Option<Duration>> = None;
+    let mut expired = false;
+    let mut options = decoded_str[2..].iter();
+    while let Some(option) = options.next() {
+        let option = option.to_lowercase();
+        if expiration.is_some() || expired {
+            return RespValue::error("ERR syntax error");
+        }
+        match (option.as_str(), options.next()) {
+            ("persist", None) => expiration = Some(None),
+            ("ex" | "px" | "exat" | "pxat", Some(value)) => {
+                match parse_expire_time("getex", &option, value) {
+                    Ok(Some(ttl)) => expiration = Some(Some(ttl)),
+                    Ok(None) => expired = true,
+                    Err(error) => return error,
+                }
+            }
+            _ => return RespValue::error("ERR syntax error"),
+        }
+    }
+
+    let value = match get_string(keyspace, key) {
+        Ok(Some(value)) => value.clone(),
+        Ok(None) => return RespValue::Null,
+        Err(error) => return error,
+    };
+    let response = encode_optional_string(Some(&value));
+    if expired {
+        keyspace.remove(key);
+        keyspace.notify(EventClass::Generic, "del", key);
+    } else if let Some(ttl) = expiration {
+        let had_ttl = keyspace.expiration(key).is_some();
+        keyspace.insert(key.to_string(), RedisValue::String(value), ttl);
+        if ttl.is_some() {
+            keyspace.notify(EventClass::Generic, "expire", key);
+        } else if had_ttl {
+            keyspace.notify(EventClass::Generic, "persist", key);
+        }
+    }
+    response
+}
+
+pub fn handle_mget(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("mget");
+    }
+    // Keys holding something else than a string read as missing.
+    let values = decoded_str[1..].iter().map(|key| match keyspace.get(key) {
+        Some(RedisValue::String(value)) => encode_optional_string(Some(value)),
+        _ => RespValue::Null,
+    });
+    RespValue::Array(values.collect())
+}
+
+fn set_pairs(keyspace: &mut Keyspace, pairs: &[Argument]) {
+    for pair in pairs.chunks(2) {
+        keyspace.insert(
+            pair[0].to_string(),
+            RedisValue::String(RedisString::new(pair[1].as_bytes().to_vec())),
+            None,
+        );
+        keyspace.notify(EventClass::String, "set", &pair[0]);
+    }
+}
+
+pub fn handle_mset(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 || decoded_str.len().is_multiple_of(2) {
+        return wrong_number_of_arguments("mset");
+    }
+    set_pairs(keyspace, &decoded_str[1..]);
+    RespValue::ok()
+}
+
+pub fn handle_msetnx(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 || decoded_str.len().is_multiple_of(2) {
+        return wrong_number_of_arguments("msetnx");
+    }
+    let pairs = &decoded_str[1..];
+    if pairs
+        .iter()
+        .step_by(2)
+        .any(|key| keyspace.contains_key(key))
+    {
+        return RespValue::Integer(0);
+    }
+    set_pairs(keyspace, pairs);
+    RespValue::Integer(1)
+}
+
+pub fn handle_setnx(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() != 3 {
+        return wrong_number_of_arguments("setnx");
+    }
+    if keyspace.contains_key(&decoded_str[1]) {
+        return RespValue::Integer(0);
+    }
+    set_pairs(keyspace, &decoded_str[1..]);
+    RespValue::Integer(1)
+}
+
+fn set_with_expire_time(
+    keyspace: &mut Keyspace,
+    decoded_str: &[Argument],
+    command: &str,
+    unit: &str,
+) -> RespValue {
+    if decoded_str.len() != 4 {
+        return wrong_number_of_arguments(command);
+    }
+    let ttl = match parse_expire_time(command, unit, &decoded_str[2]) {
+        Ok(Some(ttl)) => ttl,
+        Ok(None) => unreachable!("relative expire times are positive"),
+        Err(error) => return error,
+    };
+    keyspace.insert(
+        decoded_str[1].to_string(),
+        RedisValue::String(RedisString::new(decoded_str[3].as_bytes().to_vec())),
+        Some(ttl),
+    );
+    keyspace.notify(EventClass::String, "set", &decoded_str[1]);
+    keyspace.notify(EventClass::Generic, "expire", &decoded_str[1]);
+    RespValue::ok()
+}
+
+pub fn handle_setex(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    set_with_expire_time(keyspace, decoded_str, "setex", "ex")
+}
+
+pub fn handle_psetex(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    set_with_expire_time(keyspace, decoded_str, "psetex", "px")
+}
+
+// A single match of LCS IDX, as inclusive byte ranges of both strings.
+struct LcsMatch {
+    a: (usize, usize),
+    b: (usize, usize),
+}
+
+// Longest common subsequence of `a` and `b` along with the matching ranges,
+// last ones first, found by walking the dynamic programming table backwards
+// exactly like Redis does so that ties are broken the same way.
+fn longest_common_subsequence(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<LcsMatch>) {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut lcs = vec![0u8; table[a.len() * width + b.len()] as usize];
+    let mut idx = lcs.len();
+    let mut matches = Vec::new();
+    let (mut i, mut j) = (a.len(), b.len());
+    // The range currently being extended backwards, if any.
+    let mut current: Option<LcsMatch> = None;
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            lcs[idx - 1] = a[i - 1];
+            match current.as_mut() {
+                None => {
+                    current = Some(LcsMatch {
+                        a: (i - 1, i - 1),
+                        b: (j - 1, j - 1),
+                    })
+                }
+                Some(range) if range.a.0 == i && range.b.0 == j => {
+                    range.a.0 -= 1;
+                    range.b.0 -= 1;
+                }
+                Some(_) => emit = true,
+            }
+            if current
+                .as_ref()
+                .is_some_and(|range| range.a.0 == 0 || range.b.0 == 0)
+            {
+                emit = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = current.is_some();
+        }
+        if emit {
+            matches.extend(current.take());
+        }
+    }
+    (lcs, matches)
+}
+
+pub fn handle_lcs(keyspace: &mut Keyspace, decoded_str: &[Argument]) -> RespValue {
+    if decoded_str.len() < 3 {
+        return wrong_number_of_arguments("lcs");
+    }
+    let mut get_len = false;
+    let mut get_idx = false;
+    let mut with_match_len = false;
+    let mut min_match_len = 0;
+    let mut options = decoded_str[3..].iter();
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "len" => get_len = true,
+            "idx" => get_idx = true,
+            "withmatchlen" => with_match_len = true,
+            "minmatchlen" => match options.next().map(|value| parse_integer_argument(value)) {
+                Some(Ok(value)) => min_match_len = value.max(0) as usize,
+                Some(Err(error)) => return error,
+                None => return RespValue::error("ERR syntax error"),
+            },
+            _ => return RespValue::error("ERR syntax error"),
+        }
+    }
+    if get_len && get_idx {
+        return RespValue::error(
+            "ERR If you want both the length and indexes, please just use IDX.",
+        );
+    }
+
+    let mut values = Vec::with_capacity(2);
+    for key in &decoded_str[1..3] {
+        match keyspace.get(key) {
+            Some(RedisValue::String(value)) => values.push(value.as_bytes()),
+            Some(_) => {
+                return RespValue::error("ERR The specified keys must contain string values")
+            }
+            None => values.push(Cow::Borrowed(b"".as_slice())),
+        }
+    }
+    let (a, b) = (&values[0], &values[1]);
+    if (a.len() + 1).saturating_mul(b.len() + 1).saturating_mul(4) > MAX_STRING_LENGTH {
+        return RespValue::error(
+            "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len",
+        );
+    }
+
+    let (lcs, matches) = longest_common_subsequence(a, b);
+    if get_len {
+        return RespValue::Integer(lcs.len() as i64);
+    }
+    if !get_idx {
+        return RespValue::bulk(&lcs);
+    }
+    let matches: Vec<RespValue> = matches
+        .iter()
+        .filter(|found| found.a.1 - found.a.0 + 1 >= min_match_len)
+        .map(|found| {
+            let mut elements = vec![
+                RespValue::Array(vec![
+                    RespValue::Integer(found.a.0 as i64),
+                    RespValue::Integer(found.a.1 as i64),
+                ]),
+                RespValue::Array(vec![
+                    RespValue::Integer(found.b.0 as i64),
+                    RespValue::Integer(found.b.1 as i64),
+                ]),
+            ];
+            if with_match_len {
+                elements.push(RespValue::Integer((found.a.1 - found.a.0 + 1) as i64));
+            }
+            RespValue::Array(elements)
+        })
+        .collect();
+    RespValue::Array(vec![
+        RespValue::bulk("matches"),
+        RespValue::Array(matches),
+        RespValue::bulk("len"),
+        RespValue::Integer(lcs.len() as i64),
+    ])
+}
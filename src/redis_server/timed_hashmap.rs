@@ -1,69 +1,101 @@
 use std::{
-    collections::HashMap,
+    borrow::Borrow,
+    cell::Cell,
+    collections::HashSet,
+    hash::Hash,
     time::{Duration, Instant},
 };
 
+use super::{
+    eviction::{Access, Eviction, MemoryUsage},
+    indexed_map::IndexedMap,
+};
+
 #[derive(Debug)]
 struct TimedValue<T> {
     value: T,
     expiration: Option<Instant>,
+    // The memory the entry takes, key included, as last measured.
+    size: usize,
+    // Reads go through shared references and still count as accesses.
+    access: Cell<Access>,
 }
 
 impl<T> TimedValue<T> {
-    fn add(value: T, ttl: Option<Duration>) -> Self {
-        let duration = if ttl.is_none() {
-            None
-        } else {
-            let ttl = ttl.unwrap();
-            Some(Instant::now() + ttl)
-        };
-
-        if duration.is_some() {
-            println!("Duration is: {:?}", duration.unwrap());
-        }
-
+    fn add(value: T, ttl: Option<Duration>, size: usize, access: Access) -> Self {
         Self {
             value,
-            expiration: duration,
+            expiration: ttl.map(|ttl| Instant::now() + ttl),
+            size,
+            access: Cell::new(access),
         }
     }
 
     fn is_expired(&self) -> bool {
-        if self.expiration.is_some() {
-            let expiration = self.expiration.unwrap();
-            println!(
-                "Expiration is: {:?} and current time is: {:?}",
-                expiration,
-                Instant::now()
-            );
-            self.expiration.unwrap() <= Instant::now()
-        } else {
-            false
-        }
+        self.expiration
+            .is_some_and(|expiration| expiration <= Instant::now())
     }
 }
 
+// Keeps track of the memory its entries take, and of how recently and how
+// often they were accessed, for eviction.
 #[derive(Debug)]
 pub struct TimedHashMap<K, V> {
-    map: HashMap<K, TimedValue<V>>,
+    map: IndexedMap<K, TimedValue<V>>,
+    // The keys with a time to live, which the volatile policies pick from.
+    volatile: IndexedMap<K, ()>,
+    used_memory: usize,
+    // Entries handed out mutably, measured again by `used_memory`.
+    resized: HashSet<K>,
 }
 
 impl<K, V> TimedHashMap<K, V>
 where
-    K: Eq + std::hash::Hash,
+    K: Eq + Hash + Clone + MemoryUsage,
+    V: MemoryUsage,
 {
     pub fn new() -> Self {
         Self {
-            map: HashMap::new(),
+            map: IndexedMap::new(),
+            volatile: IndexedMap::new(),
+            used_memory: 0,
+            resized: HashSet::new(),
         }
     }
 
-    pub fn insert(&mut self, key: K, value: V, ttl: Option<Duration>) {
-        let timed_value: TimedValue<V> = TimedValue::add(value, ttl);
+    // Keys expired but not deleted yet included, like Redis.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn entry_size(key: &K, value: &V) -> usize {
+        size_of::<TimedValue<V>>() + key.memory_usage() + value.memory_usage()
+    }
+
+    // Overwriting a value counts as an access to it.
+    pub fn insert(&mut self, key: K, value: V, ttl: Option<Duration>, eviction: &Eviction) {
+        self.volatile.remove(&key);
+        let access = match self.map.remove(&key) {
+            Some(old) => {
+                self.used_memory -= old.size;
+                old.access.get().touch(eviction)
+            }
+            None => Access::new(),
+        };
+        let size = Self::entry_size(&key, &value);
+        self.used_memory += size;
+        let timed_value: TimedValue<V> = TimedValue::add(value, ttl, size, access);
+        if ttl.is_some() {
+            self.volatile.insert(key.clone(), ());
+        }
         self.map.insert(key, timed_value);
     }
 
-    pub fn get(&self, key: &K) -> Option<&V> {
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
         self.map.get(key).and_then(|timed_value| {
             if timed_value.is_expired() {
                 None
@@ -73,7 +105,165 @@ where
         })
     }
 
-    pub fn remove_expired_entries(&mut self) {
-        self.map.retain(|_, timed_value| !timed_value.is_expired());
+    // Like `get`, as a command reading the value.
+    pub fn access<Q>(&self, key: &Q, eviction: &Eviction) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map
+            .get(key)
+            .filter(|timed_value| !timed_value.is_expired())
+            .map(|timed_value| {
+                timed_value
+                    .access
+                    .set(timed_value.access.get().touch(eviction));
+                &timed_value.value
+            })
+    }
+
+    // The value may change size, it is measured again later.
+    pub fn access_mut<Q>(&mut self, key: &Q, eviction: &Eviction) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (key, timed_value) = self
+            .map
+            .get_key_value(key)
+            .filter(|(_, timed_value)| !timed_value.is_expired())?;
+        timed_value
+            .access
+            .set(timed_value.access.get().touch(eviction));
+        let key = key.clone();
+        let timed_value = self.map.get_mut::<K>(&key)?;
+        self.resized.insert(key);
+        Some(&mut timed_value.value)
+    }
+
+    // Whether `key` is there with its time to live over, waiting to be
+    // removed.
+    pub fn has_expired<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key).is_some_and(TimedValue::is_expired)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let timed_value = self.map.remove(key)?;
+        self.volatile.remove(key);
+        self.used_memory -= timed_value.size;
+        self.resized.remove(key);
+        Some(timed_value)
+            .filter(|timed_value| !timed_value.is_expired())
+            .map(|timed_value| timed_value.value)
+    }
+
+    pub fn expiration<Q>(&self, key: &Q) -> Option<Instant>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map
+            .get(key)
+            .filter(|timed_value| !timed_value.is_expired())
+            .and_then(|timed_value| timed_value.expiration)
+    }
+
+    // Live entries along with their expiration time, if any.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V, Option<Instant>)> {
+        self.map
+            .iter()
+            .filter(|(_, timed_value)| !timed_value.is_expired())
+            .map(|(key, timed_value)| (key, &timed_value.value, timed_value.expiration))
+    }
+
+    // Up to `count` live entries picked at random, with when they were
+    // accessed and expire. Only ones with a time to live when `volatile`.
+    // Like Redis' dictGetSomeKeys, the same entry may come up twice, and it
+    // gives up after ten picks per entry wanted.
+    pub fn sample(&self, count: usize, volatile: bool) -> Vec<(K, Access, Option<Instant>)> {
+        let mut samples = Vec::with_capacity(count);
+        for _ in 0..count * 10 {
+            if samples.len() == count {
+                break;
+            }
+            let picked = if volatile {
+                self.volatile
+                    .random()
+                    .and_then(|(key, _)| self.map.get_key_value(key))
+            } else {
+                self.map.random()
+            };
+            let Some((key, timed_value)) = picked else {
+                break;
+            };
+            if !timed_value.is_expired() {
+                samples.push((
+                    key.clone(),
+                    timed_value.access.get(),
+                    timed_value.expiration,
+                ));
+            }
+        }
+        samples
+    }
+
+    // The memory the entries take, after measuring the ones that may have
+    // changed.
+    pub fn used_memory(&mut self) -> usize {
+        for key in std::mem::take(&mut self.resized) {
+            if let Some(timed_value) = self.map.get_mut(&key) {
+                let size = Self::entry_size(&key, &timed_value.value);
+                self.used_memory = self.used_memory - timed_value.size + size;
+                timed_value.size = size;
+            }
+        }
+        self.used_memory
+    }
+
+    // Removes `key` once its time to live is over, returns whether it did.
+    pub fn remove_if_expired<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let expired = self.has_expired(key);
+        if expired {
+            self.remove(key);
+        }
+        expired
+    }
+
+    // Looks at up to `count` entries with a time to live, picked at random,
+    // and removes the expired ones. Returns how many were looked at along
+    // with the keys removed.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, Vec<K>) {
+        let sampled = count.min(self.volatile.len());
+        let mut expired = Vec::new();
+        for _ in 0..sampled {
+            let Some((key, _)) = self.volatile.random() else {
+                break;
+            };
+            let key = key.clone();
+            if self.remove_if_expired(&key) {
+                expired.push(key);
+            }
+        }
+        (sampled, expired)
     }
 }
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    client::Client,
+    resp::{Argument, Protocol, RespValue},
+    server_state::ServerState,
+    Command,
+};
+
+// Where RESP2 clients get invalidations sent to them through REDIRECT.
+const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+// What CLIENT TRACKING turned on for a connection.
+#[derive(Debug, Default)]
+struct Tracker {
+    redirect: Option<u64>,
+    bcast: bool,
+    prefixes: Vec<String>,
+    optin: bool,
+    optout: bool,
+    noloop: bool,
+    // Set by CLIENT CACHING, for the next command or transaction only.
+    caching: Option<bool>,
+    broken_redirect: bool,
+}
+
+impl Tracker {
+    // Whether the keys read by the command being run get remembered.
+    fn caches(&self) -> bool {
+        if self.optin {
+            self.caching == Some(true)
+        } else if self.optout {
+            self.caching != Some(false)
+        } else {
+            true
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+// Server assisted client side caching. In the default mode the server
+// remembers which clients read a key and tells them once, the next time it
+// changes. In BCAST mode clients hear about every key matching their
+// prefixes instead.
+#[derive(Debug, Default)]
+pub struct Tracking {
+    trackers: HashMap<u64, Tracker>,
+    keys: HashMap<String, HashSet<u64>>,
+}
+
+impl Tracking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Called on disconnect and RESET. Keys the client read stay in the table
+    // until they change, they are skipped then.
+    pub fn disable(&mut self, id: u64) {
+        self.trackers.remove(&id);
+    }
+
+    // Remembers the keys read by `commands` for the default mode, a single
+    // command or the ones a transaction ran. CLIENT CACHING lasts until the
+    // command after it, or through EXEC.
+    pub fn remember(&mut self, id: u64, commands: &[Vec<Argument>], keep_caching: bool) {
+        let Some(tracker) = self.trackers.get_mut(&id) else {
+            return;
+        };
+        let caches = !tracker.bcast && tracker.caches();
+        if !keep_caching {
+            tracker.caching = None;
+        }
+        if !caches {
+            return;
+        }
+        for decoded_str in commands {
+            let command = Command::from_name(&decoded_str[0]);
+            if !command.categories().contains(&"read") {
+                continue;
+            }
+            for index in command.key_indexes(decoded_str) {
+                self.keys
+                    .entry(decoded_str[index].to_string())
+                    .or_default()
+                    .insert(id);
+            }
+        }
+    }
+
+    // Tells the clients tracking `keys` that they changed, unless they wrote
+    // them with NOLOOP on. Each client gets one message for all of them.
+    pub fn invalidate(&mut self, state: &ServerState, keys: Vec<String>, writer: Option<u64>) {
+        if self.trackers.is_empty() {
+            self.keys.clear();
+            return;
+        }
+        let mut invalidated: HashMap<u64, Vec<String>> = HashMap::new();
+        for key in keys {
+            let readers = self.keys.remove(&key).unwrap_or_default();
+            for (id, tracker) in &self.trackers {
+                if tracker.noloop && writer == Some(*id) {
+                    continue;
+                }
+                let tracks = if tracker.bcast {
+                    tracker.matches(&key)
+                } else {
+                    readers.contains(id)
+                };
+                if tracks {
+                    let keys = invalidated.entry(*id).or_default();
+                    if !keys.contains(&key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+        }
+        for (id, keys) in invalidated {
+            self.send(state, id, Some(keys));
+        }
+    }
+
+    // After FLUSHDB and FLUSHALL every client forgets everything, which a
+    // null list of keys tells them.
+    pub fn invalidate_all(&mut self, state: &ServerState) {
+        self.keys.clear();
+        let ids: Vec<u64> = self.trackers.keys().copied().collect();
+        for id in ids {
+            self.send(state, id, None);
+        }
+    }
+
+    // RESP3 clients get a push, on their own connection or the one they
+    // redirect to. RESP2 clients have to redirect to a connection
+    // subscribed to __redis__:invalidate.
+    fn send(&mut self, state: &ServerState, id: u64, keys: Option<Vec<String>>) {
+        let Some(tracker) = self.trackers.get_mut(&id) else {
+            return;
+        };
+        let keys = keys.map_or(RespValue::NullArray, |keys| {
+            RespValue::Array(keys.into_iter().map(RespValue::bulk).collect())
+        });
+        let invalidate = RespValue::Array(vec![RespValue::bulk("invalidate"), keys.clone()]);
+        let message = match tracker.redirect {
+            None => match state.clients.protocol(id) {
+                Some(Protocol::Resp3) => (id, invalidate),
+                _ => return,
+            },
+            Some(target) => match state.clients.protocol(target) {
+                Some(Protocol::Resp3) => (target, invalidate),
+                Some(Protocol::Resp2) if state.pubsub.is_subscribed(target, INVALIDATE_CHANNEL) => {
+                    let message = RespValue::Array(vec![
+                        RespValue::bulk("message"),
+                        RespValue::bulk(INVALIDATE_CHANNEL),
+                        keys,
+                    ]);
+                    (target, message)
+                }
+                Some(Protocol::Resp2) => return,
+                // The connection redirected to went away.
+                None => {
+                    tracker.broken_redirect = true;
+                    if state.clients.protocol(id) != Some(Protocol::Resp3) {
+                        return;
+                    }
+                    let broken = RespValue::Array(vec![
+                        RespValue::bulk("tracking-redir-broken"),
+                        RespValue::Integer(target as i64),
+                    ]);
+                    (id, broken)
+                }
+            },
+        };
+        let (receiver, message) = message;
+        state.pubsub.send_to(receiver, message);
+    }
+}
+
+// CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN]
+// [OPTOUT] [NOLOOP]
+pub fn handle_tracking(
+    state: &ServerState,
+    client: &Client,
+    decoded_str: &[Argument],
+) -> RespValue {
+    let error = |message: &str| RespValue::Error(message.to_string());
+    let on = match decoded_str.get(2).map(|on| on.to_lowercase()).as_deref() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return error("ERR syntax error"),
+    };
+    let mut options = Tracker::default();
+    let mut arguments = decoded_str[3..].iter();
+    while let Some(option) = arguments.next() {
+        match option.to_lowercase().as_str() {
+            "redirect" => {
+                let Some(target) = arguments.next() else {
+                    return error("ERR syntax error");
+                };
+                let Ok(target) = target.parse::<u64>() else {
+                    return error("ERR value is not an integer or out of range");
+                };
+                if state.clients.protocol(target).is_none() {
+                    return error("ERR The client ID you want redirect to does not exist");
+                }
+                options.redirect = Some(target);
+            }
+            "prefix" => {
+                let Some(prefix) = arguments.next() else {
+                    return error("ERR syntax error");
+                };
+                options.prefixes.push(prefix.to_string());
+            }
+            "bcast" => options.bcast = true,
+            "optin" => options.optin = true,
+            "optout" => options.optout = true,
+            "noloop" => options.noloop = true,
+            _ => return error("ERR syntax error"),
+        }
+    }
+
+    let mut tracking = state.tracking.lock().unwrap();
+    if !on {
+        tracking.disable(client.id);
+        return RespValue::ok();
+    }
+    if !options.bcast && !options.prefixes.is_empty() {
+        return error("ERR PREFIX option requires BCAST mode to be enabled");
+    }
+    if options.bcast && (options.optin || options.optout) {
+        return error("ERR OPTIN and OPTOUT are not compatible with BCAST");
+    }
+    if options.optin && options.optout {
+        return error("ERR You can't use both OPTIN and OPTOUT");
+    }
+    if let Some(current) = tracking.trackers.get(&client.id) {
+        if current.bcast != options.bcast {
+            return error("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.");
+        }
+        if current.optin != options.optin || current.optout != options.optout {
+            return error("ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode.");
+        }
+        // Turning tracking on again adds prefixes to the ones already there.
+        options
+            .prefixes
+            .splice(0..0, current.prefixes.iter().cloned());
+    }
+    // A key matching two prefixes would be reported twice.
+    for (index, prefix) in options.prefixes.iter().enumerate() {
+        let overlapping = options.prefixes[..index].iter().find(|other| {
+            *other != prefix
+                && (other.starts_with(prefix.as_str()) || prefix.starts_with(other.as_str()))
+        });
+        if let Some(other) = overlapping {
+            return error(&format!(
+                "ERR Prefix '{}' overlaps with an existing prefix '{}'. Prefixes for a single client must not overlap.",
+                prefix, other
+            ));
+        }
+    }
+    options.prefixes.sort();
+    options.prefixes.dedup();
+    tracking.trackers.insert(client.id, options);
+    RespValue::ok()
+}
+
+// CLIENT CACHING YES|NO
+pub fn handle_caching(state: &ServerState, client: &Client, decoded_str: &[Argument]) -> RespValue {
+    let error = |message: &str| RespValue::Error(message.to_string());
+    let mut tracking = state.tracking.lock().unwrap();
+    let Some(tracker) = tracking
+        .trackers
+        .get_mut(&client.id)
+        .filter(|tracker| tracker.optin || tracker.optout)
+    else {
+        return error("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled");
+    };
+    match decoded_str[2].to_lowercase().as_str() {
+        "yes" if tracker.optin => tracker.caching = Some(true),
+        "yes" => {
+            return error(
+                "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.",
+            )
+        }
+        "no" if tracker.optout => tracker.caching = Some(false),
+        "no" => {
+            return error(
+                "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.",
+            )
+        }
+        _ => return error("ERR syntax error"),
+    }
+    RespValue::ok()
+}
+
+// -1 without tracking, 0 when invalidations go to the client itself.
+fn redirect_id(tracker: Option<&Tracker>) -> i64 {
+    match tracker {
+        None => -1,
+        Some(tracker) => tracker.redirect.map_or(0, |target| target as i64),
+    }
+}
+
+// CLIENT GETREDIR
+pub fn handle_getredir(state: &ServerState, client: &Client) -> RespValue {
+    let tracking = state.tracking.lock().unwrap();
+    RespValue::Integer(redirect_id(tracking.trackers.get(&client.id)))
+}
+
+// CLIENT TRACKINGINFO
+pub fn handle_trackinginfo(state: &ServerState, client: &Client) -> RespValue {
+    let tracking = state.tracking.lock().unwrap();
+    let tracker = tracking.trackers.get(&client.id);
+    let flags: Vec<&str> = match tracker {
+        None => vec!["off"],
+        Some(tracker) => {
+            let mut flags = vec!["on"];
+            let options = [
+                (tracker.bcast, "bcast"),
+                (tracker.optin, "optin"),
+                (tracker.optout, "optout"),
+                (tracker.caching == Some(true), "caching-yes"),
+                (tracker.caching == Some(false), "caching-no"),
+                (tracker.noloop, "noloop"),
+                (tracker.broken_redirect, "broken_redirect"),
+            ];
+            flags.extend(
+                options
+                    .iter()
+                    .filter(|(set, _)| *set)
+                    .map(|(_, flag)| *flag),
+            );
+            flags
+        }
+    };
+    let prefixes = tracker.map_or(Vec::new(), |tracker| {
+        tracker.prefixes.iter().map(RespValue::bulk).collect()
+    });
+    RespValue::Map(vec![
+        (
+            RespValue::bulk("flags"),
+            RespValue::Set(flags.into_iter().map(RespValue::bulk).collect()),
+        ),
+        (
+            RespValue::bulk("redirect"),
+            RespValue::Integer(redirect_id(tracker)),
+        ),
+        (RespValue::bulk("prefixes"), RespValue::Array(prefixes)),
+    ])
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use super::{
+    keyspace::Keyspace,
+    resp::{Argument, RespValue},
+    server_state::ServerState,
+    wrong_number_of_arguments, Command,
+};
+
+// Commands queued by MULTI on one connection, until EXEC or DISCARD, and the
+// keys WATCHed beforehand.
+#[derive(Debug)]
+pub struct Transaction {
+    state: Arc<ServerState>,
+    queued: Option<Vec<Vec<Argument>>>,
+    // Set when a command was rejected while queueing, EXEC then fails.
+    aborted: bool,
+    // Watched keys with their database, their version at the time of WATCH
+    // and whether they had expired by then.
+    watched: Vec<(usize, String, u64, bool)>,
+}
+
+impl Transaction {
+    pub fn new(state: &Arc<ServerState>) -> Self {
+        Self {
+            state: Arc::clone(state),
+            queued: None,
+            aborted: false,
+            watched: Vec::new(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.queued.is_some()
+    }
+
+    pub fn queued(&self) -> &[Vec<Argument>] {
+        self.queued.as_deref().unwrap_or_default()
+    }
+
+    // Makes EXEC fail, when a command got rejected before being queued.
+    pub fn abort(&mut self) {
+        if self.is_active() {
+            self.aborted = true;
+        }
+    }
+
+    // Drops whatever was queued, if anything, and forgets the watched keys.
+    pub fn reset(&mut self) {
+        self.queued = None;
+        self.aborted = false;
+        if !self.watched.is_empty() {
+            let state = Arc::clone(&self.state);
+            self.unwatch_all(&mut state.keyspace.lock().unwrap());
+        }
+    }
+
+    fn unwatch_all(&mut self, keyspace: &mut Keyspace) {
+        for (db, key, _, _) in self.watched.drain(..) {
+            keyspace.unwatch(db, &key);
+        }
+    }
+
+    // Whether a watched key changed since it was watched.
+    fn is_dirty(&self, keyspace: &Keyspace) -> bool {
+        self.watched
+            .iter()
+            .any(|(db, key, version, expired)| keyspace.changed_since(*db, key, *version, *expired))
+    }
+
+    // Queues a command sent after MULTI, unless it may not run in a
+    // transaction: it is rejected right away and makes EXEC fail. Unknown
+    // commands and wrong numbers of arguments never get here.
+    pub fn queue(&mut self, command: &Command, decoded_str: &[Argument]) -> RespValue {
+        let error = (!command.allowed_in_transaction())
+            .then(|| RespValue::error("ERR Command not allowed inside a transaction"));
+        match error {
+            Some(error) => {
+                self.aborted = true;
+                error
+            }
+            None => {
+                self.queued
+                    .get_or_insert_with(Vec::new)
+                    .push(decoded_str.to_vec());
+                RespValue::simple("QUEUED")
+            }
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+// WATCH key [key ...], in the database `db`.
+pub fn handle_watch(
+    transaction: &mut Transaction,
+    db: usize,
+    decoded_str: &[Argument],
+) -> RespValue {
+    if decoded_str.len() < 2 {
+        return wrong_number_of_arguments("watch");
+    }
+    if transaction.is_active() {
+        return RespValue::error("ERR WATCH inside MULTI is not allowed");
+    }
+    let mut keyspace = transaction.state.keyspace.lock().unwrap();
+    for key in &decoded_str[1..] {
+        if !transaction
+            .watched
+            .iter()
+            .any(|(watched_db, watched, _, _)| *watched_db == db && watched == key.as_str())
+        {
+            let (version, expired) = keyspace.watch(db, key);
+            transaction
+                .watched
+                .push((db, key.to_string(), version, expired));
+        }
+    }
+    RespValue::ok()
+}
+
+// UNWATCH
+pub fn handle_unwatch(transaction: &mut Transaction) -> RespValue {
+    transaction.reset();
+    RespValue::ok()
+}
+
+// MULTI
+pub fn handle_multi(transaction: &mut Transaction) -> RespValue {
+    if transaction.is_active() {
+        return RespValue::error("ERR MULTI calls can not be nested");
+    }
+    transaction.queued = Some(Vec::new());
+    RespValue::ok()
+}
+
+// DISCARD
+pub fn handle_discard(transaction: &mut Transaction) -> RespValue {
+    if !transaction.is_active() {
+        return RespValue::error("ERR DISCARD without MULTI");
+    }
+    transaction.reset();
+    RespValue::ok()
+}
+
+// EXEC runs every queued command without releasing the keyspace, so no other
+// connection sees the transaction halfway through. Replicas get its writes
+// wrapped in MULTI/EXEC for the same reason. It replies with a null array,
+// running nothing, when a watched key changed. A queued SELECT changes the
+// database `db` of the connection. `execute` runs each queued command against
+// the locked keyspace, the connection may have some to handle itself.
+pub fn handle_exec(
+    state: &ServerState,
+    transaction: &mut Transaction,
+    db: &mut usize,
+    mut execute: impl FnMut(&mut Keyspace, &[Argument]) -> RespValue,
+) -> RespValue {
+    let Some(queued) = transaction.queued.take() else {
+        return RespValue::error("ERR EXEC without MULTI");
+    };
+    if std::mem::take(&mut transaction.aborted) {
+        transaction.reset();
+        return RespValue::error("EXECABORT Transaction discarded because of previous errors.");
+    }
+
+    let writes = queued
+        .iter()
+        .any(|decoded_str| Command::from_name(&decoded_str[0]).is_write());
+    let mut response = Vec::with_capacity(queued.len());
+    {
+        let mut keyspace = state.lock_keyspace(*db);
+        // Keys that expired meanwhile count as changed, unless they had
+        // already when watched.
+        for (watched_db, key, _, _) in &transaction.watched {
+            keyspace.expire_if_needed(*watched_db, key);
+        }
+        state.publish_notifications(&mut keyspace);
+        let dirty = transaction.is_dirty(&keyspace);
+        transaction.unwatch_all(&mut keyspace);
+        if dirty {
+            return RespValue::NullArray;
+        }
+        for decoded_str in &queued {
+            response.push(execute(&mut keyspace, decoded_str));
+        }
+        *db = keyspace.selected();
+        state.propagate_effects(&mut keyspace);
+    }
+    if writes {
+        state.keyspace_written.notify_waiters();
+    }
+    RespValue::Array(response)
+}
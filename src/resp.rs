@@ -0,0 +1,789 @@
+// RESP values, both what clients send and what they get back, with a
+// decoder and an encoder for RESP2 and RESP3. Nothing here depends on the
+// server, tools talking to it use it as well.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespValue {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    Array(Vec<RespValue>),
+    // A nil bulk string in RESP2.
+    Null,
+    // A nil array in RESP2.
+    NullArray,
+    // The RESP3 types, RESP2 clients get them as the closest RESP2 type.
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    // Text along with its three letter format, like "txt".
+    Verbatim(String, Vec<u8>),
+    Map(Vec<(RespValue, RespValue)>),
+    Set(Vec<RespValue>),
+    Push(Vec<RespValue>),
+}
+
+// One argument of a request. Its bytes are kept as sent, so values can be
+// binary, while names, options and numbers read it as text (invalid UTF-8
+// replaced).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Argument {
+    text: String,
+    // The bytes as sent, only kept when they aren't UTF-8.
+    binary: Option<Vec<u8>>,
+}
+
+impl Argument {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Argument { text, binary: None },
+            Err(e) => Argument {
+                text: String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                binary: Some(e.into_bytes()),
+            },
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.binary.as_deref().unwrap_or(self.text.as_bytes())
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.binary.unwrap_or_else(|| self.text.into_bytes())
+    }
+}
+
+impl std::ops::Deref for Argument {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<[u8]> for Argument {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl std::fmt::Display for Argument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<String> for Argument {
+    fn from(text: String) -> Self {
+        Argument { text, binary: None }
+    }
+}
+
+impl From<&str> for Argument {
+    fn from(text: &str) -> Self {
+        Argument::from(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Argument {
+    fn from(bytes: Vec<u8>) -> Self {
+        Argument::new(bytes)
+    }
+}
+
+impl PartialEq<str> for Argument {
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Argument {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+// Like Redis, so that a bogus length can't make us allocate gigabytes.
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+const MAX_AGGREGATE_LENGTH: usize = 1024 * 1024;
+const MAX_INLINE_LENGTH: usize = 64 * 1024;
+// Aggregates nested deeper than this are refused rather than overflowing the
+// stack.
+const MAX_NESTING: usize = 128;
+
+fn read_line<'a>(input: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let rest = input.get(*pos..)?;
+    let end = rest.windows(2).position(|window| window == b"\r\n")?;
+    *pos += end + 2;
+    Some(&rest[..end])
+}
+
+fn parse_length(text: &str, max: usize, kind: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(length) if length <= max => Ok(length),
+        _ => Err(format!("invalid {} length", kind)),
+    }
+}
+
+impl RespValue {
+    pub fn ok() -> Self {
+        RespValue::SimpleString("OK".to_string())
+    }
+
+    pub fn simple(text: impl Into<String>) -> Self {
+        RespValue::SimpleString(text.into())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        RespValue::Error(message.into())
+    }
+
+    pub fn bulk(bytes: impl AsRef<[u8]>) -> Self {
+        RespValue::BulkString(bytes.as_ref().to_vec())
+    }
+
+    // An array of bulk strings, the most common reply after single values.
+    pub fn bulks<T: AsRef<[u8]>>(items: impl IntoIterator<Item = T>) -> Self {
+        RespValue::Array(items.into_iter().map(Self::bulk).collect())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, RespValue::Error(_))
+    }
+
+    // Decodes the value at the start of `input`, returns it with the number
+    // of bytes it took. None until all of it has been received.
+    pub fn decode(input: &[u8]) -> Result<Option<(RespValue, usize)>, String> {
+        let mut pos = 0;
+        Ok(Self::decode_at(input, &mut pos, 0)?.map(|value| (value, pos)))
+    }
+
+    // What clients send: an array of bulk strings, or an inline command
+    // from someone typing at nc or telnet.
+    pub fn decode_request(input: &[u8]) -> Result<Option<(RespValue, usize)>, String> {
+        let mut decoder = RequestDecoder::new();
+        decoder.buffer.extend_from_slice(input);
+        Ok(decoder.decode_next()?.map(|request| (request, decoder.pos)))
+    }
+
+    // `depth` counts the aggregates the value is in.
+    fn decode_at(input: &[u8], pos: &mut usize, depth: usize) -> Result<Option<RespValue>, String> {
+        if depth > MAX_NESTING {
+            return Err("too many nested aggregates".to_string());
+        }
+        let Some(line) = read_line(input, pos) else {
+            return Ok(None);
+        };
+        let Some((&kind, rest)) = line.split_first() else {
+            return Err("empty line".to_string());
+        };
+        let text = std::str::from_utf8(rest).map_err(|_| "invalid line".to_string())?;
+        let value = match kind {
+            b'+' => RespValue::SimpleString(text.to_string()),
+            b'-' => RespValue::Error(text.to_string()),
+            b':' => RespValue::Integer(text.parse().map_err(|_| "invalid integer".to_string())?),
+            b'$' if text == "-1" => RespValue::Null,
+            b'*' if text == "-1" => RespValue::NullArray,
+            b'_' => RespValue::Null,
+            b'$' | b'=' => {
+                let length = parse_length(text, MAX_BULK_LENGTH, "bulk")?;
+                let Some(bytes) = input.get(*pos..*pos + length) else {
+                    return Ok(None);
+                };
+                match input.get(*pos + length..*pos + length + 2) {
+                    None => return Ok(None),
+                    Some(b"\r\n") => {}
+                    Some(_) => return Err("expected CRLF after bulk data".to_string()),
+                }
+                *pos += length + 2;
+                if kind == b'$' {
+                    RespValue::BulkString(bytes.to_vec())
+                } else if bytes.len() >= 4 && bytes[3] == b':' {
+                    RespValue::Verbatim(
+                        String::from_utf8_lossy(&bytes[..3]).into_owned(),
+                        bytes[4..].to_vec(),
+                    )
+                } else {
+                    return Err("invalid verbatim string".to_string());
+                }
+            }
+            b'*' | b'~' | b'>' | b'%' => {
+                let mut count = parse_length(text, MAX_AGGREGATE_LENGTH, "multibulk")?;
+                if kind == b'%' {
+                    count *= 2;
+                }
+                let mut elements = Vec::with_capacity(count);
+                for _ in 0..count {
+                    match Self::decode_at(input, pos, depth + 1)? {
+                        Some(element) => elements.push(element),
+                        None => return Ok(None),
+                    }
+                }
+                match kind {
+                    b'*' => RespValue::Array(elements),
+                    b'~' => RespValue::Set(elements),
+                    b'>' => RespValue::Push(elements),
+                    _ => {
+                        let mut elements = elements.into_iter();
+                        let mut pairs = Vec::new();
+                        while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+                            pairs.push((key, value));
+                        }
+                        RespValue::Map(pairs)
+                    }
+                }
+            }
+            b',' => RespValue::Double(match text {
+                "inf" => f64::INFINITY,
+                "-inf" => f64::NEG_INFINITY,
+                _ => text.parse().map_err(|_| "invalid double".to_string())?,
+            }),
+            b'#' => match text {
+                "t" => RespValue::Boolean(true),
+                "f" => RespValue::Boolean(false),
+                _ => return Err("invalid boolean".to_string()),
+            },
+            b'(' => RespValue::BigNumber(text.to_string()),
+            _ => {
+                return Err(format!(
+                    "expected '$', got '{}'",
+                    char::from(kind).escape_default()
+                ))
+            }
+        };
+        Ok(Some(value))
+    }
+
+    // A request is an array of bulk strings: the command and its arguments.
+    pub fn into_command(self) -> Option<Vec<Argument>> {
+        let RespValue::Array(elements) = self else {
+            return None;
+        };
+        elements
+            .into_iter()
+            .map(|element| match element {
+                RespValue::BulkString(bytes) => Some(Argument::new(bytes)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out, protocol);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>, protocol: Protocol) {
+        let resp3 = protocol == Protocol::Resp3;
+        match self {
+            RespValue::SimpleString(text) => push_line(out, '+', text),
+            RespValue::Error(message) => push_line(out, '-', message),
+            RespValue::Integer(number) => push_line(out, ':', &number.to_string()),
+            RespValue::BulkString(bytes) => push_bulk(out, '$', bytes),
+            RespValue::Null | RespValue::NullArray if resp3 => push_line(out, '_', ""),
+            RespValue::Null => push_line(out, '$', "-1"),
+            RespValue::NullArray => push_line(out, '*', "-1"),
+            RespValue::Array(elements) => push_aggregate(out, '*', elements, protocol),
+            RespValue::Double(number) => {
+                let text = format_double(*number);
+                if resp3 {
+                    push_line(out, ',', &text)
+                } else {
+                    push_bulk(out, '$', text.as_bytes())
+                }
+            }
+            RespValue::Boolean(value) if resp3 => {
+                push_line(out, '#', if *value { "t" } else { "f" })
+            }
+            RespValue::Boolean(value) => push_line(out, ':', if *value { "1" } else { "0" }),
+            RespValue::BigNumber(number) if resp3 => push_line(out, '(', number),
+            RespValue::BigNumber(number) => push_bulk(out, '$', number.as_bytes()),
+            RespValue::Verbatim(format, text) if resp3 => {
+                let mut bytes = format!("{}:", format).into_bytes();
+                bytes.extend_from_slice(text);
+                push_bulk(out, '=', &bytes)
+            }
+            RespValue::Verbatim(_, text) => push_bulk(out, '$', text),
+            RespValue::Map(pairs) => {
+                if resp3 {
+                    push_line(out, '%', &pairs.len().to_string());
+                } else {
+                    push_line(out, '*', &(pairs.len() * 2).to_string());
+                }
+                for (key, value) in pairs {
+                    key.encode_into(out, protocol);
+                    value.encode_into(out, protocol);
+                }
+            }
+            RespValue::Set(elements) if resp3 => push_aggregate(out, '~', elements, protocol),
+            RespValue::Push(elements) if resp3 => push_aggregate(out, '>', elements, protocol),
+            RespValue::Set(elements) | RespValue::Push(elements) => {
+                push_aggregate(out, '*', elements, protocol)
+            }
+        }
+    }
+}
+
+// Decodes the requests of a client as its bytes come in. A request split
+// over several reads is picked up where the last read stopped: the header
+// and the arguments already complete aren't parsed again, and each bulk is
+// copied out once, when all of it arrived.
+#[derive(Debug, Default)]
+pub struct RequestDecoder {
+    // Bytes read, those before `pos` already decoded.
+    buffer: Vec<u8>,
+    pos: usize,
+    // The multibulk request being read: how many arguments it has, those
+    // complete so far and the length of the next one once its header is in.
+    expected: Option<usize>,
+    arguments: Vec<RespValue>,
+    bulk: Option<usize>,
+}
+
+// How much room the buffer has for each read, it grows past that for
+// requests that don't fit.
+const READ_SIZE: usize = 16 * 1024;
+
+impl RequestDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Where to read the next bytes into.
+    pub fn buffer(&mut self) -> &mut Vec<u8> {
+        self.buffer.reserve(READ_SIZE);
+        &mut self.buffer
+    }
+
+    // The bytes read but not decoded yet.
+    pub fn pending(&self) -> usize {
+        self.buffer.len() - self.pos
+    }
+
+    // The next request, None until all of it has been read.
+    pub fn decode(&mut self) -> Result<Option<RespValue>, String> {
+        let request = self.decode_next()?;
+        if self.pos == self.buffer.len() {
+            self.buffer.clear();
+            self.pos = 0;
+            // Don't hold on to the memory of a large request.
+            if self.buffer.capacity() > MAX_INLINE_LENGTH {
+                self.buffer.shrink_to(READ_SIZE);
+            }
+        } else if request.is_none() {
+            // Only what isn't complete yet is left to move.
+            self.buffer.drain(..self.pos);
+            self.pos = 0;
+        }
+        Ok(request)
+    }
+
+    fn decode_next(&mut self) -> Result<Option<RespValue>, String> {
+        let expected = match self.expected {
+            Some(expected) => expected,
+            None => {
+                match self.buffer.get(self.pos) {
+                    None => return Ok(None),
+                    Some(b'*') => {}
+                    Some(_) => {
+                        let Some((request, length)) = decode_inline(&self.buffer[self.pos..])?
+                        else {
+                            return Ok(None);
+                        };
+                        self.pos += length;
+                        return Ok(Some(request));
+                    }
+                }
+                let Some(line) = read_header(&self.buffer, &mut self.pos, "mbulk")? else {
+                    return Ok(None);
+                };
+                let text =
+                    std::str::from_utf8(&line[1..]).map_err(|_| "invalid multibulk length")?;
+                // Like Redis, an empty or null array is no command at all.
+                let count = match text.parse::<i64>() {
+                    Ok(count) if count <= 0 => return Ok(Some(RespValue::Array(Vec::new()))),
+                    _ => parse_length(text, MAX_AGGREGATE_LENGTH, "multibulk")?,
+                };
+                self.expected = Some(count);
+                self.arguments = Vec::with_capacity(count.min(1024));
+                count
+            }
+        };
+        while self.arguments.len() < expected {
+            let length = match self.bulk {
+                Some(length) => length,
+                None => {
+                    let Some(line) = read_header(&self.buffer, &mut self.pos, "bulk")? else {
+                        return Ok(None);
+                    };
+                    let Some(text) = line.strip_prefix(b"$") else {
+                        let kind = line.first().copied().unwrap_or(b' ');
+                        return Err(format!(
+                            "expected '$', got '{}'",
+                            char::from(kind).escape_default()
+                        ));
+                    };
+                    let text = std::str::from_utf8(text).map_err(|_| "invalid bulk length")?;
+                    let length = parse_length(text, MAX_BULK_LENGTH, "bulk")?;
+                    self.bulk = Some(length);
+                    length
+                }
+            };
+            let Some(bytes) = self.buffer.get(self.pos..self.pos + length + 2) else {
+                return Ok(None);
+            };
+            let (bytes, end) = bytes.split_at(length);
+            if end != b"\r\n" {
+                return Err("expected CRLF after bulk data".to_string());
+            }
+            self.arguments.push(RespValue::BulkString(bytes.to_vec()));
+            self.pos += length + 2;
+            self.bulk = None;
+        }
+        self.expected = None;
+        Ok(Some(RespValue::Array(std::mem::take(&mut self.arguments))))
+    }
+}
+
+// The header line of a multibulk request or of one of its bulks, which
+// can't be any longer than an inline request.
+fn read_header<'a>(
+    input: &'a [u8],
+    pos: &mut usize,
+    kind: &str,
+) -> Result<Option<&'a [u8]>, String> {
+    match read_line(input, pos) {
+        None if input.len() - *pos > MAX_INLINE_LENGTH => {
+            Err(format!("too big {} count string", kind))
+        }
+        line => Ok(line),
+    }
+}
+
+// A line of arguments separated by whitespace, which may be quoted like in
+// redis-cli: double quotes take escape sequences, single quotes only \'.
+fn decode_inline(input: &[u8]) -> Result<Option<(RespValue, usize)>, String> {
+    let Some(end) = input.iter().position(|&byte| byte == b'\n') else {
+        if input.len() > MAX_INLINE_LENGTH {
+            return Err("too big inline request".to_string());
+        }
+        return Ok(None);
+    };
+    if end > MAX_INLINE_LENGTH {
+        return Err("too big inline request".to_string());
+    }
+    let line = input[..end].strip_suffix(b"\r").unwrap_or(&input[..end]);
+    let arguments = split_arguments(line).ok_or("unbalanced quotes in request")?;
+    Ok(Some((
+        RespValue::Array(arguments.into_iter().map(RespValue::BulkString).collect()),
+        end + 1,
+    )))
+}
+
+pub fn split_arguments(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut arguments = Vec::new();
+    let mut pos = 0;
+    loop {
+        while line.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        if pos == line.len() {
+            return Some(arguments);
+        }
+        let mut argument = Vec::new();
+        let mut quote = None;
+        loop {
+            let Some(&byte) = line.get(pos) else {
+                // The line ended inside quotes.
+                if quote.is_some() {
+                    return None;
+                }
+                break;
+            };
+            match quote {
+                None if byte.is_ascii_whitespace() => break,
+                None if byte == b'"' || byte == b'\'' => quote = Some(byte),
+                None => argument.push(byte),
+                Some(b'"') if byte == b'\\' && pos + 1 < line.len() => {
+                    pos += 1;
+                    let hex = line
+                        .get(pos + 1..pos + 3)
+                        .and_then(|digits| std::str::from_utf8(digits).ok())
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+                    match (line[pos], hex) {
+                        (b'x', Some(value)) => {
+                            argument.push(value);
+                            pos += 2;
+                        }
+                        (b'n', _) => argument.push(b'\n'),
+                        (b'r', _) => argument.push(b'\r'),
+                        (b't', _) => argument.push(b'\t'),
+                        (b'b', _) => argument.push(0x08),
+                        (b'a', _) => argument.push(0x07),
+                        (other, _) => argument.push(other),
+                    }
+                }
+                Some(b'\'') if byte == b'\\' && line.get(pos + 1) == Some(&b'\'') => {
+                    pos += 1;
+                    argument.push(b'\'');
+                }
+                // A closing quote must end the argument.
+                Some(open) if byte == open => {
+                    if line
+                        .get(pos + 1)
+                        .is_some_and(|next| !next.is_ascii_whitespace())
+                    {
+                        return None;
+                    }
+                    pos += 1;
+                    break;
+                }
+                Some(_) => argument.push(byte),
+            }
+            pos += 1;
+        }
+        arguments.push(argument);
+    }
+}
+
+// Shortest text that reads back as the same number, like Redis.
+fn format_double(number: f64) -> String {
+    if number.is_nan() {
+        "nan".to_string()
+    } else if number.is_infinite() {
+        if number > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        number.to_string()
+    }
+}
+
+fn push_line(out: &mut Vec<u8>, kind: char, text: &str) {
+    out.extend_from_slice(format!("{}{}\r\n", kind, text).as_bytes());
+}
+
+fn push_bulk(out: &mut Vec<u8>, kind: char, bytes: &[u8]) {
+    push_line(out, kind, &bytes.len().to_string());
+    out.extend_from_slice(bytes);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn push_aggregate(out: &mut Vec<u8>, kind: char, elements: &[RespValue], protocol: Protocol) {
+    push_line(out, kind, &elements.len().to_string());
+    for element in elements {
+        element.encode_into(out, protocol);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> RespValue {
+        RespValue::Array(vec![
+            RespValue::simple("OK"),
+            RespValue::error("ERR no"),
+            RespValue::Integer(-42),
+            RespValue::bulk(b"\xff\r\n\x00"),
+            RespValue::Null,
+            RespValue::Double(1.5),
+            RespValue::Boolean(true),
+            RespValue::BigNumber("12345678901234567890".to_string()),
+            RespValue::Verbatim("txt".to_string(), b"some text".to_vec()),
+            RespValue::Map(vec![(RespValue::bulk("key"), RespValue::Integer(1))]),
+            RespValue::Set(vec![RespValue::bulk("member")]),
+            RespValue::Array(vec![]),
+        ])
+    }
+
+    #[test]
+    fn resp3_round_trips() {
+        let encoded = sample().encode(Protocol::Resp3);
+        assert_eq!(
+            RespValue::decode(&encoded),
+            Ok(Some((sample(), encoded.len())))
+        );
+    }
+
+    #[test]
+    fn waits_for_the_whole_value() {
+        let encoded = sample().encode(Protocol::Resp3);
+        for end in 0..encoded.len() {
+            assert_eq!(RespValue::decode(&encoded[..end]), Ok(None), "{}", end);
+        }
+        let request = RespValue::bulks(["SET", "key", "value"]).encode(Protocol::Resp2);
+        for end in 0..request.len() {
+            assert_eq!(RespValue::decode_request(&request[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn resp2_gets_the_closest_types() {
+        let value = RespValue::Array(vec![
+            RespValue::Null,
+            RespValue::NullArray,
+            RespValue::Double(f64::INFINITY),
+            RespValue::Boolean(false),
+            RespValue::Verbatim("txt".to_string(), b"hi".to_vec()),
+            RespValue::Map(vec![(RespValue::bulk("a"), RespValue::Integer(1))]),
+        ]);
+        assert_eq!(
+            value.encode(Protocol::Resp2),
+            b"*6\r\n$-1\r\n*-1\r\n$3\r\ninf\r\n:0\r\n$2\r\nhi\r\n*2\r\n$1\r\na\r\n:1\r\n"
+        );
+        assert_eq!(
+            RespValue::Null.encode(Protocol::Resp3),
+            RespValue::NullArray.encode(Protocol::Resp3)
+        );
+    }
+
+    #[test]
+    fn refuses_deep_nesting() {
+        let mut deep = "*1\r\n".repeat(MAX_NESTING + 2).into_bytes();
+        deep.extend_from_slice(b":1\r\n");
+        assert_eq!(
+            RespValue::decode(&deep),
+            Err("too many nested aggregates".to_string())
+        );
+        let mut allowed = "*1\r\n".repeat(MAX_NESTING).into_bytes();
+        allowed.extend_from_slice(b":1\r\n");
+        assert!(RespValue::decode(&allowed).unwrap().is_some());
+    }
+
+    #[test]
+    fn decodes_pipelined_requests() {
+        let mut input = RespValue::bulks([&b"SET"[..], b"k", b"\xff\xfe"]).encode(Protocol::Resp2);
+        let first = input.len();
+        input.extend_from_slice(b"PING\r\n");
+
+        let (request, used) = RespValue::decode_request(&input).unwrap().unwrap();
+        assert_eq!(used, first);
+        let arguments = request.into_command().unwrap();
+        assert_eq!(arguments[0], "SET");
+        assert_eq!(arguments[2].as_bytes(), b"\xff\xfe");
+        assert_eq!(arguments[2].as_str(), "\u{fffd}\u{fffd}");
+
+        let (request, used) = RespValue::decode_request(&input[first..]).unwrap().unwrap();
+        assert_eq!(used, 6);
+        assert_eq!(request, RespValue::bulks(["PING"]));
+    }
+
+    #[test]
+    fn picks_requests_up_across_reads() {
+        let mut input =
+            RespValue::bulks(["SET", "key", &"v".repeat(40_000)]).encode(Protocol::Resp2);
+        input.extend_from_slice(b"PING\r\n*1\r\n$4\r\nPING\r\n");
+        for size in [1, 7, 1024, input.len()] {
+            let mut decoder = RequestDecoder::new();
+            let mut requests = Vec::new();
+            for chunk in input.chunks(size) {
+                decoder.buffer().extend_from_slice(chunk);
+                while let Some(request) = decoder.decode().unwrap() {
+                    requests.push(request);
+                }
+            }
+            assert_eq!(
+                requests,
+                vec![
+                    RespValue::bulks(["SET", "key", &"v".repeat(40_000)]),
+                    RespValue::bulks(["PING"]),
+                    RespValue::bulks(["PING"]),
+                ]
+            );
+            assert_eq!(decoder.pending(), 0);
+        }
+
+        let mut decoder = RequestDecoder::new();
+        decoder
+            .buffer()
+            .extend_from_slice(b"*2\r\n$3\r\nGET\r\n$3\r\nk");
+        // Only the unfinished bulk is kept, its header was read.
+        assert_eq!(decoder.decode(), Ok(None));
+        assert_eq!(decoder.pending(), 1);
+        decoder
+            .buffer()
+            .extend_from_slice(&vec![b'1'; MAX_INLINE_LENGTH + 1]);
+        assert_eq!(
+            decoder.decode(),
+            Err("expected CRLF after bulk data".to_string())
+        );
+
+        let mut decoder = RequestDecoder::new();
+        decoder.buffer().extend_from_slice(b"*1\r\n");
+        decoder
+            .buffer()
+            .extend_from_slice(&vec![b'$'; MAX_INLINE_LENGTH + 1]);
+        assert_eq!(
+            decoder.decode(),
+            Err("too big bulk count string".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_requests_are_no_command() {
+        for input in [&b"*0\r\n"[..], b"*-1\r\n", b"\r\n"] {
+            let (request, used) = RespValue::decode_request(input).unwrap().unwrap();
+            assert_eq!(request, RespValue::Array(vec![]));
+            assert_eq!(used, input.len());
+        }
+    }
+
+    #[test]
+    fn reports_protocol_errors() {
+        let error = |input: &[u8]| RespValue::decode_request(input).unwrap_err();
+        assert_eq!(error(b"*1\r\n:1\r\n"), "expected '$', got ':'");
+        assert_eq!(error(b"*x\r\n"), "invalid multibulk length");
+        assert_eq!(error(b"*1\r\n$-2\r\n"), "invalid bulk length");
+        assert_eq!(
+            error(b"*1\r\n$1\r\nab\r\n"),
+            "expected CRLF after bulk data"
+        );
+        assert_eq!(error(b"*1\r\n$536870913\r\n"), "invalid bulk length");
+        assert_eq!(error(b"GET \"key\r\n"), "unbalanced quotes in request");
+        assert_eq!(
+            error(&vec![b'a'; MAX_INLINE_LENGTH + 1]),
+            "too big inline request"
+        );
+        assert_eq!(
+            RespValue::decode(b"=3\r\ntxt\r\n"),
+            Err("invalid verbatim string".to_string())
+        );
+    }
+
+    #[test]
+    fn splits_inline_arguments_like_redis_cli() {
+        assert_eq!(
+            split_arguments(br#"  SET "a b" 'it\'s' "\x41\n\q" plain "#),
+            Some(vec![
+                b"SET".to_vec(),
+                b"a b".to_vec(),
+                b"it's".to_vec(),
+                b"A\nq".to_vec(),
+                b"plain".to_vec(),
+            ])
+        );
+        assert_eq!(split_arguments(b"\"\" ''"), Some(vec![vec![], vec![]]));
+        assert_eq!(split_arguments(b"\"a\"b"), None);
+        assert_eq!(split_arguments(b"'a"), None);
+    }
+
+    #[test]
+    fn only_arrays_of_bulk_strings_are_commands() {
+        assert_eq!(RespValue::bulk("PING").into_command(), None);
+        assert_eq!(
+            RespValue::Array(vec![RespValue::bulk("GET"), RespValue::Integer(1)]).into_command(),
+            None
+        );
+    }
+}